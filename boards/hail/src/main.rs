@@ -72,7 +72,7 @@ struct Hail {
     adc: &'static capsules::adc::Adc<'static, sam4l::adc::Adc>,
     led: &'static capsules::led::LED<'static, sam4l::gpio::GPIOPin>,
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin>,
-    rng: &'static capsules::rng::SimpleRng<'static, sam4l::trng::Trng<'static>>,
+    rng: &'static capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
     ipc: kernel::ipc::IPC,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     dac: &'static capsules::dac::Dac<'static>,
@@ -405,12 +405,32 @@ pub unsafe fn reset_handler() {
     );
     sam4l::adc::ADC0.set_client(adc);
 
-    // Setup RNG
+    // Setup RNG, served by a CSPRNG so that apps don't wait on the TRNG
+    let csprng_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::csprng::Csprng::new(&sam4l::trng::TRNG, csprng_alarm)
+    );
+    csprng_alarm.set_client(csprng);
+    sam4l::trng::TRNG.set_client(csprng);
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(csprng)
+    );
+    csprng.set_client(mux_rng);
+    let app_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(app_rng);
     let rng = static_init!(
-        capsules::rng::SimpleRng<'static, sam4l::trng::Trng>,
-        capsules::rng::SimpleRng::new(&sam4l::trng::TRNG, kernel::Grant::create())
+        capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
+        capsules::rng::SimpleRng::new(app_rng, kernel::Grant::create())
     );
-    sam4l::trng::TRNG.set_client(rng);
+    app_rng.set_client(rng);
 
     // set GPIO driver controlling remaining GPIO pins
     let gpio_pins = static_init!(
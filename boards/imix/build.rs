@@ -1,4 +1,5 @@
 fn main() {
     println!("cargo:rerun-if-changed=layout.ld");
     println!("cargo:rerun-if-changed=../kernel_layout.ld");
+    println!("cargo:rerun-if-env-changed=THREAD_MASTER_KEY");
 }
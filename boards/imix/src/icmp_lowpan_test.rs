@@ -30,11 +30,13 @@
 use capsules;
 extern crate sam4l;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
 use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
 use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_send::{IP6SendMux, IP6SendStruct, IP6Sender};
 use capsules::net::sixlowpan::sixlowpan_compression;
 use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
@@ -51,6 +53,8 @@ pub const DST_ADDR: IPAddr = IPAddr([
     0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
 ]);
 
+const GATEWAY_MAC_ADDR: MacAddress = MacAddress::Short(0xf00e);
+
 /* 6LoWPAN Constants */
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8;
 static DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16];
@@ -80,18 +84,26 @@ pub unsafe fn initialize_all(
     'static,
     capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
 > {
-    let sixlowpan = static_init!(
-        Sixlowpan<'static, sam4l::ast::Ast<'static>, sixlowpan_compression::Context>,
-        Sixlowpan::new(
+    let interface = static_init!(
+        IP6Interface<'static>,
+        IP6Interface::new(
+            radio_mac,
             sixlowpan_compression::Context {
                 prefix: DEFAULT_CTX_PREFIX,
                 prefix_len: DEFAULT_CTX_PREFIX_LEN,
                 id: 0,
                 compress: false,
-            },
-            &sam4l::ast::AST
+            }
         )
     );
+    interface.autoconfigure_link_local();
+    interface.add_addr(SRC_ADDR);
+    interface.set_gateway(GATEWAY_MAC_ADDR);
+
+    let sixlowpan = static_init!(
+        Sixlowpan<'static, sam4l::ast::Ast<'static>, &'static IP6Interface<'static>>,
+        Sixlowpan::new(interface, &sam4l::ast::AST)
+    );
 
     let sixlowpan_state = sixlowpan as &SixlowpanState;
     let sixlowpan_tx = TxState::new(sixlowpan_state);
@@ -105,11 +117,16 @@ pub unsafe fn initialize_all(
 
     let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
+    let ip6_send_mux = static_init!(
+        IP6SendMux<'static>,
+        IP6SendMux::new(radio_mac, &mut RF233_BUF)
+    );
+    radio_mac.set_transmit_client(ip6_send_mux);
     let ip6_sender = static_init!(
         IP6SendStruct<'static>,
-        IP6SendStruct::new(ip6_dg, &mut RF233_BUF, sixlowpan_tx, radio_mac)
+        IP6SendStruct::new(ip6_dg, sixlowpan_tx, ip6_send_mux, interface)
     );
-    radio_mac.set_transmit_client(ip6_sender);
+    ip6_send_mux.add_sender(ip6_sender);
 
     let icmp_send_struct = static_init!(
         ICMP6SendStruct<'static, IP6SendStruct<'static>>,
@@ -512,7 +512,7 @@ fn ipv6_check_receive_packet(
 
                 match ip6_packet.payload.header {
                     TransportHeader::UDP(ref sent_udp_pkt) => {
-                        if u16::from_be(rcvudphdr.get_src_port()) != sent_udp_pkt.get_src_port() {
+                        if rcvudphdr.get_src_port() != sent_udp_pkt.get_src_port() {
                             debug!(
                                 "Mismatched src_port. Rcvd is: {:?}, expctd is: {:?}",
                                 rcvudphdr.get_src_port(),
@@ -521,7 +521,7 @@ fn ipv6_check_receive_packet(
                             test_success = false;
                         }
 
-                        if u16::from_be(rcvudphdr.get_dst_port()) != sent_udp_pkt.get_dst_port() {
+                        if rcvudphdr.get_dst_port() != sent_udp_pkt.get_dst_port() {
                             debug!(
                                 "Mismatched dst_port. Rcvd is: {:?}, expctd is: {:?}",
                                 rcvudphdr.get_dst_port(),
@@ -530,7 +530,7 @@ fn ipv6_check_receive_packet(
                             test_success = false;
                         }
 
-                        if u16::from_be(rcvudphdr.get_len()) != sent_udp_pkt.get_len() {
+                        if rcvudphdr.get_len() != sent_udp_pkt.get_len() {
                             debug!(
                                 "Mismatched udp_len. Rcvd is: {:?}, expctd is: {:?}",
                                 rcvudphdr.get_len(),
@@ -539,7 +539,7 @@ fn ipv6_check_receive_packet(
                             test_success = false;
                         }
 
-                        if u16::from_be(rcvudphdr.get_cksum()) != sent_udp_pkt.get_cksum() {
+                        if rcvudphdr.get_cksum() != sent_udp_pkt.get_cksum() {
                             debug!("mismatched cksum");
                             test_success = false;
                         }
@@ -17,8 +17,36 @@ extern crate sam4l;
 use capsules::alarm::AlarmDriver;
 use capsules::ieee802154::device::MacDevice;
 use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::net::coap::coap::COAP_PORT;
+use capsules::net::coap::coap_endpoint::CoAPEndpoint;
+use capsules::net::coap::driver::CoAPDriver;
+use capsules::net::icmpv6::driver::PingDriver;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_handler::{ICMP6Handler, Ping};
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::icmpv6::nd_host::NDHost;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_recv::{IP6RecvStruct, IP6Receiver};
+use capsules::net::ipv6::ipv6_send::{IP6SendMux, IP6SendSlot, IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::mesh::MeshForwarder;
+use capsules::net::sixlowpan::sixlowpan_compression;
+use capsules::net::sixlowpan::sixlowpan_state::{RxState, Sixlowpan, SixlowpanState, TxState};
+use capsules::net::tcp::driver::TCPDriver;
+use capsules::net::tcp::tcp::TCPHeader;
+use capsules::net::tcp::tcp_mux::TCPMux;
+use capsules::net::tcp::tcp_socket::TCPSocket;
+use capsules::net::thread::mle::{MLE, MLE_PORT};
+use capsules::net::thread::tlv::LinkMode;
+use capsules::net::udp::driver::UDPDriver;
+use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_port_table::UDPPortTable;
+use capsules::net::udp::udp_recv::{UDPBinding, UDPRecvMux};
+use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
 use capsules::rf233::RF233;
+use capsules::virtual_aes::{MuxAES128, VirtualAES128};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_flash::{FlashUser, MuxFlash};
 use capsules::virtual_i2c::{I2CDevice, MuxI2C};
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
 use kernel::hil;
@@ -71,9 +99,18 @@ static mut PROCESSES: [Option<&'static mut kernel::Process<'static>>; NUM_PROCS]
 // Save some deep nesting
 type RF233Device =
     capsules::rf233::RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>;
+type AesDevice = VirtualAES128<'static, sam4l::aes::Aes<'static>>;
+type CdcDevice = capsules::cdc::CdcAcm<
+    'static,
+    sam4l::usbc::Usbc<'static>,
+    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+>;
+type Sha256Device =
+    capsules::sha256::Sha256Software<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+type TCPAlarm = VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>;
 
 struct Imix {
-    console: &'static capsules::console::Console<'static, sam4l::usart::USART>,
+    console: &'static capsules::console::Console<'static, CdcDevice>,
     gpio: &'static capsules::gpio::GPIO<'static, sam4l::gpio::GPIOPin>,
     alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
@@ -87,15 +124,26 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
+    aes: &'static capsules::aes::AesDriver<'static, AesDevice>,
+    digest: &'static capsules::digest::DigestDriver<'static, Sha256Device>,
+    udp_driver: &'static UDPDriver<'static>,
+    ping_driver: &'static PingDriver<'static>,
+    tcp_driver: &'static TCPDriver<'static, TCPAlarm>,
+    coap_driver: &'static CoAPDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     usb_driver: &'static capsules::usb_user::UsbSyscallDriver<
         'static,
-        capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>,
+        capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
     >,
     nrf51822: &'static capsules::nrf51822_serialization::Nrf51822Serialization<
         'static,
         sam4l::usart::USART,
     >,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
+    watchdog: &'static capsules::watchdog::WatchdogService<
+        'static,
+        sam4l::wdt::Wdt,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
 }
 
 // The RF233 radio stack requires our buffers for its SPI operations:
@@ -122,6 +170,84 @@ static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 const CRYPT_SIZE: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + radio::MAX_BUF_SIZE;
 static mut CRYPT_BUF: [u8; CRYPT_SIZE] = [0x00; CRYPT_SIZE];
 
+// The AES syscall driver's CCM and GCM need three and one blocks more than
+// the driver's own buffer
+static mut APP_CCM_BUF: [u8; 176] = [0x00; 176];
+static mut APP_GCM_BUF: [u8; 144] = [0x00; 144];
+
+// 6LoWPAN sends the frames of all IPv6 senders from one buffer, and
+// reassembles received packets into one buffer per packet reassembled at
+// once. A packet whose fragments stop arriving gives its buffer up after
+// the reassembly timeout, in seconds.
+static mut LOWPAN_FRAG_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut LOWPAN_RX_BUF_0: [u8; 1280] = [0x00; 1280];
+static mut LOWPAN_RX_BUF_1: [u8; 1280] = [0x00; 1280];
+const LOWPAN_REASSEMBLY_TIMEOUT: u32 = 20;
+
+// Each IPv6 sender holds the packet it sends. UDP needs a buffer for the
+// outgoing UDP payload.
+static mut UDP_DGRAM: [u8; 200] = [0x00; 200];
+
+// ICMPv6 has its own sender, and a buffer for composing the data of Echo
+// Requests and Destination Unreachable messages. Echo Replies and errors can
+// be sent while a ping is, so the sender can queue one more packet.
+static mut ICMP_DGRAM: [u8; 200] = [0x00; 200];
+static mut ICMP_QUEUED_DGRAM: [u8; 200] = [0x00; 200];
+static mut ICMP_BUF: [u8; 200] = [0x00; 200];
+
+// Neighbor Discovery sends small messages through a sender of its own.
+static mut ND_DGRAM: [u8; 64] = [0x00; 64];
+static mut ND_BUF: [u8; 64] = [0x00; 64];
+
+// TCP sends the segments of all connections through one sender, whose
+// payload buffer bounds the segment size. Each socket holds the data it
+// sends until the peer acknowledges it.
+const TCP_MSS: usize = 200;
+static mut TCP_DGRAM: [u8; TCP_MSS] = [0x00; TCP_MSS];
+static mut TCP_TX_BUF_0: [u8; 256] = [0x00; 256];
+static mut TCP_TX_BUF_1: [u8; 256] = [0x00; 256];
+
+// MLE secures its messages itself, in a buffer that also holds the IPv6
+// addresses they are authenticated with. Its AES-CCM needs three blocks more.
+const MLE_BUF_SIZE: usize = 256;
+static mut MLE_DGRAM: [u8; 128] = [0x00; 128];
+static mut MLE_BUF: [u8; MLE_BUF_SIZE] = [0x00; MLE_BUF_SIZE];
+const MLE_CCM_SIZE: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + MLE_BUF_SIZE;
+static mut MLE_CCM_BUF: [u8; MLE_CCM_SIZE] = [0x00; MLE_CCM_SIZE];
+static mut MLE_POLL_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+// MLE keeps a bound on its frame counter in the last page of flash
+const MLE_COUNTER_ADDRESS: usize = 0x7fe00;
+static mut MLE_COUNTER_BUF: [u8; 4] = [0x00; 4];
+
+// CoAP encodes the request it sends and the response it last sent into
+// buffers of their own, which bound the payloads of apps.
+static mut COAP_DGRAM: [u8; 200] = [0x00; 200];
+static mut COAP_REQUEST_BUF: [u8; 200] = [0x00; 200];
+static mut COAP_RESPONSE_BUF: [u8; 200] = [0x00; 200];
+
+// Frames relayed through the mesh for other nodes
+static mut MESH_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+// The Thread network to attach to. Its master key is commissioned when the
+// kernel is built, through the THREAD_MASTER_KEY environment variable, and
+// the node does not attach without one. The mesh-local prefix is the default
+// of OpenThread.
+const THREAD_MESH_LOCAL_PREFIX: [u8; 8] = [0xfd, 0xde, 0xad, 0x00, 0xbe, 0xef, 0x00, 0x00];
+
+/// Parses a Thread master key written as 32 hex digits.
+fn parse_master_key(hex: &str) -> Option<[u8; 16]> {
+    let hex = hex.as_bytes();
+    if hex.len() != 32 {
+        return None;
+    }
+    let digit = |c: u8| (c as char).to_digit(16);
+    let mut key = [0; 16];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = (digit(hex[2 * i])? << 4 | digit(hex[2 * i + 1])?) as u8;
+    }
+    Some(key)
+}
+
 impl kernel::Platform for Imix {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
     where
@@ -140,14 +266,25 @@ impl kernel::Platform for Imix {
             capsules::humidity::DRIVER_NUM => f(Some(self.humidity)),
             capsules::ninedof::DRIVER_NUM => f(Some(self.ninedof)),
             capsules::crc::DRIVER_NUM => f(Some(self.crc)),
+            capsules::aes::DRIVER_NUM => f(Some(self.aes)),
+            capsules::digest::DRIVER_NUM => f(Some(self.digest)),
             capsules::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
+            capsules::net::udp::driver::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::driver::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::tcp::driver::DRIVER_NUM => f(Some(self.tcp_driver)),
+            capsules::net::coap::driver::DRIVER_NUM => f(Some(self.coap_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
+            capsules::watchdog::DRIVER_NUM => f(Some(self.watchdog)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
     }
+
+    fn watchdog(&self) -> Option<&hil::watchdog::Watchdog> {
+        Some(self.watchdog)
+    }
 }
 
 unsafe fn set_pin_primary_functions() {
@@ -244,25 +381,6 @@ pub unsafe fn reset_handler() {
         trng: true,
     });
 
-    // # CONSOLE
-
-    let console = static_init!(
-        capsules::console::Console<sam4l::usart::USART>,
-        capsules::console::Console::new(
-            &sam4l::usart::USART3,
-            115200,
-            &mut capsules::console::WRITE_BUF,
-            &mut capsules::console::READ_BUF,
-            kernel::Grant::create()
-        )
-    );
-    hil::uart::UART::set_client(&sam4l::usart::USART3, console);
-    console.initialize();
-
-    // Attach the kernel debug interface to this console
-    let kc = static_init!(capsules::console::App, capsules::console::App::default());
-    kernel::debug::assign_console_driver(Some(console), kc);
-
     // Create the Nrf51822Serialization driver for passing BLE commands
     // over UART to the nRF51822 radio.
     let nrf_serialization = static_init!(
@@ -285,6 +403,37 @@ pub unsafe fn reset_handler() {
     );
     ast.configure(mux_alarm);
 
+    // # CONSOLE
+
+    // The console is a USB CDC-ACM serial port, one of the functions of the
+    // USB device configured below. Panics are still printed on USART3.
+    let cdc_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let cdc = static_init!(
+        CdcDevice,
+        capsules::cdc::CdcAcm::new(&sam4l::usbc::USBC, cdc_alarm)
+    );
+    cdc_alarm.set_client(cdc);
+
+    let console = static_init!(
+        capsules::console::Console<CdcDevice>,
+        capsules::console::Console::new(
+            cdc,
+            115200,
+            &mut capsules::console::WRITE_BUF,
+            &mut capsules::console::READ_BUF,
+            kernel::Grant::create()
+        )
+    );
+    hil::uart::UART::set_client(cdc, console);
+    console.initialize();
+
+    // Attach the kernel debug interface to this console
+    let kc = static_init!(capsules::console::App, capsules::console::App::default());
+    kernel::debug::assign_console_driver(Some(console), kc);
+
     let virtual_alarm1 = static_init!(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm)
@@ -295,6 +444,28 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
+    // # WATCHDOG
+
+    // Apps that miss their heartbeat are restarted
+    let watchdog_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let watchdog = static_init!(
+        capsules::watchdog::WatchdogService<
+            'static,
+            sam4l::wdt::Wdt,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        >,
+        capsules::watchdog::WatchdogService::new(
+            &sam4l::wdt::WDT,
+            watchdog_alarm,
+            capsules::watchdog::Policy::RestartApp,
+            kernel::Grant::create()
+        )
+    );
+    watchdog_alarm.set_client(watchdog);
+
     // # I2C Sensors
 
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2));
@@ -505,15 +676,71 @@ pub unsafe fn reset_handler() {
         capsules::crc::Crc::new(&mut sam4l::crccu::CRCCU, kernel::Grant::create())
     );
 
+    // Apps hash with a software SHA-256 engine of their own
+    let digest_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let digest_sha256 = static_init!(
+        Sha256Device,
+        capsules::sha256::Sha256Software::new(digest_alarm)
+    );
+    digest_alarm.set_client(digest_sha256);
+    let digest = static_init!(
+        capsules::digest::DigestDriver<'static, Sha256Device>,
+        capsules::digest::DigestDriver::new(
+            digest_sha256,
+            &mut capsules::digest::DATA_BUF,
+            &mut capsules::digest::DEST_BUF,
+            kernel::Grant::create()
+        )
+    );
+    hil::digest::Digest::set_client(digest_sha256, digest);
+
     rf233_spi.set_client(rf233);
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
 
+    // The AES engine is shared by the 802.15.4 framer and the AES driver
+    let mux_aes = static_init!(
+        MuxAES128<'static, sam4l::aes::Aes<'static>>,
+        MuxAES128::new(&sam4l::aes::AES)
+    );
+    sam4l::aes::AES.set_client(mux_aes);
+
+    let framer_aes = static_init!(AesDevice, VirtualAES128::new(mux_aes));
+    mux_aes.add_user(framer_aes);
     let aes_ccm = static_init!(
-        capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
-        capsules::aes_ccm::AES128CCM::new(&sam4l::aes::AES, &mut CRYPT_BUF)
+        capsules::aes_ccm::AES128CCM<'static, AesDevice>,
+        capsules::aes_ccm::AES128CCM::new(framer_aes, &mut CRYPT_BUF)
+    );
+    framer_aes.set_client(aes_ccm);
+    framer_aes.enable();
+
+    let app_aes = static_init!(AesDevice, VirtualAES128::new(mux_aes));
+    mux_aes.add_user(app_aes);
+    let app_aes_ccm = static_init!(
+        capsules::aes_ccm::AES128CCM<'static, AesDevice>,
+        capsules::aes_ccm::AES128CCM::new(app_aes, &mut APP_CCM_BUF)
+    );
+    let app_aes_gcm = static_init!(
+        capsules::aes_gcm::AES128GCM<'static, AesDevice>,
+        capsules::aes_gcm::AES128GCM::new(app_aes, &mut APP_GCM_BUF)
+    );
+    let aes = static_init!(
+        capsules::aes::AesDriver<'static, AesDevice>,
+        capsules::aes::AesDriver::new(
+            app_aes,
+            app_aes_ccm,
+            app_aes_gcm,
+            &mut capsules::aes::BUF,
+            &mut capsules::aes::AEAD_BUF,
+            kernel::Grant::create()
+        )
     );
-    sam4l::aes::AES.set_client(aes_ccm);
-    sam4l::aes::AES.enable();
+    app_aes.set_client(aes);
+    app_aes.enable();
+    app_aes_ccm.set_client(aes);
+    symmetric_encryption::AES128GCM::set_client(app_aes_gcm, aes);
 
     // Keeps the radio on permanently; pass-through layer
     let awake_mac: &AwakeMac<RF233Device> =
@@ -525,7 +752,7 @@ pub unsafe fn reset_handler() {
         capsules::ieee802154::framer::Framer<
             'static,
             AwakeMac<'static, RF233Device>,
-            capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
+            capsules::aes_ccm::AES128CCM<'static, AesDevice>,
         >,
         capsules::ieee802154::framer::Framer::new(awake_mac, aes_ccm)
     );
@@ -552,40 +779,505 @@ pub unsafe fn reset_handler() {
         capsules::ieee802154::RadioDriver::new(radio_mac, kernel::Grant::create(), &mut RADIO_BUF)
     );
 
-    mac_device.set_key_procedure(radio_driver);
-    mac_device.set_device_procedure(radio_driver);
     radio_mac.set_transmit_client(radio_driver);
     radio_mac.set_receive_client(radio_driver);
     radio_mac.set_pan(0xABCD);
     radio_mac.set_address(0x1008);
+    radio_mac.set_address_long([0x00, 0x12, 0x4b, 0x00, 0x00, 0x00, 0x10, 0x08]);
+
+    // 6LoWPAN, on its own MAC user, which all IPv6 senders share
+    let lowpan_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MacUser<'static>,
+        capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+    );
+    mux_mac.add_user(lowpan_mac);
+
+    // The addresses and 6LoWPAN contexts of the node
+    let interface = static_init!(
+        IP6Interface<'static>,
+        IP6Interface::new(
+            lowpan_mac,
+            sixlowpan_compression::Context {
+                prefix: [0x0; 16],
+                prefix_len: 8,
+                id: 0,
+                compress: false,
+            }
+        )
+    );
+    interface.autoconfigure_link_local();
+
+    let sixlowpan = static_init!(
+        Sixlowpan<'static, sam4l::ast::Ast<'static>, &'static IP6Interface<'static>>,
+        Sixlowpan::new(interface, &sam4l::ast::AST)
+    );
+    let sixlowpan_state = sixlowpan as &SixlowpanState;
+    let sixlowpan_rx_0 = static_init!(RxState<'static>, RxState::new(&mut LOWPAN_RX_BUF_0));
+    sixlowpan_state.add_rx_state(sixlowpan_rx_0);
+    let sixlowpan_rx_1 = static_init!(RxState<'static>, RxState::new(&mut LOWPAN_RX_BUF_1));
+    sixlowpan_state.add_rx_state(sixlowpan_rx_1);
+    sixlowpan.set_reassembly_timeout(LOWPAN_REASSEMBLY_TIMEOUT);
+    lowpan_mac.set_receive_client(sixlowpan);
+
+    let ip6_send_mux = static_init!(
+        IP6SendMux<'static>,
+        IP6SendMux::new(lowpan_mac, &mut LOWPAN_FRAG_BUF)
+    );
+    lowpan_mac.set_transmit_client(ip6_send_mux);
+
+    // Mesh-under routing, relaying frames on its own MAC user. Routes are
+    // added with `mesh_forwarder.add_route`.
+    let mesh_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MacUser<'static>,
+        capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+    );
+    mux_mac.add_user(mesh_mac);
+    let mesh_forwarder = static_init!(
+        MeshForwarder<'static>,
+        MeshForwarder::new(mesh_mac, &mut MESH_BUF)
+    );
+    mesh_mac.set_transmit_client(mesh_forwarder);
+    sixlowpan.set_mesh_forwarder(mesh_forwarder);
+
+    // UDP, with a sender of its own
+    let ip6_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::UDP(UDPHeader::new()),
+            &mut UDP_DGRAM
+        ))
+    );
+    let ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            ip6_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(ip6_sender);
+
+    let udp_send = static_init!(
+        UDPSendStruct<'static, IP6SendStruct<'static>>,
+        UDPSendStruct::new(ip6_sender)
+    );
+    ip6_sender.set_client(udp_send);
+
+    let ip6_receiver = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+    sixlowpan_state.set_rx_client(ip6_receiver);
+    let udp_port_table = static_init!(UDPPortTable, UDPPortTable::new());
+    let udp_recv_mux = static_init!(UDPRecvMux<'static>, UDPRecvMux::new(udp_port_table));
+    ip6_receiver.set_udp_client(udp_recv_mux);
+
+    let udp_driver = static_init!(
+        UDPDriver<'static>,
+        UDPDriver::new(udp_send, udp_port_table, kernel::Grant::create())
+    );
+    udp_send.set_client(udp_driver);
+    udp_recv_mux.set_app_client(udp_driver);
+
+    // ICMPv6
+    let icmp_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128)),
+            &mut ICMP_DGRAM
+        ))
+    );
+    let icmp_ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            icmp_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(icmp_ip6_sender);
+    let icmp_queued_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128)),
+            &mut ICMP_QUEUED_DGRAM
+        ))
+    );
+    let icmp_ip6_slot = static_init!(IP6SendSlot<'static>, IP6SendSlot::new(icmp_queued_dg));
+    icmp_ip6_sender.add_slot(icmp_ip6_slot);
+    let icmp_send = static_init!(
+        ICMP6SendStruct<'static, IP6SendStruct<'static>>,
+        ICMP6SendStruct::new(icmp_ip6_sender)
+    );
+    icmp_ip6_sender.set_client(icmp_send);
+
+    let icmp_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let icmp_handler = static_init!(
+        ICMP6Handler<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        ICMP6Handler::new(icmp_send, icmp_alarm, &mut ICMP_BUF)
+    );
+    icmp_send.set_client(icmp_handler);
+    icmp_alarm.set_client(icmp_handler);
+    ip6_receiver.set_icmp_client(icmp_handler);
+    udp_recv_mux.set_unbound_client(icmp_handler);
+
+    let ping_driver = static_init!(
+        PingDriver<'static>,
+        PingDriver::new(icmp_handler, kernel::Grant::create())
+    );
+    icmp_handler.set_ping_client(ping_driver);
+
+    // 6LoWPAN Neighbor Discovery, to find a router and register an address
+    let nd_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type133)),
+            &mut ND_DGRAM
+        ))
+    );
+    let nd_ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            nd_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(nd_ip6_sender);
+    let nd_send = static_init!(
+        ICMP6SendStruct<'static, IP6SendStruct<'static>>,
+        ICMP6SendStruct::new(nd_ip6_sender)
+    );
+    nd_ip6_sender.set_client(nd_send);
+
+    let nd_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let nd_host = static_init!(
+        NDHost<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        NDHost::new(nd_send, interface, nd_alarm, &mut ND_BUF)
+    );
+    nd_alarm.set_client(nd_host);
+    icmp_handler.set_nd_client(nd_host);
+
+    // A CSPRNG, shared by TCP, MLE and CoAP
+    let csprng_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        capsules::csprng::Csprng::new(&sam4l::trng::TRNG, csprng_alarm)
+    );
+    csprng_alarm.set_client(csprng);
+    sam4l::trng::TRNG.set_client(csprng);
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(csprng)
+    );
+    csprng.set_client(mux_rng);
+
+    // TCP, with a socket for each app
+    let tcp_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::TCP(TCPHeader::new()),
+            &mut TCP_DGRAM
+        ))
+    );
+    let tcp_ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            tcp_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(tcp_ip6_sender);
+    let tcp_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(tcp_rng);
+    let tcp_mux = static_init!(
+        TCPMux<'static, TCPAlarm>,
+        TCPMux::new(tcp_ip6_sender, tcp_rng, TCP_MSS)
+    );
+    tcp_ip6_sender.set_client(tcp_mux);
+    tcp_rng.set_client(tcp_mux);
+    ip6_receiver.set_tcp_client(tcp_mux);
+
+    let tcp_alarm_0 = static_init!(TCPAlarm, VirtualMuxAlarm::new(mux_alarm));
+    let tcp_socket_0 = static_init!(
+        TCPSocket<'static, TCPAlarm>,
+        TCPSocket::new(0, tcp_mux, tcp_alarm_0, &mut TCP_TX_BUF_0)
+    );
+    tcp_alarm_0.set_client(tcp_socket_0);
+    tcp_mux.add_socket(tcp_socket_0);
+    let tcp_alarm_1 = static_init!(TCPAlarm, VirtualMuxAlarm::new(mux_alarm));
+    let tcp_socket_1 = static_init!(
+        TCPSocket<'static, TCPAlarm>,
+        TCPSocket::new(1, tcp_mux, tcp_alarm_1, &mut TCP_TX_BUF_1)
+    );
+    tcp_alarm_1.set_client(tcp_socket_1);
+    tcp_mux.add_socket(tcp_socket_1);
+
+    let tcp_sockets = static_init!(
+        [&'static TCPSocket<'static, TCPAlarm>; NUM_PROCS],
+        [tcp_socket_0, tcp_socket_1]
+    );
+    let tcp_driver = static_init!(
+        TCPDriver<'static, TCPAlarm>,
+        TCPDriver::new(tcp_sockets, kernel::Grant::create())
+    );
+    for socket in tcp_sockets.iter() {
+        socket.set_client(tcp_driver);
+    }
+
+    // Thread MLE, which attaches to a parent and gives the framer the MAC
+    // key. Other keys are left to the radio driver.
+    let mle_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::UDP(UDPHeader::new()),
+            &mut MLE_DGRAM
+        ))
+    );
+    let mle_ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            mle_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(mle_ip6_sender);
+    mle_ip6_sender.set_link_security_enabled(false);
+    let mle_udp_send = static_init!(
+        UDPSendStruct<'static, IP6SendStruct<'static>>,
+        UDPSendStruct::new(mle_ip6_sender)
+    );
+    mle_ip6_sender.set_client(mle_udp_send);
+
+    let mle_aes = static_init!(AesDevice, VirtualAES128::new(mux_aes));
+    mux_aes.add_user(mle_aes);
+    let mle_aes_ccm = static_init!(
+        capsules::aes_ccm::AES128CCM<'static, AesDevice>,
+        capsules::aes_ccm::AES128CCM::new(mle_aes, &mut MLE_CCM_BUF)
+    );
+    mle_aes.set_client(mle_aes_ccm);
+    mle_aes.enable();
+
+    let sha256_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let mle_sha256 = static_init!(
+        Sha256Device,
+        capsules::sha256::Sha256Software::new(sha256_alarm)
+    );
+    sha256_alarm.set_client(mle_sha256);
+
+    let mle_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(mle_rng);
+
+    let mle_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    // MLE polls the parent on a MAC user of its own
+    let mle_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MacUser<'static>,
+        capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+    );
+    mux_mac.add_user(mle_mac);
+    let mle = static_init!(
+        MLE<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>, Sha256Device>,
+        MLE::new(
+            mle_udp_send,
+            interface,
+            mle_mac,
+            mle_aes_ccm,
+            mle_sha256,
+            mle_rng,
+            mle_alarm,
+            &mut MLE_BUF,
+            &mut MLE_POLL_BUF
+        )
+    );
+    mle_mac.set_transmit_client(mle);
+    mle_udp_send.set_client(mle);
+    mle_aes_ccm.set_client(mle);
+    hil::digest::Digest::set_client(mle_sha256, mle);
+    mle_rng.set_client(mle);
+    mle_alarm.set_client(mle);
+    let mle_binding = static_init!(UDPBinding<'static>, UDPBinding::new(MLE_PORT, mle));
+    udp_recv_mux.add_binding(mle_binding);
+
+    mle.set_key_procedure(radio_driver);
+    mle.set_device_procedure(radio_driver);
+    mac_device.set_key_procedure(mle);
+    mac_device.set_device_procedure(mle);
+    // A sleepy end device, which polls its parent for frames
+    mle.set_mode(LinkMode::SecureDataRequests as u8);
+    match option_env!("THREAD_MASTER_KEY").and_then(parse_master_key) {
+        Some(master_key) => mle.set_network(master_key, THREAD_MESH_LOCAL_PREFIX, 0),
+        None => debug!("THREAD_MASTER_KEY is not set to 32 hex digits, not attaching to Thread"),
+    }
+
+    // CoAP, which serves the resources of apps and sends their requests
+    let coap_dg = static_init!(
+        IP6Packet<'static>,
+        IP6Packet::new(IPPayload::new(
+            TransportHeader::UDP(UDPHeader::new()),
+            &mut COAP_DGRAM
+        ))
+    );
+    let coap_ip6_sender = static_init!(
+        IP6SendStruct<'static>,
+        IP6SendStruct::new(
+            coap_dg,
+            TxState::new(sixlowpan_state),
+            ip6_send_mux,
+            interface
+        )
+    );
+    ip6_send_mux.add_sender(coap_ip6_sender);
+    let coap_udp_send = static_init!(
+        UDPSendStruct<'static, IP6SendStruct<'static>>,
+        UDPSendStruct::new(coap_ip6_sender)
+    );
+    coap_ip6_sender.set_client(coap_udp_send);
+
+    let coap_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let coap_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(coap_rng);
+    let coap_endpoint = static_init!(
+        CoAPEndpoint<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        CoAPEndpoint::new(
+            coap_udp_send,
+            coap_alarm,
+            coap_rng,
+            &mut COAP_REQUEST_BUF,
+            &mut COAP_RESPONSE_BUF
+        )
+    );
+    coap_udp_send.set_client(coap_endpoint);
+    coap_alarm.set_client(coap_endpoint);
+    coap_rng.set_client(coap_endpoint);
+    let coap_binding = static_init!(UDPBinding<'static>, UDPBinding::new(COAP_PORT, coap_endpoint));
+    udp_recv_mux.add_binding(coap_binding);
+
+    let coap_driver = static_init!(
+        CoAPDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+        CoAPDriver::new(coap_endpoint, kernel::Grant::create())
+    );
+    coap_endpoint.set_client(coap_driver);
+    coap_endpoint.set_server(coap_driver);
+
+    // Share the internal flash between USB firmware downloads and the
+    // nonvolatile storage driver
+    sam4l::flashcalw::FLASH_CONTROLLER.configure();
+    let mux_flash = static_init!(
+        MuxFlash<'static, sam4l::flashcalw::FLASHCALW>,
+        MuxFlash::new(&sam4l::flashcalw::FLASH_CONTROLLER)
+    );
+    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, mux_flash);
+
+    // USB DFU, which writes app images over the first half of the app flash.
+    // The second half holds the nonvolatile storage of apps.
+    pub static mut DFU_PAGEBUFFER: sam4l::flashcalw::Sam4lPage =
+        sam4l::flashcalw::Sam4lPage::new();
+    static DFU_REGIONS: [capsules::dfu::DfuRegion; 1] = [capsules::dfu::DfuRegion {
+        kind: capsules::dfu::RegionKind::Apps,
+        start_page: 0x200,
+        num_pages: 0x100,
+        string_index: 4,
+    }];
+    let dfu_flash = static_init!(
+        FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        FlashUser::new(mux_flash)
+    );
+    let dfu = static_init!(
+        capsules::dfu::UsbDfu<'static, FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+        capsules::dfu::UsbDfu::new(dfu_flash, &DFU_REGIONS, &mut DFU_PAGEBUFFER)
+    );
+    hil::flash::HasClient::set_client(dfu_flash, dfu);
 
     // Configure the USB controller
-    let usb_client = static_init!(
+    let usb_vendor = static_init!(
         capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>,
         capsules::usbc_client::Client::new(&sam4l::usbc::USBC)
     );
+    let usb_functions = static_init!(
+        [&'static capsules::usb_composite::Function; 3],
+        [cdc, usb_vendor, dfu]
+    );
+    let usb_client = static_init!(
+        capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+        capsules::usb_composite::CompositeDevice::new(
+            &sam4l::usbc::USBC,
+            capsules::usb::DeviceDescriptor {
+                class: 0xef, // Interface association, for the console
+                subclass: 2,
+                protocol: 1,
+                manufacturer_string: 1,
+                product_string: 2,
+                serial_number_string: 3,
+                ..Default::default()
+            },
+            &["XYZ Corp.", "The Zorpinator", "Serial No. 5", "Apps"],
+            usb_functions
+        )
+    );
+    usb_client.build(
+        &mut capsules::usb_composite::CONFIGURATION_BUF,
+        sam4l::usbc::N_ENDPOINTS - 1,
+    );
     sam4l::usbc::USBC.set_client(usb_client);
 
+    // Attach now, so that the console is available without an app
+    hil::usb::Client::enable(usb_client);
+    hil::usb::Client::attach(usb_client);
+
     // Configure the USB userspace driver
     let usb_driver = static_init!(
         capsules::usb_user::UsbSyscallDriver<
             'static,
-            capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>,
+            capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
         >,
         capsules::usb_user::UsbSyscallDriver::new(usb_client, kernel::Grant::create())
     );
 
-    sam4l::flashcalw::FLASH_CONTROLLER.configure();
     pub static mut FLASH_PAGEBUFFER: sam4l::flashcalw::Sam4lPage =
         sam4l::flashcalw::Sam4lPage::new();
+    let nv_flash = static_init!(
+        FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        FlashUser::new(mux_flash)
+    );
     let nv_to_page = static_init!(
-        capsules::nonvolatile_to_pages::NonvolatileToPages<'static, sam4l::flashcalw::FLASHCALW>,
-        capsules::nonvolatile_to_pages::NonvolatileToPages::new(
-            &mut sam4l::flashcalw::FLASH_CONTROLLER,
-            &mut FLASH_PAGEBUFFER
-        )
+        capsules::nonvolatile_to_pages::NonvolatileToPages<
+            'static,
+            FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+        >,
+        capsules::nonvolatile_to_pages::NonvolatileToPages::new(nv_flash, &mut FLASH_PAGEBUFFER)
     );
-    hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, nv_to_page);
+    hil::flash::HasClient::set_client(nv_flash, nv_to_page);
 
     let nonvolatile_storage = static_init!(
         capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
@@ -593,13 +1285,17 @@ pub unsafe fn reset_handler() {
             nv_to_page,
             kernel::Grant::create(),
             0x60000, // Start address for userspace accessible region
-            0x20000, // Length of userspace accessible region
-            0,       // Start address of kernel accessible region
-            0,       // Length of kernel accessible region
+            0x1fe00, // Length of userspace accessible region
+            MLE_COUNTER_ADDRESS, // Start address of kernel accessible region
+            0x200,   // Length of kernel accessible region
+            &[],     // Apps use the storage sizes from their TBF headers,
+            0x1000,  // or get 4 KiB if they request none
             &mut capsules::nonvolatile_storage_driver::BUFFER
         )
     );
     hil::nonvolatile_storage::NonvolatileStorage::set_client(nv_to_page, nonvolatile_storage);
+    mle.set_frame_counter_storage(nonvolatile_storage, MLE_COUNTER_ADDRESS, &mut MLE_COUNTER_BUF);
+    hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, mle);
 
     let imix = Imix {
         console: console,
@@ -612,6 +1308,12 @@ pub unsafe fn reset_handler() {
         led: led,
         button: button,
         crc: crc,
+        aes: aes,
+        digest: digest,
+        udp_driver: udp_driver,
+        ping_driver: ping_driver,
+        tcp_driver: tcp_driver,
+        coap_driver: coap_driver,
         spi: spi_syscalls,
         ipc: kernel::ipc::IPC::new(),
         ninedof: ninedof,
@@ -619,6 +1321,7 @@ pub unsafe fn reset_handler() {
         usb_driver: usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
+        watchdog: watchdog,
     };
 
     let mut chip = sam4l::chip::Sam4l::new();
@@ -639,6 +1342,8 @@ pub unsafe fn reset_handler() {
     // initialization to work.
     rf233.reset();
     rf233.start();
+    nd_host.start();
+    mle.start();
 
     debug!("Initialization complete. Entering main loop");
     extern "C" {
@@ -652,5 +1357,7 @@ pub unsafe fn reset_handler() {
         FAULT_RESPONSE,
     );
 
+    hil::watchdog::Watchdog::start(imix.watchdog, 2000);
+
     kernel::main(&imix, &mut chip, &mut PROCESSES, &imix.ipc);
 }
@@ -1,7 +1,8 @@
 //! `app_layer_lowpan_frag.rs`: Test application layer sending of
 //! 6LoWPAN packets
 //!
-//! Currently this file only tests sending messages.
+//! Besides sending messages, the test prints every UDP packet that is
+//! received, via an `IP6RecvStruct` set as the 6LoWPAN receive client.
 //!
 //! To use this test suite, allocate space for a new LowpanTest structure, and
 //! set it as the client for the Sixlowpan struct and for the respective TxState
@@ -29,12 +30,16 @@
 use capsules;
 extern crate sam4l;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use capsules::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::ipv6_recv::{IP6RecvStruct, IP6Receiver};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_send::{IP6SendMux, IP6SendStruct, IP6Sender};
 use capsules::net::sixlowpan::sixlowpan_compression;
-use capsules::net::sixlowpan::sixlowpan_state::{Sixlowpan, SixlowpanState, TxState};
+use capsules::net::sixlowpan::sixlowpan_state::{RxState, Sixlowpan, SixlowpanState, TxState};
 use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_recv::UDPRecvClient;
 use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::cell::Cell;
@@ -51,6 +56,8 @@ pub const DST_ADDR: IPAddr = IPAddr([
 ]);
 pub const PAYLOAD_LEN: usize = 200;
 
+const GATEWAY_MAC_ADDR: MacAddress = MacAddress::Short(0xf00e);
+
 /* 6LoWPAN Constants */
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8;
 static DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16];
@@ -79,18 +86,26 @@ pub unsafe fn initialize_all(
     'static,
     capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
 > {
-    let sixlowpan = static_init!(
-        Sixlowpan<'static, sam4l::ast::Ast<'static>, sixlowpan_compression::Context>,
-        Sixlowpan::new(
+    let interface = static_init!(
+        IP6Interface<'static>,
+        IP6Interface::new(
+            radio_mac,
             sixlowpan_compression::Context {
                 prefix: DEFAULT_CTX_PREFIX,
                 prefix_len: DEFAULT_CTX_PREFIX_LEN,
                 id: 0,
                 compress: false,
-            },
-            &sam4l::ast::AST
+            }
         )
     );
+    interface.autoconfigure_link_local();
+    interface.add_addr(SRC_ADDR);
+    interface.set_gateway(GATEWAY_MAC_ADDR);
+
+    let sixlowpan = static_init!(
+        Sixlowpan<'static, sam4l::ast::Ast<'static>, &'static IP6Interface<'static>>,
+        Sixlowpan::new(interface, &sam4l::ast::AST)
+    );
 
     let sixlowpan_state = sixlowpan as &SixlowpanState;
     let sixlowpan_tx = TxState::new(sixlowpan_state);
@@ -121,11 +136,16 @@ pub unsafe fn initialize_all(
 
     let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
+    let ip6_send_mux = static_init!(
+        IP6SendMux<'static>,
+        IP6SendMux::new(radio_mac, &mut RF233_BUF)
+    );
+    radio_mac.set_transmit_client(ip6_send_mux);
     let ip6_sender = static_init!(
         IP6SendStruct<'static>,
-        IP6SendStruct::new(ip6_dg, &mut RF233_BUF, sixlowpan_tx, radio_mac)
+        IP6SendStruct::new(ip6_dg, sixlowpan_tx, ip6_send_mux, interface)
     );
-    radio_mac.set_transmit_client(ip6_sender);
+    ip6_send_mux.add_sender(ip6_sender);
 
     let udp_send_struct = static_init!(
         UDPSendStruct<'static, IP6SendStruct<'static>>,
@@ -145,6 +165,13 @@ pub unsafe fn initialize_all(
     udp_send_struct.set_client(app_lowpan_frag_test);
     app_lowpan_frag_test.alarm.set_client(app_lowpan_frag_test);
 
+    let default_rx_state = static_init!(RxState<'static>, RxState::new(&mut RX_STATE_BUF));
+    let ip6_receiver = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+    sixlowpan_state.add_rx_state(default_rx_state);
+    sixlowpan_state.set_rx_client(ip6_receiver);
+    ip6_receiver.set_udp_client(app_lowpan_frag_test);
+    radio_mac.set_receive_client(sixlowpan);
+
     app_lowpan_frag_test
 }
 
@@ -160,6 +187,17 @@ impl<'a, A: time::Alarm> capsules::net::udp::udp_send::UDPSendClient for LowpanT
     }
 }
 
+impl<'a, A: time::Alarm> UDPRecvClient for LowpanTest<'a, A> {
+    fn receive(&self, _ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]) {
+        debug!(
+            "Received UDP packet from port {} to port {}, {} bytes",
+            udp_header.get_src_port(),
+            udp_header.get_dst_port(),
+            payload.len()
+        );
+    }
+}
+
 impl<'a, A: time::Alarm + 'a> LowpanTest<'a, A> {
     pub fn new(
         //sixlowpan_tx: TxState<'a>,
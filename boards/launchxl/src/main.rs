@@ -37,7 +37,7 @@ pub struct Platform {
         'static,
         capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26xx::rtc::Rtc>,
     >,
-    rng: &'static capsules::rng::SimpleRng<'static, cc26xx::trng::Trng>,
+    rng: &'static capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
 }
 
 impl kernel::Platform for Platform {
@@ -194,11 +194,35 @@ pub unsafe fn reset_handler() {
     );
     virtual_alarm1.set_client(alarm);
 
+    // Setup RNG, served by a CSPRNG so that apps don't wait on the TRNG
+    let csprng_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26xx::rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let csprng = static_init!(
+        capsules::csprng::Csprng<
+            'static,
+            capsules::virtual_alarm::VirtualMuxAlarm<'static, cc26xx::rtc::Rtc>,
+        >,
+        capsules::csprng::Csprng::new(&cc26xx::trng::TRNG, csprng_alarm)
+    );
+    csprng_alarm.set_client(csprng);
+    cc26xx::trng::TRNG.set_client(csprng);
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(csprng)
+    );
+    csprng.set_client(mux_rng);
+    let app_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(app_rng);
     let rng = static_init!(
-        capsules::rng::SimpleRng<'static, cc26xx::trng::Trng>,
-        capsules::rng::SimpleRng::new(&cc26xx::trng::TRNG, kernel::Grant::create())
+        capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
+        capsules::rng::SimpleRng::new(app_rng, kernel::Grant::create())
     );
-    cc26xx::trng::TRNG.set_client(rng);
+    app_rng.set_client(rng);
 
     let launchxl = Platform {
         console,
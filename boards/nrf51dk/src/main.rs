@@ -105,7 +105,7 @@ pub struct Platform {
     led: &'static capsules::led::LED<'static, nrf5x::gpio::GPIOPin>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     alarm: &'static AlarmDriver<'static, VirtualMuxAlarm<'static, Rtc>>,
-    rng: &'static capsules::rng::SimpleRng<'static, nrf5x::trng::Trng<'static>>,
+    rng: &'static capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
 }
 
 impl kernel::Platform for Platform {
@@ -290,12 +290,44 @@ pub unsafe fn reset_handler() {
     );
     kernel::hil::sensors::TemperatureDriver::set_client(&nrf5x::temperature::TEMP, temp);
 
+    // Setup RNG, served by a CSPRNG so that apps and the BLE driver don't wait
+    // on the TRNG
+    let csprng_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm),
+        192 / 8
+    );
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::csprng::Csprng::new(&nrf5x::trng::TRNG, csprng_alarm),
+        1408 / 8
+    );
+    csprng_alarm.set_client(csprng);
+    nrf5x::trng::TRNG.set_client(csprng);
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(csprng),
+        96 / 8
+    );
+    csprng.set_client(mux_rng);
+    let app_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng),
+        160 / 8
+    );
+    mux_rng.add_user(app_rng);
     let rng = static_init!(
-        capsules::rng::SimpleRng<'static, nrf5x::trng::Trng>,
-        capsules::rng::SimpleRng::new(&mut nrf5x::trng::TRNG, kernel::Grant::create()),
+        capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
+        capsules::rng::SimpleRng::new(app_rng, kernel::Grant::create()),
         96 / 8
     );
-    nrf5x::trng::TRNG.set_client(rng);
+    app_rng.set_client(rng);
+    let ble_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng),
+        160 / 8
+    );
+    mux_rng.add_user(ble_rng);
 
     let ble_radio = static_init!(
         capsules::ble_advertising_driver::BLE<
@@ -307,9 +339,10 @@ pub unsafe fn reset_handler() {
             &mut nrf51::radio::RADIO,
             kernel::Grant::create(),
             &mut capsules::ble_advertising_driver::BUF,
-            ble_radio_virtual_alarm
+            ble_radio_virtual_alarm,
+            ble_rng
         ),
-        256 / 8
+        320 / 8
     );
     kernel::hil::ble_advertising::BleAdvertisementDriver::set_receive_client(
         &nrf51::radio::RADIO,
@@ -320,6 +353,7 @@ pub unsafe fn reset_handler() {
         ble_radio,
     );
     ble_radio_virtual_alarm.set_client(ble_radio);
+    ble_rng.set_client(ble_radio);
 
     // Start all of the clocks. Low power operation will require a better
     // approach than this.
@@ -113,6 +113,7 @@ static mut PROCESSES: [Option<&'static mut kernel::Process<'static>>; NUM_PROCS]
 
 /// Supported drivers by the platform
 pub struct Platform {
+    aes: &'static capsules::aes::AesDriver<'static, nrf5x::aes::AesECB<'static>>,
     ble_radio: &'static capsules::ble_advertising_driver::BLE<
         'static,
         nrf52::radio::Radio,
@@ -120,9 +121,14 @@ pub struct Platform {
     >,
     button: &'static capsules::button::Button<'static, nrf5x::gpio::GPIOPin>,
     console: &'static capsules::console::Console<'static, nrf52::uart::Uarte>,
+    crc: &'static capsules::crc::Crc<
+        'static,
+        capsules::sw_crc::SoftwareCrc<'static, VirtualMuxAlarm<'static, Rtc>>,
+    >,
     gpio: &'static capsules::gpio::GPIO<'static, nrf5x::gpio::GPIOPin>,
     led: &'static capsules::led::LED<'static, nrf5x::gpio::GPIOPin>,
-    rng: &'static capsules::rng::SimpleRng<'static, nrf5x::trng::Trng<'static>>,
+    pwm: &'static capsules::pwm::Pwm<'static>,
+    rng: &'static capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     ipc: kernel::ipc::IPC,
     alarm: &'static capsules::alarm::AlarmDriver<
@@ -138,9 +144,12 @@ impl kernel::Platform for Platform {
     {
         match driver_num {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::aes::DRIVER_NUM => f(Some(self.aes)),
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
+            capsules::crc::DRIVER_NUM => f(Some(self.crc)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
+            capsules::pwm::DRIVER_NUM => f(Some(self.pwm)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
@@ -231,6 +240,38 @@ pub unsafe fn reset_handler() {
         capsules::led::LED::new(led_pins)
     );
 
+    // PWM on the LED pins, so apps can dim them. While a pin runs a PWM
+    // signal, the PWM overrides the LED driver.
+    let pwm_pins = static_init!(
+        [kernel::hil::pwm::PwmPinUser<'static, nrf52::pwm::Pwm>; 4],
+        [
+            kernel::hil::pwm::PwmPinUser::new(
+                &nrf52::pwm::PWM0,
+                nrf5x::pinmux::Pinmux::new(LED1_PIN as u32)
+            ),
+            kernel::hil::pwm::PwmPinUser::new(
+                &nrf52::pwm::PWM0,
+                nrf5x::pinmux::Pinmux::new(LED2_PIN as u32)
+            ),
+            kernel::hil::pwm::PwmPinUser::new(
+                &nrf52::pwm::PWM0,
+                nrf5x::pinmux::Pinmux::new(LED3_PIN as u32)
+            ),
+            kernel::hil::pwm::PwmPinUser::new(
+                &nrf52::pwm::PWM0,
+                nrf5x::pinmux::Pinmux::new(LED4_PIN as u32)
+            ),
+        ]
+    );
+    let pwm_pin_refs = static_init!(
+        [&'static kernel::hil::pwm::PwmPin; 4],
+        [&pwm_pins[0], &pwm_pins[1], &pwm_pins[2], &pwm_pins[3]]
+    );
+    let pwm = static_init!(
+        capsules::pwm::Pwm<'static>,
+        capsules::pwm::Pwm::new(pwm_pin_refs, kernel::Grant::create())
+    );
+
     let button_pins = static_init!(
         [(&'static nrf5x::gpio::GPIOPin, capsules::button::GpioMode); 4],
         [
@@ -310,6 +351,39 @@ pub unsafe fn reset_handler() {
     let kc = static_init!(capsules::console::App, capsules::console::App::default());
     kernel::debug::assign_console_driver(Some(console), kc);
 
+    // Setup RNG, served by a CSPRNG so that apps and the BLE driver don't wait
+    // on the TRNG
+    let csprng_alarm = static_init!(
+        VirtualMuxAlarm<'static, Rtc>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let csprng = static_init!(
+        capsules::csprng::Csprng<'static, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::csprng::Csprng::new(&nrf5x::trng::TRNG, csprng_alarm)
+    );
+    csprng_alarm.set_client(csprng);
+    nrf5x::trng::TRNG.set_client(csprng);
+    let mux_rng = static_init!(
+        capsules::virtual_rng::MuxRng<'static>,
+        capsules::virtual_rng::MuxRng::new(csprng)
+    );
+    csprng.set_client(mux_rng);
+    let app_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(app_rng);
+    let rng = static_init!(
+        capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
+        capsules::rng::SimpleRng::new(app_rng, kernel::Grant::create())
+    );
+    app_rng.set_client(rng);
+    let ble_rng = static_init!(
+        capsules::virtual_rng::VirtualRng<'static>,
+        capsules::virtual_rng::VirtualRng::new(mux_rng)
+    );
+    mux_rng.add_user(ble_rng);
+
     let ble_radio = static_init!(
         capsules::ble_advertising_driver::BLE<
             'static,
@@ -320,7 +394,8 @@ pub unsafe fn reset_handler() {
             &mut nrf52::radio::RADIO,
             kernel::Grant::create(),
             &mut capsules::ble_advertising_driver::BUF,
-            ble_radio_virtual_alarm
+            ble_radio_virtual_alarm,
+            ble_rng
         )
     );
     kernel::hil::ble_advertising::BleAdvertisementDriver::set_receive_client(
@@ -332,6 +407,7 @@ pub unsafe fn reset_handler() {
         ble_radio,
     );
     ble_radio_virtual_alarm.set_client(ble_radio);
+    ble_rng.set_client(ble_radio);
 
     let temp = static_init!(
         capsules::temperature::TemperatureSensor<'static>,
@@ -342,11 +418,51 @@ pub unsafe fn reset_handler() {
     );
     kernel::hil::sensors::TemperatureDriver::set_client(&nrf5x::temperature::TEMP, temp);
 
-    let rng = static_init!(
-        capsules::rng::SimpleRng<'static, nrf5x::trng::Trng>,
-        capsules::rng::SimpleRng::new(&mut nrf5x::trng::TRNG, kernel::Grant::create())
+    // No CRC unit on the nRF52, so compute CRCs in software
+    let crc_virtual_alarm = static_init!(
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+        capsules::virtual_alarm::VirtualMuxAlarm::new(mux_alarm)
+    );
+    let sw_crc = static_init!(
+        capsules::sw_crc::SoftwareCrc<'static, VirtualMuxAlarm<'static, Rtc>>,
+        capsules::sw_crc::SoftwareCrc::new(crc_virtual_alarm)
+    );
+    crc_virtual_alarm.set_client(sw_crc);
+    let crc = static_init!(
+        capsules::crc::Crc<
+            'static,
+            capsules::sw_crc::SoftwareCrc<'static, VirtualMuxAlarm<'static, Rtc>>,
+        >,
+        capsules::crc::Crc::new(sw_crc, kernel::Grant::create())
+    );
+    sw_crc.set_client(crc);
+
+    // CCM and GCM need three and one blocks more than the driver's buffer
+    static mut CCM_BUF: [u8; 176] = [0; 176];
+    static mut GCM_BUF: [u8; 144] = [0; 144];
+    let aes_ccm = static_init!(
+        capsules::aes_ccm::AES128CCM<'static, nrf5x::aes::AesECB<'static>>,
+        capsules::aes_ccm::AES128CCM::new(&nrf5x::aes::AESECB, &mut CCM_BUF)
+    );
+    let aes_gcm = static_init!(
+        capsules::aes_gcm::AES128GCM<'static, nrf5x::aes::AesECB<'static>>,
+        capsules::aes_gcm::AES128GCM::new(&nrf5x::aes::AESECB, &mut GCM_BUF)
+    );
+    let aes = static_init!(
+        capsules::aes::AesDriver<'static, nrf5x::aes::AesECB<'static>>,
+        capsules::aes::AesDriver::new(
+            &nrf5x::aes::AESECB,
+            aes_ccm,
+            aes_gcm,
+            &mut capsules::aes::BUF,
+            &mut capsules::aes::AEAD_BUF,
+            kernel::Grant::create()
+        )
     );
-    nrf5x::trng::TRNG.set_client(rng);
+    kernel::hil::symmetric_encryption::AES128::set_client(&nrf5x::aes::AESECB, aes);
+    kernel::hil::symmetric_encryption::AES128::enable(&nrf5x::aes::AESECB);
+    kernel::hil::symmetric_encryption::AES128CCM::set_client(aes_ccm, aes);
+    kernel::hil::symmetric_encryption::AES128GCM::set_client(aes_gcm, aes);
 
     // Start all of the clocks. Low power operation will require a better
     // approach than this.
@@ -361,10 +477,13 @@ pub unsafe fn reset_handler() {
     while !nrf52::clock::CLOCK.high_started() {}
 
     let platform = Platform {
+        aes: aes,
         button: button,
         ble_radio: ble_radio,
         console: console,
+        crc: crc,
         led: led,
+        pwm: pwm,
         gpio: gpio,
         rng: rng,
         temp: temp,
@@ -0,0 +1,472 @@
+//! Provides userspace applications with AES-128 encryption.
+//!
+//! Each app loads its own key, which is kept in its grant, and can then
+//! encrypt or decrypt buffers with ECB, CTR, CBC, CCM or GCM. Requests from
+//! all apps are queued and served one at a time by the single AES engine.
+//!
+//! ECB, CTR and CBC are performed directly by the hardware. CCM and GCM are
+//! performed by `capsules::aes_ccm` and `capsules::aes_gcm`, which this driver
+//! passes the engine's completions on to while they run.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ccm = static_init!(
+//!     capsules::aes_ccm::AES128CCM<'static, nrf5x::aes::AesECB<'static>>,
+//!     capsules::aes_ccm::AES128CCM::new(&nrf5x::aes::AESECB, &mut CCM_BUF));
+//! let gcm = static_init!(
+//!     capsules::aes_gcm::AES128GCM<'static, nrf5x::aes::AesECB<'static>>,
+//!     capsules::aes_gcm::AES128GCM::new(&nrf5x::aes::AESECB, &mut GCM_BUF));
+//! let aes = static_init!(
+//!     capsules::aes::AesDriver<'static, nrf5x::aes::AesECB<'static>>,
+//!     capsules::aes::AesDriver::new(
+//!         &nrf5x::aes::AESECB,
+//!         ccm,
+//!         gcm,
+//!         &mut capsules::aes::BUF,
+//!         &mut capsules::aes::AEAD_BUF,
+//!         kernel::Grant::create()));
+//! nrf5x::aes::AESECB.set_client(aes);
+//! nrf5x::aes::AESECB.enable();
+//! ccm.set_client(aes);
+//! gcm.set_client(aes);
+//! ```
+//!
+//! `CCM_BUF` and `GCM_BUF` need room for three and one extra blocks
+//! respectively on top of `capsules::aes::AEAD_BUF`.
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: The key, 16 bytes.
+//! - `1`: The data, which is encrypted or decrypted in place. For CCM and GCM
+//!   the message is followed by room for its tag.
+//! - `4`: The IV (CBC), initial counter (CTR) or nonce (13 bytes for CCM, 12
+//!   for GCM).
+//! - `5`: Additional authenticated data for CCM and GCM. Optional.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called with the result of a request as a `ReturnCode`. A CCM or GCM
+//!   decryption whose tag does not match fails with `FAIL`, and leaves the
+//!   data untouched.
+//!
+//! ### Commands
+//!
+//! - `0`: Load the key from buffer 0.
+//! - `1`: Driver check.
+//! - `2`: Encrypt with mode `arg1`: `0` CTR, `1` ECB, `2` CBC, `3` CCM, `4`
+//!   GCM. For CCM and GCM, `arg2` is the tag length.
+//! - `3`: Decrypt, with the same arguments.
+
+use aes_ccm;
+use aes_gcm;
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{AES128, AES128CBC, AES128CCM, AES128Ctr, AES128ECB,
+                                        AES128GCM, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+                                        CCM_NONCE_LENGTH, GCM_NONCE_LENGTH, GCM_TAG_LENGTH};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x40000;
+
+pub static mut BUF: [u8; 128] = [0; 128];
+pub static mut AEAD_BUF: [u8; 128] = [0; 128];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Ctr,
+    Ecb,
+    Cbc,
+    Ccm,
+    Gcm,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Request {
+    mode: Mode,
+    encrypting: bool,
+    tag_len: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key_buffer: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    iv: Option<AppSlice<Shared, u8>>,
+    aad: Option<AppSlice<Shared, u8>>,
+    key: Option<[u8; AES128_KEY_SIZE]>,
+    /// The request the app is waiting on, if it made one
+    pending: Option<Request>,
+}
+
+pub struct AesDriver<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> {
+    aes: &'a A,
+    ccm: &'a aes_ccm::AES128CCM<'a, A>,
+    gcm: &'a aes_gcm::AES128GCM<'a, A>,
+    apps: Grant<App>,
+    serving_app: Cell<Option<AppId>>,
+    request: Cell<Option<Request>>,
+    /// Where the serving app's data starts in the kernel buffer, and its
+    /// length
+    data_pos: Cell<(usize, usize)>,
+    /// Used for ECB, CTR and CBC, which go straight to the engine
+    buffer: TakeCell<'a, [u8]>,
+    /// Used for CCM and GCM
+    aead_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> AesDriver<'a, A> {
+    pub fn new(
+        aes: &'a A,
+        ccm: &'a aes_ccm::AES128CCM<'a, A>,
+        gcm: &'a aes_gcm::AES128GCM<'a, A>,
+        buffer: &'a mut [u8],
+        aead_buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> AesDriver<'a, A> {
+        AesDriver {
+            aes: aes,
+            ccm: ccm,
+            gcm: gcm,
+            apps: grant,
+            serving_app: Cell::new(None),
+            request: Cell::new(None),
+            data_pos: Cell::new((0, 0)),
+            buffer: TakeCell::new(buffer),
+            aead_buffer: TakeCell::new(aead_buffer),
+        }
+    }
+
+    /// Start the request of the next waiting app, if the engine is free.
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            return;
+        }
+
+        for app in self.apps.iter() {
+            let started = app.enter(|app, _| {
+                let request = match app.pending {
+                    Some(request) => request,
+                    None => return false,
+                };
+                self.request.set(Some(request));
+                let result = match request.mode {
+                    Mode::Ctr | Mode::Ecb | Mode::Cbc => self.start_block_cipher(app, request),
+                    Mode::Ccm | Mode::Gcm => self.start_aead(app, request),
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.request.set(None);
+                    app.pending = None;
+                    app.callback
+                        .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+                    return false;
+                }
+                self.serving_app.set(Some(app.appid()));
+                true
+            });
+            if started {
+                return;
+            }
+        }
+    }
+
+    /// Copy the app's data into the kernel buffer and start an ECB, CTR or
+    /// CBC operation on the engine.
+    fn start_block_cipher(&self, app: &mut App, request: Request) -> ReturnCode {
+        let (key, data) = match (app.key, app.data.as_ref()) {
+            (Some(key), Some(data)) => (key, data),
+            (None, _) => return ReturnCode::ERESERVE,
+            (_, None) => return ReturnCode::EINVAL,
+        };
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let len = data.len();
+            // CTR works on any length, but the engine may want whole blocks
+            let padded_len = (len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+            let mut result = if len == 0 || padded_len > buffer.len() {
+                ReturnCode::ESIZE
+            } else if request.mode != Mode::Ctr && len != padded_len {
+                ReturnCode::EINVAL
+            } else {
+                self.aes.set_key(&key)
+            };
+            if result == ReturnCode::SUCCESS && request.mode != Mode::Ecb {
+                result = app.iv
+                    .as_ref()
+                    .map_or(ReturnCode::EINVAL, |iv| self.aes.set_iv(iv.as_ref()));
+            }
+            if result != ReturnCode::SUCCESS {
+                self.buffer.replace(buffer);
+                return result;
+            }
+
+            buffer[..len].copy_from_slice(data.as_ref());
+            buffer[len..padded_len].iter_mut().for_each(|b| *b = 0);
+            self.data_pos.set((0, len));
+
+            match request.mode {
+                Mode::Ctr => self.aes.set_mode_aes128ctr(request.encrypting),
+                Mode::Ecb => self.aes.set_mode_aes128ecb(request.encrypting),
+                _ => self.aes.set_mode_aes128cbc(request.encrypting),
+            }
+            self.aes.start_message();
+            match self.aes.crypt(None, buffer, 0, padded_len) {
+                None => ReturnCode::SUCCESS,
+                Some((result, _, buffer)) => {
+                    self.buffer.replace(buffer);
+                    result
+                }
+            }
+        })
+    }
+
+    /// Copy the app's additional data and data into the AEAD buffer and start
+    /// a CCM or GCM operation.
+    fn start_aead(&self, app: &mut App, request: Request) -> ReturnCode {
+        let (key, data) = match (app.key, app.data.as_ref()) {
+            (Some(key), Some(data)) => (key, data),
+            (None, _) => return ReturnCode::ERESERVE,
+            (_, None) => return ReturnCode::EINVAL,
+        };
+        let nonce = app.iv.as_ref().map_or(&[][..], |iv| iv.as_ref());
+        let tag_len = request.tag_len;
+        let params_ok = match request.mode {
+            Mode::Ccm => {
+                nonce.len() == CCM_NONCE_LENGTH && tag_len >= 4 && tag_len <= 16
+                    && tag_len % 2 == 0
+            }
+            _ => nonce.len() == GCM_NONCE_LENGTH && tag_len >= 4 && tag_len <= GCM_TAG_LENGTH,
+        };
+        if !params_ok || data.len() < tag_len {
+            return ReturnCode::EINVAL;
+        }
+
+        self.aead_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            let aad_len = app.aad.as_ref().map_or(0, |aad| aad.len());
+            if aad_len + data.len() > buffer.len() {
+                self.aead_buffer.replace(buffer);
+                return ReturnCode::ESIZE;
+            }
+            app.aad.as_ref().map(|aad| {
+                buffer[..aad_len].copy_from_slice(aad.as_ref());
+            });
+            buffer[aad_len..aad_len + data.len()].copy_from_slice(data.as_ref());
+            self.data_pos.set((aad_len, data.len()));
+            let m_len = data.len() - tag_len;
+
+            let (result, buffer) = if request.mode == Mode::Ccm {
+                self.ccm.set_key(&key);
+                self.ccm.set_nonce(nonce);
+                self.ccm
+                    .crypt(buffer, 0, aad_len, m_len, tag_len, true, request.encrypting)
+            } else {
+                self.gcm.set_key(&key);
+                self.gcm.set_nonce(nonce);
+                self.gcm
+                    .crypt(buffer, 0, aad_len, m_len, tag_len, request.encrypting)
+            };
+            buffer.map(|buffer| {
+                buffer.iter_mut().for_each(|b| *b = 0);
+                self.aead_buffer.replace(buffer);
+            });
+            result
+        })
+    }
+
+    /// Copy the result in `buffer` back to the serving app and tell it that
+    /// its request is done.
+    fn return_to_app(&self, buffer: &mut [u8], result: ReturnCode) {
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                if result == ReturnCode::SUCCESS {
+                    let (start, len) = self.data_pos.get();
+                    app.data.as_mut().map(|data| {
+                        let len = cmp::min(len, data.len());
+                        data.as_mut()[..len].copy_from_slice(&buffer[start..start + len]);
+                    });
+                }
+                app.pending = None;
+                app.callback
+                    .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+            });
+        });
+
+        // Don't leave the app's data behind
+        buffer.iter_mut().for_each(|b| *b = 0);
+    }
+
+    /// Move on to the next app.
+    fn finish(&self) {
+        self.request.set(None);
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+
+    fn aead_done(&self, buffer: &'static mut [u8], result: ReturnCode, tag_is_valid: bool) {
+        let result = if result == ReturnCode::SUCCESS && !tag_is_valid {
+            ReturnCode::FAIL
+        } else {
+            result
+        };
+        self.return_to_app(buffer, result);
+        self.aead_buffer.replace(buffer);
+        self.finish();
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> symmetric_encryption::Client<'a>
+    for AesDriver<'a, A>
+{
+    fn crypt_done(&self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        match self.request.get().map(|request| request.mode) {
+            Some(Mode::Ccm) => symmetric_encryption::Client::crypt_done(self.ccm, source, dest),
+            Some(Mode::Gcm) => symmetric_encryption::Client::crypt_done(self.gcm, source, dest),
+            Some(_) => {
+                self.return_to_app(dest, ReturnCode::SUCCESS);
+                self.buffer.replace(dest);
+                self.finish();
+            }
+            None => {
+                self.buffer.replace(dest);
+            }
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> symmetric_encryption::CCMClient
+    for AesDriver<'a, A>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.aead_done(buf, res, tag_is_valid);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> symmetric_encryption::GCMClient
+    for AesDriver<'a, A>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.aead_done(buf, res, tag_is_valid);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> Driver for AesDriver<'a, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        // Buffers can't change under a request in progress
+        if self.serving_app.get() == Some(appid) {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.key_buffer = slice,
+                    1 => app.data = slice,
+                    4 => app.iv = slice,
+                    5 => app.aad = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Load keys and start encryption or decryption.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Load the key from buffer 0.
+    /// - `1`: Driver check.
+    /// - `2`: Encrypt with mode `arg1`, and tag length `arg2` for CCM and GCM.
+    /// - `3`: Decrypt with mode `arg1`, and tag length `arg2` for CCM and GCM.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    if app.pending.is_some() {
+                        return ReturnCode::EBUSY;
+                    }
+                    let key = app.key_buffer.as_ref().and_then(|buffer| {
+                        if buffer.len() == AES128_KEY_SIZE {
+                            let mut key = [0; AES128_KEY_SIZE];
+                            key.copy_from_slice(buffer.as_ref());
+                            Some(key)
+                        } else {
+                            None
+                        }
+                    });
+                    match key {
+                        Some(key) => {
+                            app.key = Some(key);
+                            ReturnCode::SUCCESS
+                        }
+                        None => ReturnCode::EINVAL,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            1 => ReturnCode::SUCCESS,
+
+            2 | 3 => {
+                let mode = match arg1 {
+                    0 => Mode::Ctr,
+                    1 => Mode::Ecb,
+                    2 => Mode::Cbc,
+                    3 => Mode::Ccm,
+                    4 => Mode::Gcm,
+                    _ => return ReturnCode::EINVAL,
+                };
+                let request = Request {
+                    mode: mode,
+                    encrypting: command_num == 2,
+                    tag_len: arg2,
+                };
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        if app.pending.is_some() {
+                            return ReturnCode::EBUSY;
+                        }
+                        if app.callback.is_none() {
+                            return ReturnCode::EINVAL;
+                        }
+                        app.pending = Some(request);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.serve_waiting_apps();
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
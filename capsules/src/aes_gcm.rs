@@ -0,0 +1,538 @@
+//! Implements AES-GCM encryption/decryption/authentication using an underlying
+//! AES-CTR implementation and a software GHASH.
+//!
+//! NIST SP 800-38D. With a 96-bit nonce, the pre-counter block J0 is the nonce
+//! followed by the 32-bit counter 1. The message is copied into crypt_buf after
+//! a zero block, and two passes of AES-CTR are performed:
+//!
+//! ```text
+//! crypt_buf: [ 0 blk | -------- PData/CData (zero padded) -------- ]
+//! ctr at 0:   \_____/                                  -> H = E(K, 0)
+//! ctr at J0:  \_____________________________________________________/
+//!               E(K, J0) | CData/PData
+//! ```
+//!
+//! The first pass produces the hash key H. The second pass encrypts the zero
+//! block with J0, producing the tag mask E(K, J0), and the message with the
+//! following counters, exactly as GCM's GCTR function does.
+//!
+//! The tag is GHASH over the AuthData and the CData, XOR'd with the tag mask.
+//! GHASH is computed in software over the CData: after the second pass when
+//! encrypting, and before it when decrypting.
+
+use core::cell::Cell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{AES128, AES128Ctr, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+                                        GCM_NONCE_LENGTH, GCM_TAG_LENGTH};
+use kernel::ReturnCode;
+
+/// The shortest tag NIST SP 800-38D allows
+const GCM_MIN_TAG_LENGTH: usize = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GCMState {
+    Idle,
+    HashKey,
+    Encrypt,
+}
+
+pub struct AES128GCM<'a, A: AES128<'a> + AES128Ctr + 'a> {
+    aes: &'a A,
+    crypt_buf: TakeCell<'a, [u8]>,
+    crypt_client: Cell<Option<&'a symmetric_encryption::GCMClient>>,
+
+    state: Cell<GCMState>,
+    encrypting: Cell<bool>,
+
+    buf: TakeCell<'static, [u8]>,
+    pos: Cell<(usize, usize, usize, usize)>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; GCM_NONCE_LENGTH]>,
+    hash_key: Cell<[u8; AES128_BLOCK_SIZE]>,
+    ghash: Cell<[u8; AES128_BLOCK_SIZE]>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + 'a> AES128GCM<'a, A> {
+    pub fn new(aes: &'a A, crypt_buf: &'static mut [u8]) -> AES128GCM<'a, A> {
+        AES128GCM {
+            aes: aes,
+            crypt_buf: TakeCell::new(crypt_buf),
+            crypt_client: Cell::new(None),
+            state: Cell::new(GCMState::Idle),
+            encrypting: Cell::new(false),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0, 0)),
+            key: Cell::new(Default::default()),
+            nonce: Cell::new(Default::default()),
+            hash_key: Cell::new(Default::default()),
+            ghash: Cell::new(Default::default()),
+        }
+    }
+
+    /// The length of the message rounded up to whole blocks
+    fn padded_len(m_len: usize) -> usize {
+        (m_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE
+    }
+
+    /// Run AES-CTR starting at counter `iv` over the first `len` bytes of
+    /// crypt_buf.
+    fn start_ctr(&self, iv: &[u8; AES128_BLOCK_SIZE], len: usize) -> ReturnCode {
+        let res = self.aes.set_key(&self.key.get());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let res = self.aes.set_iv(iv);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+
+        let crypt_buf = match self.crypt_buf.take() {
+            None => return ReturnCode::ENOMEM,
+            Some(buf) => buf,
+        };
+
+        self.aes.set_mode_aes128ctr(self.encrypting.get());
+        self.aes.start_message();
+        match self.aes.crypt(None, crypt_buf, 0, len) {
+            None => ReturnCode::SUCCESS,
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res
+            }
+        }
+    }
+
+    /// Compute the hash key by encrypting a zero block with counter 0.
+    fn start_gcm_hash_key(&self) -> ReturnCode {
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+        });
+        let res = self.start_ctr(&[0; AES128_BLOCK_SIZE], AES128_BLOCK_SIZE);
+        if res == ReturnCode::SUCCESS {
+            self.state.set(GCMState::HashKey);
+        }
+        res
+    }
+
+    /// Encrypt the tag mask block and the message, with counters from J0.
+    fn start_gcm_encrypt(&self) -> ReturnCode {
+        let (_, _, m_len, _) = self.pos.get();
+        let res = self.start_ctr(
+            &pre_counter_block(&self.nonce.get()),
+            AES128_BLOCK_SIZE + Self::padded_len(m_len),
+        );
+        if res == ReturnCode::SUCCESS {
+            self.state.set(GCMState::Encrypt);
+        }
+        res
+    }
+
+    /// GHASH over the AuthData in the client's buffer and the CData in
+    /// crypt_buf.
+    fn compute_ghash(&self) {
+        let (a_off, m_off, m_len, _) = self.pos.get();
+        let h = self.hash_key.get();
+        self.buf.map(|buf| {
+            self.crypt_buf.map(|cbuf| {
+                self.ghash.set(ghash(
+                    &h,
+                    &buf[a_off..m_off],
+                    &cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len],
+                ));
+            });
+        });
+    }
+
+    fn end_gcm(&self) {
+        if self.crypt_buf.is_none() {
+            self.fail(ReturnCode::FAIL);
+            return;
+        }
+        if self.encrypting.get() {
+            self.compute_ghash();
+        }
+
+        let tag_valid = self.buf.map_or(false, |buf| {
+            self.crypt_buf.map_or(false, |cbuf| {
+                let (_, m_off, m_len, tag_len) = self.pos.get();
+                let m_end = m_off + m_len;
+                let ghash = self.ghash.get();
+
+                // The tag is GHASH XOR'd with E(K, J0)
+                let mut tag = [0u8; AES128_BLOCK_SIZE];
+                for i in 0..AES128_BLOCK_SIZE {
+                    tag[i] = ghash[i] ^ cbuf[i];
+                }
+
+                if self.encrypting.get() {
+                    buf[m_off..m_end]
+                        .copy_from_slice(&cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]);
+                    buf[m_end..m_end + tag_len].copy_from_slice(&tag[..tag_len]);
+                    true
+                } else {
+                    // Only release the plaintext if the tag is valid
+                    let valid = buf[m_end..m_end + tag_len]
+                        .iter()
+                        .zip(tag[..tag_len].iter())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+                    if valid {
+                        buf[m_off..m_end].copy_from_slice(
+                            &cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len],
+                        );
+                    }
+                    valid
+                }
+            })
+        });
+
+        // Don't leave the message behind in crypt_buf
+        self.crypt_buf
+            .map(|cbuf| cbuf.iter_mut().for_each(|b| *b = 0));
+
+        self.state.set(GCMState::Idle);
+        if let Some(client) = self.crypt_client.get() {
+            self.buf.take().map(|buf| {
+                client.crypt_done(buf, ReturnCode::SUCCESS, tag_valid);
+            });
+        }
+    }
+
+    fn fail(&self, res: ReturnCode) {
+        self.state.set(GCMState::Idle);
+        self.buf.take().map(|buf| {
+            if let Some(client) = self.crypt_client.get() {
+                client.crypt_done(buf, res, false);
+            }
+        });
+    }
+}
+
+/// The pre-counter block J0 for a 96-bit nonce: the nonce followed by the
+/// 32-bit counter 1. The message blocks are encrypted with the counters that
+/// follow, which is inc32 since a message never reaches 2^32 blocks.
+fn pre_counter_block(nonce: &[u8; GCM_NONCE_LENGTH]) -> [u8; AES128_BLOCK_SIZE] {
+    let mut j0 = [0u8; AES128_BLOCK_SIZE];
+    j0[..GCM_NONCE_LENGTH].copy_from_slice(nonce);
+    j0[AES128_BLOCK_SIZE - 1] = 1;
+    j0
+}
+
+/// Multiply `x` by `y` in GF(2^128), as defined for GHASH (NIST SP 800-38D,
+/// Algorithm 1).
+fn gf128_mul(x: &[u8; AES128_BLOCK_SIZE], y: &[u8; AES128_BLOCK_SIZE]) -> [u8; AES128_BLOCK_SIZE] {
+    let mut z = [0u8; AES128_BLOCK_SIZE];
+    let mut v = *y;
+    for i in 0..128 {
+        // Add V to Z if bit i of X is set, without branching on the bit
+        let mask = 0u8.wrapping_sub((x[i / 8] >> (7 - i % 8)) & 1);
+        for j in 0..AES128_BLOCK_SIZE {
+            z[j] ^= v[j] & mask;
+        }
+
+        // V = V * x, reducing by R = 11100001 || 0^120
+        let lsb = v[AES128_BLOCK_SIZE - 1] & 1;
+        for j in (1..AES128_BLOCK_SIZE).rev() {
+            v[j] = (v[j] >> 1) | (v[j - 1] << 7);
+        }
+        v[0] >>= 1;
+        v[0] ^= 0xe1 & 0u8.wrapping_sub(lsb);
+    }
+    z
+}
+
+/// Absorb `data`, zero padded to whole blocks, into the GHASH state `y`.
+fn ghash_update(y: &mut [u8; AES128_BLOCK_SIZE], h: &[u8; AES128_BLOCK_SIZE], data: &[u8]) {
+    for chunk in data.chunks(AES128_BLOCK_SIZE) {
+        for (a, b) in y.iter_mut().zip(chunk.iter()) {
+            *a ^= *b;
+        }
+        *y = gf128_mul(y, h);
+    }
+}
+
+/// GHASH with hash key `h` over the AuthData `a` and the CData `c`, each zero
+/// padded, followed by their lengths in bits.
+fn ghash(h: &[u8; AES128_BLOCK_SIZE], a: &[u8], c: &[u8]) -> [u8; AES128_BLOCK_SIZE] {
+    let mut y = [0u8; AES128_BLOCK_SIZE];
+    ghash_update(&mut y, h, a);
+    ghash_update(&mut y, h, c);
+
+    let mut lengths = [0u8; AES128_BLOCK_SIZE];
+    let a_bits = (a.len() as u64) * 8;
+    let c_bits = (c.len() as u64) * 8;
+    for i in 0..8 {
+        lengths[i] = (a_bits >> (56 - 8 * i)) as u8;
+        lengths[8 + i] = (c_bits >> (56 - 8 * i)) as u8;
+    }
+    ghash_update(&mut y, h, &lengths);
+    y
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + 'a> symmetric_encryption::AES128GCM<'a>
+    for AES128GCM<'a, A>
+{
+    fn set_client(&'a self, client: &'a symmetric_encryption::GCMClient) {
+        self.crypt_client.set(Some(client));
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() < AES128_KEY_SIZE {
+            ReturnCode::EINVAL
+        } else {
+            let mut new_key = [0u8; AES128_KEY_SIZE];
+            new_key.copy_from_slice(&key[..AES128_KEY_SIZE]);
+            self.key.set(new_key);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() < GCM_NONCE_LENGTH {
+            ReturnCode::EINVAL
+        } else {
+            let mut new_nonce = [0u8; GCM_NONCE_LENGTH];
+            new_nonce.copy_from_slice(&nonce[..GCM_NONCE_LENGTH]);
+            self.nonce.set(new_nonce);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        tag_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.state.get() != GCMState::Idle {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if !(a_off <= m_off && m_off + m_len + tag_len <= buf.len())
+            || tag_len < GCM_MIN_TAG_LENGTH || tag_len > GCM_TAG_LENGTH
+        {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+
+        // Copy the message after the zero block
+        let fits = self.crypt_buf.map_or(false, |cbuf| {
+            let end = AES128_BLOCK_SIZE + Self::padded_len(m_len);
+            if end > cbuf.len() {
+                return false;
+            }
+            cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]
+                .copy_from_slice(&buf[m_off..m_off + m_len]);
+            cbuf[AES128_BLOCK_SIZE + m_len..end]
+                .iter_mut()
+                .for_each(|b| *b = 0);
+            true
+        });
+        if !fits {
+            return (ReturnCode::ENOMEM, Some(buf));
+        }
+
+        self.encrypting.set(encrypting);
+        self.pos.set((a_off, m_off, m_len, tag_len));
+
+        let res = self.start_gcm_hash_key();
+        if res != ReturnCode::SUCCESS {
+            (res, Some(buf))
+        } else {
+            self.buf.replace(buf);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr> symmetric_encryption::Client<'a> for AES128GCM<'a, A> {
+    fn crypt_done(&self, _: Option<&'a mut [u8]>, crypt_buf: &'a mut [u8]) {
+        self.crypt_buf.replace(crypt_buf);
+        match self.state.get() {
+            GCMState::Idle => {}
+            GCMState::HashKey => {
+                self.crypt_buf.map(|cbuf| {
+                    let mut h = [0u8; AES128_BLOCK_SIZE];
+                    h.copy_from_slice(&cbuf[..AES128_BLOCK_SIZE]);
+                    self.hash_key.set(h);
+                    cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+                });
+
+                // When decrypting, crypt_buf still holds the CData
+                if !self.encrypting.get() {
+                    self.compute_ghash();
+                }
+
+                let res = self.start_gcm_encrypt();
+                if res != ReturnCode::SUCCESS {
+                    self.fail(res);
+                }
+            }
+            GCMState::Encrypt => {
+                self.end_gcm();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::vec::Vec;
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn block(hex: &str) -> [u8; AES128_BLOCK_SIZE] {
+        let mut out = [0; AES128_BLOCK_SIZE];
+        out.copy_from_slice(&from_hex(hex));
+        out
+    }
+
+    /// A test case of McGrew and Viega, "The Galois/Counter Mode of
+    /// Operation (GCM)", with the AES outputs it lists: the hash key H and
+    /// the tag mask E(K, J0).
+    struct TestCase {
+        hash_key: &'static str,
+        tag_mask: &'static str,
+        auth_data: &'static str,
+        cipher_data: &'static str,
+        ghash: &'static str,
+        tag: &'static str,
+    }
+
+    const ZERO_KEY_H: &'static str = "66e94bd4ef8a2c3b884cfa59ca342b2e";
+    const ZERO_KEY_TAG_MASK: &'static str = "58e2fccefa7e3061367f1d57a4e7455a";
+    const KEY_3_H: &'static str = "b83b533708bf535d0aa6e52980d53b78";
+    const KEY_3_TAG_MASK: &'static str = "3247184b3c4f69a44dbcd22887bbb418";
+    const CIPHER_DATA_3: &'static str = "42831ec2217774244b7221b784d0d49c\
+                                         e3aa212f2c02a4e035c17e2329aca12e\
+                                         21d514b25466931c7d8f6a5aac84aa05\
+                                         1ba30b396a0aac973d58e091473f5985";
+
+    const TEST_CASES: [TestCase; 4] = [
+        TestCase {
+            hash_key: ZERO_KEY_H,
+            tag_mask: ZERO_KEY_TAG_MASK,
+            auth_data: "",
+            cipher_data: "",
+            ghash: "00000000000000000000000000000000",
+            tag: "58e2fccefa7e3061367f1d57a4e7455a",
+        },
+        TestCase {
+            hash_key: ZERO_KEY_H,
+            tag_mask: ZERO_KEY_TAG_MASK,
+            auth_data: "",
+            cipher_data: "0388dace60b6a392f328c2b971b2fe78",
+            ghash: "f38cbb1ad69223dcc3457ae5b6b0f885",
+            tag: "ab6e47d42cec13bdf53a67b21257bddf",
+        },
+        TestCase {
+            hash_key: KEY_3_H,
+            tag_mask: KEY_3_TAG_MASK,
+            auth_data: "",
+            cipher_data: CIPHER_DATA_3,
+            ghash: "7f1b32b81b820d02614f8895ac1d4eac",
+            tag: "4d5c2af327cd64a62cf35abd2ba6fab4",
+        },
+        TestCase {
+            hash_key: KEY_3_H,
+            tag_mask: KEY_3_TAG_MASK,
+            auth_data: "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+            // The first 60 bytes of the CData of test case 3
+            cipher_data: "42831ec2217774244b7221b784d0d49c\
+                          e3aa212f2c02a4e035c17e2329aca12e\
+                          21d514b25466931c7d8f6a5aac84aa05\
+                          1ba30b396a0aac973d58e091",
+            ghash: "698e57f70e6ecc7fd9463b7260a9ae5f",
+            tag: "5bc94fbc3221a5db94fae95ae7121a47",
+        },
+    ];
+
+    #[test]
+    fn gf128_mul_first_block() {
+        // X1 = C1 * H in test cases 2 and 3
+        assert_eq!(
+            gf128_mul(&block("0388dace60b6a392f328c2b971b2fe78"), &block(ZERO_KEY_H)),
+            block("5e2ec746917062882c85b0685353deb7")
+        );
+        assert_eq!(
+            gf128_mul(&block(&CIPHER_DATA_3[..32]), &block(KEY_3_H)),
+            block("59ed3f2bb1a0aaa07c9f56c6a504647b")
+        );
+    }
+
+    #[test]
+    fn gf128_mul_identity() {
+        // The bit order of GHASH puts the element 1 in the top bit
+        let one = block("80000000000000000000000000000000");
+        assert_eq!(gf128_mul(&one, &block(KEY_3_H)), block(KEY_3_H));
+        assert_eq!(gf128_mul(&block(KEY_3_H), &one), block(KEY_3_H));
+    }
+
+    #[test]
+    fn ghash_test_cases() {
+        for case in TEST_CASES.iter() {
+            assert_eq!(
+                ghash(
+                    &block(case.hash_key),
+                    &from_hex(case.auth_data),
+                    &from_hex(case.cipher_data)
+                ),
+                block(case.ghash)
+            );
+        }
+    }
+
+    #[test]
+    fn ghash_update_in_pieces() {
+        // Absorbing whole blocks one at a time is the same as all at once
+        let h = block(KEY_3_H);
+        let data = from_hex(CIPHER_DATA_3);
+        let mut whole = [0; AES128_BLOCK_SIZE];
+        ghash_update(&mut whole, &h, &data);
+        let mut pieces = [0; AES128_BLOCK_SIZE];
+        for chunk in data.chunks(AES128_BLOCK_SIZE) {
+            ghash_update(&mut pieces, &h, chunk);
+        }
+        assert_eq!(whole, pieces);
+    }
+
+    #[test]
+    fn tag_test_cases() {
+        for case in TEST_CASES.iter() {
+            let ghash = ghash(
+                &block(case.hash_key),
+                &from_hex(case.auth_data),
+                &from_hex(case.cipher_data),
+            );
+            let mask = block(case.tag_mask);
+            let mut tag = [0; AES128_BLOCK_SIZE];
+            for i in 0..AES128_BLOCK_SIZE {
+                tag[i] = ghash[i] ^ mask[i];
+            }
+            assert_eq!(tag, block(case.tag));
+        }
+    }
+
+    #[test]
+    fn pre_counter_block_test_cases() {
+        // Y0 of test cases 1 and 2, and of 3 and 4
+        assert_eq!(
+            pre_counter_block(&[0; GCM_NONCE_LENGTH]),
+            block("00000000000000000000000000000001")
+        );
+        let mut nonce = [0; GCM_NONCE_LENGTH];
+        nonce.copy_from_slice(&from_hex("cafebabefacedbaddecaf888"));
+        assert_eq!(
+            pre_counter_block(&nonce),
+            block("cafebabefacedbaddecaf88800000001")
+        );
+    }
+}
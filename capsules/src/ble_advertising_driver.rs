@@ -60,7 +60,8 @@
 //! -----
 //!
 //! You need a device that provides the `kernel::BleAdvertisementDriver` trait along with a virtual
-//! timer to perform events and not block the entire kernel
+//! timer to perform events and not block the entire kernel, and a random number generator for the
+//! static addresses (e.g. a `capsules::virtual_rng::VirtualRng`)
 //!
 //! ```rust
 //!     let ble_radio = static_init!(
@@ -70,7 +71,9 @@
 //!         &mut nrf52::radio::RADIO,
 //!     kernel::Grant::create(),
 //!         &mut nrf5x::ble_advertising_driver::BUF,
-//!         ble_radio_virtual_alarm));
+//!         ble_radio_virtual_alarm,
+//!         ble_rng));
+//!    ble_rng.set_client(ble_radio);
 //!    nrf5x::ble_advertising_hil::BleAdvertisementDriver::set_rx_client(&nrf52::radio::RADIO,
 //!                                                                      ble_radio);
 //!    nrf5x::ble_advertising_hil::BleAdvertisementDriver::set_tx_client(&nrf52::radio::RADIO,
@@ -100,6 +103,7 @@ use core::cmp;
 use kernel;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::rng;
 use kernel::hil::time::Frequency;
 use kernel::returncode::ReturnCode;
 
@@ -166,7 +170,8 @@ pub struct App {
 
     // Advertising meta-data
     adv_data: Option<kernel::AppSlice<kernel::Shared, u8>>,
-    address: [u8; PACKET_ADDR_LEN],
+    /// The static address, `None` until it has been generated
+    address: Option<[u8; PACKET_ADDR_LEN]>,
     pdu_type: AdvPduType,
     advertisement_interval_ms: u32,
     tx_power: u8,
@@ -188,7 +193,7 @@ impl Default for App {
             alarm_data: AlarmData::new(),
             adv_data: None,
             scan_buffer: None,
-            address: [0; PACKET_ADDR_LEN],
+            address: None,
             pdu_type: ADV_NONCONN_IND,
             scan_callback: None,
             process_status: Some(BLEState::NotInitialized),
@@ -209,24 +214,27 @@ impl App {
     // • At least one bit of the random part of the address shall be 0
     // • At least one bit of the random part of the address shall be 1
     //
-    // Note that endianness is a potential problem here as this is suppose to be platform
-    // independent therefore use 0xf0 as both byte 1 and byte 6 i.e., the two most significant bits
-    // are equal to one regardless of endianness
+    // The address is sent least significant byte first, so the two most significant bits are in
+    // the last byte.
     //
-    // Byte 1            0xf0
-    // Byte 2-5          random
-    // Byte 6            0xf0
-    // FIXME: For now use AppId as "randomness"
-    fn generate_random_address(&mut self, appid: kernel::AppId) -> ReturnCode {
-        self.address = [
-            0xf0,
-            (appid.idx() & 0xff) as u8,
-            ((appid.idx() << 8) & 0xff) as u8,
-            ((appid.idx() << 16) & 0xff) as u8,
-            ((appid.idx() << 24) & 0xff) as u8,
-            0xf0,
-        ];
-        ReturnCode::SUCCESS
+    // Returns `false` if the random words ran out before a valid address was found.
+    fn generate_random_address(&mut self, randomness: &mut Iterator<Item = u32>) -> bool {
+        while let (Some(low), Some(high)) = (randomness.next(), randomness.next()) {
+            let random_high = (high & 0x3fff) as u16;
+            if (low == 0 && random_high == 0) || (low == !0 && random_high == 0x3fff) {
+                continue;
+            }
+            self.address = Some([
+                low as u8,
+                (low >> 8) as u8,
+                (low >> 16) as u8,
+                (low >> 24) as u8,
+                random_high as u8,
+                (random_high >> 8) as u8 | 0xc0,
+            ]);
+            return true;
+        }
+        false
     }
 
     fn send_advertisement<'a, B, A>(&self, ble: &BLE<'a, B, A>, channel: RadioChannel) -> ReturnCode
@@ -234,6 +242,10 @@ impl App {
         B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig + 'a,
         A: kernel::hil::time::Alarm + 'a,
     {
+        let address = match self.address {
+            Some(address) => address,
+            None => return ReturnCode::FAIL,
+        };
         self.adv_data
             .as_ref()
             .map(|adv_data| {
@@ -259,7 +271,7 @@ impl App {
                             header[1] = (payload_len & 0x3f) as u8;
 
                             let (adva, data) = payload.split_at_mut(6);
-                            adva.copy_from_slice(&self.address);
+                            adva.copy_from_slice(&address);
                             data[..adv_data_len].copy_from_slice(adv_data_corrected);
                         }
                         let total_len = cmp::min(PACKET_LENGTH, payload_len + 2);
@@ -307,6 +319,7 @@ where
     app: kernel::Grant<App>,
     kernel_tx: kernel::common::take_cell::TakeCell<'static, [u8]>,
     alarm: &'a A,
+    rng: &'a rng::RNG,
     sending_app: Cell<Option<kernel::AppId>>,
     receiving_app: Cell<Option<kernel::AppId>>,
 }
@@ -321,6 +334,7 @@ where
         container: kernel::Grant<App>,
         tx_buf: &'static mut [u8],
         alarm: &'a A,
+        rng: &'a rng::RNG,
     ) -> BLE<'a, B, A> {
         BLE {
             radio: radio,
@@ -328,6 +342,7 @@ where
             app: container,
             kernel_tx: kernel::common::take_cell::TakeCell::new(tx_buf),
             alarm: alarm,
+            rng: rng,
             sending_app: Cell::new(None),
             receiving_app: Cell::new(None),
         }
@@ -398,6 +413,14 @@ where
                         return;
                     }
 
+                    if app.process_status == Some(BLEState::AdvertisingIdle)
+                        && app.address.is_none()
+                    {
+                        // The address hasn't been generated yet, try again next period
+                        app.set_next_alarm::<A::Frequency>(self.alarm.now());
+                        return;
+                    }
+
                     app.alarm_data.expiration = Expiration::Disabled;
 
                     match app.process_status {
@@ -538,6 +561,34 @@ where
     }
 }
 
+// Generates static addresses for apps that don't have one yet
+impl<'a, B, A> rng::Client for BLE<'a, B, A>
+where
+    B: ble_advertising::BleAdvertisementDriver + ble_advertising::BleConfig + 'a,
+    A: kernel::hil::time::Alarm + 'a,
+{
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        let mut done = true;
+        for app in self.app.iter() {
+            done = app.enter(|app, _| {
+                if app.adv_data.is_some() && app.address.is_none() {
+                    app.generate_random_address(randomness)
+                } else {
+                    true
+                }
+            });
+            if !done {
+                break;
+            }
+        }
+        if done {
+            rng::Continue::Done
+        } else {
+            rng::Continue::More
+        }
+    }
+}
+
 // System Call implementation
 impl<'a, B, A> kernel::Driver for BLE<'a, B, A>
 where
@@ -646,12 +697,12 @@ where
             0 => self.app
                 .enter(appid, |app, _| {
                     app.adv_data = slice;
-                    if let ReturnCode::SUCCESS = app.generate_random_address(appid) {
-                        app.process_status = Some(BLEState::Initialized);
-                        ReturnCode::SUCCESS
-                    } else {
-                        ReturnCode::FAIL
+                    app.process_status = Some(BLEState::Initialized);
+                    if app.address.is_none() {
+                        // The address is filled in once randomness arrives
+                        self.rng.get();
                     }
+                    ReturnCode::SUCCESS
                 })
                 .unwrap_or_else(|err| err.into()),
 
@@ -0,0 +1,527 @@
+//! USB CDC-ACM virtual serial port.
+//!
+//! `CdcAcm` is a USB function that presents itself as a Communications Device
+//! Class, Abstract Control Model device: the standard "USB modem" that Linux
+//! exposes as `/dev/ttyACM*`, macOS as `/dev/cu.usbmodem*`, and Windows as a
+//! COM port without an extra driver. It is added to a
+//! `capsules::usb_composite::CompositeDevice`, either alone or next to other
+//! functions.
+//!
+//! It implements `hil::uart::UART`, so it can replace a USART underneath
+//! `capsules::console::Console` (and therefore the kernel `debug!` output).
+//! The line coding requested by the host (baud rate, parity, etc.) has no
+//! effect on the data and is only stored so that the host can read it back.
+//!
+//! Data is transferred over a pair of bulk endpoints one packet at a time, so
+//! transmissions wait until a terminal on the host side opens the port and
+//! starts reading. The function also has an interrupt endpoint for
+//! notifications, but never sends any.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let cdc_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let cdc = static_init!(
+//!     capsules::cdc::CdcAcm<'static, sam4l::usbc::Usbc<'static>,
+//!                           VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::cdc::CdcAcm::new(&sam4l::usbc::USBC, cdc_alarm));
+//! cdc_alarm.set_client(cdc);
+//!
+//! let usb_functions = static_init!(
+//!     [&'static capsules::usb_composite::Function; 1],
+//!     [cdc]);
+//! let usb_device = static_init!(
+//!     capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_composite::CompositeDevice::new(
+//!         &sam4l::usbc::USBC,
+//!         capsules::usb::DeviceDescriptor {
+//!             class: 0xef, // Interface association
+//!             subclass: 2,
+//!             protocol: 1,
+//!             product_id: 0xabce,
+//!             manufacturer_string: 1,
+//!             product_string: 2,
+//!             ..Default::default()
+//!         },
+//!         &["XYZ Corp.", "Tock Serial Port"],
+//!         usb_functions));
+//! usb_device.build(&mut capsules::usb_composite::CONFIGURATION_BUF, sam4l::usbc::N_ENDPOINTS - 1);
+//! sam4l::usbc::USBC.set_client(usb_device);
+//!
+//! let console = static_init!(
+//!     capsules::console::Console<capsules::cdc::CdcAcm<'static,
+//!         sam4l::usbc::Usbc<'static>, VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+//!     capsules::console::Console::new(
+//!         cdc,
+//!         115200,
+//!         &mut capsules::console::WRITE_BUF,
+//!         &mut capsules::console::READ_BUF,
+//!         kernel::Grant::create()));
+//! hil::uart::UART::set_client(cdc, console);
+//!
+//! // Connect to the host
+//! hil::usb::Client::enable(usb_device);
+//! hil::usb::Client::attach(usb_device);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::VolatileCell;
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::hil::usb::*;
+use usb::*;
+use usb_composite::{Function, SetupResult};
+
+const MAX_PACKET_SIZE: usize = 8;
+
+// Communications class codes
+const CLASS_COMMUNICATIONS: u8 = 0x02;
+const SUBCLASS_ACM: u8 = 0x02;
+const PROTOCOL_AT_COMMANDS: u8 = 0x01;
+const CLASS_DATA: u8 = 0x0a;
+
+// Class-specific descriptor types and subtypes
+const CS_INTERFACE: u8 = 0x24;
+const SUBTYPE_HEADER: u8 = 0x00;
+const SUBTYPE_CALL_MANAGEMENT: u8 = 0x01;
+const SUBTYPE_ACM: u8 = 0x02;
+const SUBTYPE_UNION: u8 = 0x06;
+
+// Class-specific requests
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+
+const LINE_CODING_LEN: usize = 7;
+
+/// A class-specific interface descriptor (CDC 1.2 section 5.2.3) whose body
+/// is a fixed sequence of bytes.
+struct FunctionalDescriptor<'a> {
+    subtype: u8,
+    body: &'a [u8],
+}
+
+impl<'a> Descriptor for FunctionalDescriptor<'a> {
+    fn size(&self) -> usize {
+        3 + self.body.len()
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        let len = self.size();
+        buf[0].set(len as u8);
+        buf[1].set(CS_INTERFACE);
+        buf[2].set(self.subtype);
+        for (i, b) in self.body.iter().enumerate() {
+            buf[3 + i].set(*b);
+        }
+        len
+    }
+}
+
+/// The serial parameters the host last asked for.
+#[derive(Copy, Clone)]
+struct LineCoding {
+    baud_rate: u32,
+    stop_bits: u8,
+    parity: u8,
+    data_bits: u8,
+}
+
+impl Default for LineCoding {
+    fn default() -> Self {
+        LineCoding {
+            baud_rate: 115200,
+            stop_bits: 0, // 1 stop bit
+            parity: 0,    // None
+            data_bits: 8,
+        }
+    }
+}
+
+pub struct CdcAcm<'a, C: 'a, A: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    // Used to complete receptions from data that was already buffered
+    alarm: &'a A,
+
+    // Interface and endpoint numbers assigned when the configuration was
+    // built
+    comm_interface: Cell<u8>,
+    endpoint_notify: Cell<usize>,
+    endpoint_data_in: Cell<usize>,
+    endpoint_data_out: Cell<usize>,
+
+    // An eight-byte buffer for each endpoint
+    notify_buffer: [VolatileCell<u8>; 8],
+    data_in_buffer: [VolatileCell<u8>; 8],
+    data_out_buffer: [VolatileCell<u8>; 8],
+
+    line_coding: Cell<LineCoding>,
+
+    // DTR and RTS as last set by the host
+    control_line_state: Cell<u16>,
+
+    uart_client: Cell<Option<&'static uart::Client>>,
+
+    // Transmission in progress
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+
+    // Reception in progress
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_offset: Cell<usize>,
+
+    // An OUT packet that has been consumed from the endpoint but not yet
+    // copied to a receive buffer
+    staging_buf: [Cell<u8>; MAX_PACKET_SIZE],
+    staging_start: Cell<usize>,
+    staging_end: Cell<usize>,
+
+    delayed_in: Cell<bool>,
+    delayed_out: Cell<bool>,
+}
+
+impl<'a, C: UsbController, A: hil::time::Alarm> CdcAcm<'a, C, A> {
+    pub fn new(controller: &'a C, alarm: &'a A) -> Self {
+        CdcAcm {
+            controller: controller,
+            alarm: alarm,
+            comm_interface: Cell::new(0),
+            endpoint_notify: Cell::new(0),
+            endpoint_data_in: Cell::new(0),
+            endpoint_data_out: Cell::new(0),
+            notify_buffer: Default::default(),
+            data_in_buffer: Default::default(),
+            data_out_buffer: Default::default(),
+            line_coding: Cell::new(LineCoding::default()),
+            control_line_state: Cell::new(0),
+            uart_client: Cell::new(None),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_offset: Cell::new(0),
+            staging_buf: Default::default(),
+            staging_start: Cell::new(0),
+            staging_end: Cell::new(0),
+            delayed_in: Cell::new(false),
+            delayed_out: Cell::new(false),
+        }
+    }
+
+    /// Whether a program on the host has the port open, as signaled by DTR.
+    pub fn is_connected(&self) -> bool {
+        self.control_line_state.get() & 1 != 0
+    }
+
+    /// Copy as much staged OUT data as fits into the receive buffer, and
+    /// report the reception if the buffer is now full.
+    fn drain_staging(&self) {
+        let start = self.staging_start.get();
+        let end = self.staging_end.get();
+        if start == end {
+            return;
+        }
+
+        let done = self.rx_buffer.map_or(false, |rx| {
+            let offset = self.rx_offset.get();
+            let count = min(end - start, self.rx_len.get() - offset);
+            for i in 0..count {
+                rx[offset + i] = self.staging_buf[start + i].get();
+            }
+            self.rx_offset.set(offset + count);
+            if start + count == end {
+                self.staging_start.set(0);
+                self.staging_end.set(0);
+            } else {
+                self.staging_start.set(start + count);
+            }
+            offset + count == self.rx_len.get()
+        });
+
+        if done {
+            self.rx_buffer.take().map(|rx| {
+                let len = self.rx_len.get();
+                self.uart_client.get().map(move |client| {
+                    client.receive_complete(rx, len, uart::Error::CommandComplete);
+                });
+            });
+        }
+
+        // The staging buffer is free again, so accept more data
+        if self.staging_start.get() == self.staging_end.get() && self.delayed_out.take() {
+            self.controller.endpoint_bulk_resume(self.endpoint_data_out.get());
+        }
+    }
+}
+
+impl<'a, C: UsbController, A: hil::time::Alarm> uart::UART for CdcAcm<'a, C, A> {
+    fn set_client(&self, client: &'static uart::Client) {
+        self.uart_client.set(Some(client));
+    }
+
+    /// The parameters are ignored: the host chooses the line coding.
+    fn init(&self, _params: uart::UARTParams) {}
+
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        if self.tx_buffer.is_some() {
+            return;
+        }
+        self.tx_len.set(min(tx_len, tx_data.len()));
+        self.tx_offset.set(0);
+        self.tx_buffer.replace(tx_data);
+
+        // In case we reported Delay before, alert the controller
+        // that we now have data to send
+        if self.delayed_in.take() {
+            self.controller.endpoint_bulk_resume(self.endpoint_data_in.get());
+        }
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        if self.rx_buffer.is_some() {
+            return;
+        }
+        self.rx_len.set(min(rx_len, rx_buffer.len()));
+        self.rx_offset.set(0);
+        self.rx_buffer.replace(rx_buffer);
+
+        if self.staging_start.get() != self.staging_end.get() {
+            // Data is already waiting; hand it over from the alarm so the
+            // client is not called back from within this call.
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        } else if self.delayed_out.take() {
+            self.controller.endpoint_bulk_resume(self.endpoint_data_out.get());
+        }
+    }
+}
+
+impl<'a, C: UsbController, A: hil::time::Alarm> hil::time::Client for CdcAcm<'a, C, A> {
+    fn fired(&self) {
+        self.drain_staging();
+    }
+}
+
+impl<'a, C: UsbController, A: hil::time::Alarm> Function for CdcAcm<'a, C, A> {
+    fn add_descriptors(&self, builder: &mut ConfigurationBuilder) {
+        let comm_interface = builder.next_interface_number();
+        let data_interface = comm_interface + 1;
+        self.comm_interface.set(comm_interface);
+
+        // Lets the host tell which interfaces belong together when the
+        // serial port is one of several functions
+        builder.add(&InterfaceAssociationDescriptor {
+            first_interface: comm_interface,
+            interface_count: 2,
+            function_class: CLASS_COMMUNICATIONS,
+            function_subclass: SUBCLASS_ACM,
+            function_protocol: PROTOCOL_AT_COMMANDS,
+            string_index: 0,
+        });
+
+        // Communications interface: functional descriptors and the
+        // notification endpoint
+        builder.add_interface(InterfaceDescriptor {
+            num_endpoints: 1,
+            interface_class: CLASS_COMMUNICATIONS,
+            interface_subclass: SUBCLASS_ACM,
+            interface_protocol: PROTOCOL_AT_COMMANDS,
+            ..Default::default()
+        });
+        builder.add(&FunctionalDescriptor {
+            subtype: SUBTYPE_HEADER,
+            body: &[0x10, 0x01], // CDC 1.10
+        });
+        builder.add(&FunctionalDescriptor {
+            subtype: SUBTYPE_CALL_MANAGEMENT,
+            body: &[0x00, data_interface], // No call management
+        });
+        builder.add(&FunctionalDescriptor {
+            subtype: SUBTYPE_ACM,
+            body: &[0x02], // Supports the line coding and control line requests
+        });
+        builder.add(&FunctionalDescriptor {
+            subtype: SUBTYPE_UNION,
+            body: &[comm_interface, data_interface],
+        });
+        builder
+            .add_endpoint(
+                TransferDirection::DeviceToHost,
+                TransferType::Interrupt,
+                MAX_PACKET_SIZE as u16,
+                255,
+            )
+            .map(|endpoint| self.endpoint_notify.set(endpoint));
+
+        // Data interface: a Bulk-In and a Bulk-Out endpoint
+        builder.add_interface(InterfaceDescriptor {
+            num_endpoints: 2,
+            interface_class: CLASS_DATA,
+            interface_subclass: 0,
+            interface_protocol: 0,
+            ..Default::default()
+        });
+        builder
+            .add_endpoint(
+                TransferDirection::DeviceToHost,
+                TransferType::Bulk,
+                MAX_PACKET_SIZE as u16,
+                0,
+            )
+            .map(|endpoint| self.endpoint_data_in.set(endpoint));
+        builder
+            .add_endpoint(
+                TransferDirection::HostToDevice,
+                TransferType::Bulk,
+                MAX_PACKET_SIZE as u16,
+                0,
+            )
+            .map(|endpoint| self.endpoint_data_out.set(endpoint));
+    }
+
+    fn enable_endpoints(&self) {
+        self.controller.endpoint_set_buffer(self.endpoint_notify.get(), &self.notify_buffer);
+        self.controller.endpoint_in_enable(
+            TransferType::Interrupt,
+            self.endpoint_notify.get(),
+            MAX_PACKET_SIZE,
+        );
+
+        self.controller.endpoint_set_buffer(self.endpoint_data_in.get(), &self.data_in_buffer);
+        self.controller.endpoint_in_enable(
+            TransferType::Bulk,
+            self.endpoint_data_in.get(),
+            MAX_PACKET_SIZE,
+        );
+
+        self.controller.endpoint_set_buffer(self.endpoint_data_out.get(), &self.data_out_buffer);
+        self.controller.endpoint_out_enable(
+            TransferType::Bulk,
+            self.endpoint_data_out.get(),
+            MAX_PACKET_SIZE,
+        );
+    }
+
+    fn bus_reset(&self) {
+        // The host has to open the port again
+        self.control_line_state.set(0);
+        self.line_coding.set(LineCoding::default());
+        self.delayed_in.set(false);
+        self.delayed_out.set(false);
+    }
+
+    /// Handle a class-specific request to the communications interface
+    fn ctrl_setup(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult {
+        match setup_data.request_type.request_type() {
+            RequestType::Class => {}
+            _ => return SetupResult::Error(CtrlSetupResult::ErrNonstandardRequest),
+        }
+        if setup_data.index != self.comm_interface.get() as u16 {
+            return SetupResult::Error(CtrlSetupResult::ErrInvalidDeviceIndex);
+        }
+        match setup_data.request_code {
+            SET_LINE_CODING => SetupResult::Ok,
+            GET_LINE_CODING => {
+                let coding = self.line_coding.get();
+                for i in 0..4 {
+                    response[i].set((coding.baud_rate >> (8 * i)) as u8);
+                }
+                response[4].set(coding.stop_bits);
+                response[5].set(coding.parity);
+                response[6].set(coding.data_bits);
+                SetupResult::In(LINE_CODING_LEN)
+            }
+            SET_CONTROL_LINE_STATE => {
+                self.control_line_state.set(setup_data.value);
+                SetupResult::Ok
+            }
+            _ => SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType),
+        }
+    }
+
+    fn ctrl_out(&self, setup_data: &SetupData, packet: &[VolatileCell<u8>]) -> CtrlOutResult {
+        if setup_data.request_code != SET_LINE_CODING || packet.len() != LINE_CODING_LEN {
+            return CtrlOutResult::Halted;
+        }
+        let mut baud_rate = 0;
+        for i in 0..4 {
+            baud_rate |= (packet[i].get() as u32) << (8 * i);
+        }
+        self.line_coding.set(LineCoding {
+            baud_rate: baud_rate,
+            stop_bits: packet[4].get(),
+            parity: packet[5].get(),
+            data_bits: packet[6].get(),
+        });
+        CtrlOutResult::Ok
+    }
+
+    /// Handle a Bulk IN transaction
+    fn bulk_in(&self, endpoint: usize) -> BulkInResult {
+        if endpoint != self.endpoint_data_in.get() {
+            // Nothing to notify the host of
+            return BulkInResult::Delay;
+        }
+
+        let offset = self.tx_offset.get();
+        let packet_bytes = min(MAX_PACKET_SIZE, self.tx_len.get().saturating_sub(offset));
+        let sent = self.tx_buffer.map_or(false, |tx| {
+            let packet = &self.data_in_buffer;
+            for i in 0..packet_bytes {
+                packet[i].set(tx[offset + i]);
+            }
+            true
+        });
+
+        if !sent || packet_bytes == 0 {
+            // Nothing to send
+            self.delayed_in.set(true);
+            return BulkInResult::Delay;
+        }
+
+        self.tx_offset.set(offset + packet_bytes);
+        if offset + packet_bytes == self.tx_len.get() {
+            self.tx_buffer.take().map(|tx| {
+                self.uart_client.get().map(move |client| {
+                    client.transmit_complete(tx, uart::Error::CommandComplete);
+                });
+            });
+        }
+        BulkInResult::Packet(packet_bytes)
+    }
+
+    /// Handle a Bulk OUT transaction
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> BulkOutResult {
+        if endpoint != self.endpoint_data_out.get() {
+            return BulkOutResult::Error;
+        }
+
+        if self.staging_start.get() != self.staging_end.get() {
+            // The previous packet hasn't been received yet; wait until it
+            // is drained
+            self.delayed_out.set(true);
+            return BulkOutResult::Delay;
+        }
+
+        // Consume the packet from the endpoint buffer
+        let new_len = min(packet_bytes as usize, MAX_PACKET_SIZE);
+        let packet = &self.data_out_buffer;
+        for i in 0..new_len {
+            self.staging_buf[i].set(packet[i].get());
+        }
+        self.staging_start.set(0);
+        self.staging_end.set(new_len);
+
+        self.drain_staging();
+        BulkOutResult::Ok
+    }
+}
@@ -15,6 +15,9 @@
 //!
 //! ```
 //!
+//! On chips without a CRC unit, `capsules::sw_crc::SoftwareCrc` computes the
+//! same algorithms in software.
+//!
 //! ## CRC Algorithms
 //!
 //! The capsule supports two general purpose CRC algorithms, as well as a few
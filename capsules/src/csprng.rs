@@ -0,0 +1,363 @@
+//! Cryptographically secure random numbers seeded from a hardware TRNG.
+//!
+//! Hardware TRNGs are slow, and every `get()` on one waits for fresh samples.
+//! `Csprng` instead keeps a ChaCha20 key, seeded with 256 bits from the TRNG,
+//! and implements `hil::rng::RNG` by generating ChaCha20 keystream. Requests
+//! are answered from an alarm right away once the generator is seeded.
+//!
+//! The key is replaced with fresh keystream after every client callback, so
+//! numbers that have already been handed out can't be recovered from the
+//! generator's state later. New TRNG samples are mixed into the key every
+//! `RESEED_INTERVAL_S` seconds, and after `RESEED_WORDS` words of output. The
+//! generator keeps serving from its current key while a reseed is collecting
+//! samples.
+//!
+//! `Csprng` has a single client; use a `capsules::virtual_rng::MuxRng` to
+//! share it between several users.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let csprng_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let csprng = static_init!(
+//!     capsules::csprng::Csprng<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::csprng::Csprng::new(&sam4l::trng::TRNG, csprng_alarm));
+//! csprng_alarm.set_client(csprng);
+//! sam4l::trng::TRNG.set_client(csprng);
+//!
+//! let rng = static_init!(
+//!     capsules::rng::SimpleRng<'static, capsules::csprng::Csprng<'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+//!     capsules::rng::SimpleRng::new(csprng, kernel::Grant::create()));
+//! csprng.set_client(rng);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil::rng;
+use kernel::hil::time::{self, Alarm, Frequency};
+
+/// How often new TRNG samples are mixed in, in seconds
+pub const RESEED_INTERVAL_S: u32 = 60;
+
+/// How many words may be generated before new TRNG samples are mixed in
+pub const RESEED_WORDS: usize = 1 << 16;
+
+/// How many words a client is offered per callback
+const WORDS_PER_CALLBACK: usize = 64;
+
+const KEY_WORDS: usize = 8;
+const BLOCK_WORDS: usize = 16;
+
+pub struct Csprng<'a, A: Alarm + 'a> {
+    trng: &'a rng::RNG,
+    alarm: &'a A,
+    client: Cell<Option<&'a rng::Client>>,
+    key: Cell<[u32; KEY_WORDS]>,
+    counter: Cell<u64>,
+    /// Keystream not yet handed out, from `block_idx` on
+    block: Cell<[u32; BLOCK_WORDS]>,
+    block_idx: Cell<usize>,
+    seeded: Cell<bool>,
+    /// TRNG samples collected for the next reseed
+    entropy: Cell<[u32; KEY_WORDS]>,
+    entropy_len: Cell<usize>,
+    reseeding: Cell<bool>,
+    reseed_at: Cell<u32>,
+    words_since_reseed: Cell<usize>,
+    /// Whether the client is waiting for randomness
+    requested: Cell<bool>,
+}
+
+impl<'a, A: Alarm + 'a> Csprng<'a, A> {
+    pub fn new(trng: &'a rng::RNG, alarm: &'a A) -> Csprng<'a, A> {
+        Csprng {
+            trng: trng,
+            alarm: alarm,
+            client: Cell::new(None),
+            key: Cell::new([0; KEY_WORDS]),
+            counter: Cell::new(0),
+            block: Cell::new([0; BLOCK_WORDS]),
+            block_idx: Cell::new(BLOCK_WORDS),
+            seeded: Cell::new(false),
+            entropy: Cell::new([0; KEY_WORDS]),
+            entropy_len: Cell::new(0),
+            reseeding: Cell::new(false),
+            reseed_at: Cell::new(0),
+            words_since_reseed: Cell::new(0),
+            requested: Cell::new(false),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a rng::Client) {
+        self.client.set(Some(client));
+    }
+
+    /// Ask the TRNG for the samples of the next reseed, unless that is
+    /// already underway.
+    fn start_reseed(&self) {
+        if !self.reseeding.get() {
+            self.reseeding.set(true);
+            self.entropy_len.set(0);
+            self.trng.get();
+        }
+    }
+
+    /// Mix `entropy` into the key. The new key depends on both the old key
+    /// and the samples, so a bad TRNG can't make the generator worse.
+    fn reseed(&self, entropy: &[u32; KEY_WORDS]) {
+        let mut key = [0; KEY_WORDS];
+        for (k, e) in key.iter_mut().zip(entropy.iter()) {
+            *k = self.next_word() ^ e;
+        }
+        self.set_key(key);
+        self.words_since_reseed.set(0);
+    }
+
+    /// Replace the key with fresh keystream, and drop what is left of the
+    /// current block.
+    fn rekey(&self) {
+        let mut key = [0; KEY_WORDS];
+        for k in key.iter_mut() {
+            *k = self.next_word();
+        }
+        self.set_key(key);
+    }
+
+    fn set_key(&self, key: [u32; KEY_WORDS]) {
+        self.key.set(key);
+        self.counter.set(0);
+        self.block.set([0; BLOCK_WORDS]);
+        self.block_idx.set(BLOCK_WORDS);
+    }
+
+    fn next_word(&self) -> u32 {
+        if self.block_idx.get() == BLOCK_WORDS {
+            let counter = self.counter.get();
+            self.block.set(chacha20_block(&self.key.get(), counter));
+            self.counter.set(counter.wrapping_add(1));
+            self.block_idx.set(0);
+        }
+        let idx = self.block_idx.get();
+        let mut block = self.block.get();
+        let word = block[idx];
+        block[idx] = 0;
+        self.block.set(block);
+        self.block_idx.set(idx + 1);
+        word
+    }
+
+    /// Offer the client a batch of words.
+    fn serve(&self) {
+        self.requested.set(false);
+        self.client.get().map(|client| {
+            let mut randomness = Randomness {
+                csprng: self,
+                remaining: WORDS_PER_CALLBACK,
+            };
+            if client.randomness_available(&mut randomness) == rng::Continue::More {
+                self.requested.set(true);
+            }
+            let used = WORDS_PER_CALLBACK - randomness.remaining;
+            self.words_since_reseed
+                .set(self.words_since_reseed.get() + used);
+        });
+        self.rekey();
+
+        if self.words_since_reseed.get() >= RESEED_WORDS {
+            self.start_reseed();
+        }
+    }
+
+    /// Wake up again right away if the client is waiting, otherwise when the
+    /// next reseed is due.
+    fn set_next_alarm(&self) {
+        if self.requested.get() && self.seeded.get() {
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        } else if self.seeded.get() {
+            self.alarm.set_alarm(self.reseed_at.get());
+        }
+    }
+
+    fn reseed_interval() -> u32 {
+        RESEED_INTERVAL_S * <A::Frequency>::frequency()
+    }
+}
+
+/// Keystream offered to a client, a word at a time.
+struct Randomness<'b, 'a: 'b, A: Alarm + 'a> {
+    csprng: &'b Csprng<'a, A>,
+    remaining: usize,
+}
+
+impl<'b, 'a: 'b, A: Alarm + 'a> Iterator for Randomness<'b, 'a, A> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.remaining == 0 {
+            None
+        } else {
+            self.remaining -= 1;
+            Some(self.csprng.next_word())
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> rng::RNG for Csprng<'a, A> {
+    fn get(&self) {
+        self.requested.set(true);
+        if self.seeded.get() {
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        } else {
+            // The client is served once the first seed arrives
+            self.start_reseed();
+        }
+    }
+}
+
+/// Collects TRNG samples for reseeding.
+impl<'a, A: Alarm + 'a> rng::Client for Csprng<'a, A> {
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        let mut entropy = self.entropy.get();
+        let mut len = self.entropy_len.get();
+        while len < KEY_WORDS {
+            match randomness.next() {
+                Some(word) => {
+                    entropy[len] = word;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        if len < KEY_WORDS {
+            self.entropy.set(entropy);
+            self.entropy_len.set(len);
+            return rng::Continue::More;
+        }
+
+        self.reseed(&entropy);
+        self.entropy.set([0; KEY_WORDS]);
+        self.entropy_len.set(0);
+        self.reseeding.set(false);
+
+        if !self.seeded.get() {
+            self.seeded.set(true);
+            self.reseed_at
+                .set(self.alarm.now().wrapping_add(Self::reseed_interval()));
+        }
+        self.set_next_alarm();
+        rng::Continue::Done
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for Csprng<'a, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        // The reseed is due once `now` has passed `reseed_at`
+        if now.wrapping_sub(self.reseed_at.get()) < (1 << 31) {
+            self.reseed_at.set(now.wrapping_add(Self::reseed_interval()));
+            self.start_reseed();
+        }
+        if self.requested.get() {
+            self.serve();
+        }
+        self.set_next_alarm();
+    }
+}
+
+fn quarter_round(state: &mut [u32; BLOCK_WORDS], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// The ChaCha20 block function (RFC 7539) with a 64-bit block counter and an
+/// all-zero nonce.
+fn chacha20_block(key: &[u32; KEY_WORDS], counter: u64) -> [u32; BLOCK_WORDS] {
+    let mut input = [0; BLOCK_WORDS];
+    input[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    for (s, i) in state.iter_mut().zip(input.iter()) {
+        *s = s.wrapping_add(*i);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The block serialized as in RFC 7539, each word little endian
+    fn serialize(block: &[u32; BLOCK_WORDS]) -> [u8; 4 * BLOCK_WORDS] {
+        let mut out = [0; 4 * BLOCK_WORDS];
+        for (i, word) in block.iter().enumerate() {
+            for j in 0..4 {
+                out[4 * i + j] = (word >> (8 * j)) as u8;
+            }
+        }
+        out
+    }
+
+    fn from_hex(hex: &str) -> [u8; 4 * BLOCK_WORDS] {
+        let mut out = [0; 4 * BLOCK_WORDS];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // RFC 7539 section 2.1.1
+    #[test]
+    fn quarter_round_vector() {
+        let mut state = [0; BLOCK_WORDS];
+        state[..4].copy_from_slice(&[0x11111111, 0x01020304, 0x9b8d6f43, 0x01234567]);
+        quarter_round(&mut state, 0, 1, 2, 3);
+        assert_eq!(state[..4], [0xea2a92f4, 0xcb1cf8ce, 0x4581472e, 0x5881c4bb]);
+    }
+
+    // RFC 7539 appendix A.1, test vector #1: all-zero key and nonce, block
+    // counter 0
+    #[test]
+    fn block_vector_1() {
+        assert_eq!(
+            serialize(&chacha20_block(&[0; KEY_WORDS], 0))[..],
+            from_hex(
+                "76b8e0ada0f13d90405d6ae55386bd28bdd219b8a08ded1aa836efcc8b770dc7\
+                 da41597c5157488d7724e03fb8d84a376a43b8f41518a11cc387b669b2ee6586"
+            )[..]
+        );
+    }
+
+    // RFC 7539 appendix A.1, test vector #2: as #1 with block counter 1
+    #[test]
+    fn block_vector_2() {
+        assert_eq!(
+            serialize(&chacha20_block(&[0; KEY_WORDS], 1))[..],
+            from_hex(
+                "9f07e7be5551387a98ba977c732d080dcb0f29a048e3656912c6533e32ee7aed\
+                 29b721769ce64e43d57133b074d839d531ed1f28510afb45ace10a1f4b794d6f"
+            )[..]
+        );
+    }
+}
@@ -0,0 +1,522 @@
+//! USB Device Firmware Upgrade (DFU 1.1) class.
+//!
+//! `UsbDfu` is a USB function that lets a host program such as `dfu-util`
+//! download new images into flash. Each region of flash the board chooses to
+//! expose is an alternate setting of the DFU interface: typically the
+//! application region, holding a sequence of TBF app images, and optionally a
+//! staging area for a new kernel image that a bootloader later installs.
+//!
+//! Images are written one flash page at a time through `hil::flash::Flash`,
+//! usually a `capsules::virtual_flash::FlashUser` so that other capsules can
+//! keep using the flash. The DFU transfer size is the flash page size, so each
+//! download block fills one page. The first page of an app image is checked
+//! to start with a TBF header before anything is written, and after the last
+//! block the page following the image is erased so that the kernel does not
+//! load stale apps left behind it. Kernel images are written as they are.
+//!
+//! The device only runs in DFU mode (there is no run-time mode to detach
+//! from) and is manifestation tolerant, so several images can be downloaded
+//! without resetting. Uploading is not supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut DFU_PAGEBUFFER: sam4l::flashcalw::Sam4lPage =
+//!     sam4l::flashcalw::Sam4lPage::new();
+//! static DFU_REGIONS: [capsules::dfu::DfuRegion; 2] = [
+//!     capsules::dfu::DfuRegion {
+//!         kind: capsules::dfu::RegionKind::Apps,
+//!         start_page: 0x200,
+//!         num_pages: 0x100,
+//!         string_index: 3,
+//!     },
+//!     capsules::dfu::DfuRegion {
+//!         kind: capsules::dfu::RegionKind::Kernel,
+//!         start_page: 0x300,
+//!         num_pages: 0x100,
+//!         string_index: 4,
+//!     },
+//! ];
+//!
+//! let dfu_flash = static_init!(
+//!     capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::virtual_flash::FlashUser::new(mux_flash));
+//! let dfu = static_init!(
+//!     capsules::dfu::UsbDfu<'static,
+//!         capsules::virtual_flash::FlashUser<'static, sam4l::flashcalw::FLASHCALW>>,
+//!     capsules::dfu::UsbDfu::new(dfu_flash, &DFU_REGIONS, &mut DFU_PAGEBUFFER));
+//! hil::flash::HasClient::set_client(dfu_flash, dfu);
+//!
+//! let usb_functions = static_init!(
+//!     [&'static capsules::usb_composite::Function; 1],
+//!     [dfu]);
+//! let usb_device = static_init!(
+//!     capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_composite::CompositeDevice::new(
+//!         &sam4l::usbc::USBC,
+//!         capsules::usb::DeviceDescriptor {
+//!             product_id: 0xabce,
+//!             manufacturer_string: 1,
+//!             product_string: 2,
+//!             ..Default::default()
+//!         },
+//!         &["XYZ Corp.", "Tock DFU Device", "Apps", "Kernel staging"],
+//!         usb_functions));
+//! usb_device.build(&mut capsules::usb_composite::CONFIGURATION_BUF, sam4l::usbc::N_ENDPOINTS - 1);
+//! sam4l::usbc::USBC.set_client(usb_device);
+//! hil::usb::Client::enable(usb_device);
+//! hil::usb::Client::attach(usb_device);
+//! ```
+//!
+//! Then, on the host:
+//!
+//! ```plain
+//! $ dfu-util -d 6667:abce -a 0 -D apps.tbf
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::VolatileCell;
+use kernel::hil;
+use kernel::hil::usb::*;
+use kernel::ReturnCode;
+use usb::*;
+use usb_composite::{Function, SetupResult};
+
+// Class requests
+const DFU_DETACH: u8 = 0;
+const DFU_DNLOAD: u8 = 1;
+const DFU_UPLOAD: u8 = 2;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+// Standard SET_INTERFACE request, which selects a region
+const SET_INTERFACE: u8 = 11;
+
+const DESCRIPTOR_DFU_FUNCTIONAL: u8 = 0x21;
+
+/// How long the host should wait before polling again while a flash
+/// operation is in progress, in milliseconds
+const BUSY_POLL_TIMEOUT: u32 = 10;
+
+/// What a region of flash holds, which decides how downloads to it are
+/// checked.
+#[derive(Copy, Clone, PartialEq)]
+pub enum RegionKind {
+    /// A sequence of TBF app images
+    Apps,
+    /// A kernel image, installed later by the bootloader
+    Kernel,
+}
+
+/// A region of flash exposed as one alternate setting of the DFU interface.
+pub struct DfuRegion {
+    pub kind: RegionKind,
+    /// First flash page of the region
+    pub start_page: usize,
+    /// Length of the region in pages
+    pub num_pages: usize,
+    /// Index of the string naming the region, or 0 for none
+    pub string_index: u8,
+}
+
+/// Device states as reported to the host (DFU 1.1 section 6.1.2)
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle = 2,
+    DnloadSync = 3,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    Error = 10,
+}
+
+/// Status codes as reported to the host (DFU 1.1 section 6.1.2)
+#[derive(Copy, Clone, PartialEq)]
+enum Status {
+    Ok = 0x00,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrErase = 0x04,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrStalledPkt = 0x0f,
+}
+
+struct DfuFunctionalDescriptor {
+    transfer_size: u16,
+}
+
+impl Descriptor for DfuFunctionalDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(DESCRIPTOR_DFU_FUNCTIONAL);
+        buf[2].set(0x05); // bitCanDnload | bitManifestationTolerant
+        buf[3].set((1000 & 0xff) as u8); // wDetachTimeOut, unused
+        buf[4].set((1000 >> 8) as u8);
+        buf[5].set((self.transfer_size & 0xff) as u8);
+        buf[6].set((self.transfer_size >> 8) as u8);
+        buf[7].set(0x10); // DFU 1.1
+        buf[8].set(0x01);
+        9
+    }
+}
+
+pub struct UsbDfu<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    regions: &'static [DfuRegion],
+    pagebuffer: TakeCell<'static, F::Page>,
+    page_size: usize,
+
+    // Interface number assigned when the configuration was built
+    interface: Cell<u8>,
+    // The selected alternate setting, an index into `regions`
+    region: Cell<usize>,
+
+    state: Cell<State>,
+    status: Cell<Status>,
+
+    /// Bytes of the current page received so far
+    received: Cell<usize>,
+    /// Pages of the image written so far
+    pages_written: Cell<usize>,
+    /// Bytes of the image received so far
+    image_len: Cell<usize>,
+    /// Total size from the TBF header of the first app in the image
+    app_size: Cell<usize>,
+}
+
+impl<'a, F: hil::flash::Flash> UsbDfu<'a, F> {
+    pub fn new(
+        flash: &'a F,
+        regions: &'static [DfuRegion],
+        pagebuffer: &'static mut F::Page,
+    ) -> UsbDfu<'a, F> {
+        let page_size = pagebuffer.as_mut().len();
+        UsbDfu {
+            flash: flash,
+            regions: regions,
+            pagebuffer: TakeCell::new(pagebuffer),
+            page_size: page_size,
+            interface: Cell::new(0),
+            region: Cell::new(0),
+            state: Cell::new(State::Idle),
+            status: Cell::new(Status::Ok),
+            received: Cell::new(0),
+            pages_written: Cell::new(0),
+            image_len: Cell::new(0),
+            app_size: Cell::new(0),
+        }
+    }
+
+    fn current_region(&self) -> &DfuRegion {
+        &self.regions[self.region.get()]
+    }
+
+    fn fail(&self, status: Status) {
+        self.status.set(status);
+        self.state.set(State::Error);
+    }
+
+    fn busy(&self) -> bool {
+        match self.state.get() {
+            State::DnBusy | State::Manifest => true,
+            _ => false,
+        }
+    }
+
+    /// Start a download block of `length` bytes
+    fn dnload(&self, length: usize) -> SetupResult {
+        match self.state.get() {
+            State::Idle => {
+                self.received.set(0);
+                self.pages_written.set(0);
+                self.image_len.set(0);
+                self.app_size.set(0);
+            }
+            State::DnloadIdle => {}
+            _ => {
+                self.fail(Status::ErrStalledPkt);
+                return SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType);
+            }
+        }
+
+        if length == 0 {
+            if self.state.get() == State::Idle {
+                // Nothing was downloaded
+                self.fail(Status::ErrNotDone);
+                return SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType);
+            }
+            self.state.set(State::ManifestSync);
+            return SetupResult::Ok;
+        }
+
+        if self.received.get() + length > self.page_size
+            || self.pages_written.get() >= self.current_region().num_pages
+        {
+            self.fail(Status::ErrAddress);
+            return SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType);
+        }
+        self.state.set(State::DnloadSync);
+        SetupResult::Ok
+    }
+
+    /// Write the page buffer to the next page of the region, padding it with
+    /// erased bytes if it is not full.
+    fn write_page(&self) {
+        let region = self.current_region();
+        let received = self.received.get();
+        let is_first_page = self.pages_written.get() == 0;
+
+        let result = self.pagebuffer.take().map_or(ReturnCode::EBUSY, |pagebuffer| {
+            let valid = {
+                let page = pagebuffer.as_mut();
+                for b in page[received..].iter_mut() {
+                    *b = 0xff;
+                }
+                if is_first_page && region.kind == RegionKind::Apps {
+                    // Refuse anything that doesn't begin with a version 2
+                    // TBF header, before overwriting the apps already there
+                    let version = (page[0] as u16) | ((page[1] as u16) << 8);
+                    let total_size = (page[4] as usize) | ((page[5] as usize) << 8)
+                        | ((page[6] as usize) << 16)
+                        | ((page[7] as usize) << 24);
+                    self.app_size.set(total_size);
+                    received >= 8 && version == 2 && total_size > 0
+                } else {
+                    true
+                }
+            };
+            if !valid {
+                self.pagebuffer.replace(pagebuffer);
+                return ReturnCode::EINVAL;
+            }
+            self.flash
+                .write_page(region.start_page + self.pages_written.get(), pagebuffer)
+        });
+
+        match result {
+            ReturnCode::SUCCESS => {}
+            ReturnCode::EINVAL => self.fail(Status::ErrFile),
+            _ => self.fail(Status::ErrWrite),
+        }
+    }
+
+    /// Start manifesting the downloaded image by writing out its last page
+    fn manifest(&self) {
+        self.state.set(State::Manifest);
+        if self.received.get() > 0 {
+            self.write_page();
+        } else {
+            self.terminate();
+        }
+    }
+
+    /// Erase the page after an app image, so the kernel stops looking for
+    /// apps there
+    fn terminate(&self) {
+        let region = self.current_region();
+        let next = self.pages_written.get();
+        if region.kind == RegionKind::Apps && next < region.num_pages {
+            if self.flash.erase_page(region.start_page + next) != ReturnCode::SUCCESS {
+                self.fail(Status::ErrErase);
+            }
+        } else {
+            self.finish_manifest();
+        }
+    }
+
+    fn finish_manifest(&self) {
+        if self.image_len.get() < self.app_size.get() {
+            // The image ended partway through its first app
+            self.fail(Status::ErrNotDone);
+        } else {
+            self.status.set(Status::Ok);
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn get_status(&self, response: &[Cell<u8>]) -> SetupResult {
+        // The host polls for status to move the download along
+        match self.state.get() {
+            State::DnloadSync => {
+                if self.received.get() == self.page_size {
+                    self.state.set(State::DnBusy);
+                    self.write_page();
+                } else {
+                    self.state.set(State::DnloadIdle);
+                }
+            }
+            State::ManifestSync => self.manifest(),
+            _ => {}
+        }
+
+        let poll_timeout = if self.busy() { BUSY_POLL_TIMEOUT } else { 0 };
+        response[0].set(self.status.get() as u8);
+        response[1].set((poll_timeout & 0xff) as u8);
+        response[2].set(((poll_timeout >> 8) & 0xff) as u8);
+        response[3].set(((poll_timeout >> 16) & 0xff) as u8);
+        response[4].set(self.state.get() as u8);
+        response[5].set(0); // No status description string
+        SetupResult::In(6)
+    }
+
+    fn class_request(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult {
+        match setup_data.request_code {
+            DFU_DETACH => SetupResult::Ok,
+            DFU_DNLOAD => self.dnload(setup_data.length as usize),
+            DFU_GETSTATUS => self.get_status(response),
+            DFU_CLRSTATUS => {
+                if self.state.get() == State::Error {
+                    self.status.set(Status::Ok);
+                    self.state.set(State::Idle);
+                }
+                SetupResult::Ok
+            }
+            DFU_GETSTATE => {
+                response[0].set(self.state.get() as u8);
+                SetupResult::In(1)
+            }
+            DFU_ABORT => {
+                if !self.busy() {
+                    self.state.set(State::Idle);
+                }
+                SetupResult::Ok
+            }
+            DFU_UPLOAD => {
+                // Uploading is not supported
+                self.fail(Status::ErrStalledPkt);
+                SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType)
+            }
+            _ => {
+                self.fail(Status::ErrStalledPkt);
+                SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType)
+            }
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash> Function for UsbDfu<'a, F> {
+    fn add_descriptors(&self, builder: &mut ConfigurationBuilder) {
+        // One alternate setting for each region
+        let interface = builder.next_interface_number();
+        for (setting, region) in self.regions.iter().enumerate() {
+            let d = InterfaceDescriptor {
+                interface_number: interface,
+                alternate_setting: setting as u8,
+                num_endpoints: 0,
+                interface_class: 0xfe, // Application specific
+                interface_subclass: 0x01, // Device firmware upgrade
+                interface_protocol: 0x02, // DFU mode
+                string_index: region.string_index,
+            };
+            if setting == 0 {
+                builder.add_interface(d);
+            } else {
+                builder.add(&d);
+            }
+        }
+        self.interface.set(interface);
+
+        builder.add(&DfuFunctionalDescriptor {
+            transfer_size: self.page_size as u16,
+        });
+    }
+
+    fn enable_endpoints(&self) {
+        // DFU uses only the default control endpoint
+    }
+
+    fn bus_reset(&self) {
+        // Abandon a download, unless flash is still being written
+        if !self.busy() {
+            self.status.set(Status::Ok);
+            self.state.set(State::Idle);
+        }
+    }
+
+    fn ctrl_setup(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult {
+        if setup_data.index & 0xff != self.interface.get() as u16 {
+            return SetupResult::Error(CtrlSetupResult::ErrInvalidDeviceIndex);
+        }
+        match setup_data.request_type.request_type() {
+            RequestType::Standard if setup_data.request_code == SET_INTERFACE => {
+                let setting = setup_data.value as usize;
+                if setting >= self.regions.len() || self.busy() {
+                    return SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType);
+                }
+                self.region.set(setting);
+                self.status.set(Status::Ok);
+                self.state.set(State::Idle);
+                SetupResult::Ok
+            }
+            RequestType::Class => self.class_request(setup_data, response),
+            _ => SetupResult::Error(CtrlSetupResult::ErrNonstandardRequest),
+        }
+    }
+
+    fn ctrl_out(&self, setup_data: &SetupData, packet: &[VolatileCell<u8>]) -> CtrlOutResult {
+        if setup_data.request_code != DFU_DNLOAD || self.state.get() != State::DnloadSync {
+            return CtrlOutResult::Halted;
+        }
+        let received = self.received.get();
+        let len = min(packet.len(), self.page_size - received);
+        self.pagebuffer.map_or(CtrlOutResult::Halted, |pagebuffer| {
+            let page = pagebuffer.as_mut();
+            for i in 0..len {
+                page[received + i] = packet[i].get();
+            }
+            self.received.set(received + len);
+            self.image_len.set(self.image_len.get() + len);
+            CtrlOutResult::Ok
+        })
+    }
+
+    fn bulk_in(&self, _endpoint: usize) -> BulkInResult {
+        BulkInResult::Error
+    }
+
+    fn bulk_out(&self, _endpoint: usize, _packet_bytes: u32) -> BulkOutResult {
+        BulkOutResult::Error
+    }
+}
+
+impl<'a, F: hil::flash::Flash> hil::flash::Client<F> for UsbDfu<'a, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, _error: hil::flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: hil::flash::Error) {
+        self.pagebuffer.replace(pagebuffer);
+        if error != hil::flash::Error::CommandComplete {
+            self.fail(Status::ErrWrite);
+            return;
+        }
+        self.pages_written.set(self.pages_written.get() + 1);
+        self.received.set(0);
+        match self.state.get() {
+            State::DnBusy => self.state.set(State::DnloadIdle),
+            State::Manifest => self.terminate(),
+            _ => {}
+        }
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        if error != hil::flash::Error::CommandComplete {
+            self.fail(Status::ErrErase);
+            return;
+        }
+        if self.state.get() == State::Manifest {
+            self.finish_manifest();
+        }
+    }
+}
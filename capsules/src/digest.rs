@@ -0,0 +1,305 @@
+//! Provides userspace applications with SHA-256 and HMAC-SHA256.
+//!
+//! Apps share a single digest engine. Requests are queued, and served one at a
+//! time: the app's data is copied into a kernel buffer and added to the
+//! message in chunks, and the digest is copied back into the app's
+//! destination buffer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let digest = static_init!(
+//!     capsules::digest::DigestDriver<'static, capsules::sha256::Sha256Software<'static,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>>>,
+//!     capsules::digest::DigestDriver::new(
+//!         sha256,
+//!         &mut capsules::digest::DATA_BUF,
+//!         &mut capsules::digest::DEST_BUF,
+//!         kernel::Grant::create()));
+//! hil::digest::Digest::set_client(sha256, digest);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: The HMAC key.
+//! - `1`: The data to hash.
+//! - `2`: The buffer the digest is written to, at least 32 bytes.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called with the result of a request as a `ReturnCode`.
+//!
+//! ### Commands
+//!
+//! - `0`: Driver check.
+//! - `1`: Compute the digest of the data buffer with algorithm `arg1`: `0`
+//!   for SHA-256, `1` for HMAC-SHA256 with the key buffer.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::hil::digest::{Digest, HmacSha256, Sha256, SHA256_OUTPUT_SIZE};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x40003;
+
+pub static mut DATA_BUF: [u8; 128] = [0; 128];
+pub static mut DEST_BUF: [u8; SHA256_OUTPUT_SIZE] = [0; SHA256_OUTPUT_SIZE];
+
+#[derive(Copy, Clone, PartialEq)]
+enum Algorithm {
+    Sha256,
+    HmacSha256,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    key: Option<AppSlice<Shared, u8>>,
+    data: Option<AppSlice<Shared, u8>>,
+    dest: Option<AppSlice<Shared, u8>>,
+    /// The algorithm the app is waiting on, if it made a request
+    pending: Option<Algorithm>,
+}
+
+pub struct DigestDriver<'a, D: Digest<'a> + Sha256 + HmacSha256 + 'a> {
+    digest: &'a D,
+    apps: Grant<App>,
+    serving_app: Cell<Option<AppId>>,
+    /// How much of the serving app's data has been added
+    data_offset: Cell<usize>,
+    data_buffer: TakeCell<'static, [u8]>,
+    dest_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, D: Digest<'a> + Sha256 + HmacSha256 + 'a> DigestDriver<'a, D> {
+    pub fn new(
+        digest: &'a D,
+        data_buffer: &'static mut [u8],
+        dest_buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> DigestDriver<'a, D> {
+        DigestDriver {
+            digest: digest,
+            apps: grant,
+            serving_app: Cell::new(None),
+            data_offset: Cell::new(0),
+            data_buffer: TakeCell::new(data_buffer),
+            dest_buffer: TakeCell::new(dest_buffer),
+        }
+    }
+
+    /// Start the request of the next waiting app, if the engine is free.
+    fn serve_waiting_apps(&self) {
+        if self.serving_app.get().is_some() {
+            return;
+        }
+
+        for app in self.apps.iter() {
+            let started = app.enter(|app, _| {
+                let algorithm = match app.pending {
+                    Some(algorithm) => algorithm,
+                    None => return false,
+                };
+                let result = match algorithm {
+                    Algorithm::Sha256 => self.digest.set_mode_sha256(),
+                    Algorithm::HmacSha256 => match app.key {
+                        Some(ref key) => self.digest.set_mode_hmacsha256(key.as_ref()),
+                        None => ReturnCode::EINVAL,
+                    },
+                };
+                if result != ReturnCode::SUCCESS {
+                    app.pending = None;
+                    app.callback
+                        .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+                    return false;
+                }
+                self.serving_app.set(Some(app.appid()));
+                true
+            });
+            if started {
+                self.data_offset.set(0);
+                self.add_next_chunk();
+                return;
+            }
+        }
+    }
+
+    /// Add the next chunk of the serving app's data, or run the digest once
+    /// all of it has been added.
+    fn add_next_chunk(&self) {
+        let appid = match self.serving_app.get() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let result = self.apps
+            .enter(appid, |app, _| {
+                let offset = self.data_offset.get();
+                let remaining = app.data.as_ref().map_or(0, |data| data.len() - offset);
+                if remaining == 0 {
+                    return self.dest_buffer.take().map_or(ReturnCode::EBUSY, |dest| {
+                        let (result, dest) = self.digest.run(dest);
+                        dest.map(|dest| self.dest_buffer.replace(dest));
+                        result
+                    });
+                }
+
+                self.data_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+                    let len = cmp::min(remaining, buffer.len());
+                    app.data.as_ref().map(|data| {
+                        buffer[..len].copy_from_slice(&data.as_ref()[offset..offset + len]);
+                    });
+                    self.data_offset.set(offset + len);
+                    let (result, buffer) = self.digest.add_data(buffer, len);
+                    buffer.map(|buffer| self.data_buffer.replace(buffer));
+                    result
+                })
+            })
+            .unwrap_or_else(|err| err.into());
+
+        if result != ReturnCode::SUCCESS {
+            self.finish(result);
+        }
+    }
+
+    /// End the serving app's request and move on to the next app.
+    fn finish(&self, result: ReturnCode) {
+        self.digest.clear_data();
+        self.serving_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.pending = None;
+                app.callback
+                    .map(|mut cb| cb.schedule(From::from(result), 0, 0));
+            });
+        });
+        self.serving_app.set(None);
+        self.serve_waiting_apps();
+    }
+}
+
+impl<'a, D: Digest<'a> + Sha256 + HmacSha256 + 'a> hil::digest::Client for DigestDriver<'a, D> {
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]) {
+        self.data_buffer.replace(data);
+        if result == ReturnCode::SUCCESS {
+            self.add_next_chunk();
+        } else {
+            self.finish(result);
+        }
+    }
+
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]) {
+        let mut result = result;
+        if result == ReturnCode::SUCCESS {
+            self.serving_app.get().map(|appid| {
+                let _ = self.apps.enter(appid, |app, _| {
+                    result = app.dest.as_mut().map_or(ReturnCode::EINVAL, |dest| {
+                        if dest.len() < SHA256_OUTPUT_SIZE || digest.len() < SHA256_OUTPUT_SIZE {
+                            return ReturnCode::ESIZE;
+                        }
+                        dest.as_mut()[..SHA256_OUTPUT_SIZE]
+                            .copy_from_slice(&digest[..SHA256_OUTPUT_SIZE]);
+                        ReturnCode::SUCCESS
+                    });
+                });
+            });
+        }
+        self.dest_buffer.replace(digest);
+        self.finish(result);
+    }
+}
+
+impl<'a, D: Digest<'a> + Sha256 + HmacSha256 + 'a> Driver for DigestDriver<'a, D> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| match allow_num {
+                // Buffers can't change under a request that is queued or in
+                // progress
+                _ if app.pending.is_some() => ReturnCode::EBUSY,
+                0 => {
+                    app.key = slice;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.data = slice;
+                    ReturnCode::SUCCESS
+                }
+                2 => {
+                    app.dest = slice;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Compute digests.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Compute a digest. `arg1` is `0` for SHA-256 and `1` for
+    ///   HMAC-SHA256.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                let algorithm = match arg1 {
+                    0 => Algorithm::Sha256,
+                    1 => Algorithm::HmacSha256,
+                    _ => return ReturnCode::EINVAL,
+                };
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        if app.pending.is_some() {
+                            return ReturnCode::EBUSY;
+                        }
+                        let dest_ok = app.dest
+                            .as_ref()
+                            .map_or(false, |dest| dest.len() >= SHA256_OUTPUT_SIZE);
+                        let key_ok = algorithm == Algorithm::Sha256 || app.key.is_some();
+                        if app.callback.is_none() || !dest_ok || !key_ok {
+                            return ReturnCode::EINVAL;
+                        }
+                        app.pending = Some(algorithm);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.serve_waiting_apps();
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
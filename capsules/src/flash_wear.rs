@@ -0,0 +1,683 @@
+//! Track flash wear and retire pages that fail.
+//!
+//! `FlashWear` sits between a flash controller and its users (typically
+//! `MuxFlash`) and manages a region of flash pages. For every page in the
+//! region it counts how many times the page has been erased (a page write
+//! counts as an erase, since writing a page erases it first). After every
+//! write the page is read back and compared against what was written. A page
+//! that fails verification, or whose write or erase reports an error, is
+//! marked bad and the logical page is remapped to the least worn spare page.
+//! If no spare page is left, or the spare page cannot be written or erased,
+//! the operation fails with `FlashError`. Writes are retried from a copy of
+//! the user's buffer, so the buffer is always returned to the client.
+//!
+//! The erase counts, bad page flags and remapping table are kept in RAM and
+//! stored in a metadata page. The metadata page is rewritten whenever a page is
+//! remapped and otherwise every `sync_interval` tracked operations. The
+//! interval must be at least the number of data pages, so that the metadata
+//! page does not wear out faster than the pages it tracks on average. Up to
+//! `sync_interval` erase counts may be lost on reset.
+//!
+//! Pages outside the managed region are passed through unchanged, except for
+//! the spare pages and the metadata page, which cannot be accessed by users.
+//!
+//! ```plain
+//!                 hil::flash::Flash
+//!                ┌─────────────────┐
+//!                │                 │
+//!                │    FlashWear    │
+//!                │                 │
+//!                └─────────────────┘
+//!                 hil::flash::Flash
+//! ```
+//!
+//! Metadata page layout (all values little endian):
+//!
+//! ```plain
+//! 0         4          8                              8+4*P                 8+4*P+2*L
+//! +---------+----------+-------------------------------+-----------------------+
+//! |  magic  |    P     |  P x u32 (bit 31: bad page,   |  L x u16 (physical    |
+//! |         |          |  bits 0-30: erase count)      |  index of each page)  |
+//! +---------+----------+-------------------------------+-----------------------+
+//! ```
+//!
+//! where `P` is the number of physical pages (data and spare) and `L` the
+//! number of logical data pages.
+//!
+//! Usage
+//! -----
+//!
+//! ```
+//! // Track 64 pages starting at page 0x180, with 8 spares after them, and keep
+//! // the metadata in page 0x1ff.
+//! pub static mut WEAR_COUNTS: [u32; 72] = [0; 72];
+//! pub static mut WEAR_MAP: [u16; 64] = [0; 64];
+//! pub static mut WEAR_METABUFFER: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+//! pub static mut WEAR_VERIFYBUFFER: sam4l::flashcalw::Sam4lPage = sam4l::flashcalw::Sam4lPage::new();
+//! let flash_wear = static_init!(
+//!     capsules::flash_wear::FlashWear<'static, sam4l::flashcalw::FLASHCALW>,
+//!     capsules::flash_wear::FlashWear::new(
+//!         &sam4l::flashcalw::FLASH_CONTROLLER,
+//!         0x180,
+//!         &mut WEAR_COUNTS,
+//!         &mut WEAR_MAP,
+//!         0x1ff,
+//!         64,
+//!         &mut WEAR_METABUFFER,
+//!         &mut WEAR_VERIFYBUFFER));
+//! hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, flash_wear);
+//!
+//! let mux_flash = static_init!(
+//!     capsules::virtual_flash::MuxFlash<'static, capsules::flash_wear::FlashWear<'static, sam4l::flashcalw::FLASHCALW>>,
+//!     capsules::virtual_flash::MuxFlash::new(flash_wear));
+//! hil::flash::HasClient::set_client(flash_wear, mux_flash);
+//! flash_wear.initialize();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Marks a valid metadata page ("WEAR").
+const MAGIC: u32 = 0x57454152;
+
+/// Bit in an erase count entry marking the page as bad.
+const BAD_PAGE: u32 = 1 << 31;
+
+/// Result to report to the client once the metadata has been saved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pending {
+    None,
+    Write(hil::flash::Error),
+    Erase(hil::flash::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Reading the metadata page.
+    Init,
+    /// Forwarding a read, or any operation outside the managed region.
+    Passthrough,
+    Write { logical: usize, physical: usize },
+    /// Writing the copy of the user's buffer in `verifybuffer` to a spare.
+    Rewrite { logical: usize, physical: usize },
+    Verify { logical: usize, physical: usize },
+    Erase { logical: usize, physical: usize },
+    SaveMetadata { pending: Pending },
+}
+
+/// Summary of the wear of the managed region.
+#[derive(Clone, Copy, Debug)]
+pub struct WearStatistics {
+    /// Lowest erase count of any good page.
+    pub min_erases: u32,
+    /// Highest erase count of any good page.
+    pub max_erases: u32,
+    /// Number of pages that have been retired.
+    pub bad_pages: usize,
+    /// Number of good spare pages left for remapping.
+    pub spare_pages: usize,
+}
+
+pub struct FlashWear<'a, F: hil::flash::Flash + 'static> {
+    flash: &'a F,
+    client: Cell<Option<&'a hil::flash::Client<FlashWear<'a, F>>>>,
+    state: Cell<State>,
+    initialized: Cell<bool>,
+    /// First physical page of the managed region.
+    start_page: usize,
+    /// Number of logical pages exposed to users.
+    data_pages: usize,
+    /// Number of physical pages in the region, data and spares.
+    physical_pages: usize,
+    /// Page holding the stored metadata.
+    metadata_page: usize,
+    /// Number of tracked operations between metadata saves.
+    sync_interval: usize,
+    operations_since_sync: Cell<usize>,
+    /// Set when a page was remapped and the metadata must be saved.
+    dirty: Cell<bool>,
+    /// Erase count and bad flag for each physical page.
+    counts: TakeCell<'static, [u32]>,
+    /// Physical page index for each logical page.
+    map: TakeCell<'static, [u16]>,
+    /// Buffer for reading and writing the metadata page.
+    metabuffer: TakeCell<'static, F::Page>,
+    /// Buffer for reading back written pages, and for retrying writes.
+    verifybuffer: TakeCell<'static, F::Page>,
+    /// The user's buffer while a write is being verified or remapped.
+    buffer: TakeCell<'static, F::Page>,
+}
+
+impl<'a, F: hil::flash::Flash + 'a> FlashWear<'a, F> {
+    /// Create a wear tracking layer.
+    ///
+    /// The managed region starts at `start_page` and has one logical page for
+    /// each entry of `map`. `counts` must be at least as long as `map`; the
+    /// additional physical pages are used as spares. The metadata is stored in
+    /// `metadata_page`, which must be outside of the managed region, and is
+    /// saved every `sync_interval` tracked operations, which must be at least
+    /// the number of logical pages.
+    pub fn new(
+        flash: &'a F,
+        start_page: usize,
+        counts: &'static mut [u32],
+        map: &'static mut [u16],
+        metadata_page: usize,
+        sync_interval: usize,
+        metabuffer: &'static mut F::Page,
+        verifybuffer: &'static mut F::Page,
+    ) -> FlashWear<'a, F> {
+        let data_pages = map.len();
+        let physical_pages = counts.len();
+        FlashWear {
+            flash: flash,
+            client: Cell::new(None),
+            state: Cell::new(State::Idle),
+            initialized: Cell::new(false),
+            start_page: start_page,
+            data_pages: data_pages,
+            physical_pages: physical_pages,
+            metadata_page: metadata_page,
+            sync_interval: sync_interval,
+            operations_since_sync: Cell::new(0),
+            dirty: Cell::new(false),
+            counts: TakeCell::new(counts),
+            map: TakeCell::new(map),
+            metabuffer: TakeCell::new(metabuffer),
+            verifybuffer: TakeCell::new(verifybuffer),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Load the metadata from flash. Tracked pages cannot be accessed until
+    /// this completes, so it should be called while the board is being set up,
+    /// before any users of the flash start. If the metadata page does not hold
+    /// valid metadata the region is treated as new. If it cannot be read,
+    /// tracked pages stay inaccessible and `initialize` can be called again.
+    pub fn initialize(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.physical_pages < self.data_pages || self.physical_pages > 0x10000
+            || self.sync_interval < self.data_pages
+        {
+            return ReturnCode::EINVAL;
+        }
+
+        self.metabuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |metabuffer| {
+                if 8 + 4 * self.physical_pages + 2 * self.data_pages > metabuffer.as_mut().len() {
+                    self.metabuffer.replace(metabuffer);
+                    return ReturnCode::ESIZE;
+                }
+                self.state.set(State::Init);
+                let rval = self.flash.read_page(self.metadata_page, metabuffer);
+                if rval != ReturnCode::SUCCESS {
+                    self.state.set(State::Idle);
+                }
+                rval
+            })
+    }
+
+    /// Number of times the physical page currently backing `page` has been
+    /// erased, or `None` if `page` is not in the managed region.
+    pub fn erase_count(&self, page: usize) -> Option<u32> {
+        if page < self.start_page || page >= self.start_page + self.data_pages {
+            return None;
+        }
+        let logical = page - self.start_page;
+        self.map.map_or(None, |map| {
+            let physical = map[logical] as usize;
+            self.counts
+                .map_or(None, |counts| Some(counts[physical] & !BAD_PAGE))
+        })
+    }
+
+    /// Summarize the wear of the managed region.
+    pub fn statistics(&self) -> WearStatistics {
+        let mut stats = WearStatistics {
+            min_erases: 0,
+            max_erases: 0,
+            bad_pages: 0,
+            spare_pages: 0,
+        };
+        let mut first = true;
+        self.counts.map(|counts| {
+            self.map.map(|map| {
+                for (physical, &count) in counts.iter().enumerate() {
+                    if count & BAD_PAGE != 0 {
+                        stats.bad_pages += 1;
+                        continue;
+                    }
+                    if first || count < stats.min_erases {
+                        stats.min_erases = count;
+                    }
+                    if first || count > stats.max_erases {
+                        stats.max_erases = count;
+                    }
+                    first = false;
+                    if is_spare(counts, map, physical) {
+                        stats.spare_pages += 1;
+                    }
+                }
+            });
+        });
+        stats
+    }
+
+    /// Returns the logical index of `page` if it is in the managed region,
+    /// `Err(EINVAL)` if it is reserved by this layer and `Ok(None)` otherwise.
+    fn lookup(&self, page: usize) -> Result<Option<usize>, ReturnCode> {
+        if page == self.metadata_page {
+            Err(ReturnCode::EINVAL)
+        } else if page >= self.start_page && page < self.start_page + self.data_pages {
+            Ok(Some(page - self.start_page))
+        } else if page >= self.start_page && page < self.start_page + self.physical_pages {
+            Err(ReturnCode::EINVAL)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn physical_for(&self, logical: usize) -> usize {
+        self.map.map_or(logical, |map| map[logical] as usize)
+    }
+
+    fn count_erase(&self, physical: usize) {
+        self.counts.map(|counts| {
+            if counts[physical] & !BAD_PAGE != !BAD_PAGE {
+                counts[physical] += 1;
+            }
+        });
+    }
+
+    /// Retire `physical` and move `logical` to the least worn spare page.
+    /// Returns the new physical page, if there was a spare left.
+    fn remap(&self, logical: usize, physical: usize) -> Option<usize> {
+        self.dirty.set(true);
+        self.counts.map_or(None, |counts| {
+            self.map.map_or(None, |map| {
+                counts[physical] |= BAD_PAGE;
+
+                let mut best: Option<(usize, u32)> = None;
+                for (candidate, &count) in counts.iter().enumerate() {
+                    if !is_spare(counts, map, candidate) {
+                        continue;
+                    }
+                    if best.map_or(true, |(_, best_count)| count < best_count) {
+                        best = Some((candidate, count));
+                    }
+                }
+
+                best.map(|(spare, _)| {
+                    map[logical] = spare as u16;
+                    spare
+                })
+            })
+        })
+    }
+
+    /// Fresh metadata: no wear and every logical page on its own physical page.
+    fn reset_metadata(&self) {
+        self.counts.map(|counts| {
+            for count in counts.iter_mut() {
+                *count = 0;
+            }
+        });
+        self.map.map(|map| {
+            for (logical, entry) in map.iter_mut().enumerate() {
+                *entry = logical as u16;
+            }
+        });
+    }
+
+    /// Load the metadata from the metadata page buffer. Returns `false` if it
+    /// does not hold valid metadata for this region.
+    fn parse_metadata(&self, page: &[u8]) -> bool {
+        if read_u32(page, 0) != MAGIC || read_u32(page, 4) as usize != self.physical_pages {
+            return false;
+        }
+
+        let map_offset = 8 + 4 * self.physical_pages;
+        let valid = (0..self.data_pages).all(|logical| {
+            (read_u16(page, map_offset + 2 * logical) as usize) < self.physical_pages
+        });
+        if !valid {
+            return false;
+        }
+
+        self.counts.map(|counts| {
+            for (physical, count) in counts.iter_mut().enumerate() {
+                *count = read_u32(page, 8 + 4 * physical);
+            }
+        });
+        self.map.map(|map| {
+            for (logical, entry) in map.iter_mut().enumerate() {
+                *entry = read_u16(page, map_offset + 2 * logical);
+            }
+        });
+        true
+    }
+
+    /// Write the metadata to flash, reporting `pending` to the client when
+    /// done.
+    fn save_metadata(&self, pending: Pending) {
+        self.operations_since_sync.set(0);
+        self.dirty.set(false);
+        let rval = self.metabuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |metabuffer| {
+                {
+                    let page = metabuffer.as_mut();
+                    for byte in page.iter_mut() {
+                        *byte = 0xFF;
+                    }
+                    write_u32(page, 0, MAGIC);
+                    write_u32(page, 4, self.physical_pages as u32);
+                    self.counts.map(|counts| {
+                        for (physical, &count) in counts.iter().enumerate() {
+                            write_u32(page, 8 + 4 * physical, count);
+                        }
+                    });
+                    let map_offset = 8 + 4 * self.physical_pages;
+                    self.map.map(|map| {
+                        for (logical, &entry) in map.iter().enumerate() {
+                            write_u16(page, map_offset + 2 * logical, entry);
+                        }
+                    });
+                }
+                self.state.set(State::SaveMetadata { pending: pending });
+                self.flash.write_page(self.metadata_page, metabuffer)
+            });
+
+        if rval != ReturnCode::SUCCESS {
+            // Could not save, but the metadata in RAM is still correct, so
+            // don't hold up the client.
+            self.finish(pending);
+        }
+    }
+
+    /// Called after a tracked operation. Saves the metadata if a page was
+    /// remapped or enough operations have happened since the last save, and
+    /// otherwise reports the result straight away.
+    fn complete(&self, pending: Pending) {
+        self.operations_since_sync
+            .set(self.operations_since_sync.get() + 1);
+        if self.dirty.get() || self.operations_since_sync.get() >= self.sync_interval {
+            self.save_metadata(pending);
+        } else {
+            self.finish(pending);
+        }
+    }
+
+    fn finish(&self, pending: Pending) {
+        self.state.set(State::Idle);
+        match pending {
+            Pending::None => {}
+            Pending::Write(error) => {
+                self.buffer.take().map(|buffer| {
+                    self.client
+                        .get()
+                        .map(move |client| client.write_complete(buffer, error));
+                });
+            }
+            Pending::Erase(error) => {
+                self.client.get().map(|client| client.erase_complete(error));
+            }
+        }
+    }
+
+    /// A write to `physical` failed or did not verify. Try again on a spare,
+    /// writing a copy of the user's buffer so that the buffer can be returned
+    /// to the client even if the flash does not take the write.
+    fn retry_write(&self, logical: usize, physical: usize) {
+        let rval = self.remap(logical, physical)
+            .map_or(ReturnCode::FAIL, |spare| {
+                self.verifybuffer
+                    .take()
+                    .map_or(ReturnCode::ERESERVE, |copy| {
+                        self.buffer.map(|buffer| {
+                            copy.as_mut().copy_from_slice(buffer.as_mut());
+                        });
+                        self.state.set(State::Rewrite {
+                            logical: logical,
+                            physical: spare,
+                        });
+                        self.flash.write_page(self.start_page + spare, copy)
+                    })
+            });
+        if rval != ReturnCode::SUCCESS {
+            self.complete(Pending::Write(hil::flash::Error::FlashError));
+        }
+    }
+
+    /// A write to `physical` completed. Read the page back to check it
+    /// against the user's buffer, or try again on a spare if it failed.
+    fn written(&self, logical: usize, physical: usize, error: hil::flash::Error) {
+        self.count_erase(physical);
+        if error != hil::flash::Error::CommandComplete {
+            self.retry_write(logical, physical);
+            return;
+        }
+
+        let rval = self.verifybuffer
+            .take()
+            .map_or(ReturnCode::ERESERVE, |verifybuffer| {
+                self.state.set(State::Verify {
+                    logical: logical,
+                    physical: physical,
+                });
+                self.flash
+                    .read_page(self.start_page + physical, verifybuffer)
+            });
+        if rval != ReturnCode::SUCCESS {
+            self.complete(Pending::Write(hil::flash::Error::CommandComplete));
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'a, C: hil::flash::Client<Self>> hil::flash::HasClient<'a, C>
+    for FlashWear<'a, F>
+{
+    fn set_client(&'a self, client: &'a C) {
+        self.client.set(Some(client));
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'a> hil::flash::Flash for FlashWear<'a, F> {
+    type Page = F::Page;
+
+    fn read_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        match self.lookup(page_number) {
+            Err(rval) => rval,
+            Ok(None) => {
+                self.state.set(State::Passthrough);
+                self.flash.read_page(page_number, buf)
+            }
+            Ok(Some(logical)) => {
+                if !self.initialized.get() {
+                    return ReturnCode::EOFF;
+                }
+                self.state.set(State::Passthrough);
+                self.flash
+                    .read_page(self.start_page + self.physical_for(logical), buf)
+            }
+        }
+    }
+
+    fn write_page(&self, page_number: usize, buf: &'static mut Self::Page) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        match self.lookup(page_number) {
+            Err(rval) => rval,
+            Ok(None) => {
+                self.state.set(State::Passthrough);
+                self.flash.write_page(page_number, buf)
+            }
+            Ok(Some(logical)) => {
+                if !self.initialized.get() {
+                    return ReturnCode::EOFF;
+                }
+                let physical = self.physical_for(logical);
+                self.state.set(State::Write {
+                    logical: logical,
+                    physical: physical,
+                });
+                self.flash.write_page(self.start_page + physical, buf)
+            }
+        }
+    }
+
+    fn erase_page(&self, page_number: usize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        match self.lookup(page_number) {
+            Err(rval) => rval,
+            Ok(None) => {
+                self.state.set(State::Passthrough);
+                self.flash.erase_page(page_number)
+            }
+            Ok(Some(logical)) => {
+                if !self.initialized.get() {
+                    return ReturnCode::EOFF;
+                }
+                let physical = self.physical_for(logical);
+                self.state.set(State::Erase {
+                    logical: logical,
+                    physical: physical,
+                });
+                self.flash.erase_page(self.start_page + physical)
+            }
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'a> hil::flash::Client<F> for FlashWear<'a, F> {
+    fn read_complete(&self, pagebuffer: &'static mut F::Page, error: hil::flash::Error) {
+        match self.state.get() {
+            State::Init => {
+                if error != hil::flash::Error::CommandComplete {
+                    // Resetting the metadata would throw away the wear of
+                    // every page, so stay uninitialized instead.
+                    self.metabuffer.replace(pagebuffer);
+                    self.state.set(State::Idle);
+                    debug!("FlashWear: cannot read metadata page: {:?}", error);
+                    return;
+                }
+                let valid = self.parse_metadata(pagebuffer.as_mut());
+                self.metabuffer.replace(pagebuffer);
+                self.initialized.set(true);
+                if valid {
+                    self.state.set(State::Idle);
+                } else {
+                    self.reset_metadata();
+                    self.save_metadata(Pending::None);
+                }
+            }
+            State::Verify { logical, physical } => {
+                let matches = error == hil::flash::Error::CommandComplete
+                    && self.buffer.map_or(false, |buffer| {
+                        buffer.as_mut() == pagebuffer.as_mut()
+                    });
+                self.verifybuffer.replace(pagebuffer);
+                if matches {
+                    self.complete(Pending::Write(hil::flash::Error::CommandComplete));
+                } else {
+                    self.retry_write(logical, physical);
+                }
+            }
+            _ => {
+                self.state.set(State::Idle);
+                self.client.get().map(move |client| {
+                    client.read_complete(pagebuffer, error);
+                });
+            }
+        }
+    }
+
+    fn write_complete(&self, pagebuffer: &'static mut F::Page, error: hil::flash::Error) {
+        match self.state.get() {
+            State::Write { logical, physical } => {
+                self.buffer.replace(pagebuffer);
+                self.written(logical, physical, error);
+            }
+            State::Rewrite { logical, physical } => {
+                self.verifybuffer.replace(pagebuffer);
+                self.written(logical, physical, error);
+            }
+            State::SaveMetadata { pending } => {
+                self.metabuffer.replace(pagebuffer);
+                self.finish(pending);
+            }
+            _ => {
+                self.state.set(State::Idle);
+                self.client.get().map(move |client| {
+                    client.write_complete(pagebuffer, error);
+                });
+            }
+        }
+    }
+
+    fn erase_complete(&self, error: hil::flash::Error) {
+        match self.state.get() {
+            State::Erase { logical, physical } => {
+                self.count_erase(physical);
+                if error == hil::flash::Error::CommandComplete {
+                    self.complete(Pending::Erase(error));
+                } else {
+                    let rval = self.remap(logical, physical)
+                        .map_or(ReturnCode::FAIL, |spare| {
+                            self.state.set(State::Erase {
+                                logical: logical,
+                                physical: spare,
+                            });
+                            self.flash.erase_page(self.start_page + spare)
+                        });
+                    if rval != ReturnCode::SUCCESS {
+                        self.complete(Pending::Erase(hil::flash::Error::FlashError));
+                    }
+                }
+            }
+            _ => {
+                self.state.set(State::Idle);
+                self.client.get().map(|client| client.erase_complete(error));
+            }
+        }
+    }
+}
+
+/// Whether the physical page index is a good page not backing any logical
+/// page.
+fn is_spare(counts: &[u32], map: &[u16], physical: usize) -> bool {
+    counts[physical] & BAD_PAGE == 0 && !map.iter().any(|&entry| entry as usize == physical)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (buf[offset] as u32) | (buf[offset + 1] as u32) << 8 | (buf[offset + 2] as u32) << 16
+        | (buf[offset + 3] as u32) << 24
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) | (buf[offset + 1] as u16) << 8
+}
+
+fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+    buf[offset + 2] = (value >> 16) as u8;
+    buf[offset + 3] = (value >> 24) as u8;
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset] = value as u8;
+    buf[offset + 1] = (value >> 8) as u8;
+}
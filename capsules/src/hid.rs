@@ -0,0 +1,651 @@
+//! USB Human Interface Device class.
+//!
+//! `UsbHid` is a USB function with a report descriptor chosen by the board,
+//! so it can appear to the host as a keyboard, a mouse, or a vendor-defined
+//! device that a host program talks to through the OS HID layer (e.g. hidraw
+//! or hidapi) without installing a driver. It is added to a
+//! `capsules::usb_composite::CompositeDevice`.
+//!
+//! Input reports are sent on an interrupt IN endpoint, and output reports are
+//! received on an interrupt OUT endpoint or through `SET_REPORT` control
+//! requests. Reports longer than a packet are split across several packets.
+//! Reports on the OUT endpoint wait until an app asks for one, but a
+//! `SET_REPORT` that arrives while no app is waiting is dropped, since the
+//! control endpoint cannot be held up.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let hid = static_init!(
+//!     capsules::hid::UsbHid<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::hid::UsbHid::new(
+//!         &sam4l::usbc::USBC,
+//!         capsules::hid::HidConfig {
+//!             subclass: capsules::hid::SUBCLASS_BOOT,
+//!             protocol: capsules::hid::PROTOCOL_KEYBOARD,
+//!             report_descriptor: &capsules::hid::KEYBOARD_REPORT_DESCRIPTOR,
+//!             out_report_len: 1,
+//!         },
+//!         &mut capsules::hid::SEND_BUF,
+//!         &mut capsules::hid::RECV_BUF,
+//!         kernel::Grant::create()));
+//!
+//! let usb_functions = static_init!(
+//!     [&'static capsules::usb_composite::Function; 1],
+//!     [hid]);
+//! let usb_device = static_init!(
+//!     capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_composite::CompositeDevice::new(
+//!         &sam4l::usbc::USBC,
+//!         capsules::usb::DeviceDescriptor {
+//!             product_id: 0xabcf,
+//!             manufacturer_string: 1,
+//!             product_string: 2,
+//!             ..Default::default()
+//!         },
+//!         &["XYZ Corp.", "Tock HID Device"],
+//!         usb_functions));
+//! usb_device.build(&mut capsules::usb_composite::CONFIGURATION_BUF, sam4l::usbc::N_ENDPOINTS - 1);
+//! sam4l::usbc::USBC.set_client(usb_device);
+//! hil::usb::Client::enable(usb_device);
+//! hil::usb::Client::attach(usb_device);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - `allow` 0: buffer holding the input report to send.
+//! - `allow` 1: buffer to receive output reports into.
+//! - `subscribe` 0: called with the report length when a report was sent.
+//! - `subscribe` 1: called with the report length when a report was received.
+//! - `command` 0: check whether the driver exists.
+//! - `command` 1: send the first `arg1` bytes of the allowed buffer as an
+//!   input report.
+//! - `command` 2: receive the next output report from the host. Until some
+//!   app asks for a report, the device refuses (NAKs) output reports.
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::VolatileCell;
+use kernel::hil::usb::*;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use usb::*;
+use usb_composite::{Function, SetupResult};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x20006;
+
+pub static mut SEND_BUF: [u8; 64] = [0; 64];
+pub static mut RECV_BUF: [u8; 64] = [0; 64];
+
+const MAX_PACKET_SIZE: usize = 8;
+
+const CLASS_HID: u8 = 0x03;
+
+/// Interface subclass for devices that support the simplified boot protocol
+pub const SUBCLASS_NONE: u8 = 0;
+pub const SUBCLASS_BOOT: u8 = 1;
+
+/// Interface protocol for boot devices
+pub const PROTOCOL_NONE: u8 = 0;
+pub const PROTOCOL_KEYBOARD: u8 = 1;
+pub const PROTOCOL_MOUSE: u8 = 2;
+
+// Class descriptor types
+const DESCRIPTOR_HID: u8 = 0x21;
+const DESCRIPTOR_REPORT: u8 = 0x22;
+
+// Class-specific requests
+const GET_REPORT: u8 = 0x01;
+const GET_IDLE: u8 = 0x02;
+const GET_PROTOCOL: u8 = 0x03;
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+
+/// Report descriptor of a boot protocol keyboard (HID 1.11 appendix B.1):
+/// 8-byte input reports of modifier keys and up to six key codes, and 1-byte
+/// output reports of LED states.
+pub static KEYBOARD_REPORT_DESCRIPTOR: [u8; 63] = [
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifier byte
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LED report
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): LED report padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): key arrays (6 bytes)
+    0xc0,       // End Collection
+];
+
+/// How the device presents itself to the host.
+#[derive(Copy, Clone)]
+pub struct HidConfig {
+    /// `SUBCLASS_BOOT` if the report descriptor follows the boot protocol
+    pub subclass: u8,
+
+    /// `PROTOCOL_KEYBOARD` or `PROTOCOL_MOUSE` for boot devices
+    pub protocol: u8,
+
+    /// Describes the format of every report
+    pub report_descriptor: &'static [u8],
+
+    /// Length of an output report. An output report ends after this many
+    /// bytes, or with a packet shorter than the maximum packet size.
+    pub out_report_len: usize,
+}
+
+/// The HID class descriptor (HID 1.11 section 6.2.1), which points the host
+/// at a single report descriptor.
+struct HidDescriptor {
+    report_descriptor_length: u16,
+}
+
+impl Descriptor for HidDescriptor {
+    fn size(&self) -> usize {
+        9
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(9); // Size of descriptor
+        buf[1].set(DESCRIPTOR_HID);
+        buf[2].set(0x11); // HID 1.11
+        buf[3].set(0x01);
+        buf[4].set(0); // Not localized
+        buf[5].set(1); // One class descriptor follows
+        buf[6].set(DESCRIPTOR_REPORT);
+        buf[7].set((self.report_descriptor_length & 0xff) as u8);
+        buf[8].set((self.report_descriptor_length >> 8) as u8);
+        9
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    send_callback: Option<Callback>,
+    recv_callback: Option<Callback>,
+    send_buffer: Option<AppSlice<Shared, u8>>,
+    recv_buffer: Option<AppSlice<Shared, u8>>,
+    /// Length of an input report waiting to be sent
+    pending_send: Option<usize>,
+    /// Whether the app wants the next output report
+    pending_recv: bool,
+}
+
+pub struct UsbHid<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    config: HidConfig,
+
+    // Interface and endpoint numbers assigned when the configuration was
+    // built
+    interface: Cell<u8>,
+    endpoint_in: Cell<usize>,
+    endpoint_out: Cell<usize>,
+
+    // An eight-byte buffer for each endpoint
+    in_buffer: [VolatileCell<u8>; 8],
+    out_buffer: [VolatileCell<u8>; 8],
+
+    idle_rate: Cell<u8>,
+    report_protocol: Cell<bool>,
+
+    // The input report being sent, or the last one sent
+    send_buffer: TakeCell<'static, [u8]>,
+    send_len: Cell<usize>,
+    send_offset: Cell<usize>,
+    sending_app: Cell<Option<AppId>>,
+
+    // The output report being received
+    recv_buffer: TakeCell<'static, [u8]>,
+    recv_len: Cell<usize>,
+
+    delayed_in: Cell<bool>,
+    delayed_out: Cell<bool>,
+
+    apps: Grant<App>,
+}
+
+impl<'a, C: UsbController> UsbHid<'a, C> {
+    pub fn new(
+        controller: &'a C,
+        config: HidConfig,
+        send_buffer: &'static mut [u8],
+        recv_buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> Self {
+        UsbHid {
+            controller: controller,
+            config: config,
+            interface: Cell::new(0),
+            endpoint_in: Cell::new(0),
+            endpoint_out: Cell::new(0),
+            in_buffer: Default::default(),
+            out_buffer: Default::default(),
+            idle_rate: Cell::new(0),
+            report_protocol: Cell::new(true),
+            send_buffer: TakeCell::new(send_buffer),
+            send_len: Cell::new(0),
+            send_offset: Cell::new(0),
+            sending_app: Cell::new(None),
+            recv_buffer: TakeCell::new(recv_buffer),
+            recv_len: Cell::new(0),
+            delayed_in: Cell::new(false),
+            delayed_out: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    /// Handle a GET_DESCRIPTOR request for one of the class descriptors,
+    /// which are addressed to the interface.
+    fn class_descriptor_request(
+        &self,
+        setup_data: &SetupData,
+        response: &[Cell<u8>],
+    ) -> SetupResult {
+        match (setup_data.value >> 8) as u8 {
+            DESCRIPTOR_HID => {
+                let d = HidDescriptor {
+                    report_descriptor_length: self.config.report_descriptor.len() as u16,
+                };
+                SetupResult::In(d.write_to(response))
+            }
+            DESCRIPTOR_REPORT => SetupResult::InStatic(self.config.report_descriptor),
+            _ => SetupResult::Error(CtrlSetupResult::ErrUnrecognizedDescriptorType),
+        }
+    }
+
+    /// Handle a class-specific request to the interface.
+    fn class_request(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult {
+        match setup_data.request_code {
+            GET_REPORT => {
+                let len = min(self.send_len.get(), response.len());
+                self.send_buffer.map(|report| {
+                    for i in 0..len {
+                        response[i].set(report[i]);
+                    }
+                });
+                SetupResult::In(len)
+            }
+            SET_REPORT => {
+                self.recv_len.set(0);
+                SetupResult::Ok
+            }
+            GET_IDLE => {
+                response[0].set(self.idle_rate.get());
+                SetupResult::In(1)
+            }
+            SET_IDLE => {
+                // Reports are only sent when an app asks, so the idle rate
+                // has no effect
+                self.idle_rate.set((setup_data.value >> 8) as u8);
+                SetupResult::Ok
+            }
+            GET_PROTOCOL => {
+                response[0].set(self.report_protocol.get() as u8);
+                SetupResult::In(1)
+            }
+            SET_PROTOCOL => {
+                self.report_protocol.set(setup_data.value != 0);
+                SetupResult::Ok
+            }
+            _ => SetupResult::Error(CtrlSetupResult::ErrUnrecognizedRequestType),
+        }
+    }
+
+    /// If no report is being sent, copy the next app's input report into the
+    /// send buffer and ask the controller to send it.
+    fn send_next(&self) {
+        if self.sending_app.get().is_some() {
+            return;
+        }
+        for cntr in self.apps.iter() {
+            let started = cntr.enter(|app, _| {
+                app.pending_send.take().map_or(false, |len| {
+                    let copied = app.send_buffer.as_ref().and_then(|src| {
+                        self.send_buffer.map(|dest| {
+                            let len = min(len, min(src.len(), dest.len()));
+                            dest[..len].copy_from_slice(&src.as_ref()[..len]);
+                            len
+                        })
+                    });
+                    match copied {
+                        Some(len) => {
+                            self.send_len.set(len);
+                            self.send_offset.set(0);
+                            self.sending_app.set(Some(app.appid()));
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            });
+            if started {
+                if self.delayed_in.take() {
+                    self.controller.endpoint_bulk_resume(self.endpoint_in.get());
+                }
+                break;
+            }
+        }
+    }
+
+    /// Whether some app is waiting for an output report.
+    fn recv_wanted(&self) -> bool {
+        self.apps
+            .iter()
+            .any(|cntr| cntr.enter(|app, _| app.pending_recv))
+    }
+
+    /// Append part of an output report to the receive buffer. Once
+    /// `total_len` bytes have arrived, or `last` is set, the report is given
+    /// to the first app that is waiting for one.
+    fn receive_packet(&self, packet: &[VolatileCell<u8>], total_len: usize, last: bool) {
+        let done = self.recv_buffer.map_or(false, |buf| {
+            let offset = self.recv_len.get();
+            let count = min(packet.len(), buf.len().saturating_sub(offset));
+            for i in 0..count {
+                buf[offset + i] = packet[i].get();
+            }
+            self.recv_len.set(offset + count);
+            last || offset + count >= min(total_len, buf.len())
+        });
+        if !done {
+            return;
+        }
+
+        let len = self.recv_len.get();
+        self.recv_len.set(0);
+        self.recv_buffer.map(|buf| {
+            for cntr in self.apps.iter() {
+                let delivered = cntr.enter(|app, _| {
+                    if !app.pending_recv {
+                        return false;
+                    }
+                    app.pending_recv = false;
+                    let copied = app.recv_buffer.as_mut().map_or(0, |dest| {
+                        let copy_len = min(len, dest.len());
+                        dest.as_mut()[..copy_len].copy_from_slice(&buf[..copy_len]);
+                        copy_len
+                    });
+                    app.recv_callback.map(|mut cb| cb.schedule(copied, 0, 0));
+                    true
+                });
+                if delivered {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl<'a, C: UsbController> Function for UsbHid<'a, C> {
+    fn add_descriptors(&self, builder: &mut ConfigurationBuilder) {
+        self.interface.set(builder.add_interface(InterfaceDescriptor {
+            num_endpoints: 2,
+            interface_class: CLASS_HID,
+            interface_subclass: self.config.subclass,
+            interface_protocol: self.config.protocol,
+            ..Default::default()
+        }));
+        builder.add(&HidDescriptor {
+            report_descriptor_length: self.config.report_descriptor.len() as u16,
+        });
+        builder
+            .add_endpoint(
+                TransferDirection::DeviceToHost,
+                TransferType::Interrupt,
+                MAX_PACKET_SIZE as u16,
+                10,
+            )
+            .map(|endpoint| self.endpoint_in.set(endpoint));
+        builder
+            .add_endpoint(
+                TransferDirection::HostToDevice,
+                TransferType::Interrupt,
+                MAX_PACKET_SIZE as u16,
+                10,
+            )
+            .map(|endpoint| self.endpoint_out.set(endpoint));
+    }
+
+    fn enable_endpoints(&self) {
+        self.controller.endpoint_set_buffer(self.endpoint_in.get(), &self.in_buffer);
+        self.controller.endpoint_in_enable(
+            TransferType::Interrupt,
+            self.endpoint_in.get(),
+            MAX_PACKET_SIZE,
+        );
+
+        self.controller.endpoint_set_buffer(self.endpoint_out.get(), &self.out_buffer);
+        self.controller.endpoint_out_enable(
+            TransferType::Interrupt,
+            self.endpoint_out.get(),
+            MAX_PACKET_SIZE,
+        );
+    }
+
+    fn bus_reset(&self) {
+        self.idle_rate.set(0);
+        self.report_protocol.set(true);
+        self.recv_len.set(0);
+        self.delayed_in.set(false);
+        self.delayed_out.set(false);
+    }
+
+    fn ctrl_setup(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult {
+        if setup_data.index & 0xff != self.interface.get() as u16 {
+            return SetupResult::Error(CtrlSetupResult::ErrInvalidDeviceIndex);
+        }
+        match setup_data.request_type.request_type() {
+            RequestType::Standard if setup_data.request_code == 6 => {
+                // GET_DESCRIPTOR for a descriptor type that is defined by
+                // the class
+                self.class_descriptor_request(setup_data, response)
+            }
+            RequestType::Class => self.class_request(setup_data, response),
+            _ => SetupResult::Error(CtrlSetupResult::ErrNonstandardRequest),
+        }
+    }
+
+    fn ctrl_out(&self, setup_data: &SetupData, packet: &[VolatileCell<u8>]) -> CtrlOutResult {
+        if setup_data.request_code != SET_REPORT {
+            return CtrlOutResult::Halted;
+        }
+        // Control transfers cannot be resumed once delayed, so a report that
+        // no app is waiting for is accepted and dropped
+        self.receive_packet(packet, setup_data.length as usize, false);
+        CtrlOutResult::Ok
+    }
+
+    /// Handle an Interrupt IN transaction
+    fn bulk_in(&self, endpoint: usize) -> BulkInResult {
+        if endpoint != self.endpoint_in.get() {
+            return BulkInResult::Error;
+        }
+
+        let appid = match self.sending_app.get() {
+            Some(appid) => appid,
+            None => {
+                // Nothing to send
+                self.delayed_in.set(true);
+                return BulkInResult::Delay;
+            }
+        };
+
+        let offset = self.send_offset.get();
+        let len = self.send_len.get();
+        let packet_bytes = min(MAX_PACKET_SIZE, len - offset);
+        self.send_buffer.map(|report| {
+            let packet = &self.in_buffer;
+            for i in 0..packet_bytes {
+                packet[i].set(report[offset + i]);
+            }
+        });
+        self.send_offset.set(offset + packet_bytes);
+
+        if offset + packet_bytes >= len {
+            self.sending_app.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                app.send_callback.map(|mut cb| cb.schedule(len, 0, 0));
+            });
+            self.send_next();
+        }
+        BulkInResult::Packet(packet_bytes)
+    }
+
+    /// Handle an Interrupt OUT transaction
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> BulkOutResult {
+        if endpoint != self.endpoint_out.get() {
+            return BulkOutResult::Error;
+        }
+
+        if !self.recv_wanted() {
+            // No app wants a report yet; hold the packet until one does
+            self.delayed_out.set(true);
+            return BulkOutResult::Delay;
+        }
+
+        let packet_bytes = min(packet_bytes as usize, MAX_PACKET_SIZE);
+        let packet = &self.out_buffer[..packet_bytes];
+        self.receive_packet(
+            packet,
+            self.config.out_report_len,
+            packet_bytes < MAX_PACKET_SIZE,
+        );
+        BulkOutResult::Ok
+    }
+}
+
+impl<'a, C: UsbController> Driver for UsbHid<'a, C> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            // Input report to send
+            0 => self.apps
+                .enter(appid, |app, _| {
+                    app.send_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // Buffer for received output reports
+            1 => self.apps
+                .enter(appid, |app, _| {
+                    app.recv_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            // Report sent
+            0 => self.apps
+                .enter(app_id, |app, _| {
+                    app.send_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // Report received
+            1 => self.apps
+                .enter(app_id, |app, _| {
+                    app.recv_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            // This driver is present
+            0 => ReturnCode::SUCCESS,
+
+            // Send an input report
+            1 => {
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        if app.pending_send.is_some() || self.sending_app.get() == Some(appid) {
+                            ReturnCode::EBUSY
+                        } else if app.send_buffer
+                            .as_ref()
+                            .map_or(true, |buf| arg1 == 0 || buf.len() < arg1)
+                        {
+                            ReturnCode::EINVAL
+                        } else {
+                            app.pending_send = Some(arg1);
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS {
+                    self.send_next();
+                }
+                result
+            }
+
+            // Receive an output report
+            2 => {
+                let result = self.apps
+                    .enter(appid, |app, _| {
+                        if app.pending_recv {
+                            ReturnCode::EBUSY
+                        } else if app.recv_buffer.is_none() {
+                            ReturnCode::EINVAL
+                        } else {
+                            app.pending_recv = true;
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if result == ReturnCode::SUCCESS && self.delayed_out.take() {
+                    self.controller.endpoint_bulk_resume(self.endpoint_out.get());
+                }
+                result
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -13,7 +13,7 @@
 
 use ieee802154::framer::Frame;
 use kernel::ReturnCode;
-use net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use net::ieee802154::{Header, KeyId, MacAddress, MacCommand, PanID, SecurityLevel};
 
 pub trait MacDevice<'a> {
     /// Sets the transmission client of this MAC device
@@ -69,6 +69,20 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares a MAC command frame, like `prepare_data_frame`. The payload
+    /// already holds the `command` identifier, and the command content is
+    /// appended to it.
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command: MacCommand,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
@@ -166,8 +166,9 @@ impl FrameInfo {
                 unimplemented!()
             }
             FrameType::MACCommand => {
-                // Beginning of MAC command content field
-                unimplemented!()
+                // Beginning of MAC command content field, after the command
+                // identifier
+                self.data_offset + 1
             }
             _ => {
                 // MAC payload field, which includes payload IEs
@@ -186,7 +187,7 @@ impl FrameInfo {
             // m data is the private payload field
             (
                 private_payload_offset,
-                self.unsecured_length() | private_payload_offset,
+                self.unsecured_length() - private_payload_offset,
             )
         }
     }
@@ -338,6 +339,86 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> Framer<'a, M, A> {
 
     /// Look up the key using the IEEE 802.15.4 KeyDescriptor lookup prodecure
     /// implemented elsewhere.
+    /// Prepares a frame of type `frame_type`, as in
+    /// `MacDevice::prepare_data_frame`.
+    fn prepare_frame(
+        &self,
+        frame_type: FrameType,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
+        // Steps a-e of the security procedure are implemented here.
+
+        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
+        // address should instead be some constant defined in their
+        // specification.
+        let src_addr_long = self.get_address_long();
+        let security_desc = security_needed.and_then(|(level, key_id)| {
+            self.lookup_key(level, key_id).map(|key| {
+                // TODO: lookup frame counter for device
+                let frame_counter = 0;
+                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
+                (
+                    Security {
+                        level: level,
+                        asn_in_nonce: false,
+                        frame_counter: Some(frame_counter),
+                        key_id: key_id,
+                    },
+                    key,
+                    nonce,
+                )
+            })
+        });
+        if security_needed.is_some() && security_desc.is_none() {
+            // If security was requested, fail when desired key was not found.
+            return Err(buf);
+        }
+
+        // Construct MAC header
+        let security = security_desc.map(|(sec, _, _)| sec);
+        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
+        let header = Header {
+            frame_type: frame_type,
+            /* TODO: determine this by looking at queue, and also set it in
+             * hardware so that ACKs set this flag to the right value. */
+            frame_pending: false,
+            // Unicast frames request acknowledgement
+            ack_requested: true,
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: Some(dst_pan),
+            dst_addr: Some(dst_addr),
+            src_pan: Some(src_pan),
+            src_addr: Some(src_addr),
+            security: security,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => Ok(Frame {
+                buf: buf,
+                info: FrameInfo {
+                    frame_type: frame_type,
+                    mac_payload_offset: mac_payload_offset,
+                    data_offset: data_offset,
+                    data_len: 0,
+                    mic_len: mic_len,
+                    security_params: security_desc.map(|(sec, key, nonce)| (sec.level, key, nonce)),
+                },
+            }),
+            None => Err(buf),
+        }
+    }
+
     fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<([u8; 16])> {
         self.key_procedure
             .get()
@@ -694,71 +775,39 @@ impl<'a, M: Mac + 'a, A: AES128CCM<'a> + 'a> MacDevice<'a> for Framer<'a, M, A>
         src_addr: MacAddress,
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]> {
-        // IEEE 802.15.4-2015: 9.2.1, outgoing frame security
-        // Steps a-e of the security procedure are implemented here.
-
-        // TODO: For Thread, in the case of `KeyIdMode::Source4Index`, the source
-        // address should instead be some constant defined in their
-        // specification.
-        let src_addr_long = self.get_address_long();
-        let security_desc = security_needed.and_then(|(level, key_id)| {
-            self.lookup_key(level, key_id).map(|key| {
-                // TODO: lookup frame counter for device
-                let frame_counter = 0;
-                let nonce = get_ccm_nonce(&src_addr_long, frame_counter, level);
-                (
-                    Security {
-                        level: level,
-                        asn_in_nonce: false,
-                        frame_counter: Some(frame_counter),
-                        key_id: key_id,
-                    },
-                    key,
-                    nonce,
-                )
-            })
-        });
-        if security_needed.is_some() && security_desc.is_none() {
-            // If security was requested, fail when desired key was not found.
-            return Err(buf);
-        }
-
-        // Construct MAC header
-        let security = security_desc.map(|(sec, _, _)| sec);
-        let mic_len = security.map_or(0, |sec| sec.level.mic_len());
-        let header = Header {
-            frame_type: FrameType::Data,
-            /* TODO: determine this by looking at queue, and also set it in
-             * hardware so that ACKs set this flag to the right value. */
-            frame_pending: false,
-            // Unicast data frames request acknowledgement
-            ack_requested: true,
-            version: FrameVersion::V2006,
-            seq: Some(self.data_sequence.get()),
-            dst_pan: Some(dst_pan),
-            dst_addr: Some(dst_addr),
-            src_pan: Some(src_pan),
-            src_addr: Some(src_addr),
-            security: security,
-            header_ies: Default::default(),
-            header_ies_len: 0,
-            payload_ies: Default::default(),
-            payload_ies_len: 0,
-        };
-
-        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
-            Some((data_offset, mac_payload_offset)) => Ok(Frame {
-                buf: buf,
-                info: FrameInfo {
-                    frame_type: FrameType::Data,
-                    mac_payload_offset: mac_payload_offset,
-                    data_offset: data_offset,
-                    data_len: 0,
-                    mic_len: mic_len,
-                    security_params: security_desc.map(|(sec, key, nonce)| (sec.level, key, nonce)),
-                },
-            }),
-            None => Err(buf),
+        self.prepare_frame(
+            FrameType::Data,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )
+    }
+
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command: MacCommand,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let mut frame = self.prepare_frame(
+            FrameType::MACCommand,
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            security_needed,
+        )?;
+        match frame.append_payload(&[command as u8]) {
+            ReturnCode::SUCCESS => Ok(frame),
+            _ => Err(frame.into_buf()),
         }
     }
 
@@ -279,6 +279,27 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_command_frame(
+        &self,
+        buf: &'static mut [u8],
+        dst_pan: PanID,
+        dst_addr: MacAddress,
+        src_pan: PanID,
+        src_addr: MacAddress,
+        command: MacCommand,
+        security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux.mac.prepare_command_frame(
+            buf,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            command,
+            security_needed,
+        )
+    }
+
     fn transmit(&self, frame: framer::Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         // If the muxer is idle, immediately transmit the frame, otherwise
         // attempt to queue the transmission request. However, each MAC user can
@@ -30,7 +30,10 @@
 //!
 //! In general, given a radio driver `RF233Device`,
 //! a `kernel::hil::time::Alarm`, and a `kernel::hil::rng::RNG` device, the
-//! necessary modifications to the board configuration are shown below for `imix`s:
+//! necessary modifications to the board configuration are shown below for `imix`s.
+//! A `capsules::virtual_rng::VirtualRng` on top of a `capsules::csprng::Csprng`
+//! makes a good `RNG` here, as backoffs then don't wait on the hardware TRNG
+//! and the generator can be shared with other users:
 //!
 //! ```rust
 //! // main.rs
@@ -45,8 +48,12 @@
 //! //      radio for transmitting preamble packets
 //! static mut MAC_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 //! // ...
-//! let xmac: &XMacDevice = static_init!(XMacDevice, xmac::XMac::new(rf233, alarm, rng));
-//! rng.set_client(xmac);
+//! let xmac_rng = static_init!(
+//!     capsules::virtual_rng::VirtualRng<'static>,
+//!     capsules::virtual_rng::VirtualRng::new(mux_rng));
+//! mux_rng.add_user(xmac_rng);
+//! let xmac: &XMacDevice = static_init!(XMacDevice, xmac::XMac::new(rf233, alarm, xmac_rng));
+//! xmac_rng.set_client(xmac);
 //! alarm.set_client(xmac);
 //!
 //! // Hook up the radio to the XMAC implementation.
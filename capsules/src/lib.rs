@@ -9,18 +9,25 @@ extern crate kernel;
 pub mod test;
 
 pub mod adc;
+pub mod aes;
 pub mod alarm;
 pub mod ambient_light;
 pub mod app_flash_driver;
 pub mod ble_advertising_driver;
 pub mod button;
+pub mod cdc;
 pub mod console;
 pub mod crc;
+pub mod csprng;
 pub mod dac;
+pub mod dfu;
+pub mod digest;
+pub mod flash_wear;
 pub mod fm25cl;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
+pub mod hid;
 pub mod i2c_master_slave_driver;
 pub mod isl29035;
 pub mod led;
@@ -33,24 +40,33 @@ pub mod nonvolatile_storage_driver;
 pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod pca9544a;
+pub mod pwm;
+pub mod ramdisk;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod sdcard;
+pub mod sha256;
 pub mod si7021;
 pub mod spi;
+pub mod sw_crc;
 pub mod tmp006;
 pub mod tsl2561;
 pub mod usb;
+pub mod usb_composite;
 pub mod usb_user;
 pub mod usbc_client;
+pub mod virtual_aes;
 pub mod virtual_alarm;
 pub mod virtual_flash;
 pub mod virtual_i2c;
+pub mod virtual_rng;
 pub mod virtual_spi;
+pub mod watchdog;
 #[macro_use]
 pub mod net;
 pub mod aes_ccm;
+pub mod aes_gcm;
 pub mod humidity;
 pub mod ieee802154;
 pub mod temperature;
@@ -0,0 +1,403 @@
+//! This file contains the encoding and decoding of CoAP messages, as defined
+//! in Section 3 of RFC 7252, and of the Block1 and Block2 options of
+//! block-wise transfers (RFC 7959).
+//!
+//! A message is a 4-byte header, a token of up to 8 bytes, a sequence of
+//! options and an optional payload after the payload marker. Each option is
+//! encoded as the difference between its number and that of the option
+//! before it, so options must be encoded in increasing order:
+//!
+//! ```rust
+//! let header = CoAPHeader::new(MessageType::Confirmable, code::GET, 0x1234, &[0xab]);
+//! let mut off = enc_consume!(buf; header; encode);
+//! off = enc_consume!(buf, off; encode_option, 0, option::URI_PATH, b"fw");
+//! off = enc_consume!(buf, off; encode_uint_option, option::URI_PATH, option::BLOCK2,
+//!                    BlockOption::new(0, false, 6).get_value());
+//! off = enc_consume!(buf, off; encode_payload, &[]);
+//! ```
+
+use net::stream::SResult;
+use net::stream::{decode_u16, decode_u8};
+use net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// The UDP port of CoAP
+pub const COAP_PORT: u16 = 5683;
+
+/// The longest token
+pub const MAX_TOKEN_LEN: usize = 8;
+
+const VERSION: u8 = 1;
+const HDR_SIZE: usize = 4;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+/// Method and response codes, encoded as `class << 5 | detail` (Section 12.1)
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const REQUEST_URI_TOO_LONG: u8 = 0x8e;
+
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    /// Returns whether `code` is a method
+    pub fn is_request(code: u8) -> bool {
+        code != EMPTY && code >> 5 == 0
+    }
+
+    /// Returns whether `code` is a response code, of class 2, 4 or 5
+    pub fn is_response(code: u8) -> bool {
+        match code >> 5 {
+            2 | 4 | 5 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Option numbers (Section 12.2, and Section 6 of RFC 7959)
+pub mod option {
+    pub const URI_HOST: u16 = 3;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+
+    /// Returns whether an option must be understood by the receiver of the
+    /// message, which rejects the message otherwise (Section 5.4.1)
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 != 0
+    }
+}
+
+/// The header of a CoAP message, followed by its token.
+#[derive(Copy, Clone)]
+pub struct CoAPHeader {
+    pub msg_type: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    token_len: u8,
+    token: [u8; MAX_TOKEN_LEN],
+}
+
+impl CoAPHeader {
+    /// Creates a header. Tokens longer than `MAX_TOKEN_LEN` are truncated.
+    pub fn new(msg_type: MessageType, code: u8, message_id: u16, token: &[u8]) -> CoAPHeader {
+        let token_len = if token.len() < MAX_TOKEN_LEN {
+            token.len()
+        } else {
+            MAX_TOKEN_LEN
+        };
+        let mut header = CoAPHeader {
+            msg_type: msg_type,
+            code: code,
+            message_id: message_id,
+            token_len: token_len as u8,
+            token: [0; MAX_TOKEN_LEN],
+        };
+        header.token[..token_len].copy_from_slice(&token[..token_len]);
+        header
+    }
+
+    pub fn get_token(&self) -> &[u8] {
+        &self.token[..self.token_len as usize]
+    }
+
+    /// Returns the size of the header, token included
+    pub fn get_hdr_size(&self) -> usize {
+        HDR_SIZE + self.token_len as usize
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        stream_len_cond!(buf, self.get_hdr_size());
+        let first = VERSION << 6 | (self.msg_type as u8) << 4 | self.token_len;
+        let off = enc_consume!(buf; encode_u8, first);
+        let off = enc_consume!(buf, off; encode_u8, self.code);
+        let off = enc_consume!(buf, off; encode_u16, self.message_id);
+        let off = enc_consume!(buf, off; encode_bytes, self.get_token());
+        stream_done!(off);
+    }
+
+    /// Decodes the header and token of a message. Messages of another
+    /// version, and tokens longer than `MAX_TOKEN_LEN`, are errors.
+    pub fn decode(buf: &[u8]) -> SResult<CoAPHeader> {
+        stream_len_cond!(buf, HDR_SIZE);
+        let (off, first) = dec_try!(buf; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        stream_cond!(first >> 6 == VERSION);
+        let msg_type = match (first >> 4) & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        };
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(token_len <= MAX_TOKEN_LEN);
+        stream_len_cond!(buf, off + token_len);
+        let header = CoAPHeader::new(msg_type, code, message_id, &buf[off..off + token_len]);
+        stream_done!(off + token_len, header);
+    }
+}
+
+/// Returns the 4-bit field and the extended bytes that encode the option
+/// delta or length `value`.
+fn split_ext(value: u16) -> (u8, usize, u16) {
+    if value < 13 {
+        (value as u8, 0, 0)
+    } else if value < 269 {
+        (13, 1, value - 13)
+    } else {
+        (14, 2, value - 269)
+    }
+}
+
+fn encode_ext(buf: &mut [u8], ext_len: usize, ext: u16) -> SResult {
+    match ext_len {
+        0 => {
+            stream_done!(0);
+        }
+        1 => encode_u8(buf, ext as u8),
+        _ => encode_u16(buf, ext),
+    }
+}
+
+fn decode_ext(buf: &[u8], nibble: u8) -> SResult<u16> {
+    match nibble {
+        13 => {
+            let (off, ext) = dec_try!(buf; decode_u8);
+            stream_done!(off, ext as u16 + 13);
+        }
+        14 => {
+            let (off, ext) = dec_try!(buf; decode_u16);
+            stream_cond!(ext <= u16::max_value() - 269);
+            stream_done!(off, ext + 269);
+        }
+        // 15 is reserved for the payload marker
+        15 => {
+            stream_err!();
+        }
+        _ => {
+            stream_done!(0, nibble as u16);
+        }
+    }
+}
+
+/// Encodes the option numbered `number`, after an option numbered `prev`,
+/// or 0 for the first option of a message.
+pub fn encode_option(buf: &mut [u8], prev: u16, number: u16, value: &[u8]) -> SResult {
+    stream_cond!(number >= prev && value.len() <= u16::max_value() as usize);
+    let (delta, delta_len, delta_ext) = split_ext(number - prev);
+    let (len, len_len, len_ext) = split_ext(value.len() as u16);
+    stream_len_cond!(buf, 1 + delta_len + len_len + value.len());
+    let off = enc_consume!(buf; encode_u8, delta << 4 | len);
+    let off = enc_consume!(buf, off; encode_ext, delta_len, delta_ext);
+    let off = enc_consume!(buf, off; encode_ext, len_len, len_ext);
+    let off = enc_consume!(buf, off; encode_bytes, value);
+    stream_done!(off);
+}
+
+/// Encodes an option whose value is an unsigned integer, in as few bytes as
+/// the value needs (Section 3.2).
+pub fn encode_uint_option(buf: &mut [u8], prev: u16, number: u16, value: u32) -> SResult {
+    let bytes = [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ];
+    let len = (32 - value.leading_zeros() as usize + 7) / 8;
+    encode_option(buf, prev, number, &bytes[4 - len..])
+}
+
+/// Encodes the payload marker and `payload`, or nothing if `payload` is
+/// empty.
+pub fn encode_payload(buf: &mut [u8], payload: &[u8]) -> SResult {
+    if payload.is_empty() {
+        stream_done!(0);
+    }
+    let off = enc_consume!(buf; encode_u8, PAYLOAD_MARKER);
+    let off = enc_consume!(buf, off; encode_bytes, payload);
+    stream_done!(off);
+}
+
+/// Decodes the value of an unsigned integer option.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, byte| acc << 8 | *byte as u32))
+}
+
+/// Decodes the option after an option numbered `prev`, as its number and
+/// value.
+fn decode_option<'b>(buf: &'b [u8], prev: u16) -> SResult<(u16, &'b [u8])> {
+    let (off, first) = dec_try!(buf; decode_u8);
+    let (off, delta) = dec_try!(buf, off; decode_ext, first >> 4);
+    let (off, len) = dec_try!(buf, off; decode_ext, first & 0xf);
+    let number = stream_from_option!(prev.checked_add(delta));
+    let end = off + len as usize;
+    stream_len_cond!(buf, end);
+    stream_done!(end, (number, &buf[off..end]));
+}
+
+/// Iterates over the options of a decoded message, as pairs of option
+/// number and value.
+pub struct Options<'b> {
+    buf: &'b [u8],
+    number: u16,
+}
+
+impl<'b> Iterator for Options<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'b [u8])> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        // The options were checked when the message was decoded
+        let (off, (number, value)) = decode_option(self.buf, self.number).done()?;
+        self.buf = &self.buf[off..];
+        self.number = number;
+        Some((number, value))
+    }
+}
+
+/// A decoded message, which borrows its options and payload from the
+/// buffer it was decoded from.
+pub struct CoAPMessage<'b> {
+    pub header: CoAPHeader,
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl<'b> CoAPMessage<'b> {
+    /// Decodes a message, and checks that its options are well formed.
+    /// Empty messages must have nothing after the header (Section 4.1).
+    pub fn decode(buf: &'b [u8]) -> SResult<CoAPMessage<'b>> {
+        let (off, header) = dec_try!(buf; CoAPHeader::decode);
+        if header.code == code::EMPTY {
+            stream_cond!(off == buf.len() && header.token_len == 0);
+        }
+
+        let mut end = off;
+        let mut number = 0;
+        let mut payload: &[u8] = &[];
+        while end < buf.len() {
+            if buf[end] == PAYLOAD_MARKER {
+                payload = &buf[end + 1..];
+                // A marker without payload is a format error
+                stream_cond!(!payload.is_empty());
+                break;
+            }
+            let (len, (next, _)) = dec_try!(buf, end; decode_option, number);
+            number = next;
+            end = len;
+        }
+        stream_done!(
+            buf.len(),
+            CoAPMessage {
+                header: header,
+                options: &buf[off..end],
+                payload: payload,
+            }
+        );
+    }
+
+    pub fn options(&self) -> Options<'b> {
+        Options {
+            buf: self.options,
+            number: 0,
+        }
+    }
+
+    /// Returns the value of the first option numbered `number`.
+    pub fn get_option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options()
+            .find(|&(n, _)| n == number)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the Block1 or Block2 option, or `None` if the message has
+    /// none or it is malformed.
+    pub fn get_block(&self, number: u16) -> Option<BlockOption> {
+        self.get_option(number)
+            .and_then(decode_uint)
+            .and_then(BlockOption::from_value)
+    }
+}
+
+/// The value of a Block1 or Block2 option (RFC 7959, Section 2.2): the number
+/// of the block, whether more blocks follow, and the size exponent. Blocks
+/// hold `16 << szx` bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BlockOption {
+    pub num: u32,
+    pub more: bool,
+    pub szx: u8,
+}
+
+/// The largest size exponent, for blocks of 1024 bytes
+pub const MAX_BLOCK_SZX: u8 = 6;
+
+impl BlockOption {
+    pub fn new(num: u32, more: bool, szx: u8) -> BlockOption {
+        BlockOption {
+            num: num,
+            more: more,
+            szx: szx,
+        }
+    }
+
+    /// Decodes the option from its value as an unsigned integer. The block
+    /// number has 20 bits, and the size exponent 7 is reserved.
+    pub fn from_value(value: u32) -> Option<BlockOption> {
+        let szx = (value & 0x7) as u8;
+        if szx > MAX_BLOCK_SZX || value >> 24 != 0 {
+            return None;
+        }
+        Some(BlockOption::new(value >> 4, value & 0x8 != 0, szx))
+    }
+
+    pub fn get_value(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    /// Returns the size of the block, in bytes
+    pub fn get_size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Returns the offset of the block in the whole body, in bytes
+    pub fn get_offset(&self) -> usize {
+        self.num as usize * self.get_size()
+    }
+}
@@ -0,0 +1,912 @@
+//! Implements a CoAP endpoint (RFC 7252) on top of the UDP layer, which acts
+//! both as a client, sending requests to servers, and as a server, answering
+//! the requests of clients on the CoAP port.
+//!
+//! Requests are sent as Confirmable messages, one at a time (`NSTART` is 1).
+//! A request that is not acknowledged is sent again after a timeout picked
+//! at random between `ACK_TIMEOUT` and 1.5 times `ACK_TIMEOUT`, which
+//! doubles after every retransmission, until `MAX_RETRANSMIT`
+//! retransmissions are unanswered (Section 4.2). Responses are matched to the request by their
+//! token, whether they are piggybacked on the acknowledgement or sent
+//! separately. Confirmable separate responses are acknowledged, and
+//! responses that match no request are rejected with a Reset.
+//!
+//! Block-wise transfers (RFC 7959) are only supported on the client side,
+//! for downloads: the first request can ask for a block size, the Block2
+//! option of each response is passed to the
+//! [CoAPClient](trait.CoAPClient.html), and the client asks for the next
+//! block with `next_block` once it has consumed the current one. This is how
+//! large resources, such as firmware images, are downloaded into a small
+//! buffer.
+//!
+//! Requests for the endpoint are passed to the
+//! [CoAPServer](trait.CoAPServer.html), which answers them with `respond`,
+//! either right away or later. The response is piggybacked on the
+//! acknowledgement of a Confirmable request, so the server must answer
+//! within `HANDLER_TIMEOUT`, after which the request is answered with 5.03
+//! (Service Unavailable). Requests that arrive while another one is handled
+//! are answered with 5.03 too. The server does not do block-wise transfers:
+//! requests are always answered with a whole representation, and requests
+//! for blocks other than the first and requests with a Block1 option are
+//! rejected with 4.02 (Bad Option).
+//!
+//! The message IDs of recently received Confirmable and Non-confirmable
+//! messages are remembered for `EXCHANGE_LIFETIME`, so that duplicates are
+//! processed once (Section 4.5). A duplicate of the last request answered is
+//! answered again with the same response, and a duplicate Confirmable
+//! response is acknowledged again.
+//!
+//! Message IDs are sequence numbers, seeded from the clock. Tokens are
+//! random (Section 5.3.1), and are drawn with the retransmission timeouts
+//! from an `RNG`, ahead of the requests that use them. Requests to multicast
+//! addresses are not supported, and are ignored.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coap_endpoint = static_init!(
+//!     CoAPEndpoint<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     CoAPEndpoint::new(coap_udp_send, coap_alarm, coap_rng,
+//!                       &mut COAP_REQUEST_BUF, &mut COAP_RESPONSE_BUF));
+//! coap_udp_send.set_client(coap_endpoint);
+//! coap_alarm.set_client(coap_endpoint);
+//! coap_rng.set_client(coap_endpoint);
+//!
+//! let coap_binding = static_init!(UDPBinding<'static>, UDPBinding::new(COAP_PORT, coap_endpoint));
+//! udp_recv_mux.add_binding(coap_binding);
+//!
+//! coap_endpoint.set_client(coap_driver);
+//! coap_endpoint.set_server(coap_driver);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::rng::{self, RNG};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::coap::coap::{code, option, BlockOption, CoAPHeader, CoAPMessage, MessageType};
+use net::coap::coap::{encode_option, encode_payload, encode_uint_option};
+use net::coap::coap::{COAP_PORT, MAX_BLOCK_SZX};
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::stream::SResult;
+use net::udp::udp::UDPHeader;
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::{UDPSendClient, UDPSender};
+
+/// The initial retransmission timeout, in milliseconds (Section 4.8)
+pub const ACK_TIMEOUT: u32 = 2000;
+/// Retransmissions of a Confirmable request before giving up
+pub const MAX_RETRANSMIT: u8 = 4;
+/// How long message IDs are remembered, in milliseconds
+pub const EXCHANGE_LIFETIME: u32 = 247000;
+/// How long to wait for a separate response once the request is
+/// acknowledged, in milliseconds
+pub const SEPARATE_RESPONSE_TIMEOUT: u32 = 30000;
+/// How long the server has to answer a request, in milliseconds
+pub const HANDLER_TIMEOUT: u32 = 5000;
+
+/// The longest URI path of requests sent or received, with its segments
+/// joined by '/'
+pub const MAX_URI_PATH_LEN: usize = 64;
+
+/// Received messages whose message IDs are remembered
+const DEDUP_ENTRIES: usize = 8;
+/// The length of the tokens of requests
+const TOKEN_LEN: usize = 4;
+/// Random words kept for requests, each of which takes two: one for its
+/// token and one for its retransmission timeout
+const RANDOM_POOL: usize = 8;
+
+/// The client of the requests sent by the endpoint.
+pub trait CoAPClient {
+    /// Called with the response to the request. `block2` is the Block2
+    /// option of the response; while it has more blocks, the client can ask
+    /// for the next one with `next_block`.
+    fn response(&self, code: u8, payload: &[u8], block2: Option<BlockOption>);
+
+    /// Called when the request gets no response: `ENOACK` if the server never
+    /// acknowledged it, `ECANCEL` if the server rejected it with a Reset, and
+    /// `FAIL` if the server acknowledged it but never sent the response.
+    fn request_failed(&self, result: ReturnCode);
+}
+
+/// The server the requests received by the endpoint are passed to.
+pub trait CoAPServer {
+    /// Called with a request for `uri_path`, whose segments are joined by
+    /// '/'. The server answers it with `CoAPEndpoint::respond`, possibly
+    /// before returning.
+    ///
+    /// # Return Value
+    /// `SUCCESS` if the server answers the request, and otherwise the error
+    /// that the endpoint answers it with: `ENOSUPPORT` for 4.04 (Not Found),
+    /// `EINVAL` for 4.05 (Method Not Allowed), `ESIZE` for 4.13 (Request
+    /// Entity Too Large) and `EBUSY` for 5.03 (Service Unavailable).
+    fn request(&self, method: u8, uri_path: &[u8], payload: &[u8]) -> ReturnCode;
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum RequestState {
+    /// Sent and not acknowledged yet. `timeout` is the time until the next
+    /// retransmission, in milliseconds.
+    Unacknowledged { retransmits: u8, timeout: u32 },
+    /// Acknowledged with an empty message, so the response comes separately
+    Acknowledged,
+    /// Answered or given up on
+    Done,
+}
+
+/// The request sent by the client, which is kept once it is done so that
+/// the next block can be asked for.
+#[derive(Copy, Clone)]
+struct Request {
+    dst: IPAddr,
+    dst_port: u16,
+    method: u8,
+    uri_path: [u8; MAX_URI_PATH_LEN],
+    uri_path_len: usize,
+    /// The Block2 option sent, if any
+    block2: Option<BlockOption>,
+    message_id: u16,
+    token: [u8; TOKEN_LEN],
+    /// The length of the encoded request in `request_buf`
+    len: usize,
+    state: RequestState,
+    /// The Block2 option of the response
+    response_block2: Option<BlockOption>,
+}
+
+impl Request {
+    fn get_uri_path(&self) -> &[u8] {
+        &self.uri_path[..self.uri_path_len]
+    }
+
+    fn is_from(&self, src: IPAddr, src_port: u16) -> bool {
+        self.dst.0 == src.0 && self.dst_port == src_port
+    }
+}
+
+/// A request received by the server, which is being answered.
+#[derive(Copy, Clone)]
+struct Exchange {
+    src: IPAddr,
+    src_port: u16,
+    header: CoAPHeader,
+}
+
+/// The last response sent by the server, which is held in `response_buf`.
+#[derive(Copy, Clone)]
+struct Response {
+    dst: IPAddr,
+    dst_port: u16,
+    /// The message ID of the request, if it was Confirmable and the response
+    /// is sent again for its duplicates
+    request_id: Option<u16>,
+    len: usize,
+}
+
+/// A recently received message.
+#[derive(Copy, Clone)]
+struct Recent {
+    src: IPAddr,
+    src_port: u16,
+    message_id: u16,
+    time: u32,
+}
+
+pub struct CoAPEndpoint<'a, A: Alarm + 'a> {
+    udp_sender: &'a UDPSender<'a>,
+    alarm: &'a A,
+    rng: &'a RNG,
+    client: Cell<Option<&'a CoAPClient>>,
+    server: Cell<Option<&'a CoAPServer>>,
+    next_message_id: Cell<u16>,
+    /// Random words for requests, the first `random_count` of which are
+    /// unused
+    random: Cell<[u32; RANDOM_POOL]>,
+    random_count: Cell<usize>,
+
+    /// Holds the request sent, for retransmissions
+    request_buf: TakeCell<'static, [u8]>,
+    request: Cell<Option<Request>>,
+    request_deadline: Cell<Option<u32>>,
+
+    /// Holds the last response sent, for duplicate requests
+    response_buf: TakeCell<'static, [u8]>,
+    response: Cell<Option<Response>>,
+    exchange: Cell<Option<Exchange>>,
+    exchange_deadline: Cell<Option<u32>>,
+    recent: Cell<[Option<Recent>; DEDUP_ENTRIES]>,
+
+    /// The messages waiting to be sent. Empty messages are encoded when they
+    /// are sent.
+    tx_empty: Cell<Option<(IPAddr, u16, CoAPHeader)>>,
+    tx_response: Cell<bool>,
+    tx_request: Cell<bool>,
+    sending: Cell<bool>,
+    in_send_pending: Cell<bool>,
+}
+
+impl<'a, A: Alarm + 'a> CoAPEndpoint<'a, A> {
+    /// Creates the endpoint. `udp_sender` must send from `COAP_PORT`.
+    /// Requests and responses are encoded into `request_buf` and
+    /// `response_buf`, whose lengths bound those of the messages sent.
+    pub fn new(
+        udp_sender: &'a UDPSender<'a>,
+        alarm: &'a A,
+        rng: &'a RNG,
+        request_buf: &'static mut [u8],
+        response_buf: &'static mut [u8],
+    ) -> CoAPEndpoint<'a, A> {
+        CoAPEndpoint {
+            udp_sender: udp_sender,
+            alarm: alarm,
+            rng: rng,
+            client: Cell::new(None),
+            server: Cell::new(None),
+            next_message_id: Cell::new(alarm.now() as u16),
+            random: Cell::new([0; RANDOM_POOL]),
+            random_count: Cell::new(0),
+            request_buf: TakeCell::new(request_buf),
+            request: Cell::new(None),
+            request_deadline: Cell::new(None),
+            response_buf: TakeCell::new(response_buf),
+            response: Cell::new(None),
+            exchange: Cell::new(None),
+            exchange_deadline: Cell::new(None),
+            recent: Cell::new([None; DEDUP_ENTRIES]),
+            tx_empty: Cell::new(None),
+            tx_response: Cell::new(false),
+            tx_request: Cell::new(false),
+            sending: Cell::new(false),
+            in_send_pending: Cell::new(false),
+        }
+    }
+
+    /// Sets the client of the requests, and asks the `RNG` for the
+    /// randomness of the first ones.
+    pub fn set_client(&self, client: &'a CoAPClient) {
+        self.client.set(Some(client));
+        self.rng.get();
+    }
+
+    pub fn set_server(&self, server: &'a CoAPServer) {
+        self.server.set(Some(server));
+    }
+
+    /// Returns whether a request is waiting for its response.
+    pub fn is_busy(&self) -> bool {
+        self.request
+            .get()
+            .map_or(false, |request| request.state != RequestState::Done)
+    }
+
+    /// Sends a Confirmable request to `dst`. `uri_path` holds the segments
+    /// of the path, joined by '/'. If `block_szx` is given, the request asks
+    /// for the first block of the response, of `16 << block_szx` bytes.
+    ///
+    /// # Return Value
+    /// `EBUSY` if a request is waiting for its response or the randomness
+    /// for the request is not ready yet, `EINVAL` if `method` is not a method
+    /// or the path or block size is too long, and `ESIZE` if the request does
+    /// not fit in `request_buf`.
+    pub fn request(
+        &self,
+        dst: IPAddr,
+        dst_port: u16,
+        method: u8,
+        uri_path: &[u8],
+        payload: &[u8],
+        block_szx: Option<u8>,
+    ) -> ReturnCode {
+        if self.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        if !code::is_request(method) || uri_path.len() > MAX_URI_PATH_LEN
+            || block_szx.map_or(false, |szx| szx > MAX_BLOCK_SZX)
+        {
+            return ReturnCode::EINVAL;
+        }
+        let mut request = Request {
+            dst: dst,
+            dst_port: dst_port,
+            method: method,
+            uri_path: [0; MAX_URI_PATH_LEN],
+            uri_path_len: uri_path.len(),
+            block2: block_szx.map(|szx| BlockOption::new(0, false, szx)),
+            message_id: 0,
+            token: [0; TOKEN_LEN],
+            len: 0,
+            state: RequestState::Done,
+            response_block2: None,
+        };
+        request.uri_path[..uri_path.len()].copy_from_slice(uri_path);
+        self.send_request(request, payload)
+    }
+
+    /// Asks for the block after that of the last response, with the method
+    /// and path of the last request, and without payload.
+    ///
+    /// # Return Value
+    /// `EBUSY` if a request is waiting for its response or the randomness
+    /// for the request is not ready yet, and `EINVAL` if the last response
+    /// was not followed by more blocks.
+    pub fn next_block(&self) -> ReturnCode {
+        let mut request = match self.request.get() {
+            Some(request) => request,
+            None => return ReturnCode::EINVAL,
+        };
+        if request.state != RequestState::Done {
+            return ReturnCode::EBUSY;
+        }
+        match request.response_block2 {
+            Some(block2) if block2.more => {
+                request.block2 = Some(BlockOption::new(block2.num + 1, false, block2.szx));
+                self.send_request(request, &[])
+            }
+            _ => ReturnCode::EINVAL,
+        }
+    }
+
+    /// Gives up on the request waiting for its response, without calling the
+    /// client back.
+    pub fn cancel(&self) {
+        self.request.set(None);
+        self.request_deadline.set(None);
+        self.tx_request.set(false);
+        self.update_alarm();
+    }
+
+    /// Answers the request passed to the server with `code` and `payload`.
+    /// A response that does not fit in `response_buf` is replaced by 5.00
+    /// (Internal Server Error).
+    ///
+    /// # Return Value
+    /// `EINVAL` if no request waits for a response, or `code` is not a
+    /// response code, `EBUSY` if the last response is still waiting to be
+    /// sent, and `ESIZE` if the response does not fit.
+    pub fn respond(&self, code: u8, payload: &[u8]) -> ReturnCode {
+        let exchange = match self.exchange.get() {
+            Some(exchange) => exchange,
+            None => return ReturnCode::EINVAL,
+        };
+        if !code::is_response(code) {
+            return ReturnCode::EINVAL;
+        }
+        if self.tx_response.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.exchange.set(None);
+        self.exchange_deadline.set(None);
+        self.update_alarm();
+        self.send_response(exchange, code, payload)
+    }
+
+    fn ms_to_ticks(ms: u32) -> u32 {
+        let freq = A::Frequency::frequency();
+        (ms / 1000) * freq + (ms % 1000) * freq / 1000
+    }
+
+    fn deadline(&self, ms: u32) -> u32 {
+        self.alarm.now().wrapping_add(Self::ms_to_ticks(ms))
+    }
+
+    fn has_passed(&self, deadline: u32) -> bool {
+        (self.alarm.now().wrapping_sub(deadline) as i32) >= 0
+    }
+
+    /// Sets the alarm for the earliest deadline.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let earliest = match (self.request_deadline.get(), self.exchange_deadline.get()) {
+            (Some(a), Some(b)) => Some(if (a.wrapping_sub(b) as i32) < 0 { a } else { b }),
+            (a, b) => a.or(b),
+        };
+        match earliest {
+            Some(deadline) if (deadline.wrapping_sub(now) as i32) > 0 => {
+                self.alarm.set_alarm(deadline)
+            }
+            Some(_) => self.alarm.set_alarm(now.wrapping_add(1)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    fn new_message_id(&self) -> u16 {
+        let message_id = self.next_message_id.get();
+        self.next_message_id.set(message_id.wrapping_add(1));
+        message_id
+    }
+
+    /// Takes a random word from the pool, and asks for more.
+    fn take_random(&self) -> u32 {
+        let count = self.random_count.get();
+        self.random_count.set(count - 1);
+        self.rng.get();
+        self.random.get()[count - 1]
+    }
+
+    fn encode_request(
+        buf: &mut [u8],
+        header: &CoAPHeader,
+        uri_path: &[u8],
+        block2: Option<BlockOption>,
+        payload: &[u8],
+    ) -> SResult {
+        let mut off = enc_consume!(buf; header; encode);
+        let mut prev = 0;
+        for segment in uri_path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            off = enc_consume!(buf, off; encode_option, prev, option::URI_PATH, segment);
+            prev = option::URI_PATH;
+        }
+        if let Some(block2) = block2 {
+            off = enc_consume!(buf, off; encode_uint_option, prev, option::BLOCK2,
+                               block2.get_value());
+        }
+        let off = enc_consume!(buf, off; encode_payload, payload);
+        stream_done!(off);
+    }
+
+    fn encode_response(buf: &mut [u8], header: &CoAPHeader, payload: &[u8]) -> SResult {
+        let off = enc_consume!(buf; header; encode);
+        let off = enc_consume!(buf, off; encode_payload, payload);
+        stream_done!(off);
+    }
+
+    /// Encodes `request` with a new message ID and token, and sends it.
+    fn send_request(&self, mut request: Request, payload: &[u8]) -> ReturnCode {
+        if self.random_count.get() < 2 {
+            self.rng.get();
+            return ReturnCode::EBUSY;
+        }
+        let token = self.take_random();
+        request.token = [
+            (token >> 24) as u8,
+            (token >> 16) as u8,
+            (token >> 8) as u8,
+            token as u8,
+        ];
+        request.message_id = self.new_message_id();
+        let header = CoAPHeader::new(
+            MessageType::Confirmable,
+            request.method,
+            request.message_id,
+            &request.token,
+        );
+        let encoded = self.request_buf.map(|buf| {
+            Self::encode_request(buf, &header, request.get_uri_path(), request.block2, payload)
+                .done()
+                .map(|(len, _)| len)
+        });
+        request.len = match encoded {
+            Some(Some(len)) => len,
+            Some(None) => return ReturnCode::ESIZE,
+            None => return ReturnCode::EBUSY,
+        };
+
+        let timeout = ACK_TIMEOUT + self.take_random() % (ACK_TIMEOUT / 2);
+        request.state = RequestState::Unacknowledged {
+            retransmits: 0,
+            timeout: timeout,
+        };
+        request.response_block2 = None;
+        self.request.set(Some(request));
+        self.request_deadline.set(Some(self.deadline(timeout)));
+        self.update_alarm();
+        self.tx_request.set(true);
+        self.send_pending();
+        ReturnCode::SUCCESS
+    }
+
+    /// Ends the request with its response, and passes the response to the
+    /// client.
+    fn complete_request(&self, mut request: Request, message: &CoAPMessage) {
+        let block2 = message.get_block(option::BLOCK2);
+        request.state = RequestState::Done;
+        request.response_block2 = block2;
+        self.request.set(Some(request));
+        self.request_deadline.set(None);
+        self.tx_request.set(false);
+        self.update_alarm();
+        self.client
+            .get()
+            .map(|client| client.response(message.header.code, message.payload, block2));
+    }
+
+    fn fail_request(&self, mut request: Request, result: ReturnCode) {
+        request.state = RequestState::Done;
+        request.response_block2 = None;
+        self.request.set(Some(request));
+        self.request_deadline.set(None);
+        self.tx_request.set(false);
+        self.update_alarm();
+        self.client
+            .get()
+            .map(|client| client.request_failed(result));
+    }
+
+    /// Encodes the response to `exchange` into `response_buf`, and sends it.
+    fn send_response(&self, exchange: Exchange, code: u8, payload: &[u8]) -> ReturnCode {
+        let request = exchange.header;
+        // Responses to Confirmable requests are piggybacked on the
+        // acknowledgement
+        let (msg_type, message_id, request_id) = match request.msg_type {
+            MessageType::Confirmable => (
+                MessageType::Acknowledgement,
+                request.message_id,
+                Some(request.message_id),
+            ),
+            _ => (MessageType::NonConfirmable, self.new_message_id(), None),
+        };
+        let mut header = CoAPHeader::new(msg_type, code, message_id, request.get_token());
+        let mut result = ReturnCode::SUCCESS;
+        let len = self.response_buf.map_or(0, |buf| {
+            match Self::encode_response(buf, &header, payload).done() {
+                Some((len, _)) => len,
+                None => {
+                    result = ReturnCode::ESIZE;
+                    header.code = code::INTERNAL_SERVER_ERROR;
+                    Self::encode_response(buf, &header, &[])
+                        .done()
+                        .map_or(0, |(len, _)| len)
+                }
+            }
+        });
+        if len == 0 {
+            return ReturnCode::ESIZE;
+        }
+        self.response.set(Some(Response {
+            dst: exchange.src,
+            dst_port: exchange.src_port,
+            request_id: request_id,
+            len: len,
+        }));
+        self.tx_response.set(true);
+        self.send_pending();
+        result
+    }
+
+    fn send_empty(&self, dst: IPAddr, dst_port: u16, msg_type: MessageType, message_id: u16) {
+        let header = CoAPHeader::new(msg_type, code::EMPTY, message_id, &[]);
+        self.tx_empty.set(Some((dst, dst_port, header)));
+        self.send_pending();
+    }
+
+    /// Sends the messages waiting, one at a time. The UDP layer can call
+    /// back before `send_to` returns, so a call made from the callback
+    /// leaves it to the loop below.
+    fn send_pending(&self) {
+        if self.in_send_pending.get() {
+            return;
+        }
+        self.in_send_pending.set(true);
+        while !self.sending.get() && self.send_next() {}
+        self.in_send_pending.set(false);
+    }
+
+    /// Sends the next message waiting, empty messages first, and returns
+    /// whether there was one. A message that can't be sent is lost: requests
+    /// are retransmitted, and responses are sent again for the duplicates of
+    /// their requests.
+    fn send_next(&self) -> bool {
+        if let Some((dst, dst_port, header)) = self.tx_empty.take() {
+            let mut buf = [0; 4];
+            header.encode(&mut buf);
+            self.send_to(dst, dst_port, &buf);
+        } else if self.tx_response.get() {
+            self.tx_response.set(false);
+            self.response.get().map(|response| {
+                self.response_buf
+                    .map(|buf| self.send_to(response.dst, response.dst_port, &buf[..response.len]))
+            });
+        } else if self.tx_request.get() {
+            self.tx_request.set(false);
+            self.request.get().map(|request| {
+                self.request_buf
+                    .map(|buf| self.send_to(request.dst, request.dst_port, &buf[..request.len]))
+            });
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn send_to(&self, dst: IPAddr, dst_port: u16, buf: &[u8]) {
+        self.sending.set(true);
+        if self.udp_sender.send_to(dst, dst_port, COAP_PORT, buf) != ReturnCode::SUCCESS {
+            self.sending.set(false);
+        }
+    }
+
+    /// Returns whether a message with `message_id` was received from the
+    /// same endpoint within `EXCHANGE_LIFETIME`.
+    fn is_duplicate(&self, src: IPAddr, src_port: u16, message_id: u16) -> bool {
+        let now = self.alarm.now();
+        let lifetime = Self::ms_to_ticks(EXCHANGE_LIFETIME);
+        self.recent.get().iter().any(|entry| match *entry {
+            Some(recent) => {
+                now.wrapping_sub(recent.time) < lifetime && recent.src.0 == src.0
+                    && recent.src_port == src_port && recent.message_id == message_id
+            }
+            None => false,
+        })
+    }
+
+    /// Remembers the message ID of a message processed, in place of the
+    /// oldest one remembered.
+    fn remember(&self, src: IPAddr, src_port: u16, message_id: u16) {
+        let now = self.alarm.now();
+        let mut recent = self.recent.get();
+        let slot = (0..DEDUP_ENTRIES)
+            .max_by_key(|&i| recent[i].map_or(u32::max_value(), |r| now.wrapping_sub(r.time)))
+            .unwrap_or(0);
+        recent[slot] = Some(Recent {
+            src: src,
+            src_port: src_port,
+            message_id: message_id,
+            time: now,
+        });
+        self.recent.set(recent);
+    }
+
+    fn receive_request(&self, src: IPAddr, src_port: u16, message: &CoAPMessage) {
+        let header = message.header;
+        if self.is_duplicate(src, src_port, header.message_id) {
+            let answered = self.response.get().map_or(false, |response| {
+                response.request_id == Some(header.message_id) && response.dst.0 == src.0
+                    && response.dst_port == src_port
+            });
+            if answered {
+                self.tx_response.set(true);
+                self.send_pending();
+            }
+            return;
+        }
+        // The request is dropped, to be retransmitted, if it can't be
+        // answered yet
+        if self.tx_response.get() {
+            return;
+        }
+        self.remember(src, src_port, header.message_id);
+        let exchange = Exchange {
+            src: src,
+            src_port: src_port,
+            header: header,
+        };
+        if self.exchange.get().is_some() {
+            self.send_response(exchange, code::SERVICE_UNAVAILABLE, &[]);
+            return;
+        }
+
+        let mut uri_path = [0; MAX_URI_PATH_LEN];
+        let mut uri_path_len = 0;
+        let mut error = None;
+        for (number, value) in message.options() {
+            match number {
+                option::URI_PATH => {
+                    let start = if uri_path_len > 0 { uri_path_len + 1 } else { 0 };
+                    if start + value.len() > MAX_URI_PATH_LEN {
+                        error = Some(code::REQUEST_URI_TOO_LONG);
+                        break;
+                    }
+                    if start > 0 {
+                        uri_path[uri_path_len] = b'/';
+                    }
+                    uri_path[start..start + value.len()].copy_from_slice(value);
+                    uri_path_len = start + value.len();
+                }
+                // A request for the first block gets the whole representation
+                option::BLOCK2 => {
+                    if message.get_block(option::BLOCK2).map_or(true, |b| b.num != 0) {
+                        error = Some(code::BAD_OPTION);
+                    }
+                }
+                option::URI_HOST | option::URI_PORT | option::CONTENT_FORMAT | option::ACCEPT => {}
+                number if option::is_critical(number) => error = Some(code::BAD_OPTION),
+                _ => {}
+            }
+        }
+        if let Some(error) = error {
+            self.send_response(exchange, error, &[]);
+            return;
+        }
+
+        self.exchange.set(Some(exchange));
+        let result = self.server.get().map_or(ReturnCode::ENOSUPPORT, |server| {
+            server.request(header.code, &uri_path[..uri_path_len], message.payload)
+        });
+        if self.exchange.get().is_none() {
+            // Answered already
+            return;
+        }
+        let code = match result {
+            ReturnCode::SUCCESS => {
+                self.exchange_deadline
+                    .set(Some(self.deadline(HANDLER_TIMEOUT)));
+                self.update_alarm();
+                return;
+            }
+            ReturnCode::ENOSUPPORT => code::NOT_FOUND,
+            ReturnCode::EINVAL => code::METHOD_NOT_ALLOWED,
+            ReturnCode::ESIZE => code::REQUEST_ENTITY_TOO_LARGE,
+            ReturnCode::EBUSY => code::SERVICE_UNAVAILABLE,
+            _ => code::INTERNAL_SERVER_ERROR,
+        };
+        self.respond(code, &[]);
+    }
+
+    /// Handles a response sent separately from the acknowledgement.
+    fn receive_separate_response(&self, src: IPAddr, src_port: u16, message: &CoAPMessage) {
+        let header = message.header;
+        let confirmable = header.msg_type == MessageType::Confirmable;
+        if self.is_duplicate(src, src_port, header.message_id) {
+            if confirmable {
+                self.send_empty(src, src_port, MessageType::Acknowledgement, header.message_id);
+            }
+            return;
+        }
+        let request = match self.request.get() {
+            Some(request) => request,
+            None => return,
+        };
+        if request.state == RequestState::Done || !request.is_from(src, src_port)
+            || header.get_token() != &request.token[..]
+        {
+            // Responses to no request are rejected (Section 5.3.2)
+            if confirmable {
+                self.send_empty(src, src_port, MessageType::Reset, header.message_id);
+            }
+            return;
+        }
+        self.remember(src, src_port, header.message_id);
+        if confirmable {
+            self.send_empty(src, src_port, MessageType::Acknowledgement, header.message_id);
+        }
+        self.complete_request(request, message);
+    }
+
+    /// Handles an Acknowledgement or a Reset of the request.
+    fn receive_reply(&self, src: IPAddr, src_port: u16, message: &CoAPMessage) {
+        let header = message.header;
+        let mut request = match self.request.get() {
+            Some(request) => request,
+            None => return,
+        };
+        let unacknowledged = match request.state {
+            RequestState::Unacknowledged { .. } => true,
+            _ => false,
+        };
+        if !unacknowledged || !request.is_from(src, src_port)
+            || header.message_id != request.message_id
+        {
+            return;
+        }
+        if header.msg_type == MessageType::Reset {
+            self.fail_request(request, ReturnCode::ECANCEL);
+        } else if header.code == code::EMPTY {
+            request.state = RequestState::Acknowledged;
+            self.request.set(Some(request));
+            self.tx_request.set(false);
+            self.request_deadline
+                .set(Some(self.deadline(SEPARATE_RESPONSE_TIMEOUT)));
+            self.update_alarm();
+        } else if code::is_response(header.code) && header.get_token() == &request.token[..] {
+            self.complete_request(request, message);
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> UDPRecvClient for CoAPEndpoint<'a, A> {
+    fn receive(&self, ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]) {
+        if ip6_header.dst_addr.is_multicast() {
+            return;
+        }
+        let src = ip6_header.src_addr;
+        let src_port = udp_header.get_src_port();
+        let message = match CoAPMessage::decode(payload).done() {
+            Some((_, message)) => message,
+            None => {
+                // Confirmable messages of version 1 with format errors are
+                // rejected (Section 4.2)
+                if payload.len() >= 4 && payload[0] & 0xf0 == 0x40 {
+                    let message_id = (payload[2] as u16) << 8 | payload[3] as u16;
+                    self.send_empty(src, src_port, MessageType::Reset, message_id);
+                }
+                return;
+            }
+        };
+
+        let header = message.header;
+        let confirmable = header.msg_type == MessageType::Confirmable;
+        match header.msg_type {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                self.receive_reply(src, src_port, &message)
+            }
+            _ if code::is_request(header.code) => self.receive_request(src, src_port, &message),
+            _ if code::is_response(header.code) => {
+                self.receive_separate_response(src, src_port, &message)
+            }
+            // Empty Confirmable messages are pings, which are answered with
+            // a Reset, like messages with unknown codes
+            _ if confirmable => {
+                self.send_empty(src, src_port, MessageType::Reset, header.message_id)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> UDPSendClient for CoAPEndpoint<'a, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+        self.send_pending();
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for CoAPEndpoint<'a, A> {
+    fn fired(&self) {
+        if self.request_deadline
+            .get()
+            .map_or(false, |deadline| self.has_passed(deadline))
+        {
+            self.request_deadline.set(None);
+            if let Some(mut request) = self.request.get() {
+                match request.state {
+                    RequestState::Unacknowledged {
+                        retransmits,
+                        timeout,
+                    } if retransmits < MAX_RETRANSMIT =>
+                    {
+                        request.state = RequestState::Unacknowledged {
+                            retransmits: retransmits + 1,
+                            timeout: timeout * 2,
+                        };
+                        self.request.set(Some(request));
+                        self.request_deadline.set(Some(self.deadline(timeout * 2)));
+                        self.tx_request.set(true);
+                        self.send_pending();
+                    }
+                    RequestState::Unacknowledged { .. } => {
+                        self.fail_request(request, ReturnCode::ENOACK)
+                    }
+                    RequestState::Acknowledged => self.fail_request(request, ReturnCode::FAIL),
+                    RequestState::Done => {}
+                }
+            }
+        }
+
+        if self.exchange_deadline
+            .get()
+            .map_or(false, |deadline| self.has_passed(deadline))
+        {
+            self.exchange_deadline.set(None);
+            if self.respond(code::SERVICE_UNAVAILABLE, &[]) != ReturnCode::SUCCESS {
+                self.exchange.set(None);
+            }
+        }
+        self.update_alarm();
+    }
+}
+
+impl<'a, A: Alarm + 'a> rng::Client for CoAPEndpoint<'a, A> {
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        let mut random = self.random.get();
+        let mut count = self.random_count.get();
+        while count < RANDOM_POOL {
+            match randomness.next() {
+                Some(word) => {
+                    random[count] = word;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        self.random.set(random);
+        self.random_count.set(count);
+        if count < RANDOM_POOL {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! CoAP userspace interface for serving resources and sending requests.
+//!
+//! Apps serve resources through the kernel's
+//! [CoAPEndpoint](../coap_endpoint/struct.CoAPEndpoint.html): each app lists
+//! the paths of its resources and the methods it handles, requests for them
+//! are copied into the app's request buffer, and the app answers them with a
+//! response code and payload.
+//!
+//! Apps also send requests, one at a time for all apps, since the endpoint
+//! waits for the response to each request before sending the next. For
+//! block-wise downloads, such as firmware images, each block of the
+//! response is copied into the app's response buffer, and the app asks for
+//! the next block once it has stored the current one. Block-wise transfers
+//! are only supported for requests: the resources that apps serve are
+//! answered whole.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow` System Call
+//!
+//! * 0: The paths of the app's resources, without leading '/' and each
+//!   followed by a NUL byte, for example `"led\0sensors/temp\0"`.
+//! * 1: The buffer that the payloads of requests for the app's resources are
+//!   copied into.
+//! * 2: The buffer that the payloads of responses to the app's requests are
+//!   copied into. Payloads are truncated if they do not fit.
+//! * 3: The payload of the next response or request sent. Optional.
+//! * 4: The destination of the app's requests: the IPv6 address and the
+//!   port in network byte order (18 bytes), followed by the URI path, up to
+//!   the end of the buffer or a NUL byte.
+//!
+//! ### `subscribe` System Call
+//!
+//! * 0: Request callback, with the method, the index of the requested
+//!   resource among the paths, and the length of the payload. The app must
+//!   answer within `HANDLER_TIMEOUT`.
+//! * 1: Response callback, with the response code, the length of the
+//!   payload, and the value of the Block2 option of the response, or 0 if it
+//!   has none: `num << 4 | more << 3 | szx`, for blocks of `16 << szx` bytes.
+//!   If the request gets no response, the first argument is the `ReturnCode`
+//!   instead: `ENOACK` if the server never acknowledged it, `ECANCEL` if the
+//!   server rejected it and `FAIL` if the acknowledged response never came.
+//!
+//! ### `command` System Call
+//!
+//! * 0: Driver check.
+//! * 1: Serve the resources in the paths for the methods whose bits are set
+//!   in `arg1`, as `1 << method`: 0x2 for GET, 0x4 for POST, 0x8 for PUT and
+//!   0x10 for DELETE. 0 stops serving them.
+//! * 2: Answer the last request for the app's resources with response code
+//!   `arg1` and the first `arg2` bytes of the payload.
+//! * 3: Send a request with method `arg1` and the first `arg2` bytes of the
+//!   payload to the destination. To download a resource block-wise, `arg1`
+//!   also holds `(szx + 1) << 8`, for blocks of `16 << szx` bytes. Returns
+//!   `EBUSY` if a request is waiting for its response, or if the random
+//!   token of the request is not ready yet.
+//! * 4: Ask for the next block of the response to the app's last request.
+//!   Returns `EBUSY` in the same cases as 3.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coap_driver = static_init!(
+//!     capsules::net::coap::driver::CoAPDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::net::coap::driver::CoAPDriver::new(coap_endpoint, kernel::Grant::create()));
+//! coap_endpoint.set_client(coap_driver);
+//! coap_endpoint.set_server(coap_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::hil::time::Alarm;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::coap::coap::BlockOption;
+use net::coap::coap_endpoint::{CoAPClient, CoAPEndpoint, CoAPServer};
+use net::ipv6::ip_utils::IPAddr;
+use net::stream::{decode_bytes, decode_u16, SResult};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30005;
+
+/// Length of an IPv6 address and port, as exchanged with userland
+const ENDPOINT_LEN: usize = 18;
+
+/// Decodes an address and port in the format used by the userland driver.
+fn decode_endpoint(buf: &[u8]) -> SResult<(IPAddr, u16)> {
+    stream_len_cond!(buf, ENDPOINT_LEN);
+    let mut addr = IPAddr::new();
+    let off = dec_consume!(buf; decode_bytes, &mut addr.0);
+    let (off, port) = dec_try!(buf, off; decode_u16);
+    stream_done!(off, (addr, port));
+}
+
+/// Returns the index of `uri_path` among the NUL-terminated `paths`.
+fn find_path(paths: &[u8], uri_path: &[u8]) -> Option<usize> {
+    let end = paths.iter().rposition(|b| *b == 0)?;
+    paths[..end]
+        .split(|b| *b == 0)
+        .position(|path| path == uri_path)
+}
+
+pub struct App {
+    request_callback: Option<Callback>,
+    response_callback: Option<Callback>,
+    app_paths: Option<AppSlice<Shared, u8>>,
+    app_request: Option<AppSlice<Shared, u8>>,
+    app_response: Option<AppSlice<Shared, u8>>,
+    app_payload: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    /// The methods the app serves its resources for, as bits `1 << method`
+    methods: usize,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        App {
+            request_callback: None,
+            response_callback: None,
+            app_paths: None,
+            app_request: None,
+            app_response: None,
+            app_payload: None,
+            app_cfg: None,
+            methods: 0,
+        }
+    }
+}
+
+pub struct CoAPDriver<'a, A: Alarm + 'a> {
+    endpoint: &'a CoAPEndpoint<'a, A>,
+
+    /// Grant of apps that use this CoAP driver.
+    apps: Grant<App>,
+
+    /// The app that sent the last request
+    client_app: Cell<Option<AppId>>,
+    /// The app that answers the request being handled
+    server_app: Cell<Option<AppId>>,
+}
+
+impl<'a, A: Alarm + 'a> CoAPDriver<'a, A> {
+    pub fn new(endpoint: &'a CoAPEndpoint<'a, A>, grant: Grant<App>) -> CoAPDriver<'a, A> {
+        CoAPDriver {
+            endpoint: endpoint,
+            apps: grant,
+            client_app: Cell::new(None),
+            server_app: Cell::new(None),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Runs `closure` on the app that sent the last request.
+    fn with_client_app<F>(&self, closure: F)
+    where
+        F: FnOnce(&mut App),
+    {
+        self.client_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| closure(app));
+        });
+    }
+
+    /// Sends the app's request, with method `arg1` and the first `len` bytes
+    /// of the payload.
+    fn send_request(&self, app: &App, arg1: usize, len: usize) -> ReturnCode {
+        let method = (arg1 & 0xff) as u8;
+        let block_szx = match (arg1 >> 8) & 0xf {
+            0 => None,
+            szx => Some((szx - 1) as u8),
+        };
+        let cfg = match app.app_cfg {
+            Some(ref cfg) => cfg.as_ref(),
+            None => return ReturnCode::EINVAL,
+        };
+        let (dst, dst_port) = match decode_endpoint(cfg).done() {
+            Some((_, endpoint)) => endpoint,
+            None => return ReturnCode::EINVAL,
+        };
+        let uri_path = &cfg[ENDPOINT_LEN..];
+        let uri_path = match uri_path.iter().position(|b| *b == 0) {
+            Some(end) => &uri_path[..end],
+            None => uri_path,
+        };
+        let payload: &[u8] = match app.app_payload {
+            Some(ref payload) => &payload.as_ref()[..min(len, payload.len())],
+            None => &[],
+        };
+        self.endpoint
+            .request(dst, dst_port, method, uri_path, payload, block_szx)
+    }
+}
+
+impl<'a, A: Alarm + 'a> Driver for CoAPDriver<'a, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 | 3 | 4 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_paths = slice,
+                    1 => app.app_request = slice,
+                    2 => app.app_response = slice,
+                    3 => app.app_payload = slice,
+                    4 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.request_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.response_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.do_with_app(appid, |app| {
+                if arg1 != 0 && app.app_paths.is_none() {
+                    return ReturnCode::EINVAL;
+                }
+                app.methods = arg1;
+                ReturnCode::SUCCESS
+            }),
+            2 => {
+                if self.server_app.get() != Some(appid) || arg1 > (u8::max_value() as usize) {
+                    return ReturnCode::EINVAL;
+                }
+                self.do_with_app(appid, |app| {
+                    let payload: &[u8] = match app.app_payload {
+                        Some(ref payload) => &payload.as_ref()[..min(arg2, payload.len())],
+                        None => &[],
+                    };
+                    let result = self.endpoint.respond(arg1 as u8, payload);
+                    if result != ReturnCode::EBUSY {
+                        self.server_app.set(None);
+                    }
+                    result
+                })
+            }
+            3 => self.do_with_app(appid, |app| {
+                let result = self.send_request(app, arg1, arg2);
+                if result == ReturnCode::SUCCESS {
+                    self.client_app.set(Some(appid));
+                }
+                result
+            }),
+            4 => {
+                if self.client_app.get() != Some(appid) {
+                    return ReturnCode::EINVAL;
+                }
+                self.endpoint.next_block()
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> CoAPClient for CoAPDriver<'a, A> {
+    fn response(&self, code: u8, payload: &[u8], block2: Option<BlockOption>) {
+        self.with_client_app(|app| {
+            let len = app.app_response.as_mut().map_or(0, |rbuf| {
+                let rbuf = rbuf.as_mut();
+                let len = min(rbuf.len(), payload.len());
+                rbuf[..len].copy_from_slice(&payload[..len]);
+                len
+            });
+            let block2 = block2.map_or(0, |block2| block2.get_value() as usize);
+            app.response_callback
+                .map(|mut cb| cb.schedule(code as usize, len, block2));
+        });
+    }
+
+    fn request_failed(&self, result: ReturnCode) {
+        self.with_client_app(|app| {
+            app.response_callback
+                .map(|mut cb| cb.schedule(result.into(), 0, 0));
+        });
+    }
+}
+
+impl<'a, A: Alarm + 'a> CoAPServer for CoAPDriver<'a, A> {
+    fn request(&self, method: u8, uri_path: &[u8], payload: &[u8]) -> ReturnCode {
+        let mut result = ReturnCode::ENOSUPPORT;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.methods == 0 || app.request_callback.is_none() {
+                    return;
+                }
+                let index = match app.app_paths
+                    .as_ref()
+                    .and_then(|paths| find_path(paths.as_ref(), uri_path))
+                {
+                    Some(index) => index,
+                    None => return,
+                };
+                if app.methods & (1 << method) == 0 {
+                    result = ReturnCode::EINVAL;
+                    return;
+                }
+                let copied = app.app_request.as_mut().map_or(false, |rbuf| {
+                    let rbuf = rbuf.as_mut();
+                    if payload.len() > rbuf.len() {
+                        return false;
+                    }
+                    rbuf[..payload.len()].copy_from_slice(payload);
+                    true
+                });
+                if !copied && !payload.is_empty() {
+                    result = ReturnCode::ESIZE;
+                    return;
+                }
+                self.server_app.set(Some(app.appid()));
+                app.request_callback
+                    .map(|mut cb| cb.schedule(method as usize, index, payload.len()));
+                result = ReturnCode::SUCCESS;
+            });
+            if result != ReturnCode::ENOSUPPORT {
+                break;
+            }
+        }
+        result
+    }
+}
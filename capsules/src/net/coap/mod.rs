@@ -0,0 +1,3 @@
+pub mod coap;
+pub mod coap_endpoint;
+pub mod driver;
@@ -0,0 +1,229 @@
+//! ICMPv6 userspace interface for pinging other nodes.
+//!
+//! Pings are queued, one per app, and sent in turn through a `Ping`
+//! implementation, usually an `ICMP6Handler`.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow` System Call
+//!
+//! * 0: The IPv6 address to ping, 16 bytes.
+//!
+//! ### `subscribe` System Call
+//!
+//! * 0: Ping callback, with the `ReturnCode` of the ping, the sequence number
+//!   of the Echo Request and the round-trip time in microseconds. The result
+//!   is `FAIL` if no Echo Reply arrived in time.
+//!
+//! ### `command` System Call
+//!
+//! * 0: Driver check.
+//! * 1: Ping the configured address, with `arg1` bytes of data.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ping_driver = static_init!(
+//!     capsules::net::icmpv6::driver::PingDriver<'static>,
+//!     capsules::net::icmpv6::driver::PingDriver::new(
+//!         icmp_handler,
+//!         kernel::Grant::create()));
+//! icmp_handler.set_ping_client(ping_driver);
+//! ```
+
+use core::cell::Cell;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::icmpv6::icmpv6_handler::{Ping, PingClient};
+use net::ipv6::ip_utils::IPAddr;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30003;
+
+pub struct App {
+    callback: Option<Callback>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    pending_ping: Option<(IPAddr, usize)>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        App {
+            callback: None,
+            app_cfg: None,
+            pending_ping: None,
+        }
+    }
+}
+
+pub struct PingDriver<'a> {
+    ping: &'a Ping<'a>,
+
+    /// Grant of apps that use this driver.
+    apps: Grant<App>,
+    /// ID of app whose ping is outstanding.
+    current_app: Cell<Option<AppId>>,
+}
+
+impl<'a> PingDriver<'a> {
+    pub fn new(ping: &'a Ping<'a>, grant: Grant<App>) -> PingDriver<'a> {
+        PingDriver {
+            ping: ping,
+            apps: grant,
+            current_app: Cell::new(None),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn get_next_ping_if_idle(&self) -> Option<AppId> {
+        if self.current_app.get().is_some() {
+            return None;
+        }
+        let mut pending_app = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.pending_ping.is_some() {
+                    pending_app = Some(app.appid());
+                }
+            });
+            if pending_app.is_some() {
+                break;
+            }
+        }
+        pending_app
+    }
+
+    #[inline]
+    fn perform_ping_async(&self, appid: AppId) {
+        let result = self.perform_ping_sync(appid);
+        if result != ReturnCode::SUCCESS {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(result.into(), 0, 0));
+            });
+        }
+    }
+
+    #[inline]
+    fn perform_ping_sync(&self, appid: AppId) -> ReturnCode {
+        self.do_with_app(appid, |app| {
+            let (dest, payload_len) = match app.pending_ping.take() {
+                Some(pending_ping) => pending_ping,
+                None => {
+                    return ReturnCode::SUCCESS;
+                }
+            };
+            // The ping may fail before returning
+            self.current_app.set(Some(appid));
+            let result = self.ping.ping(dest, payload_len);
+            if result != ReturnCode::SUCCESS {
+                self.current_app.set(None);
+            }
+            result
+        })
+    }
+
+    #[inline]
+    fn do_next_ping_async(&self) {
+        self.get_next_ping_if_idle()
+            .map(|appid| self.perform_ping_async(appid));
+    }
+
+    #[inline]
+    fn do_next_ping_sync(&self, new_appid: AppId) -> ReturnCode {
+        self.get_next_ping_if_idle()
+            .map(|appid| {
+                if appid == new_appid {
+                    self.perform_ping_sync(appid)
+                } else {
+                    self.perform_ping_async(appid);
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or(ReturnCode::SUCCESS)
+    }
+}
+
+impl<'a> Driver for PingDriver<'a> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self.do_with_app(appid, |app| {
+                app.app_cfg = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.do_with_app(appid, |app| {
+                if app.pending_ping.is_some() {
+                    // Cannot support more than one pending ping per process.
+                    return ReturnCode::EBUSY;
+                }
+                let dest = app.app_cfg.as_ref().and_then(|cfg| {
+                    if cfg.len() != 16 {
+                        return None;
+                    }
+                    let mut dest = IPAddr::new();
+                    dest.0.copy_from_slice(cfg.as_ref());
+                    Some(dest)
+                });
+                match dest {
+                    Some(dest) => app.pending_ping = Some((dest, arg1)),
+                    None => return ReturnCode::EINVAL,
+                }
+
+                self.do_next_ping_sync(appid)
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> PingClient for PingDriver<'a> {
+    fn ping_done(&self, result: ReturnCode, seqno: u16, rtt_us: u32) {
+        self.current_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(usize::from(result), seqno as usize, rtt_us as usize)
+                });
+            });
+        });
+        self.current_app.set(None);
+        self.do_next_ping_async();
+    }
+}
@@ -0,0 +1,291 @@
+//! This file contains the ICMPv6 handling of a node. The
+//! [ICMP6Handler](struct.ICMP6Handler.html) is set as the ICMPv6 client of an
+//! `IP6Receiver`, and:
+//!
+//! * answers every Echo Request with an Echo Reply carrying the same
+//!   identifier, sequence number and data,
+//! * implements the [Ping](trait.Ping.html) trait, which sends an Echo Request
+//!   and reports the round-trip time of the matching Echo Reply to a
+//!   [PingClient](trait.PingClient.html), and
+//! * answers UDP packets sent to ports that nobody has bound with a
+//!   Destination Unreachable (port unreachable) message, when it is set as
+//!   the unbound client of a `UDPRecvMux`, and
+//! * passes Neighbor Discovery messages on to the client set with
+//!   `set_nd_client`, usually an `NDHost`.
+//!
+//! Replies and errors are best effort: they are dropped if the `ICMP6Sender`
+//! can not queue them behind the messages it is sending.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_handler = static_init!(
+//!     ICMP6Handler<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     ICMP6Handler::new(icmp_send_struct, icmp_alarm, &mut ICMP_BUF));
+//! icmp_send_struct.set_client(icmp_handler);
+//! icmp_alarm.set_client(icmp_handler);
+//! ip6_receiver.set_icmp_client(icmp_handler);
+//! udp_recv_mux.set_unbound_client(icmp_handler);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::udp::udp::UDPHeader;
+use net::udp::udp_recv::UDPRecvClient;
+
+/// How long to wait for an Echo Reply, in milliseconds
+pub const PING_TIMEOUT_MS: u32 = 3000;
+
+/// The identifier of the Echo Requests sent by `ping`
+const ECHO_ID: u16 = 0x7ec0;
+
+/// The Destination Unreachable code for an unreachable port
+const PORT_UNREACHABLE: u8 = 4;
+
+/// The client of a `Ping` implementation.
+pub trait PingClient {
+    /// Called when a ping has completed.
+    ///
+    /// # Arguments
+    ///
+    /// `result` - `SUCCESS` if the Echo Reply arrived, `FAIL` if it did not
+    /// arrive within `PING_TIMEOUT_MS`, or the error with which the Echo
+    /// Request could not be sent
+    /// `seqno` - The sequence number of the Echo Request
+    /// `rtt_us` - The round-trip time in microseconds, if successful
+    fn ping_done(&self, result: ReturnCode, seqno: u16, rtt_us: u32);
+}
+
+/// An interface for sending Echo Requests.
+pub trait Ping<'a> {
+    /// Sets the client that is told about completed pings.
+    fn set_ping_client(&self, client: &'a PingClient);
+
+    /// Sends an Echo Request with `payload_len` bytes of data to `dest`.
+    ///
+    /// # Return Value
+    ///
+    /// `EBUSY` if a ping is already outstanding or the sender is full, and
+    /// `ESIZE` if the data does not fit in the buffer. On `SUCCESS`, the
+    /// client's `ping_done` is called once the ping completes.
+    fn ping(&self, dest: IPAddr, payload_len: usize) -> ReturnCode;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PingState {
+    Idle,
+    /// The Echo Request is being sent
+    Sending,
+    /// The Echo Request was sent
+    AwaitingReply,
+}
+
+pub struct ICMP6Handler<'a, A: Alarm + 'a> {
+    icmp_sender: &'a ICMP6Sender<'a>,
+    alarm: &'a A,
+    /// Scratch space for the data of outgoing messages
+    buf: TakeCell<'static, [u8]>,
+    ping_client: Cell<Option<&'a PingClient>>,
+    nd_client: Cell<Option<&'a ICMP6RecvClient>>,
+    ping_state: Cell<PingState>,
+    ping_dest: Cell<IPAddr>,
+    seqno: Cell<u16>,
+    sent_at: Cell<u32>,
+    /// How many messages the sender has not called back for yet
+    sending: Cell<usize>,
+    /// How many of those were queued before the Echo Request being sent
+    ahead_of_ping: Cell<usize>,
+}
+
+impl<'a, A: Alarm + 'a> ICMP6Handler<'a, A> {
+    pub fn new(
+        icmp_sender: &'a ICMP6Sender<'a>,
+        alarm: &'a A,
+        buf: &'static mut [u8],
+    ) -> ICMP6Handler<'a, A> {
+        ICMP6Handler {
+            icmp_sender: icmp_sender,
+            alarm: alarm,
+            buf: TakeCell::new(buf),
+            ping_client: Cell::new(None),
+            nd_client: Cell::new(None),
+            ping_state: Cell::new(PingState::Idle),
+            ping_dest: Cell::new(IPAddr::new()),
+            seqno: Cell::new(0),
+            sent_at: Cell::new(0),
+            sending: Cell::new(0),
+            ahead_of_ping: Cell::new(0),
+        }
+    }
+
+    /// Sets the client that receives Router Advertisements and Neighbor
+    /// Solicitations and Advertisements.
+    pub fn set_nd_client(&self, client: &'a ICMP6RecvClient) {
+        self.nd_client.set(Some(client));
+    }
+
+    fn ping_done(&self, result: ReturnCode, rtt_us: u32) {
+        self.ping_state.set(PingState::Idle);
+        self.alarm.disable();
+        self.ping_client
+            .get()
+            .map(|client| client.ping_done(result, self.seqno.get(), rtt_us));
+    }
+
+    // Sends a message, counting it until the sender calls back for it
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, payload: &[u8]) -> ReturnCode {
+        self.sending.set(self.sending.get() + 1);
+        let result = self.icmp_sender.send(dest, icmp_header, payload);
+        if result != ReturnCode::SUCCESS {
+            self.sending.set(self.sending.get() - 1);
+        }
+        result
+    }
+
+    fn send_echo_reply(&self, ip6_header: &IP6Header, id: u16, seqno: u16, payload: &[u8]) {
+        if ip6_header.src_addr.is_unspecified() {
+            return;
+        }
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type129);
+        icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+        self.send(ip6_header.src_addr, icmp_header, payload);
+    }
+
+    /// Sends a Destination Unreachable message quoting as much of the
+    /// offending packet as fits in the buffer.
+    fn send_port_unreachable(&self, ip6_header: &IP6Header, udp_header: &UDPHeader, payload: &[u8]) {
+        // Errors are never sent about multicast packets (RFC 4443 2.4)
+        if ip6_header.src_addr.is_unspecified() || ip6_header.dst_addr.is_multicast() {
+            return;
+        }
+        self.buf.map(|buf| {
+            let off = match ip6_header.encode(buf).done() {
+                Some((off, _)) => off,
+                None => return,
+            };
+            let off = match udp_header.encode(buf, off).done() {
+                Some((off, _)) => off,
+                None => return,
+            };
+            let len = cmp::min(payload.len(), buf.len() - off);
+            buf[off..off + len].copy_from_slice(&payload[..len]);
+
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type1);
+            icmp_header.set_code(PORT_UNREACHABLE);
+            self.send(ip6_header.src_addr, icmp_header, &buf[..off + len]);
+        });
+    }
+}
+
+impl<'a, A: Alarm + 'a> Ping<'a> for ICMP6Handler<'a, A> {
+    fn set_ping_client(&self, client: &'a PingClient) {
+        self.ping_client.set(Some(client));
+    }
+
+    fn ping(&self, dest: IPAddr, payload_len: usize) -> ReturnCode {
+        if self.ping_state.get() != PingState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let seqno = self.seqno.get().wrapping_add(1);
+        let result = self.buf.map_or(ReturnCode::ENOMEM, |buf| {
+            if payload_len > buf.len() {
+                return ReturnCode::ESIZE;
+            }
+            for (i, byte) in buf[..payload_len].iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type128);
+            icmp_header.set_options(ICMP6HeaderOptions::Type128 {
+                id: ECHO_ID,
+                seqno: seqno,
+            });
+            // The sender may complete the transmission before returning
+            self.ping_state.set(PingState::Sending);
+            self.seqno.set(seqno);
+            self.ping_dest.set(dest);
+            self.sent_at.set(self.alarm.now());
+            self.ahead_of_ping.set(self.sending.get());
+            self.send(dest, icmp_header, &buf[..payload_len])
+        });
+        if result == ReturnCode::SUCCESS {
+            let timeout = (PING_TIMEOUT_MS as u64 * A::Frequency::frequency() as u64 / 1000) as u32;
+            if self.ping_state.get() != PingState::Idle {
+                self.alarm
+                    .set_alarm(self.sent_at.get().wrapping_add(timeout));
+            }
+        } else {
+            self.ping_state.set(PingState::Idle);
+        }
+        result
+    }
+}
+
+impl<'a, A: Alarm + 'a> ICMP6RecvClient for ICMP6Handler<'a, A> {
+    fn receive(&self, ip6_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.send_echo_reply(&ip6_header, id, seqno, payload);
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } => {
+                if self.ping_state.get() != PingState::Idle && id == ECHO_ID
+                    && seqno == self.seqno.get()
+                    && ip6_header.src_addr.0 == self.ping_dest.get().0
+                {
+                    let ticks = self.alarm.now().wrapping_sub(self.sent_at.get());
+                    let rtt_us = ticks as u64 * 1000000 / A::Frequency::frequency() as u64;
+                    self.ping_done(ReturnCode::SUCCESS, rtt_us as u32);
+                }
+            }
+            ICMP6HeaderOptions::Type134 { .. }
+            | ICMP6HeaderOptions::Type135 { .. }
+            | ICMP6HeaderOptions::Type136 { .. } => {
+                self.nd_client
+                    .get()
+                    .map(|client| client.receive(ip6_header, icmp_header, payload));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Receives the UDP packets for unbound ports.
+impl<'a, A: Alarm + 'a> UDPRecvClient for ICMP6Handler<'a, A> {
+    fn receive(&self, ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]) {
+        self.send_port_unreachable(&ip6_header, &udp_header, payload);
+    }
+}
+
+impl<'a, A: Alarm + 'a> ICMP6SendClient for ICMP6Handler<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        if self.sending.get() > 0 {
+            self.sending.set(self.sending.get() - 1);
+        }
+        if self.ping_state.get() == PingState::Sending {
+            if self.ahead_of_ping.get() > 0 {
+                // A message queued before the Echo Request was sent
+                self.ahead_of_ping.set(self.ahead_of_ping.get() - 1);
+            } else if result == ReturnCode::SUCCESS {
+                self.ping_state.set(PingState::AwaitingReply);
+            } else {
+                self.ping_done(result, 0);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for ICMP6Handler<'a, A> {
+    fn fired(&self) {
+        if self.ping_state.get() != PingState::Idle {
+            self.ping_done(ReturnCode::FAIL, 0);
+        }
+    }
+}
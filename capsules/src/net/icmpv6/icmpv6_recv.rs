@@ -0,0 +1,22 @@
+//! This file contains the definition of the interface through which ICMPv6
+//! messages are received. The [ICMP6RecvClient](trait.ICMP6RecvClient.html)
+//! trait is implemented by upper layers, and registered with an
+//! [IP6Receiver](../../ipv6/ipv6_recv/trait.IP6Receiver.html), which passes
+//! on every ICMPv6 message with a valid checksum.
+
+use net::icmpv6::icmpv6::ICMP6Header;
+use net::ipv6::ipv6::IP6Header;
+
+/// A trait for a client of an `IP6Receiver` that handles ICMPv6 messages.
+pub trait ICMP6RecvClient {
+    /// A client callback invoked when an ICMPv6 message has been received.
+    ///
+    /// # Arguments
+    ///
+    /// `ip6_header` - The IPv6 header of the packet. Its payload length and
+    /// next header describe the ICMPv6 message, skipping any extension headers
+    /// `icmp_header` - The decoded ICMPv6 header, with its length set to the
+    /// length of the whole message
+    /// `payload` - The ICMPv6 message body following the header
+    fn receive(&self, ip6_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
@@ -38,14 +38,25 @@ pub trait ICMP6Sender<'a> {
     ///
     /// `dest` - The destination IP address
     /// `icmp_header` - The ICMPv6 header to be sent
-    /// `buf` - The byte array containing the ICMPv6 payload
+    /// `buf` - The byte array containing the ICMPv6 payload, which is
+    /// copied before this function returns
     ///
     /// # Return Value
     ///
     /// This function returns a code reporting either success or any
     /// synchronous errors. Note that any asynchronous errors are returned
     /// via the callback.
-    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode;
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode;
+
+    /// Like `send`, but sends the packet from `src` instead of the source
+    /// address the interface picks.
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dest: IPAddr,
+        icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode;
 }
 
 /// A struct that implements the `ICMP6Sender` trait.
@@ -68,12 +79,26 @@ impl<'a, T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         self.client.set(Some(client));
     }
 
-    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode {
+    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode {
         let total_len = buf.len() + icmp_header.get_hdr_size();
         icmp_header.set_len(total_len as u16);
         let transport_header = TransportHeader::ICMP(icmp_header);
         self.ip_send_struct.send_to(dest, transport_header, buf)
     }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dest: IPAddr,
+        mut icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode {
+        let total_len = buf.len() + icmp_header.get_hdr_size();
+        icmp_header.set_len(total_len as u16);
+        let transport_header = TransportHeader::ICMP(icmp_header);
+        self.ip_send_struct
+            .send_from(src, dest, transport_header, buf)
+    }
 }
 
 impl<'a, T: IP6Sender<'a>> IP6Client for ICMP6SendStruct<'a, T> {
@@ -1,2 +1,7 @@
+pub mod driver;
 pub mod icmpv6;
+pub mod icmpv6_handler;
+pub mod icmpv6_recv;
 pub mod icmpv6_send;
+pub mod nd_host;
+pub mod ndp;
@@ -0,0 +1,493 @@
+//! This file implements the host side of 6LoWPAN Neighbor Discovery
+//! (RFC 6775), with which a node joins a border router without manual
+//! configuration. The [NDHost](struct.NDHost.html):
+//!
+//! 1. Sends Router Solicitations until a router answers with a Router
+//!    Advertisement, backing off from `RTR_SOLICITATION_INTERVAL` to
+//!    `MAX_RTR_SOLICITATION_INTERVAL` seconds.
+//! 2. Adds the advertising routers to the neighbor cache of the
+//!    `IP6Interface`, from which the default router is picked, and solicits
+//!    them again before their lifetime runs out.
+//! 3. Forms an address from the first autonomous /64 prefix advertised and
+//!    the interface identifier of the link-local address, and registers it
+//!    with the router through a Neighbor Solicitation carrying an Address
+//!    Registration option (ARO). The address is added to the interface once
+//!    the router accepts it, and registered again before the registration
+//!    expires.
+//! 4. Keeps the 6LoWPAN context table of the interface in line with the
+//!    6LoWPAN Context options (6CO) in Router Advertisements. Contexts are
+//!    removed when their valid lifetime runs out or a router advertises them
+//!    with a lifetime of zero; context 0, which the interface always has,
+//!    is only no longer used for compression.
+//!
+//! Only the autoconfigured address is registered: the link-local address is
+//! formed from the EUI-64, so it needs no duplicate detection. If the router
+//! rejects the address as a duplicate, the host gives up on it; if the
+//! router does not answer or has no room for it, the host drops the router
+//! and solicits another one.
+//!
+//! Neighbor Discovery messages reach the host through an `ICMP6Handler`, and
+//! it sends through its own `ICMP6Sender`, which must send over the same
+//! interface.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let nd_host = static_init!(
+//!     NDHost<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     NDHost::new(nd_icmp_send, interface, nd_alarm, &mut ND_BUF));
+//! nd_alarm.set_client(nd_host);
+//! icmp_handler.set_nd_client(nd_host);
+//! nd_host.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use net::icmpv6::icmpv6_send::ICMP6Sender;
+use net::icmpv6::ndp::{self, NDOption, NDOptions};
+use net::ieee802154::MacAddress;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_interface::IP6Interface;
+use net::ipv6::neighbor_cache::Neighbor;
+use net::sixlowpan::sixlowpan_compression::{Context, ContextStore};
+
+/// Seconds between the first Router Solicitations
+pub const RTR_SOLICITATION_INTERVAL: u32 = 10;
+/// Router Solicitations sent before backing off
+pub const MAX_RTR_SOLICITATIONS: u32 = 3;
+/// The longest interval between Router Solicitations, in seconds
+pub const MAX_RTR_SOLICITATION_INTERVAL: u32 = 60;
+/// Seconds between Neighbor Solicitations for a registration
+pub const RETRANS_TIMER: u32 = 1;
+/// Neighbor Solicitations sent for a registration before giving up
+pub const MAX_UNICAST_SOLICIT: u8 = 3;
+/// The requested registration lifetime, in units of 60 seconds
+pub const REGISTRATION_LIFETIME: u16 = 60;
+
+/// The longest the alarm is set for, in seconds, so that the alarm counter
+/// never wraps between two readings
+const MAX_SLEEP: u32 = 3600;
+
+/// The number of 6LoWPAN context IDs
+const NUM_CONTEXT_IDS: usize = 16;
+
+/// Neighbor Discovery messages are sent with, and must arrive with, the
+/// highest hop limit, so they can't come from off the link
+const ND_HOP_LIMIT: u8 = 255;
+
+#[derive(Copy, Clone)]
+enum Registration {
+    Idle,
+    /// Neighbor Solicitations are being sent for `addr`
+    Pending {
+        addr: IPAddr,
+        router: IPAddr,
+        tries: u8,
+    },
+    Registered {
+        addr: IPAddr,
+    },
+}
+
+pub struct NDHost<'a, A: Alarm + 'a> {
+    icmp_sender: &'a ICMP6Sender<'a>,
+    interface: &'a IP6Interface<'a>,
+    alarm: &'a A,
+    /// Scratch space for the bodies of outgoing messages
+    buf: TakeCell<'static, [u8]>,
+
+    /// Seconds since `start`, as of the alarm reading `last_tick`
+    seconds: Cell<u32>,
+    last_tick: Cell<u32>,
+
+    /// When to send the next Router Solicitation, if any
+    rs_at: Cell<Option<u32>>,
+    rs_count: Cell<u32>,
+
+    registration: Cell<Registration>,
+    /// When to send the next Neighbor Solicitation, if any
+    ns_at: Cell<Option<u32>>,
+
+    /// When each context advertised by the routers expires, by context ID
+    context_expiry: Cell<[Option<u32>; NUM_CONTEXT_IDS]>,
+}
+
+impl<'a, A: Alarm + 'a> NDHost<'a, A> {
+    pub fn new(
+        icmp_sender: &'a ICMP6Sender<'a>,
+        interface: &'a IP6Interface<'a>,
+        alarm: &'a A,
+        buf: &'static mut [u8],
+    ) -> NDHost<'a, A> {
+        NDHost {
+            icmp_sender: icmp_sender,
+            interface: interface,
+            alarm: alarm,
+            buf: TakeCell::new(buf),
+            seconds: Cell::new(0),
+            last_tick: Cell::new(0),
+            rs_at: Cell::new(None),
+            rs_count: Cell::new(0),
+            registration: Cell::new(Registration::Idle),
+            ns_at: Cell::new(None),
+            context_expiry: Cell::new([None; NUM_CONTEXT_IDS]),
+        }
+    }
+
+    /// Starts soliciting routers. The link-local address of the interface
+    /// must be configured first.
+    pub fn start(&self) {
+        self.last_tick.set(self.alarm.now());
+        self.seconds.set(0);
+        self.solicit_routers();
+    }
+
+    /// Returns the number of seconds since `start`.
+    fn now(&self) -> u32 {
+        let freq = A::Frequency::frequency();
+        let elapsed = self.alarm.now().wrapping_sub(self.last_tick.get()) / freq;
+        self.last_tick
+            .set(self.last_tick.get().wrapping_add(elapsed * freq));
+        self.seconds.set(self.seconds.get() + elapsed);
+        self.seconds.get()
+    }
+
+    /// Sends a Router Solicitation now, and keeps sending them until a
+    /// router answers.
+    fn solicit_routers(&self) {
+        self.rs_count.set(0);
+        self.rs_at.set(Some(self.now()));
+        self.run();
+    }
+
+    /// Handles the timers that are due and sets the alarm for the next one.
+    fn run(&self) {
+        let now = self.now();
+
+        if self.interface.get_neighbor_cache().expire(now)
+            && self.interface.get_neighbor_cache().default_router().is_none()
+        {
+            // The last router is gone
+            self.rs_count.set(0);
+            self.rs_at.set(Some(now));
+        }
+
+        let mut context_expiry = self.context_expiry.get();
+        for (id, expiry) in context_expiry.iter_mut().enumerate() {
+            if expiry.map_or(false, |at| at <= now) {
+                *expiry = None;
+                self.remove_context(id as u8);
+            }
+        }
+        self.context_expiry.set(context_expiry);
+
+        // Before the solicitations, as a failed registration solicits
+        // routers right away
+        if self.ns_at.get().map_or(false, |at| at <= now) {
+            self.ns_at.set(None);
+            self.registration_timeout();
+        }
+
+        if self.rs_at.get().map_or(false, |at| at <= now) {
+            self.send_rs();
+            let count = self.rs_count.get() + 1;
+            self.rs_count.set(count);
+            let interval = if count < MAX_RTR_SOLICITATIONS {
+                RTR_SOLICITATION_INTERVAL
+            } else {
+                // Double the interval after each further solicitation
+                let backoff = cmp::min(count - MAX_RTR_SOLICITATIONS + 1, 3);
+                cmp::min(
+                    RTR_SOLICITATION_INTERVAL << backoff,
+                    MAX_RTR_SOLICITATION_INTERVAL,
+                )
+            };
+            self.rs_at.set(Some(now + interval));
+        }
+
+        let next = [
+            self.rs_at.get(),
+            self.ns_at.get(),
+            self.interface.get_neighbor_cache().next_expiry(),
+            self.context_expiry.get().iter().filter_map(|at| *at).min(),
+        ].iter()
+            .filter_map(|at| *at)
+            .min();
+        match next {
+            Some(at) => {
+                // At least a second, so that the alarm is in the future
+                let delay = cmp::min(cmp::max(at.saturating_sub(now), 1), MAX_SLEEP);
+                let ticks = delay * A::Frequency::frequency();
+                self.alarm
+                    .set_alarm(self.last_tick.get().wrapping_add(ticks));
+            }
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Sends the Router Solicitation to the default router, or to all
+    /// routers if there is none.
+    fn send_rs(&self) {
+        let dst = self.interface
+            .get_neighbor_cache()
+            .default_router()
+            .map_or(ndp::ALL_ROUTERS_ADDR, |router| router.ip_addr);
+        let src_mac = self.interface.get_src_mac();
+        self.buf.map(|buf| {
+            if let Some((len, _)) = ndp::encode_rs(buf, src_mac).done() {
+                let icmp_header = ICMP6Header::new(ICMP6Type::Type133);
+                self.icmp_sender.send(dst, icmp_header, &buf[..len]);
+            }
+        });
+    }
+
+    /// Sends a Neighbor Solicitation registering `addr` with `router`. It
+    /// is sent from `addr`, as the router registers the source address.
+    fn send_ns(&self, addr: IPAddr, router: IPAddr) {
+        let src_mac = self.interface.get_src_mac();
+        let eui64 = match src_mac {
+            MacAddress::Long(long_addr) => long_addr,
+            MacAddress::Short(_) => return,
+        };
+        self.buf.map(|buf| {
+            let encoded = ndp::encode_ns(buf, addr, src_mac, REGISTRATION_LIFETIME, eui64);
+            if let Some((len, _)) = encoded.done() {
+                let icmp_header = ICMP6Header::new(ICMP6Type::Type135);
+                self.icmp_sender
+                    .send_from(addr, router, icmp_header, &buf[..len]);
+            }
+        });
+    }
+
+    /// Starts registering `addr` with `router`.
+    fn register(&self, addr: IPAddr, router: IPAddr) {
+        self.registration.set(Registration::Pending {
+            addr: addr,
+            router: router,
+            tries: 1,
+        });
+        self.send_ns(addr, router);
+        self.ns_at.set(Some(self.now() + RETRANS_TIMER));
+    }
+
+    /// Called when a Neighbor Solicitation goes unanswered, or when a
+    /// registration is due for renewal.
+    fn registration_timeout(&self) {
+        match self.registration.get() {
+            Registration::Pending {
+                addr,
+                router,
+                tries,
+            } => {
+                if tries < MAX_UNICAST_SOLICIT {
+                    self.registration.set(Registration::Pending {
+                        addr: addr,
+                        router: router,
+                        tries: tries + 1,
+                    });
+                    self.send_ns(addr, router);
+                    self.ns_at.set(Some(self.now() + RETRANS_TIMER));
+                } else {
+                    // The router is unreachable
+                    self.interface.get_neighbor_cache().remove(router);
+                    self.registration_failed(addr);
+                }
+            }
+            Registration::Registered { addr } => {
+                match self.interface.get_neighbor_cache().default_router() {
+                    Some(router) => self.register(addr, router.ip_addr),
+                    None => self.registration_failed(addr),
+                }
+            }
+            Registration::Idle => {}
+        }
+    }
+
+    /// Stops using `addr` and looks for another router to register it with.
+    fn registration_failed(&self, addr: IPAddr) {
+        self.interface.remove_addr(addr);
+        self.registration.set(Registration::Idle);
+        self.ns_at.set(None);
+        self.rs_count.set(0);
+        self.rs_at.set(Some(self.now()));
+    }
+
+    /// Applies a 6LoWPAN Context option to the context table of the
+    /// interface.
+    fn update_context(&self, context: Context, valid_lifetime: u16, now: u32) {
+        let mut context_expiry = self.context_expiry.get();
+        let id = context.id as usize;
+        if valid_lifetime == 0 {
+            context_expiry[id] = None;
+            self.remove_context(context.id);
+        } else if self.interface.add_context(context) == ReturnCode::SUCCESS {
+            context_expiry[id] = Some(now + valid_lifetime as u32 * 60);
+        }
+        self.context_expiry.set(context_expiry);
+    }
+
+    /// Removes the context with ID `id` from the interface, or stops
+    /// compressing with it if it is context 0.
+    fn remove_context(&self, id: u8) {
+        if id != 0 {
+            self.interface.remove_context(id);
+        } else if let Some(context) = self.interface.get_context_from_id(0) {
+            self.interface.add_context(Context {
+                compress: false,
+                ..context
+            });
+        }
+    }
+
+    fn receive_ra(&self, ip6_header: &IP6Header, router_lifetime: u16, body: &[u8]) {
+        let router = ip6_header.src_addr;
+        if !router.is_unicast_link_local() || body.len() < ndp::RA_OPTIONS_OFFSET {
+            return;
+        }
+        let now = self.now();
+
+        let mut router_mac = None;
+        let mut prefix = None;
+        for option in NDOptions::new(&body[ndp::RA_OPTIONS_OFFSET..]) {
+            match option {
+                NDOption::SourceLinkLayerAddr(mac_addr) => router_mac = Some(mac_addr),
+                NDOption::PrefixInfo {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    prefix: ref pio_prefix,
+                    ..
+                } => {
+                    if prefix.is_none() && prefix_len == 64
+                        && flags & ndp::PREFIX_AUTONOMOUS != 0 && valid_lifetime > 0
+                    {
+                        prefix = Some(*pio_prefix);
+                    }
+                }
+                NDOption::SixlowpanContext {
+                    context_len,
+                    flags,
+                    valid_lifetime,
+                    prefix: ref context_prefix,
+                } => {
+                    let context = Context {
+                        prefix: *context_prefix,
+                        prefix_len: context_len,
+                        id: flags & ndp::CONTEXT_ID_MASK,
+                        compress: flags & ndp::CONTEXT_COMPRESS != 0,
+                    };
+                    self.update_context(context, valid_lifetime, now);
+                }
+                _ => {}
+            }
+        }
+        if router_lifetime == 0 {
+            // The router is no longer a default router
+            self.interface.get_neighbor_cache().remove(router);
+            self.run();
+            return;
+        }
+        // Without the option, the router is reached at the address its
+        // link-local address is formed from
+        let router_mac = match router_mac.or(self.interface.get_dst_mac(router)) {
+            Some(router_mac) => router_mac,
+            None => return,
+        };
+
+        self.interface.get_neighbor_cache().update(Neighbor {
+            ip_addr: router,
+            mac_addr: router_mac,
+            is_router: true,
+            expires: now + router_lifetime as u32,
+        });
+        // Solicit the router again once most of its lifetime has passed
+        self.rs_count.set(0);
+        self.rs_at
+            .set(Some(now + cmp::max(router_lifetime as u32 * 3 / 4, 1)));
+
+        let link_local = self.interface.get_link_local_addr();
+        if let (Some(prefix), Some(mut addr), Registration::Idle) =
+            (prefix, link_local, self.registration.get())
+        {
+            addr.0[..8].copy_from_slice(&prefix[..8]);
+            self.register(addr, router);
+        }
+        self.run();
+    }
+
+    fn receive_na(&self, ip6_header: &IP6Header, body: &[u8]) {
+        let (pending_addr, router) = match self.registration.get() {
+            Registration::Pending { addr, router, .. } => (addr, router),
+            _ => return,
+        };
+        let target = match ndp::decode_target(body).done() {
+            Some((_, target)) => target,
+            None => return,
+        };
+        if target.0 != pending_addr.0 || ip6_header.src_addr.0 != router.0 {
+            return;
+        }
+        let aro = NDOptions::new(&body[ndp::NS_OPTIONS_OFFSET..]).find(|option| match *option {
+            NDOption::AddrRegistration { .. } => true,
+            _ => false,
+        });
+        let (status, lifetime) = match aro {
+            Some(NDOption::AddrRegistration {
+                status, lifetime, ..
+            }) => (status, lifetime),
+            _ => return,
+        };
+
+        match status {
+            ndp::ARO_SUCCESS => {
+                self.interface.add_addr(pending_addr);
+                self.registration
+                    .set(Registration::Registered { addr: pending_addr });
+                // Renew the registration once most of its lifetime has passed
+                let lifetime = lifetime as u32 * 60;
+                self.ns_at
+                    .set(Some(self.now() + cmp::max(lifetime - lifetime / 5, 1)));
+            }
+            ndp::ARO_DUPLICATE => {
+                // Another node owns the address: give up on it
+                self.interface.remove_addr(pending_addr);
+                self.registration.set(Registration::Idle);
+                self.ns_at.set(None);
+            }
+            _ => {
+                // Try another router
+                self.interface.get_neighbor_cache().remove(router);
+                self.registration_failed(pending_addr);
+            }
+        }
+        self.run();
+    }
+}
+
+impl<'a, A: Alarm + 'a> ICMP6RecvClient for NDHost<'a, A> {
+    fn receive(&self, ip6_header: IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        if ip6_header.get_hop_limit() != ND_HOP_LIMIT || icmp_header.get_code() != 0 {
+            return;
+        }
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => self.receive_ra(&ip6_header, router_lifetime, payload),
+            ICMP6HeaderOptions::Type136 { .. } => self.receive_na(&ip6_header, payload),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for NDHost<'a, A> {
+    fn fired(&self) {
+        self.run();
+    }
+}
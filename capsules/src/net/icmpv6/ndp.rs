@@ -0,0 +1,338 @@
+//! This file contains the formats of the Neighbor Discovery messages that a
+//! 6LoWPAN host exchanges with its routers (RFC 4861, as optimized by
+//! RFC 6775):
+//!
+//! * Router Solicitation (`ICMP6Type::Type133`), carrying a Source Link-Layer
+//!   Address option,
+//! * Router Advertisement (`ICMP6Type::Type134`), carrying the router's
+//!   link-layer address, the prefixes it offers, and the 6LoWPAN contexts
+//!   of the network in 6LoWPAN Context options (6CO),
+//! * Neighbor Solicitation (`ICMP6Type::Type135`), with which the host
+//!   registers an address through the Address Registration option (ARO), and
+//! * Neighbor Advertisement (`ICMP6Type::Type136`), which carries the result
+//!   of the registration in an ARO.
+//!
+//! The fixed fields of each message are part of the `ICMP6Header`; this file
+//! encodes and decodes the rest of the message body and the options that
+//! follow it.
+//!
+//! Link-layer addresses are encoded as specified for 802.15.4 in RFC 4944:
+//! a short address takes an 8-byte option and a long address a 16-byte one.
+
+use net::icmpv6::icmpv6::ICMP6Type;
+use net::ieee802154::MacAddress;
+use net::ipv6::ip_utils::IPAddr;
+use net::stream::SResult;
+use net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// The all-routers multicast address, to which Router Solicitations are sent
+pub const ALL_ROUTERS_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// The offset of the options in the body of a Router Advertisement, after the
+/// Reachable Time and Retrans Timer fields
+pub const RA_OPTIONS_OFFSET: usize = 8;
+
+/// The offset of the options in the body of a Neighbor Solicitation or
+/// Advertisement, after the Target Address field
+pub const NS_OPTIONS_OFFSET: usize = 16;
+
+/// The Autonomous flag of a Prefix Information option
+pub const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+/// The Compression flag of a 6LoWPAN Context option: the context may be used
+/// to compress, and not only to decompress
+pub const CONTEXT_COMPRESS: u8 = 0x10;
+/// The bits of the flags of a 6LoWPAN Context option that hold the context
+/// ID
+pub const CONTEXT_ID_MASK: u8 = 0x0f;
+
+/// The registration succeeded
+pub const ARO_SUCCESS: u8 = 0;
+/// The address is already registered by another node
+pub const ARO_DUPLICATE: u8 = 1;
+/// The router has no room left to register the address
+pub const ARO_CACHE_FULL: u8 = 2;
+
+mod opt {
+    pub const SRC_LL_ADDR: u8 = 1;
+    pub const TGT_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+    pub const ADDR_REGISTRATION: u8 = 33;
+    pub const SIXLOWPAN_CONTEXT: u8 = 34;
+}
+
+/// Options are sized in units of 8 bytes
+const OPT_UNIT: usize = 8;
+
+/// A Neighbor Discovery option.
+#[derive(Copy, Clone, Debug)]
+pub enum NDOption {
+    SourceLinkLayerAddr(MacAddress),
+    TargetLinkLayerAddr(MacAddress),
+    PrefixInfo {
+        prefix_len: u8,
+        flags: u8,
+        /// In seconds
+        valid_lifetime: u32,
+        /// In seconds
+        preferred_lifetime: u32,
+        prefix: [u8; 16],
+    },
+    AddrRegistration {
+        status: u8,
+        /// In units of 60 seconds
+        lifetime: u16,
+        eui64: [u8; 8],
+    },
+    SixlowpanContext {
+        /// In bits
+        context_len: u8,
+        /// The Compression flag and the context ID
+        flags: u8,
+        /// In units of 60 seconds
+        valid_lifetime: u16,
+        prefix: [u8; 16],
+    },
+    /// An option this file does not decode, with its type
+    Unknown(u8),
+}
+
+fn ll_addr_len(mac_addr: &MacAddress) -> usize {
+    match *mac_addr {
+        MacAddress::Short(_) => OPT_UNIT,
+        MacAddress::Long(_) => 2 * OPT_UNIT,
+    }
+}
+
+fn encode_ll_addr(buf: &mut [u8], opt_type: u8, mac_addr: &MacAddress) -> SResult<usize> {
+    let len = ll_addr_len(mac_addr);
+    stream_len_cond!(buf, len);
+    let mut off = enc_consume!(buf; encode_u8, opt_type);
+    off = enc_consume!(buf, off; encode_u8, (len / OPT_UNIT) as u8);
+    off = match *mac_addr {
+        MacAddress::Short(short_addr) => enc_consume!(buf, off; encode_u16, short_addr),
+        MacAddress::Long(ref long_addr) => enc_consume!(buf, off; encode_bytes, long_addr),
+    };
+    // Padding
+    for byte in buf[off..len].iter_mut() {
+        *byte = 0;
+    }
+    stream_done!(len, len);
+}
+
+fn decode_ll_addr(buf: &[u8], len: usize) -> SResult<MacAddress> {
+    if len == OPT_UNIT {
+        let (_, short_addr) = dec_try!(buf; decode_u16);
+        stream_done!(len, MacAddress::Short(short_addr));
+    } else {
+        let mut long_addr = [0; 8];
+        dec_consume!(buf; decode_bytes, &mut long_addr);
+        stream_done!(len, MacAddress::Long(long_addr));
+    }
+}
+
+impl NDOption {
+    /// Serializes the option into `buf`, returning the number of bytes
+    /// written.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        match *self {
+            NDOption::SourceLinkLayerAddr(ref mac_addr) => {
+                encode_ll_addr(buf, opt::SRC_LL_ADDR, mac_addr)
+            }
+            NDOption::TargetLinkLayerAddr(ref mac_addr) => {
+                encode_ll_addr(buf, opt::TGT_LL_ADDR, mac_addr)
+            }
+            NDOption::PrefixInfo { .. }
+            | NDOption::SixlowpanContext { .. }
+            | NDOption::Unknown(_) => {
+                // Only routers send these
+                SResult::Error(())
+            }
+            NDOption::AddrRegistration {
+                status,
+                lifetime,
+                ref eui64,
+            } => {
+                stream_len_cond!(buf, 2 * OPT_UNIT);
+                let mut off = enc_consume!(buf; encode_u8, opt::ADDR_REGISTRATION);
+                off = enc_consume!(buf, off; encode_u8, 2);
+                off = enc_consume!(buf, off; encode_u8, status);
+                // Reserved
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u16, 0);
+                off = enc_consume!(buf, off; encode_u16, lifetime);
+                off = enc_consume!(buf, off; encode_bytes, eui64);
+                stream_done!(off, off);
+            }
+        }
+    }
+
+    /// Deserializes the option at the start of `buf`. The offset returned is
+    /// that of the next option.
+    pub fn decode(buf: &[u8]) -> SResult<NDOption> {
+        let (off, opt_type) = dec_try!(buf; decode_u8);
+        let (off, units) = dec_try!(buf, off; decode_u8);
+        let len = units as usize * OPT_UNIT;
+        // A zero length would make the options loop forever
+        stream_cond!(len > 0, ());
+        stream_len_cond!(buf, len);
+        let buf = &buf[..len];
+
+        match opt_type {
+            opt::SRC_LL_ADDR => {
+                let (_, mac_addr) = dec_try!(buf, off; decode_ll_addr, len);
+                stream_done!(len, NDOption::SourceLinkLayerAddr(mac_addr));
+            }
+            opt::TGT_LL_ADDR => {
+                let (_, mac_addr) = dec_try!(buf, off; decode_ll_addr, len);
+                stream_done!(len, NDOption::TargetLinkLayerAddr(mac_addr));
+            }
+            opt::PREFIX_INFO => {
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                // Reserved
+                let (off, _) = dec_try!(buf, off; decode_u32);
+                let mut prefix = [0; 16];
+                dec_consume!(buf, off; decode_bytes, &mut prefix);
+                stream_done!(
+                    len,
+                    NDOption::PrefixInfo {
+                        prefix_len: prefix_len,
+                        flags: flags,
+                        valid_lifetime: valid_lifetime,
+                        preferred_lifetime: preferred_lifetime,
+                        prefix: prefix,
+                    }
+                );
+            }
+            opt::ADDR_REGISTRATION => {
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                // Reserved
+                let (off, _) = dec_try!(buf, off; decode_u8);
+                let (off, _) = dec_try!(buf, off; decode_u16);
+                let (off, lifetime) = dec_try!(buf, off; decode_u16);
+                let mut eui64 = [0; 8];
+                dec_consume!(buf, off; decode_bytes, &mut eui64);
+                stream_done!(
+                    len,
+                    NDOption::AddrRegistration {
+                        status: status,
+                        lifetime: lifetime,
+                        eui64: eui64,
+                    }
+                );
+            }
+            opt::SIXLOWPAN_CONTEXT => {
+                let (off, context_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                // Reserved
+                let (off, _) = dec_try!(buf, off; decode_u16);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u16);
+                // The prefix is 8 bytes long, or 16 if it is longer than 64
+                // bits
+                let prefix_len = len - off;
+                stream_cond!(prefix_len <= 16 && prefix_len * 8 >= context_len as usize, ());
+                let mut prefix = [0; 16];
+                dec_consume!(buf, off; decode_bytes, &mut prefix[..prefix_len]);
+                stream_done!(
+                    len,
+                    NDOption::SixlowpanContext {
+                        context_len: context_len,
+                        flags: flags,
+                        valid_lifetime: valid_lifetime,
+                        prefix: prefix,
+                    }
+                );
+            }
+            _ => stream_done!(len, NDOption::Unknown(opt_type)),
+        }
+    }
+}
+
+/// An iterator over the options in a buffer. It stops at the first option
+/// that can't be decoded.
+pub struct NDOptions<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> NDOptions<'a> {
+    pub fn new(buf: &'a [u8]) -> NDOptions<'a> {
+        NDOptions { buf: buf }
+    }
+}
+
+impl<'a> Iterator for NDOptions<'a> {
+    type Item = NDOption;
+
+    fn next(&mut self) -> Option<NDOption> {
+        match NDOption::decode(self.buf) {
+            SResult::Done(off, option) => {
+                self.buf = &self.buf[off..];
+                Some(option)
+            }
+            _ => {
+                self.buf = &[];
+                None
+            }
+        }
+    }
+}
+
+/// Returns the offset of the options in the body of a message of type
+/// `icmp_type`, or `None` if it is not a Neighbor Discovery message.
+pub fn options_offset(icmp_type: ICMP6Type) -> Option<usize> {
+    match icmp_type {
+        ICMP6Type::Type133 => Some(0),
+        ICMP6Type::Type134 => Some(RA_OPTIONS_OFFSET),
+        ICMP6Type::Type135 | ICMP6Type::Type136 => Some(NS_OPTIONS_OFFSET),
+        _ => None,
+    }
+}
+
+/// Serializes the body of a Router Solicitation into `buf`.
+///
+/// # Arguments
+///
+/// `src_mac` - The link-layer address the solicitation is sent from
+pub fn encode_rs(buf: &mut [u8], src_mac: MacAddress) -> SResult<usize> {
+    let off = enc_consume!(buf; NDOption::SourceLinkLayerAddr(src_mac); encode);
+    stream_done!(off, off);
+}
+
+/// Serializes the body of a Neighbor Solicitation that registers `target`
+/// into `buf`.
+///
+/// # Arguments
+///
+/// `target` - The address being registered
+/// `src_mac` - The link-layer address the solicitation is sent from
+/// `lifetime` - The registration lifetime, in units of 60 seconds
+/// `eui64` - The EUI-64 of the host
+pub fn encode_ns(
+    buf: &mut [u8],
+    target: IPAddr,
+    src_mac: MacAddress,
+    lifetime: u16,
+    eui64: [u8; 8],
+) -> SResult<usize> {
+    let mut off = enc_consume!(buf; encode_bytes, &target.0);
+    off = enc_consume!(buf, off; NDOption::SourceLinkLayerAddr(src_mac); encode);
+    off = enc_consume!(buf, off; NDOption::AddrRegistration {
+        status: ARO_SUCCESS,
+        lifetime: lifetime,
+        eui64: eui64,
+    }; encode);
+    stream_done!(off, off);
+}
+
+/// Deserializes the Target Address of a Neighbor Solicitation or
+/// Advertisement.
+pub fn decode_target(buf: &[u8]) -> SResult<IPAddr> {
+    let mut target = IPAddr::new();
+    let off = dec_consume!(buf; decode_bytes, &mut target.0);
+    stream_done!(off, target);
+}
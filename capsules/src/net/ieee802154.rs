@@ -81,6 +81,21 @@ impl FrameType {
     }
 }
 
+/// The command frame identifiers of IEEE 802.15.4-2015, Table 7-49
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MacCommand {
+    AssociationRequest = 0x01,
+    AssociationResponse = 0x02,
+    DisassociationNotification = 0x03,
+    DataRequest = 0x04,
+    PanIdConflictNotification = 0x05,
+    OrphanNotification = 0x06,
+    BeaconRequest = 0x07,
+    CoordinatorRealignment = 0x08,
+    GtsRequest = 0x09,
+}
+
 #[repr(u16)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum FrameVersion {
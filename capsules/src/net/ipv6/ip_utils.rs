@@ -4,6 +4,7 @@
 
 use net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
 use net::ipv6::ipv6::IP6Header;
+use net::tcp::tcp::TCPHeader;
 use net::udp::udp::UDPHeader;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -111,9 +112,15 @@ pub fn compute_udp_checksum(
     //Now just need to iterate thru data and add it to the sum
     {
         let mut i: usize = 0;
-        while i < ((udp_length - 8) as usize) {
+        let payload_len = (udp_length - 8) as usize;
+        while i < payload_len {
             let msb_dat: u16 = ((payload[i]) as u16) << 8;
-            let lsb_dat: u16 = payload[i + 1] as u16;
+            // An odd-length payload is padded with a zero byte
+            let lsb_dat: u16 = if i + 1 < payload_len {
+                payload[i + 1] as u16
+            } else {
+                0
+            };
             let temp_dat: u16 = msb_dat + lsb_dat;
             sum += temp_dat as u32;
 
@@ -152,7 +159,10 @@ pub fn compute_icmp_checksum(
 
     // add options
     match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+        ICMP6HeaderOptions::Type1 { unused }
+        | ICMP6HeaderOptions::Type3 { unused }
+        | ICMP6HeaderOptions::Type133 { unused }
+        | ICMP6HeaderOptions::Type135 { unused } => {
             sum += unused >> 16; // upper 16 bits
             sum += unused & 0xffff; // lower 16 bits
         }
@@ -160,6 +170,17 @@ pub fn compute_icmp_checksum(
             sum += id as u32;
             sum += seqno as u32;
         }
+        ICMP6HeaderOptions::Type134 {
+            cur_hop_limit,
+            flags,
+            router_lifetime,
+        } => {
+            sum += ((cur_hop_limit as u32) << 8) + flags as u32;
+            sum += router_lifetime as u32;
+        }
+        ICMP6HeaderOptions::Type136 { flags } => {
+            sum += (flags as u32) << 8;
+        }
     }
 
     // add icmp payload
@@ -170,7 +191,47 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
+    }
+
+    sum = !sum;
+    sum = sum & 0xffff;
+
+    sum as u16
+}
+
+/// Computes the checksum of a TCP segment. The header is encoded without
+/// options, so only its fixed fields are summed.
+pub fn compute_tcp_checksum(
+    ip6_header: &IP6Header,
+    tcp_header: &TCPHeader,
+    payload: &[u8],
+) -> u16 {
+    let mut sum: u32 = 0;
+
+    // add ipv6 pseudo-header
+    sum += compute_ipv6_ph_sum(ip6_header);
+
+    // add the header, except for the checksum
+    sum += tcp_header.src_port as u32;
+    sum += tcp_header.dst_port as u32;
+    sum += tcp_header.seq_num >> 16;
+    sum += tcp_header.seq_num & 0xffff;
+    sum += tcp_header.ack_num >> 16;
+    sum += tcp_header.ack_num & 0xffff;
+    sum += tcp_header.offset_and_control as u32;
+    sum += tcp_header.window as u32;
+    sum += tcp_header.urg_ptr as u32;
+
+    // add tcp payload
+    let payload_len = tcp_header.get_len() - tcp_header.get_hdr_size() as u16;
+    sum += compute_sum(payload, payload_len);
+
+    // carry overflow
+    while sum > 0xffff {
+        let sum_upper = sum >> 16;
+        let sum_lower = sum & 0xffff;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -196,7 +257,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -208,7 +269,12 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd-length buffer is padded with a zero byte
+        let lsb = if i + 1 < (len as usize) {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
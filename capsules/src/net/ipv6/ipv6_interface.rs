@@ -0,0 +1,321 @@
+//! This file contains the state of an IPv6 network interface over
+//! 802.15.4. The [IP6Interface](struct.IP6Interface.html) holds:
+//!
+//! * the IPv6 addresses of the node: a link-local address, formed from the
+//!   radio's long (EUI-64) address, and up to `MAX_ADDRS - 1` configured
+//!   addresses,
+//! * the neighbor cache, from which the link-layer addresses of neighbors
+//!   and of the default router are taken,
+//! * the link-layer address of a configured default gateway, to which packets
+//!   for destinations off the link are sent if there is no default router,
+//! * the link-layer security that packets are sent with, and
+//! * the 6LoWPAN context table, which the interface implements
+//!   `ContextStore` for, so that it can be passed to `Sixlowpan`.
+//!
+//! `IP6SendStruct` asks the interface for the source address of each packet,
+//! and for the link-layer addresses it is sent from and to.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let interface = static_init!(
+//!     IP6Interface<'static>,
+//!     IP6Interface::new(radio_mac, sixlowpan_compression::Context {
+//!         prefix: [0x0; 16],
+//!         prefix_len: 8,
+//!         id: 0,
+//!         compress: false,
+//!     }));
+//! interface.autoconfigure_link_local();
+//! interface.set_gateway(MacAddress::Short(0x0001));
+//! ```
+
+use core::cell::Cell;
+use ieee802154::device::MacDevice;
+use kernel::common::take_cell::MapCell;
+use kernel::ReturnCode;
+use net::ieee802154::{KeyId, MacAddress, SecurityLevel};
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::neighbor_cache::NeighborCache;
+use net::sixlowpan::sixlowpan_compression::{self, Context, ContextStore};
+use net::util;
+
+/// The maximum number of IPv6 addresses, including the link-local address
+pub const MAX_ADDRS: usize = 4;
+
+/// The maximum number of 6LoWPAN contexts, including context 0
+pub const MAX_CONTEXTS: usize = 4;
+
+/// The 802.15.4 broadcast address, to which multicast packets are sent
+const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// The first bytes of an interface identifier formed from a short address
+const SHORT_ADDR_IID: [u8; 6] = [0, 0, 0, 0xff, 0xfe, 0];
+
+pub struct IP6Interface<'a> {
+    mac: &'a MacDevice<'a>,
+    /// The link-local address comes first
+    addrs: MapCell<[Option<IPAddr>; MAX_ADDRS]>,
+    neighbors: NeighborCache,
+    gateway: Cell<Option<MacAddress>>,
+    security: Cell<Option<(SecurityLevel, KeyId)>>,
+    /// Context 0 comes first
+    contexts: MapCell<[Option<Context>; MAX_CONTEXTS]>,
+}
+
+impl<'a> IP6Interface<'a> {
+    /// Creates an interface without addresses. `context_0` must hold the
+    /// mesh-local prefix, as required by `ContextStore`.
+    pub fn new(mac: &'a MacDevice<'a>, context_0: Context) -> IP6Interface<'a> {
+        let mut contexts = [None; MAX_CONTEXTS];
+        contexts[0] = Some(Context { id: 0, ..context_0 });
+        IP6Interface {
+            mac: mac,
+            addrs: MapCell::new([None; MAX_ADDRS]),
+            neighbors: NeighborCache::new(),
+            gateway: Cell::new(None),
+            security: Cell::new(None),
+            contexts: MapCell::new(contexts),
+        }
+    }
+
+    /// Forms the link-local address from the radio's long address. Call this
+    /// again if the long address changes.
+    pub fn autoconfigure_link_local(&self) {
+        let iid =
+            sixlowpan_compression::compute_iid(&MacAddress::Long(self.mac.get_address_long()));
+        let mut link_local = IPAddr::new();
+        link_local.set_unicast_link_local();
+        link_local.0[8..16].copy_from_slice(&iid);
+        self.addrs.map(|addrs| addrs[0] = Some(link_local));
+    }
+
+    pub fn get_link_local_addr(&self) -> Option<IPAddr> {
+        self.addrs.and_then(|addrs| addrs[0])
+    }
+
+    /// Adds a configured address.
+    ///
+    /// # Return Value
+    /// `EALREADY` if the interface already has the address, `EINVAL` if it is
+    /// link-local or multicast, and `ENOMEM` if there is no room for it.
+    pub fn add_addr(&self, addr: IPAddr) -> ReturnCode {
+        if addr.is_unicast_link_local() || addr.is_multicast() || addr.is_unspecified() {
+            return ReturnCode::EINVAL;
+        }
+        self.addrs.map_or(ReturnCode::FAIL, |addrs| {
+            if addrs[1..]
+                .iter()
+                .any(|a| a.map_or(false, |a| a.0 == addr.0))
+            {
+                return ReturnCode::EALREADY;
+            }
+            match addrs[1..].iter_mut().find(|a| a.is_none()) {
+                Some(slot) => {
+                    *slot = Some(addr);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOMEM,
+            }
+        })
+    }
+
+    /// Removes a configured address.
+    pub fn remove_addr(&self, addr: IPAddr) -> ReturnCode {
+        self.addrs.map_or(ReturnCode::FAIL, |addrs| {
+            match addrs[1..]
+                .iter_mut()
+                .find(|a| a.map_or(false, |a| a.0 == addr.0))
+            {
+                Some(slot) => {
+                    *slot = None;
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Returns whether `addr` is one of the addresses of the interface.
+    pub fn has_addr(&self, addr: IPAddr) -> bool {
+        self.addrs.map_or(false, |addrs| {
+            addrs.iter().any(|a| a.map_or(false, |a| a.0 == addr.0))
+        })
+    }
+
+    /// Picks the source address for packets to `dst`: the link-local
+    /// address for link-local destinations, and otherwise the configured
+    /// address sharing the longest prefix with `dst`. Falls back to the
+    /// link-local address, and to the unspecified address if the interface
+    /// has no address at all.
+    pub fn get_src_addr(&self, dst: IPAddr) -> IPAddr {
+        let link_local = self.get_link_local_addr();
+        // Multicast scopes below 3 (interface- and link-local) stay on the link
+        if dst.is_unicast_link_local() || (dst.is_multicast() && (dst.0[1] & 0x0f) < 3) {
+            return link_local.unwrap_or(IPAddr::new());
+        }
+        self.addrs
+            .and_then(|addrs| {
+                addrs[1..]
+                    .iter()
+                    .filter_map(|a| *a)
+                    .max_by_key(|a| common_prefix_len(&a.0, &dst.0))
+            })
+            .or(link_local)
+            .unwrap_or(IPAddr::new())
+    }
+
+    pub fn get_neighbor_cache(&self) -> &NeighborCache {
+        &self.neighbors
+    }
+
+    /// Sets the link-layer address of the default gateway, which is used
+    /// when the neighbor cache holds no router.
+    pub fn set_gateway(&self, gateway: MacAddress) {
+        self.gateway.set(Some(gateway));
+    }
+
+    pub fn get_gateway(&self) -> Option<MacAddress> {
+        self.gateway.get()
+    }
+
+    /// Sets the security level and key that frames are sent with, or `None`
+    /// to send them unsecured. The key must be known to the key procedure of
+    /// the `Framer`, or sending fails.
+    pub fn set_link_security(&self, security: Option<(SecurityLevel, KeyId)>) {
+        self.security.set(security);
+    }
+
+    pub fn get_link_security(&self) -> Option<(SecurityLevel, KeyId)> {
+        self.security.get()
+    }
+
+    /// The link-layer address packets are sent from. It is the long address,
+    /// from which the link-local address is formed.
+    pub fn get_src_mac(&self) -> MacAddress {
+        MacAddress::Long(self.mac.get_address_long())
+    }
+
+    /// Picks the link-layer address to send packets for `dst` to. Multicast
+    /// packets are broadcast, and neighbors in the cache are reached at their
+    /// cached address. Other link-local destinations are reached at the
+    /// address their interface identifier is formed from, and everything
+    /// else goes through the default router, or the gateway if there is
+    /// none. Returns `None` if `dst` is off the link and there is neither.
+    pub fn get_dst_mac(&self, dst: IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(BROADCAST_MAC_ADDR);
+        }
+        if let Some(mac_addr) = self.neighbors.lookup(dst) {
+            return Some(mac_addr);
+        }
+        if dst.is_unicast_link_local() {
+            Some(mac_from_iid(&dst.0[8..16]))
+        } else {
+            self.neighbors
+                .default_router()
+                .map(|router| router.mac_addr)
+                .or(self.gateway.get())
+        }
+    }
+
+    /// Adds a 6LoWPAN context, replacing any context with the same ID.
+    ///
+    /// # Return Value
+    /// `EINVAL` if the ID does not fit in 4 bits, and `ENOMEM` if there is no
+    /// room for the context.
+    pub fn add_context(&self, context: Context) -> ReturnCode {
+        if context.id > 0xf {
+            return ReturnCode::EINVAL;
+        }
+        self.contexts.map_or(ReturnCode::FAIL, |contexts| {
+            let slot = match contexts
+                .iter()
+                .position(|c| c.map_or(false, |c| c.id == context.id))
+            {
+                Some(i) => Some(i),
+                None => contexts.iter().position(|c| c.is_none()),
+            };
+            match slot {
+                Some(i) => {
+                    contexts[i] = Some(context);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOMEM,
+            }
+        })
+    }
+
+    /// Removes the 6LoWPAN context with ID `id`. Context 0 can't be removed.
+    pub fn remove_context(&self, id: u8) -> ReturnCode {
+        if id == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.contexts.map_or(ReturnCode::FAIL, |contexts| {
+            match contexts
+                .iter_mut()
+                .find(|c| c.map_or(false, |c| c.id == id))
+            {
+                Some(slot) => {
+                    *slot = None;
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+}
+
+impl<'a> ContextStore for IP6Interface<'a> {
+    fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
+        // The longest matching prefix wins
+        self.contexts.and_then(|contexts| {
+            contexts
+                .iter()
+                .filter_map(|c| *c)
+                .filter(|c| util::matches_prefix(&ip_addr.0, &c.prefix, c.prefix_len))
+                .max_by_key(|c| c.prefix_len)
+        })
+    }
+
+    fn get_context_from_id(&self, ctx_id: u8) -> Option<Context> {
+        self.contexts
+            .and_then(|contexts| contexts.iter().filter_map(|c| *c).find(|c| c.id == ctx_id))
+    }
+
+    fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context> {
+        self.contexts.and_then(|contexts| {
+            contexts.iter().filter_map(|c| *c).find(|c| {
+                c.prefix_len == prefix_len && util::matches_prefix(prefix, &c.prefix, prefix_len)
+            })
+        })
+    }
+}
+
+/// Inverts `compute_iid`: finds the link-layer address an interface
+/// identifier was formed from.
+fn mac_from_iid(iid: &[u8]) -> MacAddress {
+    if iid[..6] == SHORT_ADDR_IID {
+        MacAddress::Short(util::slice_to_u16(&iid[6..8]))
+    } else {
+        let mut long_addr = [0; 8];
+        long_addr.copy_from_slice(iid);
+        // Flip the universal/local bit back
+        long_addr[0] ^= 0x02;
+        MacAddress::Long(long_addr)
+    }
+}
+
+/// The number of leading bits that `a` and `b` have in common
+fn common_prefix_len(a: &[u8; 16], b: &[u8; 16]) -> u32 {
+    let mut len = 0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        let diff = x ^ y;
+        len += diff.leading_zeros();
+        if diff != 0 {
+            break;
+        }
+    }
+    len
+}
@@ -0,0 +1,200 @@
+//! This file contains the definition and implementation of a simple IPv6
+//! receive layer. The [IP6RecvStruct](struct.IP6RecvStruct.html) is set as
+//! the receive client of the 6LoWPAN layer, and parses every reassembled
+//! packet it is given. Packets with a malformed `IP6Header` or an incorrect
+//! transport checksum are dropped. The remaining UDP, TCP and ICMPv6 packets
+//! are passed, along with their decoded headers, to the
+//! [UDPRecvClient](../../udp/udp_recv/trait.UDPRecvClient.html), the
+//! [TCPRecvClient](../../tcp/tcp_mux/trait.TCPRecvClient.html) and the
+//! [ICMP6RecvClient](../../icmpv6/icmpv6_recv/trait.ICMP6RecvClient.html)
+//! registered through the [IP6Receiver](trait.IP6Receiver.html) trait.
+//!
+//! Hop-by-Hop and Destination Options extension headers are skipped. Packets
+//! carrying any other extension header, or an unsupported transport protocol,
+//! are dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ip6_receiver = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+//! sixlowpan_state.set_rx_client(ip6_receiver);
+//! ip6_receiver.set_udp_client(udp_client);
+//! ip6_receiver.set_tcp_client(tcp_client);
+//! ip6_receiver.set_icmp_client(icmp_client);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::ReturnCode;
+use net::icmpv6::icmpv6::ICMP6Header;
+use net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh};
+use net::ipv6::ip_utils::{compute_ipv6_ph_sum, compute_sum};
+use net::ipv6::ipv6::IP6Header;
+use net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use net::tcp::tcp::TCPHeader;
+use net::tcp::tcp_mux::TCPRecvClient;
+use net::udp::udp::UDPHeader;
+use net::udp::udp_recv::UDPRecvClient;
+
+/// This trait defines how upper layers register to receive the packets
+/// parsed by an `IP6Receiver`. Each transport protocol has a single client.
+pub trait IP6Receiver<'a> {
+    /// Sets the client that receives all valid UDP packets
+    ///
+    /// # Arguments
+    /// `client` - Implementation of `UDPRecvClient` to receive UDP packets
+    fn set_udp_client(&self, client: &'a UDPRecvClient);
+
+    /// Sets the client that receives all valid TCP segments
+    ///
+    /// # Arguments
+    /// `client` - Implementation of `TCPRecvClient` to receive TCP segments
+    fn set_tcp_client(&self, client: &'a TCPRecvClient);
+
+    /// Sets the client that receives all valid ICMPv6 messages
+    ///
+    /// # Arguments
+    /// `client` - Implementation of `ICMP6RecvClient` to receive ICMPv6
+    /// messages
+    fn set_icmp_client(&self, client: &'a ICMP6RecvClient);
+}
+
+/// This is a specific implementation of the `IP6Receiver` trait, which
+/// receives packets from the 6LoWPAN layer.
+pub struct IP6RecvStruct<'a> {
+    udp_client: Cell<Option<&'a UDPRecvClient>>,
+    tcp_client: Cell<Option<&'a TCPRecvClient>>,
+    icmp_client: Cell<Option<&'a ICMP6RecvClient>>,
+}
+
+impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
+    fn set_udp_client(&self, client: &'a UDPRecvClient) {
+        self.udp_client.set(Some(client));
+    }
+
+    fn set_tcp_client(&self, client: &'a TCPRecvClient) {
+        self.tcp_client.set(Some(client));
+    }
+
+    fn set_icmp_client(&self, client: &'a ICMP6RecvClient) {
+        self.icmp_client.set(Some(client));
+    }
+}
+
+impl<'a> IP6RecvStruct<'a> {
+    pub fn new() -> IP6RecvStruct<'a> {
+        IP6RecvStruct {
+            udp_client: Cell::new(None),
+            tcp_client: Cell::new(None),
+            icmp_client: Cell::new(None),
+        }
+    }
+
+    /// Checks and passes on a UDP packet. The UDP checksum is mandatory over
+    /// IPv6 (RFC 8200), so packets without one are dropped.
+    fn receive_udp(&self, ip6_header: IP6Header, buf: &[u8]) {
+        let udp_header = match UDPHeader::decode(buf).done() {
+            Some((_, udp_header)) => udp_header,
+            None => return,
+        };
+        let udp_len = udp_header.get_len() as usize;
+        if udp_len < udp_header.get_hdr_size() || udp_len > buf.len() {
+            return;
+        }
+        let payload = &buf[udp_header.get_hdr_size()..udp_len];
+        let cksum = compute_udp_checksum(&ip6_header, &udp_header, udp_len as u16, payload);
+        if !checksum_matches(udp_header.get_cksum(), cksum) {
+            return;
+        }
+        self.udp_client
+            .get()
+            .map(|client| client.receive(ip6_header, udp_header, payload));
+    }
+
+    /// Checks and passes on a TCP segment. The checksum is verified over the
+    /// raw segment, since it covers any options, which are not decoded.
+    fn receive_tcp(&self, ip6_header: IP6Header, buf: &[u8]) {
+        let (offset, tcp_header) = match TCPHeader::decode(buf).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let mut sum = compute_ipv6_ph_sum(&ip6_header) + compute_sum(buf, buf.len() as u16);
+        while sum > 0xffff {
+            sum = (sum >> 16) + (sum & 0xffff);
+        }
+        if sum != 0xffff {
+            return;
+        }
+        self.tcp_client
+            .get()
+            .map(|client| client.receive(ip6_header, tcp_header, &buf[offset..]));
+    }
+
+    /// Checks and passes on an ICMPv6 message. `ip6_header` must have the
+    /// ICMPv6 length and next header set, as they are part of the checksum.
+    fn receive_icmp(&self, ip6_header: IP6Header, buf: &[u8]) {
+        let mut icmp_header = match ICMP6Header::decode(buf).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => return,
+        };
+        icmp_header.set_len(buf.len() as u16);
+        let payload = &buf[icmp_header.get_hdr_size()..];
+        let cksum = compute_icmp_checksum(&ip6_header, &icmp_header, payload);
+        if icmp_header.get_cksum() != cksum {
+            return;
+        }
+        self.icmp_client
+            .get()
+            .map(|client| client.receive(ip6_header, icmp_header, payload));
+    }
+}
+
+impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
+    fn receive<'b>(&self, buf: &'b [u8], len: u16, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            return;
+        }
+        let len = cmp::min(len as usize, buf.len());
+        let (mut offset, ip6_header) = match IP6Header::decode(&buf[..len]).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        let packet_len = offset + ip6_header.get_payload_len() as usize;
+        if ip6_header.get_version() != 6 || packet_len > len {
+            return;
+        }
+
+        // Find the transport header behind any extension headers
+        let mut next_header = ip6_header.get_next_header();
+        while next_header == ip6_nh::HOP_OPTS || next_header == ip6_nh::DST_OPTS {
+            if offset + 2 > packet_len {
+                return;
+            }
+            let ext_len = (buf[offset + 1] as usize + 1) * 8;
+            next_header = buf[offset];
+            offset += ext_len;
+            if offset > packet_len {
+                return;
+            }
+        }
+
+        // The checksum pseudo-header covers only the transport packet
+        let mut header = ip6_header;
+        header.set_payload_len((packet_len - offset) as u16);
+        header.set_next_header(next_header);
+        match next_header {
+            ip6_nh::UDP => self.receive_udp(header, &buf[offset..packet_len]),
+            ip6_nh::TCP => self.receive_tcp(header, &buf[offset..packet_len]),
+            ip6_nh::ICMP => self.receive_icmp(header, &buf[offset..packet_len]),
+            _ => {}
+        }
+    }
+}
+
+/// A computed checksum of 0 is sent as 0xffff (RFC 768), since a 0 in the
+/// header means the checksum was not computed.
+fn checksum_matches(received: u16, computed: u16) -> bool {
+    received != 0 && (received == computed || (computed == 0 && received == 0xffff))
+}
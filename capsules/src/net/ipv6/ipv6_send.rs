@@ -5,7 +5,41 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. Each upper layer (UDP, ICMPv6, TCP,
+//! etc.) has an [IP6SendStruct](struct.IP6SendStruct.html) of its own, with
+//! its own packet buffer and `TxState`, and so can send a packet while the
+//! others are sending theirs. The `IP6SendStruct`s share an
+//! [IP6SendMux](struct.IP6SendMux.html), which owns the MAC device and the
+//! frame buffer, and takes turns between the packets being sent, one frame
+//! at a time, so that a long packet does not hold up the others until all of
+//! its fragments are sent.
+//!
+//! A sender that is asked to send a packet while it is sending another
+//! queues the new packet in one of its free
+//! [IP6SendSlot](struct.IP6SendSlot.html)s, and sends the queued packets in
+//! order, with a `send_done` callback for each. Only when all of its slots
+//! are taken does it return `EBUSY`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ip6_send_mux = static_init!(
+//!     IP6SendMux<'static>,
+//!     IP6SendMux::new(lowpan_mac, &mut LOWPAN_FRAG_BUF));
+//! lowpan_mac.set_transmit_client(ip6_send_mux);
+//!
+//! // For each upper layer
+//! let ip6_sender = static_init!(
+//!     IP6SendStruct<'static>,
+//!     IP6SendStruct::new(ip6_packet, TxState::new(sixlowpan_state), ip6_send_mux, interface));
+//! ip6_send_mux.add_sender(ip6_sender);
+//! ip6_sender.set_client(udp_send);
+//!
+//! // Optionally, for each packet the upper layer can queue
+//! let ip6_slot = static_init!(IP6SendSlot<'static>, IP6SendSlot::new(queued_packet));
+//! ip6_sender.add_slot(ip6_slot);
+//! ```
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -18,18 +52,16 @@
 
 use core::cell::Cell;
 use ieee802154::device::{MacDevice, TxClient};
+use ieee802154::framer::Frame;
 use kernel::common::take_cell::TakeCell;
+use kernel::common::{List, ListLink, ListNode};
 use kernel::ReturnCode;
 use net::ieee802154::MacAddress;
 use net::ipv6::ip_utils::IPAddr;
 use net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use net::ipv6::ipv6_interface::IP6Interface;
 use net::sixlowpan::sixlowpan_state::TxState;
 
-// TODO: These should *not* be constants, and should be set at some other
-// point during the initialization of the IP stack
-const SRC_MAC_ADDR: MacAddress = MacAddress::Short(0xf00f);
-const DST_MAC_ADDR: MacAddress = MacAddress::Short(0xf00e);
-
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -38,10 +70,9 @@ pub trait IP6Client {
     fn send_done(&self, result: ReturnCode);
 }
 
-/// This trait provides a basic IPv6 sending interface. It exposes basic
-/// configuration information for the IPv6 layer (setting the source address,
-/// setting the gateway MAC address), as well as a way to send an IPv6
-/// packet.
+/// This trait provides a basic IPv6 sending interface. The addresses that
+/// packets are sent from and to are configured on the `IP6Interface` the
+/// `IP6Sender` sends through.
 pub trait IP6Sender<'a> {
     /// This method sets the `IP6Client` for the `IP6Sender` instance, which
     /// receives the `send_done` callback when transmission has finished.
@@ -51,21 +82,6 @@ pub trait IP6Sender<'a> {
     /// `send_done` callback
     fn set_client(&self, client: &'a IP6Client);
 
-    /// This method sets the source address for packets sent from the
-    /// `IP6Sender` instance.
-    ///
-    /// # Arguments
-    /// `src_addr` - `IPAddr` to set as the source address for packets sent
-    /// from this instance of `IP6Sender`
-    fn set_addr(&self, src_addr: IPAddr);
-
-    /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
-    ///
-    /// # Arguments
-    /// `gateway` - MAC address to send the constructed packet to
-    fn set_gateway(&self, gateway: MacAddress);
-
     /// This method sets the `IP6Header` for the `IP6Sender` instance
     ///
     /// # Arguments
@@ -80,21 +96,86 @@ pub trait IP6Sender<'a> {
     /// `dst` - IPv6 address to send the packet to
     /// `transport_header` - The `TransportHeader` for the packet being sent
     /// `payload` - The transport payload for the packet being sent
+    ///
+    /// # Return Value
+    /// `FAIL` if `dst` is off the link and the interface has no gateway
     fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
         -> ReturnCode;
+
+    /// This method sends the provided transport header and payload to the
+    /// given destination IP address, from the given source address instead
+    /// of the one the interface picks. The source address need not belong
+    /// to the interface yet, so that Neighbor Discovery can register it.
+    ///
+    /// # Arguments
+    /// `src` - IPv6 address to send the packet from
+    /// `dst` - IPv6 address to send the packet to
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode;
+}
+
+/// A packet buffer for an `IP6SendStruct`, which holds a packet that is
+/// queued behind the one being sent. The buffer should be as large as the
+/// packet buffer of the sender.
+pub struct IP6SendSlot<'a> {
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    dst_mac: Cell<MacAddress>,
+    // Place of the packet in the queue of the sender, counting from 1, or 0
+    // if the slot is free
+    position: Cell<usize>,
+    next: ListLink<'a, IP6SendSlot<'a>>,
+}
+
+impl<'a> ListNode<'a, IP6SendSlot<'a>> for IP6SendSlot<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6SendSlot<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> IP6SendSlot<'a> {
+    pub fn new(ip6_packet: &'static mut IP6Packet<'static>) -> IP6SendSlot<'a> {
+        IP6SendSlot {
+            ip6_packet: TakeCell::new(ip6_packet),
+            dst_mac: Cell::new(MacAddress::Short(0)),
+            position: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
-/// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
+/// struct sends the packet using 6LoWPAN, through the `IP6SendMux` it is
+/// added to. It sends one packet at a time, and queues the packets it is
+/// given meanwhile in its `IP6SendSlot`s. It returns `EBUSY` when they are
+/// all taken.
 pub struct IP6SendStruct<'a> {
     // We want the ip6_packet field to be a TakeCell so that it is easy to mutate
     ip6_packet: TakeCell<'static, IP6Packet<'static>>,
-    src_addr: Cell<IPAddr>,
-    gateway: Cell<MacAddress>,
-    tx_buf: TakeCell<'static, [u8]>,
+    interface: &'a IP6Interface<'a>,
     sixlowpan: TxState<'a>,
-    radio: &'a MacDevice<'a>,
+    mux: &'a IP6SendMux<'a>,
+    /// Whether frames are secured as configured on the interface
+    link_security: Cell<bool>,
+    /// Whether a packet is waiting for the mux to send its fragments
+    pending: Cell<bool>,
+    /// The buffers of the packets queued behind it, and how many are taken
+    slots: List<'a, IP6SendSlot<'a>>,
+    queued: Cell<usize>,
     client: Cell<Option<&'a IP6Client>>,
+    next: ListLink<'a, IP6SendStruct<'a>>,
+}
+
+impl<'a> ListNode<'a, IP6SendStruct<'a>> for IP6SendStruct<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6SendStruct<'a>> {
+        &self.next
+    }
 }
 
 impl<'a> IP6Sender<'a> for IP6SendStruct<'a> {
@@ -102,14 +183,6 @@ impl<'a> IP6Sender<'a> for IP6SendStruct<'a> {
         self.client.set(Some(client));
     }
 
-    fn set_addr(&self, src_addr: IPAddr) {
-        self.src_addr.set(src_addr);
-    }
-
-    fn set_gateway(&self, gateway: MacAddress) {
-        self.gateway.set(gateway);
-    }
-
     fn set_header(&mut self, ip6_header: IP6Header) {
         self.ip6_packet
             .map(|ip6_packet| ip6_packet.header = ip6_header);
@@ -121,95 +194,272 @@ impl<'a> IP6Sender<'a> for IP6SendStruct<'a> {
         transport_header: TransportHeader,
         payload: &[u8],
     ) -> ReturnCode {
-        self.sixlowpan.init(SRC_MAC_ADDR, DST_MAC_ADDR, None);
-        self.init_packet(dst, transport_header, payload);
-        self.send_next_fragment()
+        self.send_from(
+            self.interface.get_src_addr(dst),
+            dst,
+            transport_header,
+            payload,
+        )
+    }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        let dst_mac = match self.interface.get_dst_mac(dst) {
+            Some(dst_mac) => dst_mac,
+            None => return ReturnCode::FAIL,
+        };
+        if self.pending.get() {
+            // A packet is being sent
+            return self.enqueue(src, dst, dst_mac, transport_header, payload);
+        }
+        let fits = self.ip6_packet
+            .map_or(false, |ip6_packet| payload.len() <= ip6_packet.payload.payload.len());
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        self.ip6_packet.map(|ip6_packet| {
+            init_packet(ip6_packet, src, dst, transport_header, payload);
+        });
+        self.start(dst_mac);
+        self.mux.send_next_fragment();
+        ReturnCode::SUCCESS
     }
 }
 
 impl<'a> IP6SendStruct<'a> {
     pub fn new(
         ip6_packet: &'static mut IP6Packet<'static>,
-        tx_buf: &'static mut [u8],
         sixlowpan: TxState<'a>,
-        radio: &'a MacDevice<'a>,
+        mux: &'a IP6SendMux<'a>,
+        interface: &'a IP6Interface<'a>,
     ) -> IP6SendStruct<'a> {
         IP6SendStruct {
             ip6_packet: TakeCell::new(ip6_packet),
-            src_addr: Cell::new(IPAddr::new()),
-            gateway: Cell::new(DST_MAC_ADDR),
-            tx_buf: TakeCell::new(tx_buf),
+            interface: interface,
             sixlowpan: sixlowpan,
-            radio: radio,
+            mux: mux,
+            link_security: Cell::new(true),
+            pending: Cell::new(false),
+            slots: List::new(),
+            queued: Cell::new(0),
             client: Cell::new(None),
+            next: ListLink::empty(),
         }
     }
 
-    fn init_packet(&self, dst_addr: IPAddr, transport_header: TransportHeader, payload: &[u8]) {
-        self.ip6_packet.map(|ip6_packet| {
-            ip6_packet.header = IP6Header::default();
-            ip6_packet.header.src_addr = self.src_addr.get();
-            ip6_packet.header.dst_addr = dst_addr;
-            ip6_packet.set_payload(transport_header, payload);
-            ip6_packet.set_transport_checksum();
+    /// Adds a slot to queue a packet in while another one is being sent.
+    /// Each slot should only be added once.
+    pub fn add_slot(&self, slot: &'a IP6SendSlot<'a>) {
+        self.slots.push_head(slot);
+    }
+
+    /// Sets whether the frames of this sender are secured with the link-layer
+    /// security of the interface. Users that secure their messages
+    /// themselves, like MLE, send unsecured frames.
+    pub fn set_link_security_enabled(&self, enabled: bool) {
+        self.link_security.set(enabled);
+    }
+
+    // Prepares the fragmentation of the packet in `ip6_packet`, for the mux
+    // to send
+    fn start(&self, dst_mac: MacAddress) {
+        let security = if self.link_security.get() {
+            self.interface.get_link_security()
+        } else {
+            None
+        };
+        self.sixlowpan
+            .init(self.interface.get_src_mac(), dst_mac, security);
+        self.pending.set(true);
+    }
+
+    // Copies a packet into a free slot, at the tail of the queue
+    fn enqueue(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        dst_mac: MacAddress,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        let slot = match self.slots.iter().find(|slot| slot.position.get() == 0) {
+            Some(slot) => slot,
+            None => return ReturnCode::EBUSY,
+        };
+        let fits = slot.ip6_packet
+            .map_or(false, |ip6_packet| payload.len() <= ip6_packet.payload.payload.len());
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        slot.ip6_packet.map(|ip6_packet| {
+            init_packet(ip6_packet, src, dst, transport_header, payload);
         });
+        slot.dst_mac.set(dst_mac);
+        self.queued.set(self.queued.get() + 1);
+        slot.position.set(self.queued.get());
+        ReturnCode::SUCCESS
     }
 
-    // Returns EBUSY if the tx_buf is not there
-    fn send_next_fragment(&self) -> ReturnCode {
+    // Starts the packet at the head of the queue, by swapping the buffer of
+    // its slot with the buffer of the packet that was sent. Its fragments are
+    // sent by the mux, which called back the sender.
+    fn start_queued(&self) {
+        let head = match self.slots.iter().find(|slot| slot.position.get() == 1) {
+            Some(head) => head,
+            None => return,
+        };
+        for slot in self.slots.iter() {
+            if slot.position.get() > 0 {
+                slot.position.set(slot.position.get() - 1);
+            }
+        }
+        self.queued.set(self.queued.get() - 1);
+        let queued_packet = head.ip6_packet.take();
         self.ip6_packet
-            .map(move |ip6_packet| match self.tx_buf.take() {
-                Some(tx_buf) => {
-                    let next_frame = self.sixlowpan.next_fragment(ip6_packet, tx_buf, self.radio);
-
-                    match next_frame {
-                        Ok((is_done, frame)) => {
-                            if is_done {
-                                self.tx_buf.replace(frame.into_buf());
-                                self.send_completed(ReturnCode::SUCCESS);
-                            } else {
-                                self.radio.transmit(frame);
-                            }
-                        }
-                        Err((retcode, buf)) => {
-                            self.tx_buf.replace(buf);
-                            self.send_completed(retcode);
-                        }
-                    }
-                    ReturnCode::SUCCESS
-                }
-                None => ReturnCode::EBUSY,
-            })
-            .unwrap_or(ReturnCode::ENOMEM)
+            .take()
+            .map(|sent_packet| head.ip6_packet.replace(sent_packet));
+        queued_packet.map(|queued_packet| self.ip6_packet.replace(queued_packet));
+        self.start(head.dst_mac.get());
+    }
+
+    fn next_fragment(
+        &self,
+        tx_buf: &'static mut [u8],
+        radio: &MacDevice,
+    ) -> Result<(bool, Frame), (ReturnCode, &'static mut [u8])> {
+        match self.ip6_packet.take() {
+            Some(ip6_packet) => {
+                let next_frame = self.sixlowpan.next_fragment(ip6_packet, tx_buf, radio);
+                self.ip6_packet.replace(ip6_packet);
+                next_frame
+            }
+            None => Err((ReturnCode::ENOMEM, tx_buf)),
+        }
     }
 
     fn send_completed(&self, result: ReturnCode) {
+        self.pending.set(false);
+        // The next packet is started first, so that packets the client sends
+        // from its callback are queued behind it
+        self.start_queued();
         self.client
             .get()
             .map(move |client| client.send_done(result));
     }
 }
 
-impl<'a> TxClient for IP6SendStruct<'a> {
-    fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.tx_buf.replace(tx_buf);
-        debug!("sendDone return code is: {:?}, acked: {}", result, acked);
-        //The below code introduces a delay between frames to prevent
-        // a race condition on the receiver
-        //it is sorta complicated bc I was having some trouble with dead code eliminationa
-        //TODO: Remove this one link layer is fixed
-        let mut i = 0;
-        let mut array: [u8; 100] = [0x0; 100]; //used in introducing delay between frames
-        while i < 1000000 {
-            array[i % 100] = (i % 100) as u8;
-            i = i + 1;
-            if i % 100000 == 0 {
-                debug!("Delay, step {:?}", i / 100000);
+fn init_packet(
+    ip6_packet: &mut IP6Packet,
+    src_addr: IPAddr,
+    dst_addr: IPAddr,
+    transport_header: TransportHeader,
+    payload: &[u8],
+) {
+    ip6_packet.header = IP6Header::default();
+    ip6_packet.header.src_addr = src_addr;
+    ip6_packet.header.dst_addr = dst_addr;
+    ip6_packet.set_payload(transport_header, payload);
+    ip6_packet.set_transport_checksum();
+}
+
+/// Sends the packets of the `IP6SendStruct`s added to it over a `MacDevice`,
+/// one frame at a time. The senders with a packet to send take turns, in
+/// the order they were added, so their fragments are interleaved.
+pub struct IP6SendMux<'a> {
+    radio: &'a MacDevice<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    senders: List<'a, IP6SendStruct<'a>>,
+    // The sender whose frame is being transmitted
+    inflight: Cell<Option<&'a IP6SendStruct<'a>>>,
+    // The sender that sent the last frame, after which the next turn starts
+    last: Cell<Option<&'a IP6SendStruct<'a>>>,
+}
+
+impl<'a> IP6SendMux<'a> {
+    pub fn new(radio: &'a MacDevice<'a>, tx_buf: &'static mut [u8]) -> IP6SendMux<'a> {
+        IP6SendMux {
+            radio: radio,
+            tx_buf: TakeCell::new(tx_buf),
+            senders: List::new(),
+            inflight: Cell::new(None),
+            last: Cell::new(None),
+        }
+    }
+
+    /// Adds a sender to the mux. Each sender should only be added once.
+    pub fn add_sender(&self, sender: &'a IP6SendStruct<'a>) {
+        self.senders.push_tail(sender);
+    }
+
+    // Returns the first sender with a packet after the one that sent the
+    // last frame, wrapping around to the head of the list.
+    fn next_sender(&self) -> Option<&'a IP6SendStruct<'a>> {
+        let last = self.last.get();
+        let mut after_last = last.is_none();
+        let mut first_pending = None;
+        for sender in self.senders.iter() {
+            if sender.pending.get() {
+                if after_last {
+                    return Some(sender);
+                }
+                if first_pending.is_none() {
+                    first_pending = Some(sender);
+                }
+            }
+            if last.map_or(false, |last| last as *const _ == sender as *const _) {
+                after_last = true;
             }
         }
-        let result = self.send_next_fragment();
-        if result != ReturnCode::SUCCESS {
-            self.send_completed(result);
+        first_pending
+    }
+
+    // Transmits the next frame of the next sender, unless a frame is being
+    // transmitted. Senders whose packets are done, or fail, are called back
+    // here, and the next sender is tried.
+    fn send_next_fragment(&self) {
+        while self.inflight.get().is_none() {
+            let sender = match self.next_sender() {
+                Some(sender) => sender,
+                None => return,
+            };
+            let tx_buf = match self.tx_buf.take() {
+                Some(tx_buf) => tx_buf,
+                None => return,
+            };
+            self.last.set(Some(sender));
+            match sender.next_fragment(tx_buf, self.radio) {
+                Ok((true, frame)) => {
+                    self.tx_buf.replace(frame.into_buf());
+                    sender.send_completed(ReturnCode::SUCCESS);
+                }
+                Ok((false, frame)) => {
+                    let (result, buf) = self.radio.transmit(frame);
+                    match buf {
+                        Some(buf) => {
+                            self.tx_buf.replace(buf);
+                            sender.send_completed(result);
+                        }
+                        None => self.inflight.set(Some(sender)),
+                    }
+                }
+                Err((retcode, buf)) => {
+                    self.tx_buf.replace(buf);
+                    sender.send_completed(retcode);
+                }
+            }
         }
     }
 }
+
+impl<'a> TxClient for IP6SendMux<'a> {
+    fn send_done(&self, tx_buf: &'static mut [u8], _acked: bool, _result: ReturnCode) {
+        self.tx_buf.replace(tx_buf);
+        self.inflight.set(None);
+        self.send_next_fragment();
+    }
+}
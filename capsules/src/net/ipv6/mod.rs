@@ -1,3 +1,6 @@
 pub mod ip_utils;
 pub mod ipv6;
+pub mod ipv6_interface;
+pub mod ipv6_recv;
 pub mod ipv6_send;
+pub mod neighbor_cache;
@@ -0,0 +1,128 @@
+//! This file contains the neighbor cache of an `IP6Interface`, which maps the
+//! IPv6 addresses of neighbors to their link-layer addresses. Under 6LoWPAN
+//! Neighbor Discovery (RFC 6775), hosts do not resolve addresses with
+//! multicast Neighbor Solicitations: the cache is filled from the Router
+//! Advertisements they receive, so it mostly holds routers. The default
+//! router, through which packets for destinations off the link are sent, is
+//! picked among them.
+//!
+//! Entries expire at a time given in seconds by whoever adds them, usually
+//! an `NDHost`, which also removes them with `expire`.
+
+use kernel::common::take_cell::MapCell;
+use kernel::ReturnCode;
+use net::ieee802154::MacAddress;
+use net::ipv6::ip_utils::IPAddr;
+
+/// The maximum number of neighbors in the cache
+pub const MAX_NEIGHBORS: usize = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Neighbor {
+    pub ip_addr: IPAddr,
+    pub mac_addr: MacAddress,
+    pub is_router: bool,
+    /// When the entry expires, in seconds
+    pub expires: u32,
+}
+
+pub struct NeighborCache {
+    neighbors: MapCell<[Option<Neighbor>; MAX_NEIGHBORS]>,
+}
+
+impl NeighborCache {
+    pub fn new() -> NeighborCache {
+        NeighborCache {
+            neighbors: MapCell::new([None; MAX_NEIGHBORS]),
+        }
+    }
+
+    /// Adds a neighbor, replacing the entry for the same address. When the
+    /// cache is full, a host entry is evicted to make room for a router.
+    ///
+    /// # Return Value
+    /// `ENOMEM` if there is no room for the neighbor.
+    pub fn update(&self, neighbor: Neighbor) -> ReturnCode {
+        self.neighbors.map_or(ReturnCode::FAIL, |neighbors| {
+            let slot = neighbors
+                .iter()
+                .position(|n| n.map_or(false, |n| n.ip_addr.0 == neighbor.ip_addr.0))
+                .or_else(|| neighbors.iter().position(|n| n.is_none()))
+                .or_else(|| {
+                    if neighbor.is_router {
+                        neighbors
+                            .iter()
+                            .position(|n| n.map_or(false, |n| !n.is_router))
+                    } else {
+                        None
+                    }
+                });
+            match slot {
+                Some(i) => {
+                    neighbors[i] = Some(neighbor);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOMEM,
+            }
+        })
+    }
+
+    /// Removes the neighbor with address `ip_addr`.
+    pub fn remove(&self, ip_addr: IPAddr) {
+        self.neighbors.map(|neighbors| {
+            for n in neighbors.iter_mut() {
+                if n.map_or(false, |n| n.ip_addr.0 == ip_addr.0) {
+                    *n = None;
+                }
+            }
+        });
+    }
+
+    /// Returns the link-layer address of the neighbor with address `ip_addr`.
+    pub fn lookup(&self, ip_addr: IPAddr) -> Option<MacAddress> {
+        self.neighbors.and_then(|neighbors| {
+            neighbors
+                .iter()
+                .filter_map(|n| *n)
+                .find(|n| n.ip_addr.0 == ip_addr.0)
+                .map(|n| n.mac_addr)
+        })
+    }
+
+    /// Picks the default router: the router whose entry expires last.
+    pub fn default_router(&self) -> Option<Neighbor> {
+        self.neighbors.and_then(|neighbors| {
+            neighbors
+                .iter()
+                .filter_map(|n| *n)
+                .filter(|n| n.is_router)
+                .max_by_key(|n| n.expires)
+        })
+    }
+
+    /// Removes the entries that expire at or before `now`, and returns
+    /// whether there were any.
+    pub fn expire(&self, now: u32) -> bool {
+        self.neighbors.map_or(false, |neighbors| {
+            let mut expired = false;
+            for n in neighbors.iter_mut() {
+                if n.map_or(false, |n| n.expires <= now) {
+                    *n = None;
+                    expired = true;
+                }
+            }
+            expired
+        })
+    }
+
+    /// Returns when the next entry expires.
+    pub fn next_expiry(&self) -> Option<u32> {
+        self.neighbors.and_then(|neighbors| {
+            neighbors
+                .iter()
+                .filter_map(|n| *n)
+                .map(|n| n.expires)
+                .min()
+        })
+    }
+}
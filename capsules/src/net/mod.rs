@@ -1,10 +1,11 @@
 //! Modules for IPv6 over 6LoWPAN stack
 
+#[macro_use]
+pub mod stream;
 pub mod frag_utils;
 pub mod sixlowpan;
 pub mod util;
-#[macro_use]
-pub mod stream;
+pub mod coap;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
@@ -0,0 +1,364 @@
+//! This file implements mesh-under forwarding for 6LoWPAN (RFC 4944,
+//! Sections 5.2 and 11.1), with which frames reach nodes that are not
+//! neighbors of their originator by being relayed at the link layer,
+//! without being reassembled or decompressed on the way.
+//!
+//! Frames sent along a mesh route start with a mesh addressing header, which
+//! carries the link-layer addresses of the originator and of the final
+//! destination, and the number of hops the frame may still take. Frames
+//! broadcast through the mesh also carry a broadcast header (LOWPAN_BC0),
+//! whose sequence number lets nodes drop the copies they already received.
+//! Both headers come before the fragment header, so each fragment of a
+//! packet is forwarded on its own.
+//!
+//! The [MeshForwarder](struct.MeshForwarder.html) holds the routes to the
+//! final destinations that are not neighbors, relays the frames that
+//! `Sixlowpan` receives for other nodes, and remembers the broadcasts it has
+//! seen. Once it is set on `Sixlowpan`:
+//!
+//! * `TxState`s send packets for a destination with a route to the route's
+//!   next hop, behind a mesh header, and broadcast packets through the mesh
+//!   if `set_broadcast_hops` has been called,
+//! * received frames with a mesh header are taken as coming from the
+//!   originator and sent to the final destination, as RFC 4944 requires
+//!   for the decompression and reassembly of their packets,
+//! * frames for other nodes are relayed, and broadcasts are both received
+//!   and relayed, until they run out of hops.
+//!
+//! The forwarder has one frame buffer: frames that arrive while it is
+//! relaying another are dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mesh_forwarder = static_init!(
+//!     MeshForwarder<'static>,
+//!     MeshForwarder::new(mesh_mac, &mut MESH_BUF));
+//! mesh_mac.set_transmit_client(mesh_forwarder);
+//! sixlowpan.set_mesh_forwarder(mesh_forwarder);
+//! mesh_forwarder.add_route(MacAddress::Short(0x0002), MacAddress::Short(0x0001));
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use ieee802154::device::{MacDevice, TxClient};
+use kernel::common::take_cell::{MapCell, TakeCell};
+use kernel::ReturnCode;
+use net::ieee802154::{Header, MacAddress};
+use net::stream::SResult;
+use net::stream::{decode_bytes, decode_u16, decode_u8};
+use net::stream::{encode_bytes, encode_u16, encode_u8};
+
+pub mod lowpan_mesh {
+    pub const MESH_HDR: u8 = 0b10000000;
+    pub const MESH_HDR_MASK: u8 = 0b11000000;
+    /// The originator address is short
+    pub const ORIGINATOR_SHORT: u8 = 0b00100000;
+    /// The final destination address is short
+    pub const FINAL_SHORT: u8 = 0b00010000;
+    pub const HOPS_LEFT_MASK: u8 = 0b00001111;
+    pub const BC0_HDR: u8 = 0b01010000;
+    pub const BC0_HDR_SIZE: usize = 2;
+}
+
+/// The most hops a mesh header can carry
+pub const MAX_HOPS_LEFT: u8 = lowpan_mesh::HOPS_LEFT_MASK;
+
+/// The number of mesh routes
+pub const MAX_MESH_ROUTES: usize = 8;
+
+/// The number of broadcasts remembered to drop their copies
+pub const BROADCAST_HISTORY: usize = 8;
+
+/// The 802.15.4 broadcast address, the final destination of mesh broadcasts
+pub const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// A mesh addressing header.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MeshHeader {
+    pub hops_left: u8,
+    pub originator: MacAddress,
+    pub final_dst: MacAddress,
+}
+
+fn encode_mac_addr(buf: &mut [u8], mac_addr: &MacAddress) -> SResult<usize> {
+    let off = match *mac_addr {
+        MacAddress::Short(short_addr) => enc_consume!(buf; encode_u16, short_addr),
+        MacAddress::Long(ref long_addr) => enc_consume!(buf; encode_bytes, long_addr),
+    };
+    stream_done!(off, off);
+}
+
+fn decode_mac_addr(buf: &[u8], short: bool) -> SResult<MacAddress> {
+    if short {
+        let (off, short_addr) = dec_try!(buf; decode_u16);
+        stream_done!(off, MacAddress::Short(short_addr));
+    } else {
+        let mut long_addr = [0; 8];
+        let off = dec_consume!(buf; decode_bytes, &mut long_addr);
+        stream_done!(off, MacAddress::Long(long_addr));
+    }
+}
+
+fn is_short(mac_addr: &MacAddress) -> bool {
+    match *mac_addr {
+        MacAddress::Short(_) => true,
+        MacAddress::Long(_) => false,
+    }
+}
+
+impl MeshHeader {
+    /// Serializes the header into `buf`, returning the number of bytes
+    /// written.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_cond!(self.hops_left <= MAX_HOPS_LEFT, ());
+        let mut dispatch = lowpan_mesh::MESH_HDR | self.hops_left;
+        if is_short(&self.originator) {
+            dispatch |= lowpan_mesh::ORIGINATOR_SHORT;
+        }
+        if is_short(&self.final_dst) {
+            dispatch |= lowpan_mesh::FINAL_SHORT;
+        }
+        let mut off = enc_consume!(buf; encode_u8, dispatch);
+        off = enc_consume!(buf, off; encode_mac_addr, &self.originator);
+        off = enc_consume!(buf, off; encode_mac_addr, &self.final_dst);
+        stream_done!(off, off);
+    }
+
+    /// Deserializes the header at the start of `buf`. The offset returned is
+    /// that of the header that follows it.
+    pub fn decode(buf: &[u8]) -> SResult<MeshHeader> {
+        let (off, dispatch) = dec_try!(buf; decode_u8);
+        stream_cond!(dispatch & lowpan_mesh::MESH_HDR_MASK == lowpan_mesh::MESH_HDR, ());
+        let originator_short = dispatch & lowpan_mesh::ORIGINATOR_SHORT != 0;
+        let final_short = dispatch & lowpan_mesh::FINAL_SHORT != 0;
+        let (off, originator) = dec_try!(buf, off; decode_mac_addr, originator_short);
+        let (off, final_dst) = dec_try!(buf, off; decode_mac_addr, final_short);
+        stream_done!(
+            off,
+            MeshHeader {
+                hops_left: dispatch & lowpan_mesh::HOPS_LEFT_MASK,
+                originator: originator,
+                final_dst: final_dst,
+            }
+        );
+    }
+}
+
+pub fn is_mesh(packet: &[u8]) -> bool {
+    packet.len() > 0 && packet[0] & lowpan_mesh::MESH_HDR_MASK == lowpan_mesh::MESH_HDR
+}
+
+pub fn is_bc0(packet: &[u8]) -> bool {
+    packet.len() >= lowpan_mesh::BC0_HDR_SIZE && packet[0] == lowpan_mesh::BC0_HDR
+}
+
+/// A route to a final destination that is not a neighbor.
+#[derive(Copy, Clone, Debug)]
+pub struct MeshRoute {
+    pub final_dst: MacAddress,
+    pub next_hop: MacAddress,
+}
+
+pub struct MeshForwarder<'a> {
+    mac: &'a MacDevice<'a>,
+    tx_buf: TakeCell<'static, [u8]>,
+    routes: MapCell<[Option<MeshRoute>; MAX_MESH_ROUTES]>,
+    hops_left: Cell<u8>,
+    broadcast_hops: Cell<u8>,
+    /// The originators and sequence numbers of the last broadcasts received
+    broadcasts: MapCell<[Option<(MacAddress, u8)>; BROADCAST_HISTORY]>,
+    next_broadcast: Cell<usize>,
+}
+
+impl<'a> MeshForwarder<'a> {
+    /// Creates a forwarder that relays frames through `mac`. `tx_buf` must be
+    /// at least the length of an 802.15.4 frame.
+    pub fn new(mac: &'a MacDevice<'a>, tx_buf: &'static mut [u8]) -> MeshForwarder<'a> {
+        MeshForwarder {
+            mac: mac,
+            tx_buf: TakeCell::new(tx_buf),
+            routes: MapCell::new([None; MAX_MESH_ROUTES]),
+            hops_left: Cell::new(MAX_HOPS_LEFT),
+            broadcast_hops: Cell::new(0),
+            broadcasts: MapCell::new([None; BROADCAST_HISTORY]),
+            next_broadcast: Cell::new(0),
+        }
+    }
+
+    /// Adds a route to `final_dst` through the neighbor `next_hop`,
+    /// replacing any route to `final_dst`.
+    ///
+    /// # Return Value
+    /// `ENOMEM` if there is no room for the route.
+    pub fn add_route(&self, final_dst: MacAddress, next_hop: MacAddress) -> ReturnCode {
+        self.routes.map_or(ReturnCode::FAIL, |routes| {
+            let slot = match routes
+                .iter()
+                .position(|r| r.map_or(false, |r| r.final_dst == final_dst))
+            {
+                Some(i) => Some(i),
+                None => routes.iter().position(|r| r.is_none()),
+            };
+            match slot {
+                Some(i) => {
+                    routes[i] = Some(MeshRoute {
+                        final_dst: final_dst,
+                        next_hop: next_hop,
+                    });
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOMEM,
+            }
+        })
+    }
+
+    /// Removes the route to `final_dst`.
+    pub fn remove_route(&self, final_dst: MacAddress) -> ReturnCode {
+        self.routes.map_or(ReturnCode::FAIL, |routes| {
+            match routes
+                .iter_mut()
+                .find(|r| r.map_or(false, |r| r.final_dst == final_dst))
+            {
+                Some(slot) => {
+                    *slot = None;
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Sets the hops that frames sent along a route may take, at most
+    /// `MAX_HOPS_LEFT`. The default is `MAX_HOPS_LEFT`.
+    pub fn set_hops_left(&self, hops_left: u8) {
+        self.hops_left.set(cmp::min(hops_left, MAX_HOPS_LEFT));
+    }
+
+    /// Sets the hops that broadcasts sent by this node may take, at most
+    /// `MAX_HOPS_LEFT`. The default, 0, broadcasts to the neighbors only.
+    pub fn set_broadcast_hops(&self, hops_left: u8) {
+        self.broadcast_hops.set(cmp::min(hops_left, MAX_HOPS_LEFT));
+    }
+
+    /// Returns the next hop and the hops left with which to send a packet to
+    /// `final_dst`, or `None` if it should be sent directly.
+    pub fn get_route(&self, final_dst: MacAddress) -> Option<(MacAddress, u8)> {
+        if final_dst == BROADCAST_MAC_ADDR {
+            return match self.broadcast_hops.get() {
+                0 => None,
+                hops_left => Some((BROADCAST_MAC_ADDR, hops_left)),
+            };
+        }
+        self.routes
+            .and_then(|routes| {
+                routes
+                    .iter()
+                    .filter_map(|r| *r)
+                    .find(|r| r.final_dst == final_dst && r.next_hop != final_dst)
+            })
+            .map(|route| (route.next_hop, self.hops_left.get()))
+    }
+
+    /// Returns whether `mac_addr` is one of the addresses of this node.
+    pub fn is_local(&self, mac_addr: MacAddress) -> bool {
+        match mac_addr {
+            MacAddress::Short(short_addr) => short_addr == self.mac.get_address(),
+            MacAddress::Long(long_addr) => long_addr == self.mac.get_address_long(),
+        }
+    }
+
+    /// Returns whether the broadcast with sequence number `seq` from
+    /// `originator` was already received, and remembers it if it was not.
+    pub fn is_duplicate_broadcast(&self, originator: MacAddress, seq: u8) -> bool {
+        self.broadcasts.map_or(false, |broadcasts| {
+            if broadcasts.iter().any(|b| *b == Some((originator, seq))) {
+                return true;
+            }
+            let next = self.next_broadcast.get();
+            broadcasts[next] = Some((originator, seq));
+            self.next_broadcast.set((next + 1) % BROADCAST_HISTORY);
+            false
+        })
+    }
+
+    /// Relays a received frame one hop further towards its final
+    /// destination, with one hop less left.
+    ///
+    /// # Arguments
+    /// `header` - The MAC header of the received frame, whose PANs and
+    /// security the frame is relayed with
+    /// `mesh_header` - The mesh header of the received frame
+    /// `payload` - The payload of the received frame after the mesh header
+    ///
+    /// # Return Value
+    /// `EINVAL` if the frame has no hops left, and `EBUSY` if another frame
+    /// is being relayed.
+    pub fn forward(&self, header: &Header, mesh_header: MeshHeader, payload: &[u8]) -> ReturnCode {
+        if mesh_header.hops_left <= 1 {
+            return ReturnCode::EINVAL;
+        }
+        let next_hop = if mesh_header.final_dst == BROADCAST_MAC_ADDR {
+            BROADCAST_MAC_ADDR
+        } else {
+            self.get_route(mesh_header.final_dst)
+                .map_or(mesh_header.final_dst, |(next_hop, _)| next_hop)
+        };
+        let tx_buf = match self.tx_buf.take() {
+            Some(tx_buf) => tx_buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let pan = self.mac.get_pan();
+        let security = header.security.map(|sec| (sec.level, sec.key_id));
+        let mut frame = match self.mac.prepare_data_frame(
+            tx_buf,
+            header.dst_pan.unwrap_or(pan),
+            next_hop,
+            header.src_pan.unwrap_or(pan),
+            MacAddress::Long(self.mac.get_address_long()),
+            security,
+        ) {
+            Ok(frame) => frame,
+            Err(tx_buf) => {
+                self.tx_buf.replace(tx_buf);
+                return ReturnCode::FAIL;
+            }
+        };
+
+        let mut mesh_buf = [0; 1 + 2 * 8];
+        let mesh_header = MeshHeader {
+            hops_left: mesh_header.hops_left - 1,
+            ..mesh_header
+        };
+        let mesh_len = match mesh_header.encode(&mut mesh_buf).done() {
+            Some((len, _)) => len,
+            None => {
+                self.tx_buf.replace(frame.into_buf());
+                return ReturnCode::FAIL;
+            }
+        };
+        if mesh_len + payload.len() > frame.remaining_data_capacity() {
+            self.tx_buf.replace(frame.into_buf());
+            return ReturnCode::ESIZE;
+        }
+        let mut result = frame.append_payload(&mesh_buf[..mesh_len]);
+        if result == ReturnCode::SUCCESS {
+            result = frame.append_payload(payload);
+        }
+        if result != ReturnCode::SUCCESS {
+            self.tx_buf.replace(frame.into_buf());
+            return result;
+        }
+
+        let (result, tx_buf) = self.mac.transmit(frame);
+        tx_buf.map(|tx_buf| self.tx_buf.replace(tx_buf));
+        result
+    }
+}
+
+impl<'a> TxClient for MeshForwarder<'a> {
+    fn send_done(&self, tx_buf: &'static mut [u8], _acked: bool, _result: ReturnCode) {
+        self.tx_buf.replace(tx_buf);
+    }
+}
@@ -1,2 +1,3 @@
+pub mod mesh;
 pub mod sixlowpan_compression;
 pub mod sixlowpan_state;
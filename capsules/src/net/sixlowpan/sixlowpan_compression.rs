@@ -91,16 +91,14 @@ pub struct Context {
 }
 
 /// LoWPAN encoding requires being able to look up the existence of contexts,
-/// which are essentially IPv6 address prefixes. Any implementation must ensure
-/// that context 0 is always available and contains the mesh-local prefix.
+/// which are essentially IPv6 address prefixes. Context 0 should hold the
+/// mesh-local prefix; packets that refer to a context the store does not
+/// have, including context 0, fail to decompress.
 pub trait ContextStore {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context>;
     fn get_context_from_id(&self, ctx_id: u8) -> Option<Context>;
-    fn get_context_0(&self) -> Context {
-        match self.get_context_from_id(0) {
-            Some(ctx) => ctx,
-            None => panic!("Context 0 not found"),
-        }
+    fn get_context_0(&self) -> Option<Context> {
+        self.get_context_from_id(0)
     }
     fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context>;
 }
@@ -112,7 +110,7 @@ pub fn compute_iid(mac_addr: &MacAddress) -> [u8; 8] {
         &MacAddress::Short(short_addr) => {
             // IID is 0000:00ff:fe00:XXXX, where XXXX is 16-bit MAC
             let mut iid: [u8; 8] = iphc::MAC_BASE;
-            iid[6] = (short_addr >> 1) as u8;
+            iid[6] = (short_addr >> 8) as u8;
             iid[7] = (short_addr & 0xff) as u8;
             iid
         }
@@ -151,6 +149,22 @@ impl ContextStore for Context {
     }
 }
 
+/// Lets a statically allocated context table, such as an `IP6Interface`, be
+/// shared between `Sixlowpan` and its owner.
+impl<'b, C: ContextStore + ?Sized> ContextStore for &'b C {
+    fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
+        (**self).get_context_from_addr(ip_addr)
+    }
+
+    fn get_context_from_id(&self, ctx_id: u8) -> Option<Context> {
+        (**self).get_context_from_id(ctx_id)
+    }
+
+    fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context> {
+        (**self).get_context_from_prefix(prefix, prefix_len)
+    }
+}
+
 pub fn is_lowpan(packet: &[u8]) -> bool {
     (packet[0] & iphc::DISPATCH[0]) == iphc::DISPATCH[0]
 }
@@ -875,7 +889,7 @@ fn decompress_cie(
     iphc_header: u8,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(Context, Context), ()> {
+) -> Result<(Option<Context>, Option<Context>), ()> {
     let ctx_0 = ctx_store.get_context_0();
     let (mut src_ctx, mut dst_ctx) = (ctx_0, ctx_0);
     if iphc_header & iphc::CID != 0 {
@@ -884,10 +898,10 @@ fn decompress_cie(
         *consumed += 1;
 
         if sci != 0 {
-            src_ctx = ctx_store.get_context_from_id(sci).ok_or(())?;
+            src_ctx = Some(ctx_store.get_context_from_id(sci).ok_or(())?);
         }
         if dci != 0 {
-            dst_ctx = ctx_store.get_context_from_id(dci).ok_or(())?;
+            dst_ctx = Some(ctx_store.get_context_from_id(dci).ok_or(())?);
         }
     }
     Ok((src_ctx, dst_ctx))
@@ -956,7 +970,7 @@ fn decompress_src(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
     mac_addr: &MacAddress,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -970,7 +984,7 @@ fn decompress_src(
             sam_mode,
             &mut ip6_header.src_addr,
             mac_addr,
-            ctx,
+            ctx.as_ref().ok_or(())?,
             buf,
             consumed,
         )?;
@@ -985,7 +999,7 @@ fn decompress_dst(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
     mac_addr: &MacAddress,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -1000,7 +1014,7 @@ fn decompress_dst(
             dam_mode,
             &mut ip6_header.dst_addr,
             mac_addr,
-            ctx,
+            ctx.as_ref().ok_or(())?,
             buf,
             consumed,
         )?;
@@ -1014,7 +1028,7 @@ fn decompress_dst(
 fn decompress_multicast(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -1022,6 +1036,7 @@ fn decompress_multicast(
     let dam_mode = iphc_header & iphc::DAM_MASK;
     let ip_addr: &mut IPAddr = &mut ip6_header.dst_addr;
     if uses_context {
+        let ctx = ctx.as_ref().ok_or(())?;
         match dam_mode {
             iphc::DAM_INLINE => {
                 // DAC = 1, DAM = 00: 48 bits
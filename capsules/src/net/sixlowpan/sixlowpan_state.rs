@@ -125,7 +125,8 @@
 // reassembly state corresponding to a single IPv6 packet. Note that since
 // they are maintained as a list, several RxStates can be allocated at compile
 // time, and each RxState corresponds to a distinct IPv6 packet that can be
-// reassembled simultaneously. Finally, the SixlowpanRxClient trait defines
+// reassembled simultaneously. The board decides how many RxStates to add,
+// and how long to wait for the fragments of a packet. Finally, the SixlowpanRxClient trait defines
 // the interface between the upper (IP) layer and the Sixlowpan layer for
 // reception. Each object is examined in greater detail below:
 //
@@ -195,16 +196,16 @@
 // increased the complexity of this layer substantially, and further,
 // necessitated additional initialization complexity by the upper layer.
 //
-// Single TxState:
+// Multiple TxStates:
 // Although both the RxState and TxState structs are treated similarly by
 // the Sixlowpan layer, many aspects of their control flow differ
-// significantly. The final design decision was to have a single upper layer
-// that serialized (or virtualized) both the reception and transmission of
-// IPv6 packets. As a result, only a single outstanding transmission made
-// sense, and thus the layer was designed to have a serial transmit path.
-// Note that this differs greatly from the RxState model, but since we
-// cannot serialize reception in the same way, it did not make sense to treat
-// both RxState and TxState structs identically.
+// significantly. A TxState only produces the frames of one packet, and
+// leaves sending them to the layer above. Each upper layer that sends
+// packets has a TxState of its own (in its `IP6SendStruct`), and the
+// `IP6SendMux` that the `IP6SendStruct`s share takes one frame from each
+// TxState with a packet in turn. Several packets can thus be sent at once,
+// with their fragments interleaved. They all share the global datagram tag
+// kept by Sixlowpan, so that their fragments can be told apart.
 //
 // TODOs and Known Issues
 // ----------------------------------
@@ -237,11 +238,12 @@ use kernel::ReturnCode;
 use net::frag_utils::Bitmap;
 use net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
 use net::ipv6::ipv6::IP6Packet;
+use net::sixlowpan::mesh::{self, lowpan_mesh, MeshForwarder, MeshHeader, BROADCAST_MAC_ADDR};
 use net::sixlowpan::sixlowpan_compression;
 use net::sixlowpan::sixlowpan_compression::{is_lowpan, ContextStore};
 use net::util::{slice_to_u16, u16_to_slice};
 
-// Reassembly timeout in seconds
+// Default reassembly timeout in seconds, the maximum RFC 4944 allows
 const FRAG_TIMEOUT: u32 = 60;
 
 /// Objects that implement this trait can set themselves to be the client
@@ -297,7 +299,11 @@ fn is_fragment(packet: &[u8]) -> bool {
 
 pub trait SixlowpanState<'a> {
     fn next_dgram_tag(&self) -> u16;
+    fn next_broadcast_seq(&self) -> u8;
     fn get_ctx_store(&self) -> &ContextStore;
+    /// Returns the next hop and the hops left with which to send a packet to
+    /// `dst_mac_addr` through the mesh, or `None` to send it directly.
+    fn get_mesh_route(&self, dst_mac_addr: MacAddress) -> Option<(MacAddress, u8)>;
     fn add_rx_state(&self, rx_state: &'a RxState<'a>);
     fn set_rx_client(&'a self, client: &'a SixlowpanRxClient);
 }
@@ -317,6 +323,8 @@ pub struct TxState<'a> {
     src_mac_addr: Cell<MacAddress>,
     dst_mac_addr: Cell<MacAddress>,
     security: Cell<Option<(SecurityLevel, KeyId)>>,
+    // The next hop and hops left if the packet is sent through the mesh
+    mesh_route: Cell<Option<(MacAddress, u8)>>,
     dgram_tag: Cell<u16>, // Used to identify particular fragment streams
     dgram_size: Cell<u16>,
     dgram_offset: Cell<usize>,
@@ -342,6 +350,7 @@ impl<'a> TxState<'a> {
             src_mac_addr: Cell::new(MacAddress::Short(0)),
             dst_mac_addr: Cell::new(MacAddress::Short(0)),
             security: Cell::new(None),
+            mesh_route: Cell::new(None),
 
             // Internal fields
             dgram_tag: Cell::new(0),
@@ -362,6 +371,9 @@ impl<'a> TxState<'a> {
     /// `security` - Any security options (necessary since the size of the
     /// produced MAC frame is dependent on the security options)
     ///
+    /// If `dst_mac_addr` is reached through the mesh, the frames are sent to
+    /// the next hop, with a mesh header addressed to `dst_mac_addr`.
+    ///
     /// # Return Value
     ///
     /// This function returns a `ReturnCode`, which indicates success or
@@ -380,6 +392,8 @@ impl<'a> TxState<'a> {
             self.src_mac_addr.set(src_mac_addr);
             self.dst_mac_addr.set(dst_mac_addr);
             self.security.set(security);
+            self.mesh_route
+                .set(self.sixlowpan.get_mesh_route(dst_mac_addr));
             self.busy.set(false);
             ReturnCode::SUCCESS
         }
@@ -410,12 +424,15 @@ impl<'a> TxState<'a> {
         frag_buf: &'static mut [u8],
         radio: &MacDevice,
     ) -> Result<(bool, Frame), (ReturnCode, &'static mut [u8])> {
+        let link_dst_mac_addr = self.mesh_route
+            .get()
+            .map_or(self.dst_mac_addr.get(), |(next_hop, _)| next_hop);
         // This consumes frag_buf
         let frame = radio
             .prepare_data_frame(
                 frag_buf,
                 self.dst_pan.get(),
-                self.dst_mac_addr.get(),
+                link_dst_mac_addr,
                 self.src_pan.get(),
                 self.src_mac_addr.get(),
                 self.security.get(),
@@ -488,16 +505,25 @@ impl<'a> TxState<'a> {
         // TODO: This -2 is added to account for the FCS; this should be changed
         // in the MAC code
         let mut remaining_capacity = frame.remaining_data_capacity() - 2;
+        match self.write_mesh_hdr(&mut frame) {
+            Ok(mesh_len) => remaining_capacity -= mesh_len,
+            Err(code) => return Err((code, frame.into_buf())),
+        }
 
         // Need to fragment
         if lowpan_len > remaining_capacity {
-            remaining_capacity -= self.write_frag_hdr(&mut frame, true);
+            match self.write_frag_hdr(&mut frame, true) {
+                Ok(frag_len) => remaining_capacity -= frag_len,
+                Err(code) => return Err((code, frame.into_buf())),
+            }
         }
 
         // Write the 6lowpan header
         if written <= remaining_capacity {
-            // TODO: Check success
-            frame.append_payload(&lowpan_packet[0..written]);
+            let result = frame.append_payload(&lowpan_packet[0..written]);
+            if result != ReturnCode::SUCCESS {
+                return Err((result, frame.into_buf()));
+            }
             remaining_capacity -= written;
         } else {
             return Err((ReturnCode::ESIZE, frame.into_buf()));
@@ -526,7 +552,14 @@ impl<'a> TxState<'a> {
     ) -> Result<Frame, (ReturnCode, &'static mut [u8])> {
         let dgram_offset = self.dgram_offset.get();
         let mut remaining_capacity = frame.remaining_data_capacity();
-        remaining_capacity -= self.write_frag_hdr(&mut frame, false);
+        match self.write_mesh_hdr(&mut frame) {
+            Ok(mesh_len) => remaining_capacity -= mesh_len,
+            Err(code) => return Err((code, frame.into_buf())),
+        }
+        match self.write_frag_hdr(&mut frame, false) {
+            Ok(frag_len) => remaining_capacity -= frag_len,
+            Err(code) => return Err((code, frame.into_buf())),
+        }
 
         // This rounds payload_len down to the nearest multiple of 8 if it
         // is not the last fragment (per RFC 4944)
@@ -579,7 +612,36 @@ impl<'a> TxState<'a> {
         (payload_len, dgram_offset)
     }
 
-    fn write_frag_hdr(&self, frame: &mut Frame, first_frag: bool) -> usize {
+    // Writes the mesh header, and the broadcast header of broadcasts, if the
+    // packet is sent through the mesh. Each fragment carries both, and a
+    // broadcast sequence number of its own, so that it is relayed on its own.
+    fn write_mesh_hdr(&self, frame: &mut Frame) -> Result<usize, ReturnCode> {
+        let hops_left = match self.mesh_route.get() {
+            Some((_, hops_left)) => hops_left,
+            None => return Ok(0),
+        };
+        let mesh_header = MeshHeader {
+            hops_left: hops_left,
+            originator: self.src_mac_addr.get(),
+            final_dst: self.dst_mac_addr.get(),
+        };
+        let mut mesh_buf = [0 as u8; 1 + 2 * 8 + lowpan_mesh::BC0_HDR_SIZE];
+        let mut written = match mesh_header.encode(&mut mesh_buf).done() {
+            Some((written, _)) => written,
+            None => return Err(ReturnCode::FAIL),
+        };
+        if mesh_header.final_dst == BROADCAST_MAC_ADDR {
+            mesh_buf[written] = lowpan_mesh::BC0_HDR;
+            mesh_buf[written + 1] = self.sixlowpan.next_broadcast_seq();
+            written += lowpan_mesh::BC0_HDR_SIZE;
+        }
+        match frame.append_payload(&mesh_buf[0..written]) {
+            ReturnCode::SUCCESS => Ok(written),
+            code => Err(code),
+        }
+    }
+
+    fn write_frag_hdr(&self, frame: &mut Frame, first_frag: bool) -> Result<usize, ReturnCode> {
         if first_frag {
             let mut frag_header = [0 as u8; lowpan_frag::FRAG1_HDR_SIZE];
             set_frag_hdr(
@@ -590,9 +652,10 @@ impl<'a> TxState<'a> {
                 &mut frag_header,
                 true,
             );
-            // TODO: Check success
-            frame.append_payload(&frag_header);
-            lowpan_frag::FRAG1_HDR_SIZE
+            match frame.append_payload(&frag_header) {
+                ReturnCode::SUCCESS => Ok(lowpan_frag::FRAG1_HDR_SIZE),
+                code => Err(code),
+            }
         } else {
             let mut frag_header = [0 as u8; lowpan_frag::FRAGN_HDR_SIZE];
             set_frag_hdr(
@@ -602,9 +665,10 @@ impl<'a> TxState<'a> {
                 &mut frag_header,
                 first_frag,
             );
-            // TODO: Check success
-            frame.append_payload(&frag_header);
-            lowpan_frag::FRAGN_HDR_SIZE
+            match frame.append_payload(&frag_header) {
+                ReturnCode::SUCCESS => Ok(lowpan_frag::FRAGN_HDR_SIZE),
+                code => Err(code),
+            }
         }
     }
 
@@ -677,9 +741,10 @@ impl<'a> RxState<'a> {
 
     // Checks if a given RxState is free or expired (and thus, can be freed).
     // This function implements the reassembly timeout for 6LoWPAN lazily.
-    fn is_busy(&self, frequency: u32, current_time: u32) -> bool {
-        let expired = current_time >= (self.start_time.get() + FRAG_TIMEOUT * frequency);
-        if expired {
+    // `timeout` is in clock ticks.
+    fn is_busy(&self, current_time: u32, timeout: u32) -> bool {
+        let expired = current_time.wrapping_sub(self.start_time.get()) >= timeout;
+        if self.busy.get() && expired {
             self.end_receive(None, ReturnCode::FAIL);
         }
         self.busy.get()
@@ -773,18 +838,24 @@ impl<'a> RxState<'a> {
 /// To receive packets, `Sixlowpan` needs one or more
 /// [RxState](struct.RxState.html)s which can be added with `add_rx_state`. More
 /// [RxState](struct.RxState.html)s allow the `Sixlowpan` to receive more
-/// packets concurrently.
+/// packets concurrently, and `set_reassembly_timeout` bounds how long a
+/// packet whose fragments stopped arriving holds one.
 ///
 /// Finally, `set_client` controls the client that will receive transmission
-/// completion and reception callbacks.
+/// completion and reception callbacks, and `set_mesh_forwarder` lets packets
+/// be sent and relayed through the mesh.
 pub struct Sixlowpan<'a, A: time::Alarm + 'a, C: ContextStore> {
     pub ctx_store: C,
     clock: &'a A,
     tx_dgram_tag: Cell<u16>,
+    tx_broadcast_seq: Cell<u8>,
     rx_client: Cell<Option<&'a SixlowpanRxClient>>,
+    mesh_forwarder: Cell<Option<&'a MeshForwarder<'a>>>,
 
     // Receive state
     rx_states: List<'a, RxState<'a>>,
+    // In seconds
+    reassembly_timeout: Cell<u32>,
 }
 
 // This function is called after receiving a frame
@@ -794,15 +865,30 @@ impl<'a, A: time::Alarm, C: ContextStore> RxClient for Sixlowpan<'a, A, C> {
         // a callback for an invalid frame reception
         // TODO: Handle the case where the addresses are None/elided - they
         // should not default to the zero address
-        let src_mac_addr = header.src_addr.unwrap_or(MacAddress::Short(0));
-        let dst_mac_addr = header.dst_addr.unwrap_or(MacAddress::Short(0));
-
-        let (rx_state, returncode) = self.receive_frame(
-            &buf[data_offset..data_offset + data_len],
-            data_len,
-            src_mac_addr,
-            dst_mac_addr,
-        );
+        let mut src_mac_addr = header.src_addr.unwrap_or(MacAddress::Short(0));
+        let mut dst_mac_addr = header.dst_addr.unwrap_or(MacAddress::Short(0));
+        let mut payload = &buf[data_offset..data_offset + data_len];
+
+        if mesh::is_mesh(payload) {
+            let (offset, mesh_header) = match MeshHeader::decode(payload).done() {
+                Some(decoded) => decoded,
+                None => return,
+            };
+            payload = &payload[offset..];
+            if !self.receive_mesh(&header, mesh_header, payload) {
+                return;
+            }
+            // The packet is decompressed and reassembled as sent from the
+            // originator to the final destination
+            src_mac_addr = mesh_header.originator;
+            dst_mac_addr = mesh_header.final_dst;
+        }
+        if mesh::is_bc0(payload) {
+            payload = &payload[lowpan_mesh::BC0_HDR_SIZE..];
+        }
+
+        let (rx_state, returncode) =
+            self.receive_frame(payload, payload.len(), src_mac_addr, dst_mac_addr);
         // Reception completed if rx_state is not None. Note that this can
         // also occur for some fail states (e.g. dropping an invalid packet)
         rx_state.map(|state| state.end_receive(self.rx_client.get(), returncode));
@@ -821,10 +907,22 @@ impl<'a, A: time::Alarm, C: ContextStore> SixlowpanState<'a> for Sixlowpan<'a, A
         dgram_tag
     }
 
+    fn next_broadcast_seq(&self) -> u8 {
+        let seq = self.tx_broadcast_seq.get().wrapping_add(1);
+        self.tx_broadcast_seq.set(seq);
+        seq
+    }
+
     fn get_ctx_store(&self) -> &ContextStore {
         &self.ctx_store
     }
 
+    fn get_mesh_route(&self, dst_mac_addr: MacAddress) -> Option<(MacAddress, u8)> {
+        self.mesh_forwarder
+            .get()
+            .and_then(|mesh_forwarder| mesh_forwarder.get_route(dst_mac_addr))
+    }
+
     /// Adds an additional `RxState` for reassembling IPv6 packets
     ///
     /// Each [RxState](struct.RxState.html) struct allows an additional IPv6
@@ -859,9 +957,62 @@ impl<'a, A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
             ctx_store: ctx_store,
             clock: clock,
             tx_dgram_tag: Cell::new(0),
+            tx_broadcast_seq: Cell::new(0),
             rx_client: Cell::new(None),
+            mesh_forwarder: Cell::new(None),
 
             rx_states: List::new(),
+            reassembly_timeout: Cell::new(FRAG_TIMEOUT),
+        }
+    }
+
+    /// Sets the number of seconds after which the reassembly of a packet
+    /// whose fragments stopped arriving is given up, freeing its `RxState`
+    /// for other packets. The default is 60 seconds, the maximum RFC 4944
+    /// allows, and longer timeouts are reduced to it. A shorter timeout
+    /// frees `RxState`s sooner on busy networks.
+    pub fn set_reassembly_timeout(&self, timeout: u32) {
+        self.reassembly_timeout
+            .set(min(timeout, FRAG_TIMEOUT));
+    }
+
+    fn reassembly_timeout_ticks(&self) -> u32 {
+        self.reassembly_timeout.get() * A::Frequency::frequency()
+    }
+
+    /// Sets the [MeshForwarder](../mesh/struct.MeshForwarder.html) that
+    /// holds the mesh routes, and relays the frames received for other
+    /// nodes. Without one, packets are only sent to neighbors, and every
+    /// frame with a mesh header is taken as addressed to this node.
+    pub fn set_mesh_forwarder(&self, mesh_forwarder: &'a MeshForwarder<'a>) {
+        self.mesh_forwarder.set(Some(mesh_forwarder));
+    }
+
+    // Relays a frame with a mesh header if it is for another node or
+    // broadcast, and returns whether this node should receive it too.
+    // `payload` follows the mesh header.
+    fn receive_mesh(&self, header: &Header, mesh_header: MeshHeader, payload: &[u8]) -> bool {
+        let mesh_forwarder = match self.mesh_forwarder.get() {
+            Some(mesh_forwarder) => mesh_forwarder,
+            None => return true,
+        };
+        if mesh_forwarder.is_local(mesh_header.originator) {
+            // Our own frame, relayed back to us
+            return false;
+        }
+        if mesh_header.final_dst == BROADCAST_MAC_ADDR {
+            if mesh::is_bc0(payload)
+                && mesh_forwarder.is_duplicate_broadcast(mesh_header.originator, payload[1])
+            {
+                return false;
+            }
+            mesh_forwarder.forward(header, mesh_header, payload);
+            true
+        } else if mesh_forwarder.is_local(mesh_header.final_dst) {
+            true
+        } else {
+            mesh_forwarder.forward(header, mesh_header, payload);
+            false
         }
     }
 
@@ -902,7 +1053,7 @@ impl<'a, A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
     ) -> (Option<&RxState<'a>>, ReturnCode) {
         let rx_state = self.rx_states
             .iter()
-            .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+            .find(|state| !state.is_busy(self.clock.now(), self.reassembly_timeout_ticks()));
         rx_state
             .map(|state| {
                 state.start_receive(
@@ -934,8 +1085,13 @@ impl<'a, A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
                             let remaining = payload_len - consumed;
                             packet[written..written + remaining]
                                 .copy_from_slice(&payload[consumed..consumed + remaining]);
+                            // The datagram size is the size of the
+                            // decompressed packet, not of the frame payload
+                            state.dgram_size.set((written + remaining) as u16);
                         }
                         Err(_) => {
+                            state.packet.replace(packet);
+                            state.busy.set(false);
                             return (None, ReturnCode::FAIL);
                         }
                     }
@@ -970,7 +1126,7 @@ impl<'a, A: time::Alarm, C: ContextStore> Sixlowpan<'a, A, C> {
         if rx_state.is_none() {
             rx_state = self.rx_states
                 .iter()
-                .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+                .find(|state| !state.is_busy(self.clock.now(), self.reassembly_timeout_ticks()));
             // Initialize new state
             rx_state.map(|state| {
                 state.start_receive(
@@ -0,0 +1,386 @@
+//! TCP userspace interface for stream connections.
+//!
+//! Each app can hold one connection at a time, on one of the
+//! [TCPSocket](../tcp_socket/struct.TCPSocket.html)s the board gives the
+//! driver. A socket is taken when the app listens or connects, and handed
+//! back when the connection is over. Received data is appended to the app's
+//! receive buffer until the app marks it as read; data that does not fit is
+//! left to the peer to retransmit.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow` System Call
+//!
+//! * 0: The buffer that received data is appended to.
+//! * 1: The data to send.
+//! * 2: The destination of the next connection, 18 bytes: the IPv6 address
+//!   followed by the port in network byte order.
+//! * 3: Where the peer of each established connection is written, in the
+//!   same format. Optional.
+//!
+//! ### `subscribe` System Call
+//!
+//! * 0: Receive callback, with the number of unread bytes in the receive
+//!   buffer.
+//! * 1: Send callback, with the number of bytes the peer has acknowledged,
+//!   which frees as much room in the send buffer of the socket.
+//! * 2: Connection callback, with the event and, for `closed`, the
+//!   `ReturnCode` of the connection: `SUCCESS` if it was closed on both sides,
+//!   `ECANCEL` if the peer reset it and `ENOACK` if the peer stopped
+//!   answering. The events are 0 for `connected`, 1 for `remote closed` (no
+//!   more data will be received) and 2 for `closed`, after which the app can
+//!   open another connection.
+//!
+//! ### `command` System Call
+//!
+//! * 0: Driver check.
+//! * 1: Listen on port `arg1`, and accept the first connection to it.
+//! * 2: Connect from local port `arg1` to the configured destination.
+//!   Returns `EBUSY` if no random initial sequence number is ready yet,
+//!   which can happen right after boot.
+//! * 3: Queue the first `arg1` bytes of the data to send. Returns the number
+//!   of bytes queued, which is less than `arg1` if the send buffer is full.
+//! * 4: Mark the receive buffer as read, so that data is written from its
+//!   start again.
+//! * 5: Close the connection once the queued data is sent. A connection that
+//!   is not established yet is dropped at once, without a callback.
+//! * 6: Abort the connection with a reset, without a callback.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_sockets = static_init!(
+//!     [&'static TCPSocket<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>; 2],
+//!     [tcp_socket_0, tcp_socket_1]);
+//! let tcp_driver = static_init!(
+//!     capsules::net::tcp::driver::TCPDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::net::tcp::driver::TCPDriver::new(tcp_sockets, kernel::Grant::create()));
+//! for socket in tcp_sockets.iter() {
+//!     socket.set_client(tcp_driver);
+//! }
+//! ```
+
+use core::cmp::min;
+use kernel::hil::time::Alarm;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::ipv6::ip_utils::IPAddr;
+use net::stream::{decode_bytes, decode_u16, encode_bytes, encode_u16, SResult};
+use net::tcp::tcp_socket::{TCPClient, TCPSocket, TCPState};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30004;
+
+/// Length of an IPv6 address and port, as exchanged with userland
+const ENDPOINT_LEN: usize = 18;
+
+/// The events of the connection callback
+const EVENT_CONNECTED: usize = 0;
+const EVENT_REMOTE_CLOSED: usize = 1;
+const EVENT_CLOSED: usize = 2;
+
+/// Decodes an address and port in the format used by the userland driver.
+fn decode_endpoint(buf: &[u8]) -> SResult<(IPAddr, u16)> {
+    stream_len_cond!(buf, ENDPOINT_LEN);
+    let mut addr = IPAddr::new();
+    let off = dec_consume!(buf; decode_bytes, &mut addr.0);
+    let (off, port) = dec_try!(buf, off; decode_u16);
+    stream_done!(off, (addr, port));
+}
+
+/// Encodes an address and port in the format expected by the userland driver.
+fn encode_endpoint(buf: &mut [u8], addr: IPAddr, port: u16) -> SResult {
+    stream_len_cond!(buf, ENDPOINT_LEN);
+    let off = enc_consume!(buf; encode_bytes, &addr.0);
+    let off = enc_consume!(buf, off; encode_u16, port);
+    stream_done!(off);
+}
+
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    conn_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    app_remote_cfg: Option<AppSlice<Shared, u8>>,
+    /// The ID of the socket of the app's connection
+    socket: Option<usize>,
+    /// Bytes in the receive buffer that the app has not read
+    rx_len: usize,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        App {
+            rx_callback: None,
+            tx_callback: None,
+            conn_callback: None,
+            app_read: None,
+            app_write: None,
+            app_cfg: None,
+            app_remote_cfg: None,
+            socket: None,
+            rx_len: 0,
+        }
+    }
+}
+
+pub struct TCPDriver<'a, A: Alarm + 'a> {
+    /// The sockets apps can use. The ID of each is its index.
+    sockets: &'a [&'a TCPSocket<'a, A>],
+
+    /// Grant of apps that use this TCP driver.
+    apps: Grant<App>,
+}
+
+impl<'a, A: Alarm + 'a> TCPDriver<'a, A> {
+    pub fn new(sockets: &'a [&'a TCPSocket<'a, A>], grant: Grant<App>) -> TCPDriver<'a, A> {
+        TCPDriver {
+            sockets: sockets,
+            apps: grant,
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Returns the app that holds socket `id`.
+    fn owner(&self, id: usize) -> Option<AppId> {
+        let mut owner = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.socket == Some(id) {
+                    owner = Some(app.appid());
+                }
+            });
+            if owner.is_some() {
+                break;
+            }
+        }
+        owner
+    }
+
+    /// Returns a socket that is closed and that no app holds.
+    fn free_socket(&self) -> Option<&'a TCPSocket<'a, A>> {
+        self.sockets
+            .iter()
+            .find(|socket| {
+                socket.get_state() == TCPState::Closed && self.owner(socket.get_id()).is_none()
+            })
+            .map(|socket| *socket)
+    }
+
+    /// Opens a connection for the app with `open`, on a free socket.
+    fn open<F>(&self, appid: AppId, open: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App, &TCPSocket<'a, A>) -> ReturnCode,
+    {
+        let socket = match self.free_socket() {
+            Some(socket) => socket,
+            None => return ReturnCode::ENOMEM,
+        };
+        self.do_with_app(appid, |app| {
+            if app.socket.is_some() {
+                return ReturnCode::EALREADY;
+            }
+            let result = open(app, socket);
+            if result == ReturnCode::SUCCESS {
+                app.socket = Some(socket.get_id());
+                app.rx_len = 0;
+            }
+            result
+        })
+    }
+
+    /// Performs an action on the socket of the app.
+    fn do_with_socket<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App, &TCPSocket<'a, A>) -> ReturnCode,
+    {
+        self.do_with_app(appid, |app| {
+            match app.socket.and_then(|id| self.sockets.get(id)) {
+                Some(socket) => closure(app, socket),
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Runs `closure` on the app that holds socket `id`, if any.
+    fn with_owner<F>(&self, id: usize, closure: F)
+    where
+        F: FnOnce(&mut App),
+    {
+        self.owner(id).map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| closure(app));
+        });
+    }
+}
+
+impl<'a, A: Alarm + 'a> Driver for TCPDriver<'a, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => {
+                        app.app_read = slice;
+                        app.rx_len = 0;
+                    }
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    3 => app.app_remote_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.rx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.tx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            2 => self.do_with_app(app_id, |app| {
+                app.conn_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                if arg1 > (u16::max_value() as usize) {
+                    return ReturnCode::EINVAL;
+                }
+                self.open(appid, |_, socket| socket.listen(arg1 as u16))
+            }
+            2 => {
+                if arg1 > (u16::max_value() as usize) {
+                    return ReturnCode::EINVAL;
+                }
+                self.open(appid, |app, socket| {
+                    match app.app_cfg
+                        .as_ref()
+                        .and_then(|cfg| decode_endpoint(cfg.as_ref()).done())
+                    {
+                        Some((_, (addr, port))) => socket.connect(addr, port, arg1 as u16),
+                        None => ReturnCode::EINVAL,
+                    }
+                })
+            }
+            3 => self.do_with_socket(appid, |app, socket| {
+                let result = app.app_write.as_ref().map(|data| {
+                    let data = data.as_ref();
+                    socket.send(&data[..min(arg1, data.len())])
+                });
+                match result {
+                    Some(Ok(len)) => ReturnCode::SuccessWithValue { value: len },
+                    Some(Err(err)) => err,
+                    None => ReturnCode::EINVAL,
+                }
+            }),
+            4 => self.do_with_app(appid, |app| {
+                app.rx_len = 0;
+                ReturnCode::SUCCESS
+            }),
+            5 => self.do_with_socket(appid, |app, socket| {
+                let result = socket.close();
+                if socket.get_state() == TCPState::Closed {
+                    app.socket = None;
+                }
+                result
+            }),
+            6 => self.do_with_socket(appid, |app, socket| {
+                socket.abort();
+                app.socket = None;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> TCPClient for TCPDriver<'a, A> {
+    fn connected(&self, socket: usize) {
+        let (addr, port) = match self.sockets.get(socket) {
+            Some(socket) => socket.get_remote(),
+            None => return,
+        };
+        self.with_owner(socket, |app| {
+            app.app_remote_cfg
+                .as_mut()
+                .map(|cfg| encode_endpoint(cfg.as_mut(), addr, port));
+            app.conn_callback
+                .map(|mut cb| cb.schedule(EVENT_CONNECTED, 0, 0));
+        });
+    }
+
+    fn received(&self, socket: usize, data: &[u8]) -> usize {
+        let mut taken = 0;
+        self.with_owner(socket, |app| {
+            let rx_len = app.rx_len;
+            taken = app.app_read.as_mut().map_or(0, |rbuf| {
+                let rbuf = rbuf.as_mut();
+                let len = min(rbuf.len().saturating_sub(rx_len), data.len());
+                rbuf[rx_len..rx_len + len].copy_from_slice(&data[..len]);
+                len
+            });
+            if taken > 0 {
+                app.rx_len += taken;
+                app.rx_callback
+                    .map(|mut cb| cb.schedule(app.rx_len, 0, 0));
+            }
+        });
+        taken
+    }
+
+    fn sent(&self, socket: usize, len: usize) {
+        self.with_owner(socket, |app| {
+            app.tx_callback.map(|mut cb| cb.schedule(len, 0, 0));
+        });
+    }
+
+    fn remote_closed(&self, socket: usize) {
+        self.with_owner(socket, |app| {
+            app.conn_callback
+                .map(|mut cb| cb.schedule(EVENT_REMOTE_CLOSED, 0, 0));
+        });
+    }
+
+    fn closed(&self, socket: usize, result: ReturnCode) {
+        self.with_owner(socket, |app| {
+            app.socket = None;
+            app.conn_callback
+                .map(|mut cb| cb.schedule(EVENT_CLOSED, result.into(), 0));
+        });
+    }
+}
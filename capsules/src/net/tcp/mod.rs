@@ -0,0 +1,4 @@
+pub mod driver;
+pub mod tcp;
+pub mod tcp_mux;
+pub mod tcp_socket;
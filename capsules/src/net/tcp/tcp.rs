@@ -0,0 +1,208 @@
+//! This file contains the structs and methods associated with the TCP header.
+//! This includes getters and setters for the various header fields, as well
+//! as the standard encode/decode functionality required for serializing
+//! the struct for transmission.
+//!
+//! TCP options are not supported: headers are always encoded without them,
+//! and the options of decoded headers are skipped.
+
+use net::stream::SResult;
+use net::stream::{decode_u16, decode_u32};
+use net::stream::{encode_u16, encode_u32};
+
+/// The control bits of the TCP header
+pub mod tcp_flags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+    pub const URG: u8 = 0x20;
+}
+
+/// The size of a TCP header without options
+const MIN_HDR_SIZE: usize = 20;
+
+/// The `TCPHeader` struct follows the layout for the TCP segment header.
+/// Note that the implementation of this struct provides getters and setters
+/// for the various fields of the header, to avoid confusion with endian-ness.
+#[derive(Copy, Clone)]
+pub struct TCPHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq_num: u32,
+    pub ack_num: u32,
+    pub offset_and_control: u16,
+    pub window: u16,
+    pub cksum: u16,
+    pub urg_ptr: u16,
+    pub len: u16, // Not a real TCP field, here for convenience
+}
+
+impl Default for TCPHeader {
+    fn default() -> TCPHeader {
+        TCPHeader {
+            src_port: 0,
+            dst_port: 0,
+            seq_num: 0,
+            ack_num: 0,
+            offset_and_control: ((MIN_HDR_SIZE / 4) as u16) << 12,
+            window: 0,
+            cksum: 0,
+            urg_ptr: 0,
+            len: MIN_HDR_SIZE as u16,
+        }
+    }
+}
+
+impl TCPHeader {
+    pub fn new() -> TCPHeader {
+        TCPHeader::default()
+    }
+
+    pub fn set_dst_port(&mut self, port: u16) {
+        self.dst_port = port;
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.src_port = port;
+    }
+
+    pub fn set_seq_num(&mut self, seq_num: u32) {
+        self.seq_num = seq_num;
+    }
+
+    pub fn set_ack_num(&mut self, ack_num: u32) {
+        self.ack_num = ack_num;
+    }
+
+    /// Sets the control bits, from `tcp_flags`
+    pub fn set_flags(&mut self, flags: u8) {
+        self.offset_and_control = (self.offset_and_control & 0xffc0) | (flags & 0x3f) as u16;
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn set_cksum(&mut self, cksum: u16) {
+        self.cksum = cksum;
+    }
+
+    /// Sets the length of the whole segment, header included
+    pub fn set_len(&mut self, len: u16) {
+        self.len = len;
+    }
+
+    pub fn get_src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    pub fn get_dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    pub fn get_seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    pub fn get_ack_num(&self) -> u32 {
+        self.ack_num
+    }
+
+    /// Returns the control bits, from `tcp_flags`
+    pub fn get_flags(&self) -> u8 {
+        (self.offset_and_control & 0x3f) as u8
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.get_flags() & flag != 0
+    }
+
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    pub fn get_cksum(&self) -> u16 {
+        self.cksum
+    }
+
+    /// Returns the length of the whole segment, header included
+    pub fn get_len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns the size of the header, options included
+    pub fn get_hdr_size(&self) -> usize {
+        ((self.offset_and_control >> 12) as usize) * 4
+    }
+
+    /// This function serializes the `TCPHeader` into the provided buffer.
+    /// The options area, if the data offset leaves room for one, is zeroed.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `TCPHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.src_port);
+        off = enc_consume!(buf, off; encode_u16, self.dst_port);
+        off = enc_consume!(buf, off; encode_u32, self.seq_num);
+        off = enc_consume!(buf, off; encode_u32, self.ack_num);
+        off = enc_consume!(buf, off; encode_u16, self.offset_and_control);
+        off = enc_consume!(buf, off; encode_u16, self.window);
+        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.urg_ptr);
+        let end = offset + self.get_hdr_size();
+        for byte in buf[off..end].iter_mut() {
+            *byte = 0;
+        }
+        stream_done!(end, end);
+    }
+
+    /// This function deserializes the `TCPHeader` from the provided buffer.
+    /// The length of the segment is taken to be the length of `buf`.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized TCP segment
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `TCPHeader` struct wrapped in an SResult. The
+    /// offset returned is that of the payload, after any options.
+    pub fn decode(buf: &[u8]) -> SResult<TCPHeader> {
+        stream_len_cond!(buf, MIN_HDR_SIZE);
+        let mut tcp_header = Self::new();
+        let off = 0;
+        let (off, src_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.src_port = src_port;
+        let (off, dst_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.dst_port = dst_port;
+        let (off, seq_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.seq_num = seq_num;
+        let (off, ack_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.ack_num = ack_num;
+        let (off, offset_and_control) = dec_try!(buf, off; decode_u16);
+        tcp_header.offset_and_control = offset_and_control;
+        let (off, window) = dec_try!(buf, off; decode_u16);
+        tcp_header.window = window;
+        let (off, cksum) = dec_try!(buf, off; decode_u16);
+        tcp_header.cksum = cksum;
+        let (_, urg_ptr) = dec_try!(buf, off; decode_u16);
+        tcp_header.urg_ptr = urg_ptr;
+
+        let hdr_size = tcp_header.get_hdr_size();
+        stream_cond!(hdr_size >= MIN_HDR_SIZE && hdr_size <= buf.len(), ());
+        tcp_header.len = buf.len() as u16;
+        stream_done!(hdr_size, tcp_header);
+    }
+}
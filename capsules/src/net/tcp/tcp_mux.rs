@@ -0,0 +1,271 @@
+//! This file contains the definition of the interface through which TCP
+//! segments are received, and the [TCPMux](struct.TCPMux.html), which
+//! connects the [TCPSocket](../tcp_socket/struct.TCPSocket.html)s of the node
+//! to the IP layer.
+//!
+//! The mux is the `TCPRecvClient` of an
+//! [IP6Receiver](../../ipv6/ipv6_recv/trait.IP6Receiver.html), and passes
+//! each segment to the socket whose connection it belongs to, or else to a
+//! socket listening on its destination port. Segments that belong to no
+//! socket are answered with a reset, unless they were sent to a multicast
+//! address.
+//!
+//! The mux also keeps a few random initial sequence numbers ready for the
+//! sockets, drawn from an `RNG` that should be a CSPRNG, so that sequence
+//! numbers can't be guessed by off-path attackers (RFC 6528).
+//!
+//! The sockets share the `IP6Sender` of the mux, which sends one segment at
+//! a time. A socket that has a segment to send while the sender is busy waits
+//! for its turn: when a transmission completes, the mux lets the waiting
+//! sockets send in turn, starting after the socket that sent last.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let tcp_mux = static_init!(
+//!     TCPMux<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     TCPMux::new(tcp_ip6_sender, tcp_rng, TCP_MSS));
+//! tcp_ip6_sender.set_client(tcp_mux);
+//! tcp_rng.set_client(tcp_mux);
+//! ip6_receiver.set_tcp_client(tcp_mux);
+//!
+//! let socket = static_init!(
+//!     TCPSocket<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     TCPSocket::new(0, tcp_mux, socket_alarm, &mut TCP_TX_BUF));
+//! socket_alarm.set_client(socket);
+//! tcp_mux.add_socket(socket);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::List;
+use kernel::hil::rng::{self, RNG};
+use kernel::hil::time::Alarm;
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::{IP6Header, TransportHeader};
+use net::ipv6::ipv6_send::{IP6Client, IP6Sender};
+use net::tcp::tcp::{tcp_flags, TCPHeader};
+use net::tcp::tcp_socket::{TCPSocket, TCPState};
+
+/// The `receive` function in this trait is invoked for each TCP segment that
+/// is received. Note that `IP6Receiver::set_tcp_client` must be called to set
+/// the client.
+pub trait TCPRecvClient {
+    /// This function is called when a TCP segment has been received
+    ///
+    /// # Arguments
+    /// `ip6_header` - The IPv6 header of the packet. Its payload length and
+    /// next header describe the TCP segment, skipping any extension headers
+    /// `tcp_header` - The decoded TCP header
+    /// `payload` - The TCP payload, after any options
+    fn receive(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload: &[u8]);
+}
+
+/// How many initial sequence numbers the mux keeps ready
+const ISN_POOL: usize = 4;
+
+pub struct TCPMux<'a, A: Alarm + 'a> {
+    ip_sender: &'a IP6Sender<'a>,
+    rng: &'a RNG,
+    /// Random initial sequence numbers, the first `isn_count` of which are
+    /// unused
+    isns: Cell<[u32; ISN_POOL]>,
+    isn_count: Cell<usize>,
+    /// The largest payload the sender can take
+    mss: usize,
+    sockets: List<'a, TCPSocket<'a, A>>,
+    busy: Cell<bool>,
+    /// The position of the socket to give the first turn to
+    next_turn: Cell<usize>,
+}
+
+impl<'a, A: Alarm + 'a> TCPMux<'a, A> {
+    /// Creates a mux that sends through `ip_sender`, whose packet must have
+    /// room for payloads of `mss` bytes, and takes initial sequence numbers
+    /// from `rng`.
+    pub fn new(ip_sender: &'a IP6Sender<'a>, rng: &'a RNG, mss: usize) -> TCPMux<'a, A> {
+        TCPMux {
+            ip_sender: ip_sender,
+            rng: rng,
+            isns: Cell::new([0; ISN_POOL]),
+            isn_count: Cell::new(0),
+            mss: mss,
+            sockets: List::new(),
+            busy: Cell::new(false),
+            next_turn: Cell::new(0),
+        }
+    }
+
+    /// Adds a socket to the mux. This also starts filling the pool of
+    /// initial sequence numbers.
+    pub fn add_socket(&self, socket: &'a TCPSocket<'a, A>) {
+        self.sockets.push_head(socket);
+        self.rng.get();
+    }
+
+    /// Takes a random initial sequence number, or `None` if none is ready
+    /// yet. The pool is refilled in the background.
+    pub fn take_isn(&self) -> Option<u32> {
+        let count = self.isn_count.get();
+        self.rng.get();
+        if count == 0 {
+            return None;
+        }
+        self.isn_count.set(count - 1);
+        Some(self.isns.get()[count - 1])
+    }
+
+    /// The maximum segment size: the largest payload a socket may send
+    pub fn get_mss(&self) -> usize {
+        self.mss
+    }
+
+    /// Returns whether a socket is listening on, or connected from, `port`.
+    pub fn port_in_use(&self, port: u16) -> bool {
+        self.sockets
+            .iter()
+            .any(|socket| socket.get_state() != TCPState::Closed && socket.get_local_port() == port)
+    }
+
+    /// Sends a segment. The header is completed with the length and
+    /// checksum of the segment by the IP layer.
+    ///
+    /// # Arguments
+    /// `src` - The address to send from, or `None` to let the interface
+    /// pick it
+    /// `dst` - The address to send to
+    /// `tcp_header` - The header of the segment
+    /// `payload` - The payload, which is copied before this function returns
+    ///
+    /// # Return Value
+    /// `EBUSY` if another segment is being sent, and otherwise the result of
+    /// `IP6Sender::send_to` or `IP6Sender::send_from`.
+    pub fn send(
+        &self,
+        src: Option<IPAddr>,
+        dst: IPAddr,
+        tcp_header: TCPHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        if payload.len() > self.mss {
+            return ReturnCode::ESIZE;
+        }
+        // The sender may complete the transmission before returning
+        self.busy.set(true);
+        let transport_header = TransportHeader::TCP(tcp_header);
+        let result = match src {
+            Some(src) => self.ip_sender.send_from(src, dst, transport_header, payload),
+            None => self.ip_sender.send_to(dst, transport_header, payload),
+        };
+        if result != ReturnCode::SUCCESS {
+            self.busy.set(false);
+        }
+        result
+    }
+
+    /// Answers a segment with a reset (RFC 793, section 3.4). The reset is
+    /// dropped if the sender is busy. Segments to or from multicast
+    /// addresses are never answered (RFC 1122, section 4.2.3.10).
+    pub fn send_reset(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload_len: usize) {
+        if tcp_header.has_flag(tcp_flags::RST) {
+            return;
+        }
+        if ip6_header.dst_addr.is_multicast() || ip6_header.src_addr.is_multicast() {
+            return;
+        }
+        let mut reset = TCPHeader::new();
+        reset.set_src_port(tcp_header.get_dst_port());
+        reset.set_dst_port(tcp_header.get_src_port());
+        if tcp_header.has_flag(tcp_flags::ACK) {
+            reset.set_seq_num(tcp_header.get_ack_num());
+            reset.set_flags(tcp_flags::RST);
+        } else {
+            let mut seg_len = payload_len as u32;
+            if tcp_header.has_flag(tcp_flags::SYN) {
+                seg_len += 1;
+            }
+            if tcp_header.has_flag(tcp_flags::FIN) {
+                seg_len += 1;
+            }
+            reset.set_ack_num(tcp_header.get_seq_num().wrapping_add(seg_len));
+            reset.set_flags(tcp_flags::RST | tcp_flags::ACK);
+        }
+        self.send(Some(ip6_header.dst_addr), ip6_header.src_addr, reset, &[]);
+    }
+
+    /// Lets the sockets waiting to send take their turns, until the sender
+    /// is busy again.
+    fn send_pending(&self) {
+        let count = self.sockets.iter().count();
+        let first = self.next_turn.get();
+        for i in 0..count {
+            if self.busy.get() {
+                break;
+            }
+            let turn = (first + i) % count;
+            self.sockets.iter().nth(turn).map(|socket| {
+                if socket.is_output_pending() {
+                    socket.output();
+                    if self.busy.get() {
+                        self.next_turn.set(turn + 1);
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> IP6Client for TCPMux<'a, A> {
+    /// Lost segments are retransmitted by the sockets, so the result is not
+    /// passed on.
+    fn send_done(&self, _result: ReturnCode) {
+        self.busy.set(false);
+        self.send_pending();
+    }
+}
+
+impl<'a, A: Alarm + 'a> rng::Client for TCPMux<'a, A> {
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        let mut isns = self.isns.get();
+        let mut count = self.isn_count.get();
+        while count < ISN_POOL {
+            match randomness.next() {
+                Some(isn) => {
+                    isns[count] = isn;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        self.isns.set(isns);
+        self.isn_count.set(count);
+        if count < ISN_POOL {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a> TCPRecvClient for TCPMux<'a, A> {
+    fn receive(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload: &[u8]) {
+        let src_port = tcp_header.get_src_port();
+        let dst_port = tcp_header.get_dst_port();
+        let socket = self.sockets
+            .iter()
+            .find(|socket| socket.is_connection(ip6_header.src_addr, src_port, dst_port))
+            .or_else(|| {
+                self.sockets
+                    .iter()
+                    .find(|socket| socket.is_listening(dst_port))
+            });
+        match socket {
+            Some(socket) => socket.receive(ip6_header, tcp_header, payload),
+            None => self.send_reset(ip6_header, tcp_header, payload.len()),
+        }
+    }
+}
@@ -0,0 +1,851 @@
+//! This file implements a TCP connection endpoint, following the subset of
+//! RFC 793 that a constrained node needs. A [TCPSocket](struct.TCPSocket.html)
+//! either opens a connection to a remote port, or listens on a local port and
+//! becomes the connection of the first peer that connects to it. It then
+//! carries a byte stream each way, and closes the connection with a FIN, or
+//! aborts it with a reset.
+//!
+//! The socket is kept small at the expense of throughput:
+//!
+//! * Data is sent from a buffer given to the socket, which holds the bytes
+//!   until they are acknowledged. Segments are no larger than the MSS of the
+//!   `TCPMux`, and as many are in flight as the peer's window allows.
+//! * Received data is passed to the client as soon as it arrives in order,
+//!   and the client takes as much of it as it has room for. Segments that
+//!   arrive out of order, and data the client does not take, are dropped, to
+//!   be retransmitted by the peer. The window advertised is a fixed
+//!   `RCV_WND` bytes.
+//! * Unacknowledged segments are sent again after a retransmission timeout
+//!   (RTO), computed from the measured round-trip time as in RFC 6298 and
+//!   doubled after each retransmission. Every segment from the first
+//!   unacknowledged one on is sent again. The connection is dropped after
+//!   `MAX_RETRIES` retransmissions in a row.
+//! * TCP options are neither sent nor understood, the urgent pointer is
+//!   ignored, and ACKs are not delayed.
+//!
+//! The socket needs an alarm of its own, for retransmissions and for the
+//! TIME-WAIT state. Each socket has an ID, chosen by whoever creates it, that
+//! is passed back to its [TCPClient](trait.TCPClient.html) so that a client
+//! can serve several sockets.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::{ListLink, ListNode};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::tcp::tcp::{tcp_flags, TCPHeader};
+use net::tcp::tcp_mux::TCPMux;
+
+/// The window advertised to peers, in bytes
+pub const RCV_WND: u16 = 256;
+/// The RTO before the round-trip time is measured, in milliseconds. RFC 6298
+/// uses 1 second, but multihop 6LoWPAN links are slower.
+pub const INITIAL_RTO: u32 = 3000;
+/// The shortest RTO, in milliseconds
+pub const MIN_RTO: u32 = 1000;
+/// The longest RTO, in milliseconds
+pub const MAX_RTO: u32 = 60000;
+/// Retransmissions in a row after which the connection is dropped
+pub const MAX_RETRIES: u8 = 6;
+/// How long a closed connection lingers in TIME-WAIT, in milliseconds
+pub const TIME_WAIT: u32 = 30000;
+
+/// The states of a connection, from RFC 793
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TCPState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// The callbacks of a `TCPSocket`. Each one is passed the ID of the socket.
+pub trait TCPClient {
+    /// The connection has been established
+    fn connected(&self, socket: usize);
+
+    /// Data has arrived. The client returns how many bytes it took, from the
+    /// start of `data`: the rest is dropped and will be retransmitted.
+    fn received(&self, socket: usize, data: &[u8]) -> usize;
+
+    /// The peer has acknowledged `len` bytes, which have been freed from the
+    /// send buffer
+    fn sent(&self, socket: usize, len: usize);
+
+    /// The peer has closed its side of the connection: no more data will be
+    /// received, but data can still be sent until the socket is closed
+    fn remote_closed(&self, socket: usize);
+
+    /// The connection is over: `SUCCESS` if it was closed on both sides,
+    /// `ECANCEL` if the peer reset it and `ENOACK` if the peer stopped
+    /// acknowledging segments. The socket can be used again.
+    fn closed(&self, socket: usize, result: ReturnCode);
+}
+
+/// Sequence numbers wrap around, so they are compared by their distance
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+pub struct TCPSocket<'a, A: Alarm + 'a> {
+    id: usize,
+    mux: &'a TCPMux<'a, A>,
+    alarm: &'a A,
+    client: Cell<Option<&'a TCPClient>>,
+    state: Cell<TCPState>,
+    /// Whether the connection was accepted by listening, in which case a
+    /// reset during the handshake makes the socket listen again
+    passive: Cell<bool>,
+
+    /// The address the peer sends to, once known
+    local_addr: Cell<Option<IPAddr>>,
+    local_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+    remote_port: Cell<u16>,
+
+    /// The send sequence space: the initial sequence number, the oldest
+    /// unacknowledged number, the next number to send, and one past the
+    /// highest number sent so far
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    snd_max: Cell<u32>,
+    snd_wnd: Cell<u16>,
+    /// Data not acknowledged yet. The first `tx_len` bytes are queued, the
+    /// first of which has sequence number `tx_seq`.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_seq: Cell<u32>,
+    /// A FIN follows the queued data
+    fin_queued: Cell<bool>,
+
+    /// The next sequence number expected from the peer
+    rcv_nxt: Cell<u32>,
+    ack_pending: Cell<bool>,
+    /// A segment is waiting for the sender to be free
+    output_pending: Cell<bool>,
+
+    /// The current RTO, in milliseconds
+    rto: Cell<u32>,
+    /// The smoothed round-trip time and its variation, in milliseconds
+    srtt: Cell<Option<(u32, u32)>>,
+    /// The segment being timed: its sequence number, and when it was sent
+    rtt_timing: Cell<Option<(u32, u32)>>,
+    retries: Cell<u8>,
+
+    next: ListLink<'a, TCPSocket<'a, A>>,
+}
+
+impl<'a, A: Alarm + 'a> ListNode<'a, TCPSocket<'a, A>> for TCPSocket<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, TCPSocket<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: Alarm + 'a> TCPSocket<'a, A> {
+    /// Creates a closed socket.
+    ///
+    /// # Arguments
+    /// `id` - The ID passed to the client
+    /// `mux` - The mux the socket is added to
+    /// `alarm` - The alarm of the socket, whose client it must be
+    /// `tx_buf` - The send buffer, which bounds how much data can be queued
+    pub fn new(
+        id: usize,
+        mux: &'a TCPMux<'a, A>,
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+    ) -> TCPSocket<'a, A> {
+        TCPSocket {
+            id: id,
+            mux: mux,
+            alarm: alarm,
+            client: Cell::new(None),
+            state: Cell::new(TCPState::Closed),
+            passive: Cell::new(false),
+            local_addr: Cell::new(None),
+            local_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            remote_port: Cell::new(0),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_max: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_len: Cell::new(0),
+            tx_seq: Cell::new(0),
+            fin_queued: Cell::new(false),
+            rcv_nxt: Cell::new(0),
+            ack_pending: Cell::new(false),
+            output_pending: Cell::new(false),
+            rto: Cell::new(INITIAL_RTO),
+            srtt: Cell::new(None),
+            rtt_timing: Cell::new(None),
+            retries: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a TCPClient) {
+        self.client.set(Some(client));
+    }
+
+    pub fn get_id(&self) -> usize {
+        self.id
+    }
+
+    pub fn get_state(&self) -> TCPState {
+        self.state.get()
+    }
+
+    pub fn get_local_port(&self) -> u16 {
+        self.local_port.get()
+    }
+
+    /// Returns the address and port of the peer
+    pub fn get_remote(&self) -> (IPAddr, u16) {
+        (self.remote_addr.get(), self.remote_port.get())
+    }
+
+    /// Starts listening on `port`.
+    ///
+    /// # Return Value
+    /// `EBUSY` if the socket is in use or another socket uses `port`, and
+    /// `EINVAL` if `port` is 0.
+    pub fn listen(&self, port: u16) -> ReturnCode {
+        if self.state.get() != TCPState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self.mux.port_in_use(port) {
+            return ReturnCode::EBUSY;
+        }
+        self.reset();
+        self.local_port.set(port);
+        self.passive.set(true);
+        self.state.set(TCPState::Listen);
+        ReturnCode::SUCCESS
+    }
+
+    /// Opens a connection from local port `src_port` to port `dst_port` of
+    /// `dst`. The client is told once the connection is established.
+    ///
+    /// # Return Value
+    /// `EBUSY` if the socket is in use, another socket uses `src_port` or
+    /// no initial sequence number is ready yet, and `EINVAL` if a port is 0.
+    pub fn connect(&self, dst: IPAddr, dst_port: u16, src_port: u16) -> ReturnCode {
+        if self.state.get() != TCPState::Closed {
+            return ReturnCode::EBUSY;
+        }
+        if dst_port == 0 || src_port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if self.mux.port_in_use(src_port) || !self.init_send_seq() {
+            return ReturnCode::EBUSY;
+        }
+        self.reset();
+        self.local_port.set(src_port);
+        self.remote_addr.set(dst);
+        self.remote_port.set(dst_port);
+        self.passive.set(false);
+        self.state.set(TCPState::SynSent);
+        self.output();
+        ReturnCode::SUCCESS
+    }
+
+    /// Queues data to send. Data can be queued as soon as the connection is
+    /// being opened, and until the socket is closed.
+    ///
+    /// # Return Value
+    /// The number of bytes queued, which is less than `data.len()` if the
+    /// send buffer is full, or `EINVAL` if no data can be sent.
+    pub fn send(&self, data: &[u8]) -> Result<usize, ReturnCode> {
+        match self.state.get() {
+            TCPState::SynSent
+            | TCPState::SynReceived
+            | TCPState::Established
+            | TCPState::CloseWait => {}
+            _ => return Err(ReturnCode::EINVAL),
+        }
+        if self.fin_queued.get() {
+            return Err(ReturnCode::EINVAL);
+        }
+        let tx_len = self.tx_len.get();
+        let queued = self.tx_buf.map_or(0, |buf| {
+            let len = cmp::min(buf.len() - tx_len, data.len());
+            buf[tx_len..tx_len + len].copy_from_slice(&data[..len]);
+            len
+        });
+        self.tx_len.set(tx_len + queued);
+        self.output();
+        Ok(queued)
+    }
+
+    /// Closes the sending side of the connection: a FIN is sent after the
+    /// queued data, once the connection is established. A socket that is
+    /// listening or still opening a connection is closed at once, without a
+    /// callback.
+    ///
+    /// # Return Value
+    /// `EINVAL` if the socket is closed, and `EALREADY` if it is closing.
+    pub fn close(&self) -> ReturnCode {
+        match self.state.get() {
+            TCPState::Closed => ReturnCode::EINVAL,
+            TCPState::Listen | TCPState::SynSent => {
+                self.set_closed();
+                ReturnCode::SUCCESS
+            }
+            TCPState::SynReceived => {
+                self.fin_queued.set(true);
+                ReturnCode::SUCCESS
+            }
+            TCPState::Established => {
+                self.fin_queued.set(true);
+                self.state.set(TCPState::FinWait1);
+                self.output();
+                ReturnCode::SUCCESS
+            }
+            TCPState::CloseWait => {
+                self.fin_queued.set(true);
+                self.state.set(TCPState::LastAck);
+                self.output();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EALREADY,
+        }
+    }
+
+    /// Drops the connection at once, without a callback. The peer is sent a
+    /// reset if the connection is synchronized, and the sender is free.
+    pub fn abort(&self) {
+        match self.state.get() {
+            TCPState::SynReceived
+            | TCPState::Established
+            | TCPState::FinWait1
+            | TCPState::FinWait2
+            | TCPState::CloseWait => {
+                self.send_segment(tcp_flags::RST, self.snd_nxt.get(), &[]);
+            }
+            _ => {}
+        }
+        self.set_closed();
+    }
+
+    /// Returns whether a segment from port `src_port` of `src_addr` to
+    /// `dst_port` belongs to the connection of this socket.
+    pub fn is_connection(&self, src_addr: IPAddr, src_port: u16, dst_port: u16) -> bool {
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen => false,
+            _ => {
+                self.local_port.get() == dst_port && self.remote_port.get() == src_port
+                    && self.remote_addr.get().0 == src_addr.0
+            }
+        }
+    }
+
+    pub fn is_listening(&self, port: u16) -> bool {
+        self.state.get() == TCPState::Listen && self.local_port.get() == port
+    }
+
+    pub fn is_output_pending(&self) -> bool {
+        self.output_pending.get()
+    }
+
+    /// Forgets the previous connection.
+    fn reset(&self) {
+        self.local_addr.set(None);
+        self.tx_len.set(0);
+        self.fin_queued.set(false);
+        self.ack_pending.set(false);
+        self.output_pending.set(false);
+        self.rto.set(INITIAL_RTO);
+        self.srtt.set(None);
+        self.rtt_timing.set(None);
+        self.retries.set(0);
+    }
+
+    fn set_closed(&self) {
+        self.state.set(TCPState::Closed);
+        self.alarm.disable();
+        self.reset();
+    }
+
+    /// Closes the connection and tells the client.
+    fn terminate(&self, result: ReturnCode) {
+        self.set_closed();
+        self.client.get().map(|client| client.closed(self.id, result));
+    }
+
+    /// The connection is over, but lingers for `TIME_WAIT` in case the
+    /// peer's FIN is retransmitted. The client can use the socket again
+    /// once it is closed.
+    fn enter_time_wait(&self) {
+        self.state.set(TCPState::TimeWait);
+        self.set_timer(TIME_WAIT);
+        self.client
+            .get()
+            .map(|client| client.closed(self.id, ReturnCode::SUCCESS));
+    }
+
+    /// Picks a random initial sequence number from the mux (RFC 6528).
+    /// Returns `false` if none is ready yet.
+    fn init_send_seq(&self) -> bool {
+        let iss = match self.mux.take_isn() {
+            Some(iss) => iss,
+            None => return false,
+        };
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_max.set(iss);
+        self.tx_seq.set(iss.wrapping_add(1));
+        true
+    }
+
+    fn ms_to_ticks(ms: u32) -> u32 {
+        let freq = A::Frequency::frequency();
+        (ms / 1000) * freq + (ms % 1000) * freq / 1000
+    }
+
+    fn ticks_to_ms(ticks: u32) -> u32 {
+        let freq = A::Frequency::frequency();
+        (ticks / freq) * 1000 + (ticks % freq) * 1000 / freq
+    }
+
+    fn set_timer(&self, ms: u32) {
+        let ticks = Self::ms_to_ticks(ms);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    /// Updates the RTO with a round-trip time sample, as in RFC 6298.
+    fn update_rto(&self, rtt: u32) {
+        let (srtt, rttvar) = match self.srtt.get() {
+            None => (rtt, rtt / 2),
+            Some((srtt, rttvar)) => {
+                let delta = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                ((7 * srtt + rtt) / 8, (3 * rttvar + delta) / 4)
+            }
+        };
+        self.srtt.set(Some((srtt, rttvar)));
+        self.rto
+            .set(cmp::min(cmp::max(srtt + 4 * rttvar, MIN_RTO), MAX_RTO));
+    }
+
+    /// Whether the FIN has been sent and acknowledged
+    fn fin_acked(&self) -> bool {
+        self.fin_queued.get() && self.tx_len.get() == 0
+            && self.snd_una.get() == self.tx_seq.get().wrapping_add(1)
+    }
+
+    fn is_synchronized(&self) -> bool {
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen | TCPState::SynSent | TCPState::SynReceived => {
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Picks the next segment to send: its flags, its sequence number, and
+    /// the offset and length of its payload in the send buffer.
+    fn next_segment(&self) -> Option<(u8, u32, usize, usize)> {
+        let state = self.state.get();
+        let snd_nxt = self.snd_nxt.get();
+        if (state == TCPState::SynSent || state == TCPState::SynReceived)
+            && snd_nxt == self.iss.get()
+        {
+            let flags = if state == TCPState::SynSent {
+                tcp_flags::SYN
+            } else {
+                tcp_flags::SYN | tcp_flags::ACK
+            };
+            return Some((flags, snd_nxt, 0, 0));
+        }
+
+        if self.is_synchronized() {
+            let data_end = self.tx_seq.get().wrapping_add(self.tx_len.get() as u32);
+            if seq_lt(snd_nxt, data_end) {
+                let in_flight = snd_nxt.wrapping_sub(self.snd_una.get());
+                let wnd = self.snd_wnd.get() as u32;
+                let room = if in_flight < wnd {
+                    wnd - in_flight
+                } else if in_flight == 0 {
+                    // Probe a zero window with a single byte
+                    1
+                } else {
+                    0
+                };
+                let len = cmp::min(
+                    cmp::min(data_end.wrapping_sub(snd_nxt), room) as usize,
+                    self.mux.get_mss(),
+                );
+                if len > 0 {
+                    let offset = snd_nxt.wrapping_sub(self.tx_seq.get()) as usize;
+                    return Some((tcp_flags::ACK | tcp_flags::PSH, snd_nxt, offset, len));
+                }
+            }
+            if self.fin_queued.get() && snd_nxt == data_end {
+                return Some((tcp_flags::FIN | tcp_flags::ACK, snd_nxt, 0, 0));
+            }
+        }
+
+        if self.ack_pending.get() {
+            return Some((tcp_flags::ACK, snd_nxt, 0, 0));
+        }
+        None
+    }
+
+    fn send_segment(&self, flags: u8, seq: u32, payload: &[u8]) -> ReturnCode {
+        let mut tcp_header = TCPHeader::new();
+        tcp_header.set_src_port(self.local_port.get());
+        tcp_header.set_dst_port(self.remote_port.get());
+        tcp_header.set_seq_num(seq);
+        if flags & tcp_flags::ACK != 0 {
+            tcp_header.set_ack_num(self.rcv_nxt.get());
+        }
+        tcp_header.set_flags(flags);
+        tcp_header.set_window(RCV_WND);
+        self.mux.send(
+            self.local_addr.get(),
+            self.remote_addr.get(),
+            tcp_header,
+            payload,
+        )
+    }
+
+    /// Sends the next segment that is due, if any. If the sender is busy,
+    /// the `TCPMux` calls this again when it is this socket's turn.
+    pub fn output(&self) {
+        let (flags, seq, offset, len) = match self.next_segment() {
+            Some(segment) => segment,
+            None => {
+                self.output_pending.set(false);
+                return;
+            }
+        };
+        let result = self.tx_buf.map_or(ReturnCode::FAIL, |buf| {
+            self.send_segment(flags, seq, &buf[offset..offset + len])
+        });
+        // More segments may be due once the sender is free
+        self.output_pending.set(true);
+        if result == ReturnCode::EBUSY {
+            return;
+        }
+
+        // Segments that could not be sent are treated as lost
+        let mut seg_len = len as u32;
+        if flags & (tcp_flags::SYN | tcp_flags::FIN) != 0 {
+            seg_len += 1;
+        }
+        self.ack_pending.set(false);
+        if seg_len == 0 {
+            return;
+        }
+        let end = seq.wrapping_add(seg_len);
+        self.snd_nxt.set(end);
+        if seq_lt(self.snd_max.get(), end) {
+            // New data is timed, unless a segment already is (Karn)
+            if seq == self.snd_max.get() && self.rtt_timing.get().is_none() {
+                self.rtt_timing.set(Some((seq, self.alarm.now())));
+            }
+            self.snd_max.set(end);
+        }
+        if !self.alarm.is_armed() {
+            self.set_timer(self.rto.get());
+        }
+    }
+
+    /// Handles an acceptable acknowledgment number.
+    fn process_ack(&self, ack: u32) {
+        let mut acked = ack.wrapping_sub(self.snd_una.get()) as usize;
+        if self.snd_una.get() == self.iss.get() {
+            // The SYN
+            acked -= 1;
+        }
+        let data_acked = cmp::min(acked, self.tx_len.get());
+        if data_acked > 0 {
+            let tx_len = self.tx_len.get();
+            self.tx_buf.map(|buf| {
+                for i in data_acked..tx_len {
+                    buf[i - data_acked] = buf[i];
+                }
+            });
+            self.tx_len.set(tx_len - data_acked);
+            self.tx_seq
+                .set(self.tx_seq.get().wrapping_add(data_acked as u32));
+        }
+        self.snd_una.set(ack);
+        if seq_lt(self.snd_nxt.get(), ack) {
+            self.snd_nxt.set(ack);
+        }
+
+        if let Some((seq, sent_at)) = self.rtt_timing.get() {
+            if seq_lt(seq, ack) {
+                let ticks = self.alarm.now().wrapping_sub(sent_at);
+                self.update_rto(Self::ticks_to_ms(ticks));
+                self.rtt_timing.set(None);
+            }
+        }
+        self.retries.set(0);
+        if ack == self.snd_max.get() {
+            self.alarm.disable();
+        } else {
+            self.set_timer(self.rto.get());
+        }
+
+        if data_acked > 0 {
+            self.client
+                .get()
+                .map(|client| client.sent(self.id, data_acked));
+        }
+    }
+
+    /// Retransmits from the first unacknowledged segment on.
+    fn retransmit(&self) {
+        if self.snd_una.get() == self.snd_max.get() {
+            return;
+        }
+        if self.retries.get() >= MAX_RETRIES {
+            self.abort();
+            self.client
+                .get()
+                .map(|client| client.closed(self.id, ReturnCode::ENOACK));
+            return;
+        }
+        self.retries.set(self.retries.get() + 1);
+        self.rto.set(cmp::min(self.rto.get() * 2, MAX_RTO));
+        self.rtt_timing.set(None);
+        self.snd_nxt.set(self.snd_una.get());
+        self.set_timer(self.rto.get());
+        self.output();
+    }
+
+    fn receive_listen(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload_len: usize) {
+        if tcp_header.has_flag(tcp_flags::RST) {
+            return;
+        }
+        if tcp_header.has_flag(tcp_flags::ACK) {
+            self.mux.send_reset(ip6_header, tcp_header, payload_len);
+            return;
+        }
+        // SYNs to multicast addresses are ignored (RFC 1122, section
+        // 4.2.3.10). Without a sequence number the SYN is dropped, and the
+        // peer sends it again.
+        if !tcp_header.has_flag(tcp_flags::SYN) || ip6_header.dst_addr.is_multicast()
+            || !self.init_send_seq()
+        {
+            return;
+        }
+        // Data sent with the SYN is not acknowledged, so the peer sends it
+        // again
+        self.local_addr.set(Some(ip6_header.dst_addr));
+        self.remote_addr.set(ip6_header.src_addr);
+        self.remote_port.set(tcp_header.get_src_port());
+        self.rcv_nxt.set(tcp_header.get_seq_num().wrapping_add(1));
+        self.snd_wnd.set(tcp_header.get_window());
+        self.state.set(TCPState::SynReceived);
+        self.output();
+    }
+
+    fn receive_syn_sent(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload_len: usize) {
+        let ack = tcp_header.get_ack_num();
+        let has_ack = tcp_header.has_flag(tcp_flags::ACK);
+        let ack_ok = has_ack && seq_lt(self.iss.get(), ack) && seq_le(ack, self.snd_max.get());
+        if has_ack && !ack_ok {
+            self.mux.send_reset(ip6_header, tcp_header, payload_len);
+            return;
+        }
+        if tcp_header.has_flag(tcp_flags::RST) {
+            if ack_ok {
+                self.terminate(ReturnCode::ECANCEL);
+            }
+            return;
+        }
+        if !tcp_header.has_flag(tcp_flags::SYN) {
+            return;
+        }
+        self.local_addr.set(Some(ip6_header.dst_addr));
+        self.rcv_nxt.set(tcp_header.get_seq_num().wrapping_add(1));
+        self.snd_wnd.set(tcp_header.get_window());
+        self.ack_pending.set(true);
+        if ack_ok {
+            self.process_ack(ack);
+            self.state.set(TCPState::Established);
+            self.client.get().map(|client| client.connected(self.id));
+        } else {
+            // Both sides opened at once: answer with a SYN-ACK
+            self.state.set(TCPState::SynReceived);
+            self.snd_nxt.set(self.iss.get());
+        }
+        self.output();
+    }
+
+    /// Handles a segment for this socket, as described in the "SEGMENT
+    /// ARRIVES" section of RFC 793.
+    pub fn receive(&self, ip6_header: IP6Header, tcp_header: TCPHeader, payload: &[u8]) {
+        match self.state.get() {
+            TCPState::Closed => return,
+            TCPState::Listen => {
+                self.receive_listen(ip6_header, tcp_header, payload.len());
+                return;
+            }
+            TCPState::SynSent => {
+                self.receive_syn_sent(ip6_header, tcp_header, payload.len());
+                return;
+            }
+            _ => {}
+        }
+
+        let seq = tcp_header.get_seq_num();
+        let rcv_nxt = self.rcv_nxt.get();
+        if tcp_header.has_flag(tcp_flags::RST) {
+            // Only an exact match is trusted (RFC 5961)
+            if seq == rcv_nxt {
+                if self.state.get() == TCPState::SynReceived && self.passive.get() {
+                    self.set_closed();
+                    self.state.set(TCPState::Listen);
+                } else {
+                    self.terminate(ReturnCode::ECANCEL);
+                }
+            }
+            return;
+        }
+
+        // Only segments that start at or overlap RCV.NXT are accepted
+        let mut seg_len = payload.len() as u32;
+        if tcp_header.has_flag(tcp_flags::SYN) {
+            seg_len += 1;
+        }
+        if tcp_header.has_flag(tcp_flags::FIN) {
+            seg_len += 1;
+        }
+        let acceptable = seq == rcv_nxt
+            || (seq_lt(seq, rcv_nxt) && seq_lt(rcv_nxt, seq.wrapping_add(seg_len)));
+        if !acceptable {
+            if self.state.get() == TCPState::SynReceived && tcp_header.has_flag(tcp_flags::SYN) {
+                // The SYN-ACK was lost: send it again
+                self.snd_nxt.set(self.iss.get());
+            }
+            self.ack_pending.set(true);
+            self.output();
+            return;
+        }
+        let mut skip = rcv_nxt.wrapping_sub(seq) as usize;
+        if tcp_header.has_flag(tcp_flags::SYN) {
+            if skip == 0 {
+                // A SYN within the window means the peer has lost track
+                self.abort();
+                self.client
+                    .get()
+                    .map(|client| client.closed(self.id, ReturnCode::ECANCEL));
+                return;
+            }
+            skip -= 1;
+        }
+        let payload = &payload[cmp::min(skip, payload.len())..];
+
+        if !tcp_header.has_flag(tcp_flags::ACK) {
+            return;
+        }
+        let ack = tcp_header.get_ack_num();
+        let ack_ok = seq_lt(self.snd_una.get(), ack) && seq_le(ack, self.snd_max.get());
+        if self.state.get() == TCPState::SynReceived {
+            if !ack_ok {
+                self.mux.send_reset(ip6_header, tcp_header, payload.len());
+                return;
+            }
+            if self.fin_queued.get() {
+                self.state.set(TCPState::FinWait1);
+            } else {
+                self.state.set(TCPState::Established);
+            }
+            self.client.get().map(|client| client.connected(self.id));
+        }
+        if ack_ok {
+            self.process_ack(ack);
+        } else if seq_lt(self.snd_max.get(), ack) {
+            // Acknowledges something not sent yet
+            self.ack_pending.set(true);
+            self.output();
+            return;
+        }
+        self.snd_wnd.set(tcp_header.get_window());
+
+        if self.fin_acked() {
+            match self.state.get() {
+                TCPState::FinWait1 => self.state.set(TCPState::FinWait2),
+                TCPState::Closing => self.enter_time_wait(),
+                TCPState::LastAck => {
+                    self.terminate(ReturnCode::SUCCESS);
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        let mut fin = tcp_header.has_flag(tcp_flags::FIN);
+        match self.state.get() {
+            TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => {
+                if payload.len() > 0 {
+                    let taken = self.client
+                        .get()
+                        .map_or(payload.len(), |client| client.received(self.id, payload));
+                    let taken = cmp::min(taken, payload.len());
+                    self.rcv_nxt
+                        .set(self.rcv_nxt.get().wrapping_add(taken as u32));
+                    self.ack_pending.set(true);
+                    if taken < payload.len() {
+                        // The FIN comes after the data that was dropped
+                        fin = false;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if fin {
+            self.rcv_nxt.set(self.rcv_nxt.get().wrapping_add(1));
+            self.ack_pending.set(true);
+            match self.state.get() {
+                TCPState::Established => {
+                    self.state.set(TCPState::CloseWait);
+                    self.client
+                        .get()
+                        .map(|client| client.remote_closed(self.id));
+                }
+                TCPState::FinWait1 => self.state.set(TCPState::Closing),
+                TCPState::FinWait2 => self.enter_time_wait(),
+                TCPState::TimeWait => self.set_timer(TIME_WAIT),
+                _ => {}
+            }
+        }
+        self.output();
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for TCPSocket<'a, A> {
+    fn fired(&self) {
+        match self.state.get() {
+            TCPState::Closed | TCPState::Listen => {}
+            TCPState::TimeWait => self.set_closed(),
+            _ => self.retransmit(),
+        }
+    }
+}
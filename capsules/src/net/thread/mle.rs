@@ -0,0 +1,1303 @@
+//! Implements Mesh Link Establishment (MLE) for attaching a Sleepy End Device
+//! (SED) to a Thread network, as outlined in Chapter 4 of the Thread 1.1.1
+//! Specification.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//!     1. A child device multicasts a Parent Request MLE command.
+//!     2. Each potential parent device on the network unicasts a Parent
+//!        Response MLE command.
+//!     3. The child device selects a parent based on a hierarchy of
+//!        connectivity metrics and unicasts a Child ID Request MLE
+//!        command.
+//!     4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! The [MLE](struct.MLE.html) capsule first sends the Parent Request to
+//! routers only, and to routers and router-eligible end devices if no router
+//! answers. Among the parents that answer, it picks the one that hears it
+//! best, as reported in the Link Margin TLV, and then the one with the
+//! highest priority. Once the parent assigns it a short address (RLOC16) in
+//! the Child ID Response, the node:
+//!
+//! * takes the RLOC16 as its 802.15.4 short address,
+//! * adds its RLOC address, formed from the mesh-local prefix and the RLOC16,
+//!   to the `IP6Interface`,
+//! * sends packets off the link through the parent, and
+//! * secures its frames with the MAC key. The capsule implements the
+//!   `KeyProcedure` and `DeviceProcedure` of the `Framer`, through which the
+//!   framer finds the MAC key and the extended address of the parent.
+//!
+//! The child asks the parent to keep it for `CHILD_TIMEOUT` seconds, and
+//! sends a Child Update Request before that time runs out. If the parent
+//! does not answer after `MAX_CHILD_UPDATE_REQUESTS` tries, or answers that
+//! it no longer knows the child, the child detaches and attaches again. Child
+//! Update Requests from the parent are answered with a Child Update
+//! Response.
+//!
+//! MLE messages are secured by MLE itself rather than by the link layer:
+//! they are encrypted with AES-CCM under the MLE key, with the IPv6 source
+//! and destination addresses and the auxiliary security header as
+//! authenticated data. The MAC and MLE keys are derived with HMAC-SHA256
+//! from the network master key and the key sequence (Section 7.1.4). A node
+//! that has not attached yet follows a network whose key has been rotated:
+//! when it receives a message with a newer key sequence, it derives the keys
+//! for that key sequence, and takes them on once a message verifies under
+//! them.
+//!
+//! The frame counter of MLE messages must never repeat under a key. If the
+//! board gives MLE a place in nonvolatile storage with
+//! `set_frame_counter_storage`, MLE stores a bound `FRAME_COUNTER_GUARD`
+//! above the frame counter there, and moves the bound up before the frame
+//! counter reaches it. After a reboot the frame counter resumes from the
+//! stored bound. Otherwise the frame counter starts from 0 at every boot.
+//!
+//! The master key and the mesh-local prefix are commissioned out of band and
+//! passed to `set_network`.
+//!
+//! A sleepy child, whose mode does not have the `ReceiverOnWhenIdle` bit
+//! set, polls its parent for the frames that the parent holds for it with
+//! 802.15.4 Data Requests. It polls every `POLL_PERIOD` milliseconds while
+//! attached, and right after it sends a Child ID Request or a Child Update
+//! Request, whose responses the parent holds too. The Data Requests are sent
+//! through the `MacDevice` given to MLE, whose transmit client must be MLE.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mle = static_init!(
+//!     MLE<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>, MLEDigest>,
+//!     MLE::new(mle_udp_send, interface, mle_mac, mle_aes_ccm, mle_sha256, mle_rng,
+//!              mle_alarm, &mut MLE_BUF, &mut MLE_POLL_BUF));
+//! mle_mac.set_transmit_client(mle);
+//! mle_udp_send.set_client(mle);
+//! mle_aes_ccm.set_client(mle);
+//! hil::digest::Digest::set_client(mle_sha256, mle);
+//! mle_rng.set_client(mle);
+//! mle_alarm.set_client(mle);
+//! mle_ip6_sender.set_link_security_enabled(false);
+//!
+//! let mle_binding = static_init!(UDPBinding<'static>, UDPBinding::new(MLE_PORT, mle));
+//! udp_recv_mux.add_binding(mle_binding);
+//!
+//! mle.set_key_procedure(radio_driver);
+//! mle.set_device_procedure(radio_driver);
+//! mac_device.set_key_procedure(mle);
+//! mac_device.set_device_procedure(mle);
+//!
+//! mle.set_network(MASTER_KEY, MESH_LOCAL_PREFIX, 0);
+//! mle.set_frame_counter_storage(nonvolatile_storage, 0x7fe00, &mut MLE_COUNTER_BUF);
+//! hil::nonvolatile_storage::NonvolatileStorage::set_client(nonvolatile_storage, mle);
+//! mle.start();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use ieee802154::device::{MacDevice, TxClient};
+use ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use kernel::common::take_cell::TakeCell;
+use kernel::hil::digest::{self, Digest, HmacSha256, SHA256_OUTPUT_SIZE};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::rng::{self, RNG};
+use kernel::hil::symmetric_encryption::{AES128CCM, CCMClient, CCM_NONCE_LENGTH};
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ReturnCode;
+use net::ieee802154::{KeyId, MacAddress, MacCommand, SecurityLevel};
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::ipv6::ipv6_interface::IP6Interface;
+use net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType};
+use net::udp::udp::UDPHeader;
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::{UDPSendClient, UDPSender};
+
+/// The UDP port MLE messages are sent from and to
+pub const MLE_PORT: u16 = 19788;
+
+/// The child timeout requested from the parent, in seconds
+pub const CHILD_TIMEOUT: u32 = 240;
+
+/// How long to wait for Parent Responses from routers, in milliseconds
+pub const PARENT_REQUEST_ROUTER_TIMEOUT: u32 = 750;
+/// How long to wait for Parent Responses from routers and router-eligible
+/// end devices, in milliseconds
+pub const PARENT_REQUEST_REED_TIMEOUT: u32 = 1250;
+/// How long to wait for a Child ID Response, in milliseconds
+pub const CHILD_ID_RESPONSE_TIMEOUT: u32 = 1250;
+/// Child ID Requests sent before giving up on a parent
+pub const MAX_CHILD_ID_REQUESTS: u8 = 3;
+/// How long to wait for a Child Update Response, in milliseconds
+pub const CHILD_UPDATE_RESPONSE_TIMEOUT: u32 = 1000;
+/// Child Update Requests sent before giving up on the parent
+pub const MAX_CHILD_UPDATE_REQUESTS: u8 = 3;
+/// How often a sleepy child polls its parent, in milliseconds
+pub const POLL_PERIOD: u32 = 4000;
+/// The longest wait between attach attempts, in milliseconds. The wait
+/// starts at a second and doubles after every failed attempt.
+pub const MAX_ATTACH_BACKOFF: u32 = 60000;
+/// How far above the frame counter the bound in nonvolatile storage is set.
+/// The bound is moved up once the frame counter is within half of this of
+/// it.
+pub const FRAME_COUNTER_GUARD: u32 = 1024;
+
+/// MLE command types (Section 4.4)
+pub mod command {
+    pub const CHILD_UPDATE_REQUEST: u8 = 13;
+    pub const CHILD_UPDATE_RESPONSE: u8 = 14;
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+}
+
+/// The Thread version sent in the Version TLV
+const THREAD_VERSION: u16 = 2;
+
+/// The first byte of a message secured by MLE
+const SECURITY_SUITE_MLE: u8 = 0;
+/// ENC-MIC-32, with the key sequence as key source (key ID mode 2)
+const SECURITY_CONTROL: u8 = 0x15;
+const MIC_LEN: usize = 4;
+const AUX_HDR_LEN: usize = 10;
+
+/// The layout of `buf` while a message is secured: the authenticated data,
+/// made of the source and destination addresses and the auxiliary security
+/// header, followed by the command and its TLVs, and the MIC. Once the
+/// message is secured, the last byte of the destination address is
+/// overwritten with the security suite, so that the message is sent from
+/// there on.
+const SRC_OFF: usize = 0;
+const DST_OFF: usize = 16;
+const AUX_OFF: usize = 32;
+const CMD_OFF: usize = AUX_OFF + AUX_HDR_LEN;
+
+/// The label the keys are derived with, after the key sequence
+const KEY_LABEL: &'static [u8] = b"Thread";
+
+/// The link-local multicast address of all routers
+const ALL_ROUTERS_ADDR: IPAddr = IPAddr([
+    0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02
+]);
+
+/// The 802.15.4 short address of a node without one
+const NO_SHORT_ADDR: u16 = 0xfffe;
+
+/// MLE messages are sent with, and must arrive with, the highest hop limit
+const MLE_HOP_LIMIT: u8 = 255;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum MLEState {
+    /// `start` has not been called
+    Disabled,
+    /// Waiting for the keys, or for the next attach attempt
+    Detached,
+    /// Waiting for Parent Responses. `reeds` is whether router-eligible end
+    /// devices were asked too.
+    ParentRequest { reeds: bool },
+    /// Waiting for the Child ID Response
+    ChildIdRequest { tries: u8 },
+    /// Attached to the parent
+    Child,
+    /// Attached, and waiting for the Child Update Response
+    ChildUpdateRequest { tries: u8 },
+}
+
+/// The parent, or a candidate parent during the attach.
+#[derive(Copy, Clone)]
+struct Parent {
+    ip_addr: IPAddr,
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    link_margin: u8,
+    priority: u8,
+    /// The challenge to answer in the Child ID Request
+    challenge: [u8; 8],
+    /// The last MLE frame counter received from the parent
+    frame_counter: u32,
+}
+
+impl Parent {
+    /// Returns whether the parent is a better choice than `other`.
+    fn is_better_than(&self, other: &Parent) -> bool {
+        // The priority is the top two bits, as a signed number
+        let priority = |parent: &Parent| ((parent.priority as i8) >> 6) as i32;
+        (self.link_margin, priority(self)) > (other.link_margin, priority(other))
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Operation {
+    Idle,
+    /// The keys for `key_sequence` are being derived
+    DerivingKeys { key_sequence: u32 },
+    /// A message of `len` bytes, command included, is being secured
+    Securing { dst: IPAddr, len: usize },
+    /// A message of `len` bytes from `src` is being verified, under the
+    /// MLE key for `key_sequence`
+    Verifying {
+        src: IPAddr,
+        len: usize,
+        frame_counter: u32,
+        key_sequence: u32,
+    },
+}
+
+impl Operation {
+    fn is_idle(&self) -> bool {
+        match *self {
+            Operation::Idle => true,
+            _ => false,
+        }
+    }
+}
+
+/// The TLVs of a received message that MLE acts on.
+#[derive(Copy, Clone, Default)]
+struct Message {
+    command: u8,
+    source_address: Option<u16>,
+    address16: Option<u16>,
+    challenge: Option<[u8; 8]>,
+    response: Option<[u8; 8]>,
+    link_margin: Option<u8>,
+    parent_priority: Option<u8>,
+    mle_frame_counter: Option<u32>,
+    status: Option<u8>,
+}
+
+impl Message {
+    fn decode(buf: &[u8]) -> Option<Message> {
+        let mut message = Message::default();
+        message.command = *buf.first()?;
+        let mut tlvs = &buf[1..];
+        while tlvs.len() >= 2 {
+            let tlv_len = 2 + tlvs[1] as usize;
+            if tlv_len > tlvs.len() {
+                return None;
+            }
+            // TLVs that can't be decoded are skipped
+            if let Some((_, tlv)) = Tlv::decode(&tlvs[..tlv_len]).done() {
+                match tlv {
+                    Tlv::SourceAddress(addr) => message.source_address = Some(addr),
+                    Tlv::Address16(addr) => message.address16 = Some(addr),
+                    Tlv::Challenge(challenge) => message.challenge = Some(challenge),
+                    Tlv::Response(response) => message.response = Some(response),
+                    Tlv::LinkMargin(margin) => message.link_margin = Some(margin),
+                    Tlv::Connectivity {
+                        parent_priority, ..
+                    } => message.parent_priority = Some(parent_priority),
+                    Tlv::MleFrameCounter(counter) => message.mle_frame_counter = Some(counter),
+                    Tlv::Status(status) => message.status = Some(status),
+                    _ => {}
+                }
+            }
+            tlvs = &tlvs[tlv_len..];
+        }
+        Some(message)
+    }
+}
+
+/// Returns the key index of the MAC key, and of the key source of MLE
+/// messages, for `key_sequence`.
+fn key_index(key_sequence: u32) -> u8 {
+    ((key_sequence & 0x7f) + 1) as u8
+}
+
+/// Returns the extended address of the node with the link-local address
+/// `addr`, whose interface identifier is formed from it.
+fn ext_addr_from_link_local(addr: &IPAddr) -> [u8; 8] {
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&addr.0[8..16]);
+    ext_addr[0] ^= 0x02;
+    ext_addr
+}
+
+/// Returns the CCM nonce of an MLE message (Section 4.9).
+fn mle_nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8] = (frame_counter >> 24) as u8;
+    nonce[9] = (frame_counter >> 16) as u8;
+    nonce[10] = (frame_counter >> 8) as u8;
+    nonce[11] = frame_counter as u8;
+    nonce[12] = SecurityLevel::EncMic32 as u8;
+    nonce
+}
+
+pub struct MLE<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> {
+    udp_sender: &'a UDPSender<'a>,
+    interface: &'a IP6Interface<'a>,
+    mac: &'a MacDevice<'a>,
+    ccm: &'a AES128CCM<'a>,
+    digest: &'a D,
+    rng: &'a RNG,
+    alarm: &'a A,
+    /// Holds the message being secured or verified, or the input and output
+    /// of the key derivation
+    buf: TakeCell<'static, [u8]>,
+    operation: Cell<Operation>,
+    /// Holds the Data Requests sent to the parent
+    poll_buf: TakeCell<'static, [u8]>,
+
+    master_key: Cell<Option<[u8; 16]>>,
+    mesh_local_prefix: Cell<[u8; 8]>,
+    key_sequence: Cell<u32>,
+    /// The MAC and MLE keys, once derived for the key sequence
+    keys: Cell<Option<([u8; 16], [u8; 16])>>,
+    /// The keys for a newer key sequence seen while attaching, taken on
+    /// once a message verifies under them
+    candidate_keys: Cell<Option<(u32, ([u8; 16], [u8; 16]))>>,
+    frame_counter: Cell<u32>,
+    /// The frame counter MLE may not reach, as it is not stored yet
+    frame_counter_limit: Cell<u32>,
+    /// Where the bound on the frame counter is stored
+    frame_counter_storage: Cell<Option<(&'a NonvolatileStorage, usize)>>,
+    /// Holds the bound while it is read or written
+    frame_counter_buf: TakeCell<'static, [u8]>,
+    mode: Cell<u8>,
+
+    state: Cell<MLEState>,
+    parent: Cell<Option<Parent>>,
+    /// The challenge sent in the request awaiting a response
+    challenge: Cell<[u8; 8]>,
+    /// Random bytes for the next challenge
+    next_challenge: Cell<[u8; 8]>,
+    attach_backoff: Cell<u32>,
+    /// The polls left until the next Child Update Request
+    polls_until_update: Cell<u32>,
+
+    /// The procedures for the keys and devices MLE does not manage
+    key_procedure: Cell<Option<&'a KeyProcedure>>,
+    device_procedure: Cell<Option<&'a DeviceProcedure>>,
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> MLE<'a, A, D> {
+    /// Creates the MLE capsule. `udp_sender` must send through `interface`,
+    /// without link-layer security, and `buf` must hold the largest message
+    /// received, plus 32 bytes. `poll_buf` must hold a radio frame.
+    pub fn new(
+        udp_sender: &'a UDPSender<'a>,
+        interface: &'a IP6Interface<'a>,
+        mac: &'a MacDevice<'a>,
+        ccm: &'a AES128CCM<'a>,
+        digest: &'a D,
+        rng: &'a RNG,
+        alarm: &'a A,
+        buf: &'static mut [u8],
+        poll_buf: &'static mut [u8],
+    ) -> MLE<'a, A, D> {
+        MLE {
+            udp_sender: udp_sender,
+            interface: interface,
+            mac: mac,
+            ccm: ccm,
+            digest: digest,
+            rng: rng,
+            alarm: alarm,
+            buf: TakeCell::new(buf),
+            operation: Cell::new(Operation::Idle),
+            poll_buf: TakeCell::new(poll_buf),
+            master_key: Cell::new(None),
+            mesh_local_prefix: Cell::new([0; 8]),
+            key_sequence: Cell::new(0),
+            keys: Cell::new(None),
+            candidate_keys: Cell::new(None),
+            frame_counter: Cell::new(0),
+            frame_counter_limit: Cell::new(u32::max_value()),
+            frame_counter_storage: Cell::new(None),
+            frame_counter_buf: TakeCell::empty(),
+            mode: Cell::new(LinkMode::SecureDataRequests as u8),
+            state: Cell::new(MLEState::Disabled),
+            parent: Cell::new(None),
+            challenge: Cell::new([0; 8]),
+            next_challenge: Cell::new([0; 8]),
+            attach_backoff: Cell::new(0),
+            polls_until_update: Cell::new(0),
+            key_procedure: Cell::new(None),
+            device_procedure: Cell::new(None),
+        }
+    }
+
+    /// Sets the procedure that keys other than the MAC key are looked up
+    /// with.
+    pub fn set_key_procedure(&self, key_procedure: &'a KeyProcedure) {
+        self.key_procedure.set(Some(key_procedure));
+    }
+
+    /// Sets the procedure that devices other than the parent are looked up
+    /// with.
+    pub fn set_device_procedure(&self, device_procedure: &'a DeviceProcedure) {
+        self.device_procedure.set(Some(device_procedure));
+    }
+
+    /// Sets the commissioned parameters of the network to attach to.
+    pub fn set_network(&self, master_key: [u8; 16], mesh_local_prefix: [u8; 8], key_sequence: u32) {
+        self.master_key.set(Some(master_key));
+        self.mesh_local_prefix.set(mesh_local_prefix);
+        self.key_sequence.set(key_sequence);
+        self.keys.set(None);
+        self.candidate_keys.set(None);
+    }
+
+    /// Keeps the bound on the frame counter at `address` in `storage`, in
+    /// the 4 bytes of `buf`. MLE must be the client of `storage`. Erased
+    /// storage, all ones, counts as a bound of 0.
+    pub fn set_frame_counter_storage(
+        &self,
+        storage: &'a NonvolatileStorage,
+        address: usize,
+        buf: &'static mut [u8],
+    ) {
+        self.frame_counter_storage.set(Some((storage, address)));
+        self.frame_counter_buf.replace(buf);
+        self.frame_counter_limit.set(0);
+    }
+
+    /// Sets the mode bits, from `LinkMode`, sent in the Mode TLV. The default
+    /// is that of a sleepy child: `SecureDataRequests`.
+    pub fn set_mode(&self, mode: u8) {
+        self.mode.set(mode);
+    }
+
+    /// Whether the child is sleepy, and must poll its parent for frames
+    fn is_sleepy(&self) -> bool {
+        self.mode.get() & LinkMode::ReceiverOnWhenIdle as u8 == 0
+    }
+
+    pub fn get_state(&self) -> MLEState {
+        self.state.get()
+    }
+
+    /// The RLOC16 of the node, if it is attached
+    pub fn get_rloc16(&self) -> Option<u16> {
+        match self.state.get() {
+            MLEState::Child | MLEState::ChildUpdateRequest { .. } => {
+                Some(self.mac.get_address())
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts attaching to the network. The link-local address of the
+    /// interface must be configured first. If the frame counter is stored,
+    /// MLE reads the stored bound and stores a new one first, and does not
+    /// start if either fails.
+    ///
+    /// # Return Value
+    /// `EOFF` if `set_network` has not been called, and `EALREADY` if MLE
+    /// has already started.
+    pub fn start(&self) -> ReturnCode {
+        if self.master_key.get().is_none() {
+            return ReturnCode::EOFF;
+        }
+        if self.state.get() != MLEState::Disabled {
+            return ReturnCode::EALREADY;
+        }
+        match self.frame_counter_storage.get() {
+            Some((storage, address)) => match self.frame_counter_buf.take() {
+                Some(buf) => storage.read(buf, address, 4),
+                None => ReturnCode::EALREADY,
+            },
+            None => self.attach(),
+        }
+    }
+
+    fn attach(&self) -> ReturnCode {
+        self.state.set(MLEState::Detached);
+        self.attach_backoff.set(0);
+        self.rng.get();
+        if self.keys.get().is_some() {
+            self.send_parent_request(false);
+            ReturnCode::SUCCESS
+        } else {
+            self.derive_keys(self.key_sequence.get())
+        }
+    }
+
+    /// Stores a new bound, `FRAME_COUNTER_GUARD` above the frame counter.
+    fn store_frame_counter(&self) -> ReturnCode {
+        let (storage, address) = match self.frame_counter_storage.get() {
+            Some(storage) => storage,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let buf = match self.frame_counter_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        // All ones reads back as erased storage
+        let bound = cmp::min(
+            self.frame_counter.get().saturating_add(FRAME_COUNTER_GUARD),
+            u32::max_value() - 1,
+        );
+        buf[0] = bound as u8;
+        buf[1] = (bound >> 8) as u8;
+        buf[2] = (bound >> 16) as u8;
+        buf[3] = (bound >> 24) as u8;
+        storage.write(buf, address, 4)
+    }
+
+    /// Derives the MAC and MLE keys for `key_sequence`:
+    /// HMAC-SHA256(master key, key sequence || "Thread").
+    fn derive_keys(&self, key_sequence: u32) -> ReturnCode {
+        let master_key = match self.master_key.get() {
+            Some(master_key) => master_key,
+            None => return ReturnCode::EOFF,
+        };
+        if !self.operation.get().is_idle() {
+            return ReturnCode::EBUSY;
+        }
+        let buf = match self.buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let result = self.digest.set_mode_hmacsha256(&master_key);
+        if result != ReturnCode::SUCCESS {
+            self.buf.replace(buf);
+            return result;
+        }
+        buf[0] = (key_sequence >> 24) as u8;
+        buf[1] = (key_sequence >> 16) as u8;
+        buf[2] = (key_sequence >> 8) as u8;
+        buf[3] = key_sequence as u8;
+        buf[4..4 + KEY_LABEL.len()].copy_from_slice(KEY_LABEL);
+        match self.digest.add_data(buf, 4 + KEY_LABEL.len()) {
+            (ReturnCode::SUCCESS, _) => {
+                self.operation.set(Operation::DerivingKeys {
+                    key_sequence: key_sequence,
+                });
+                ReturnCode::SUCCESS
+            }
+            (result, buf) => {
+                buf.map(|buf| self.buf.replace(buf));
+                result
+            }
+        }
+    }
+
+    fn keys_derived(&self) {
+        match self.state.get() {
+            MLEState::Detached => self.send_parent_request(false),
+            MLEState::Child | MLEState::ChildUpdateRequest { .. } => {
+                // The parent's frames are now secured with the new MAC key
+                self.interface.set_link_security(Some((
+                    SecurityLevel::EncMic32,
+                    KeyId::Index(key_index(self.key_sequence.get())),
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes on `key_sequence`, whose keys a message has verified under.
+    fn adopt_key_sequence(&self, key_sequence: u32) {
+        match (self.state.get(), self.candidate_keys.get()) {
+            (MLEState::Detached, Some((candidate_sequence, keys)))
+            | (MLEState::ParentRequest { .. }, Some((candidate_sequence, keys)))
+                if candidate_sequence == key_sequence =>
+            {
+                self.key_sequence.set(key_sequence);
+                self.keys.set(Some(keys));
+                self.candidate_keys.set(None);
+            }
+            _ => {}
+        }
+    }
+
+    /// Attaching fails if the keys for the key sequence cannot be derived.
+    /// Candidate keys are derived again for the next message.
+    fn derivation_failed(&self, operation: Operation) {
+        if let Operation::DerivingKeys { key_sequence } = operation {
+            if key_sequence != self.key_sequence.get() {
+                return;
+            }
+        }
+        self.attach_failed();
+    }
+
+    /// Sets the alarm to fire in `ms` milliseconds.
+    fn set_timer(&self, ms: u32) {
+        let freq = A::Frequency::frequency();
+        let ticks = (ms / 1000) * freq + (ms % 1000) * freq / 1000;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    /// Waits until the next Child Update Request is due, polling the parent
+    /// in the meantime if the child is sleepy.
+    fn wait_for_update(&self) {
+        let update_period = CHILD_TIMEOUT * 1000 * 3 / 4;
+        if self.is_sleepy() {
+            self.polls_until_update.set(update_period / POLL_PERIOD);
+            self.set_timer(POLL_PERIOD);
+        } else {
+            self.polls_until_update.set(0);
+            self.set_timer(update_period);
+        }
+    }
+
+    /// Polls the parent for the frames it holds for the child, with an
+    /// 802.15.4 Data Request secured with the MAC key. Nothing is sent if
+    /// the previous poll is still being sent.
+    fn send_data_request(&self) {
+        let parent = match self.parent.get() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let buf = match self.poll_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let src_addr = match self.get_rloc16() {
+            Some(rloc16) => MacAddress::Short(rloc16),
+            None => MacAddress::Long(self.mac.get_address_long()),
+        };
+        let pan = self.mac.get_pan();
+        let security = Some((
+            SecurityLevel::EncMic32,
+            KeyId::Index(key_index(self.key_sequence.get())),
+        ));
+        match self.mac.prepare_command_frame(
+            buf,
+            pan,
+            MacAddress::Short(parent.rloc16),
+            pan,
+            src_addr,
+            MacCommand::DataRequest,
+            security,
+        ) {
+            Ok(frame) => {
+                if let (_, Some(buf)) = self.mac.transmit(frame) {
+                    self.poll_buf.replace(buf);
+                }
+            }
+            Err(buf) => {
+                self.poll_buf.replace(buf);
+            }
+        }
+    }
+
+    /// Takes the random challenge for the next request, and asks for new
+    /// randomness for the one after.
+    fn new_challenge(&self) -> [u8; 8] {
+        let challenge = self.next_challenge.get();
+        self.challenge.set(challenge);
+        self.rng.get();
+        challenge
+    }
+
+    /// Secures an MLE message and sends it to `dst`.
+    ///
+    /// # Return Value
+    /// `EBUSY` if another message is being secured or verified, the keys
+    /// are not derived yet, or the frame counter has reached the stored
+    /// bound, and `ESIZE` if the message does not fit in `buf`.
+    fn send(&self, dst: IPAddr, command: u8, tlvs: &[Tlv]) -> ReturnCode {
+        let (_, mle_key) = match self.keys.get() {
+            Some(keys) => keys,
+            None => return ReturnCode::EBUSY,
+        };
+        if !self.operation.get().is_idle() {
+            return ReturnCode::EBUSY;
+        }
+        let frame_counter = self.frame_counter.get();
+        if frame_counter >= self.frame_counter_limit.get() {
+            return ReturnCode::EBUSY;
+        }
+        let buf = match self.buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+
+        let key_sequence = self.key_sequence.get();
+        buf[SRC_OFF..DST_OFF].copy_from_slice(&self.interface.get_src_addr(dst).0);
+        buf[DST_OFF..AUX_OFF].copy_from_slice(&dst.0);
+        buf[AUX_OFF] = SECURITY_CONTROL;
+        // The frame counter is little-endian, like in the MAC header
+        buf[AUX_OFF + 1] = frame_counter as u8;
+        buf[AUX_OFF + 2] = (frame_counter >> 8) as u8;
+        buf[AUX_OFF + 3] = (frame_counter >> 16) as u8;
+        buf[AUX_OFF + 4] = (frame_counter >> 24) as u8;
+        buf[AUX_OFF + 5] = (key_sequence >> 24) as u8;
+        buf[AUX_OFF + 6] = (key_sequence >> 16) as u8;
+        buf[AUX_OFF + 7] = (key_sequence >> 8) as u8;
+        buf[AUX_OFF + 8] = key_sequence as u8;
+        buf[AUX_OFF + 9] = key_index(key_sequence);
+        buf[CMD_OFF] = command;
+        let mut off = CMD_OFF + 1;
+        for tlv in tlvs.iter() {
+            match tlv.encode(&mut buf[off..]).done() {
+                Some((len, _)) => off += len,
+                None => {
+                    self.buf.replace(buf);
+                    return ReturnCode::ESIZE;
+                }
+            }
+        }
+        if off + MIC_LEN > buf.len() {
+            self.buf.replace(buf);
+            return ReturnCode::ESIZE;
+        }
+
+        self.frame_counter.set(frame_counter + 1);
+        if self.frame_counter_limit.get() - frame_counter <= FRAME_COUNTER_GUARD / 2 {
+            self.store_frame_counter();
+        }
+        let nonce = mle_nonce(&self.mac.get_address_long(), frame_counter);
+        self.ccm.set_key(&mle_key);
+        self.ccm.set_nonce(&nonce);
+        let len = off - CMD_OFF;
+        match self.ccm.crypt(buf, SRC_OFF, CMD_OFF, len, MIC_LEN, true, true) {
+            (ReturnCode::SUCCESS, _) => {
+                self.operation.set(Operation::Securing { dst: dst, len: len });
+                ReturnCode::SUCCESS
+            }
+            (result, buf) => {
+                buf.map(|buf| self.buf.replace(buf));
+                result
+            }
+        }
+    }
+
+    fn send_parent_request(&self, reeds: bool) {
+        self.state.set(MLEState::ParentRequest { reeds: reeds });
+        if !reeds {
+            self.parent.set(None);
+        }
+        let scan_mask = if reeds {
+            MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+        } else {
+            MulticastResponder::Router as u8
+        };
+        let challenge = self.new_challenge();
+        self.send(
+            ALL_ROUTERS_ADDR,
+            command::PARENT_REQUEST,
+            &[
+                Tlv::Mode(self.mode.get()),
+                Tlv::Challenge(challenge),
+                Tlv::ScanMask(scan_mask),
+                Tlv::Version(THREAD_VERSION),
+            ],
+        );
+        self.set_timer(if reeds {
+            PARENT_REQUEST_REED_TIMEOUT
+        } else {
+            PARENT_REQUEST_ROUTER_TIMEOUT
+        });
+    }
+
+    fn send_child_id_request(&self, tries: u8) {
+        let parent = match self.parent.get() {
+            Some(parent) => parent,
+            None => return self.attach_failed(),
+        };
+        self.state.set(MLEState::ChildIdRequest { tries: tries });
+        let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+        self.send(
+            parent.ip_addr,
+            command::CHILD_ID_REQUEST,
+            &[
+                Tlv::Response(parent.challenge),
+                // The framer does not count frames yet
+                Tlv::LinkLayerFrameCounter(0),
+                Tlv::MleFrameCounter(self.frame_counter.get()),
+                Tlv::Mode(self.mode.get()),
+                Tlv::Timeout(CHILD_TIMEOUT),
+                Tlv::Version(THREAD_VERSION),
+                Tlv::TlvRequest(&requested),
+            ],
+        );
+        self.set_timer(CHILD_ID_RESPONSE_TIMEOUT);
+    }
+
+    fn send_child_update_request(&self, tries: u8) {
+        let parent = match self.parent.get() {
+            Some(parent) => parent,
+            None => return self.detach(),
+        };
+        self.state.set(MLEState::ChildUpdateRequest { tries: tries });
+        let challenge = self.new_challenge();
+        self.send(
+            parent.ip_addr,
+            command::CHILD_UPDATE_REQUEST,
+            &[
+                Tlv::SourceAddress(self.mac.get_address()),
+                Tlv::Mode(self.mode.get()),
+                Tlv::Challenge(challenge),
+                Tlv::Timeout(CHILD_TIMEOUT),
+            ],
+        );
+        self.set_timer(CHILD_UPDATE_RESPONSE_TIMEOUT);
+    }
+
+    /// Answers a Child Update Request from the parent, echoing its
+    /// challenge if it sent one.
+    fn send_child_update_response(&self, parent: &Parent, challenge: Option<[u8; 8]>) {
+        let source_address = Tlv::SourceAddress(self.mac.get_address());
+        let mode = Tlv::Mode(self.mode.get());
+        let timeout = Tlv::Timeout(CHILD_TIMEOUT);
+        match challenge {
+            Some(challenge) => self.send(
+                parent.ip_addr,
+                command::CHILD_UPDATE_RESPONSE,
+                &[
+                    source_address,
+                    mode,
+                    timeout,
+                    Tlv::Response(challenge),
+                    Tlv::LinkLayerFrameCounter(0),
+                    Tlv::MleFrameCounter(self.frame_counter.get()),
+                ],
+            ),
+            None => self.send(
+                parent.ip_addr,
+                command::CHILD_UPDATE_RESPONSE,
+                &[source_address, mode, timeout],
+            ),
+        };
+    }
+
+    /// Returns the RLOC address for `rloc16`: the mesh-local prefix, followed
+    /// by 0000:00ff:fe00:RLOC16.
+    fn rloc_addr(&self, rloc16: u16) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..8].copy_from_slice(&self.mesh_local_prefix.get());
+        addr.0[11] = 0xff;
+        addr.0[12] = 0xfe;
+        addr.0[14] = (rloc16 >> 8) as u8;
+        addr.0[15] = rloc16 as u8;
+        addr
+    }
+
+    /// Completes the attach, with the RLOC16 assigned by the parent.
+    fn become_child(&self, parent: Parent, rloc16: u16) {
+        self.parent.set(Some(parent));
+        self.attach_backoff.set(0);
+        self.mac.set_address(rloc16);
+        self.mac.config_commit();
+        self.interface.add_addr(self.rloc_addr(rloc16));
+        self.interface.set_gateway(MacAddress::Short(parent.rloc16));
+        self.interface.set_link_security(Some((
+            SecurityLevel::EncMic32,
+            KeyId::Index(key_index(self.key_sequence.get())),
+        )));
+        self.state.set(MLEState::Child);
+        self.wait_for_update();
+    }
+
+    /// Leaves the parent and attaches again.
+    fn detach(&self) {
+        if let Some(rloc16) = self.get_rloc16() {
+            self.interface.remove_addr(self.rloc_addr(rloc16));
+        }
+        self.interface.set_link_security(None);
+        self.mac.set_address(NO_SHORT_ADDR);
+        self.mac.config_commit();
+        self.parent.set(None);
+        self.send_parent_request(false);
+    }
+
+    /// Waits before the next attach attempt, longer after each failure.
+    fn attach_failed(&self) {
+        self.state.set(MLEState::Detached);
+        self.parent.set(None);
+        let backoff = cmp::min(cmp::max(self.attach_backoff.get() * 2, 1000), MAX_ATTACH_BACKOFF);
+        self.attach_backoff.set(backoff);
+        self.set_timer(backoff);
+    }
+
+    /// Acts on a verified message from `src`.
+    fn receive_message(&self, src: IPAddr, frame_counter: u32, message: Message) {
+        let from_parent = |parent: &Parent| {
+            parent.ip_addr.0 == src.0 && frame_counter > parent.frame_counter
+        };
+        match (message.command, self.state.get()) {
+            (command::PARENT_RESPONSE, MLEState::ParentRequest { .. }) => {
+                let (rloc16, challenge, response, link_margin) = match (
+                    message.source_address,
+                    message.challenge,
+                    message.response,
+                    message.link_margin,
+                ) {
+                    (Some(rloc16), Some(challenge), Some(response), Some(link_margin)) => {
+                        (rloc16, challenge, response, link_margin)
+                    }
+                    _ => return,
+                };
+                if response != self.challenge.get() {
+                    return;
+                }
+                let candidate = Parent {
+                    ip_addr: src,
+                    ext_addr: ext_addr_from_link_local(&src),
+                    rloc16: rloc16,
+                    link_margin: link_margin,
+                    priority: message.parent_priority.unwrap_or(0),
+                    challenge: challenge,
+                    frame_counter: message.mle_frame_counter.unwrap_or(frame_counter),
+                };
+                let better = self.parent
+                    .get()
+                    .map_or(true, |parent| candidate.is_better_than(&parent));
+                if better {
+                    self.parent.set(Some(candidate));
+                }
+            }
+            (command::CHILD_ID_RESPONSE, MLEState::ChildIdRequest { .. }) => {
+                let mut parent = match self.parent.get() {
+                    Some(parent) if from_parent(&parent) => parent,
+                    _ => return,
+                };
+                let rloc16 = match message.address16 {
+                    Some(rloc16) => rloc16,
+                    None => return,
+                };
+                parent.frame_counter = frame_counter;
+                if let Some(source_address) = message.source_address {
+                    parent.rloc16 = source_address;
+                }
+                self.become_child(parent, rloc16);
+            }
+            (command::CHILD_UPDATE_RESPONSE, MLEState::ChildUpdateRequest { .. }) => {
+                let mut parent = match self.parent.get() {
+                    Some(parent) if from_parent(&parent) => parent,
+                    _ => return,
+                };
+                if message.status.is_some() {
+                    // The parent no longer knows the child
+                    return self.detach();
+                }
+                if message.response != Some(self.challenge.get()) {
+                    return;
+                }
+                parent.frame_counter = frame_counter;
+                self.parent.set(Some(parent));
+                self.state.set(MLEState::Child);
+                self.wait_for_update();
+            }
+            (command::CHILD_UPDATE_REQUEST, MLEState::Child)
+            | (command::CHILD_UPDATE_REQUEST, MLEState::ChildUpdateRequest { .. }) => {
+                let mut parent = match self.parent.get() {
+                    Some(parent) if from_parent(&parent) => parent,
+                    _ => return,
+                };
+                parent.frame_counter = frame_counter;
+                self.parent.set(Some(parent));
+                self.send_child_update_response(&parent, message.challenge);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> time::Client for MLE<'a, A, D> {
+    fn fired(&self) {
+        match self.state.get() {
+            MLEState::Disabled => {}
+            MLEState::Detached => {
+                if self.keys.get().is_some() {
+                    self.send_parent_request(false);
+                } else if self.derive_keys(self.key_sequence.get()) != ReturnCode::SUCCESS {
+                    self.attach_failed();
+                }
+            }
+            MLEState::ParentRequest { reeds } => {
+                if self.parent.get().is_some() {
+                    self.send_child_id_request(1);
+                } else if !reeds {
+                    self.send_parent_request(true);
+                } else {
+                    self.attach_failed();
+                }
+            }
+            MLEState::ChildIdRequest { tries } => {
+                if tries < MAX_CHILD_ID_REQUESTS {
+                    self.send_child_id_request(tries + 1);
+                } else {
+                    self.attach_failed();
+                }
+            }
+            MLEState::Child => {
+                let polls = self.polls_until_update.get();
+                if polls > 0 {
+                    self.polls_until_update.set(polls - 1);
+                    self.send_data_request();
+                    self.set_timer(POLL_PERIOD);
+                } else {
+                    self.send_child_update_request(1);
+                }
+            }
+            MLEState::ChildUpdateRequest { tries } => {
+                if tries < MAX_CHILD_UPDATE_REQUESTS {
+                    self.send_child_update_request(tries + 1);
+                } else {
+                    self.detach();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> UDPRecvClient for MLE<'a, A, D> {
+    fn receive(&self, ip6_header: IP6Header, _udp_header: UDPHeader, payload: &[u8]) {
+        let src = ip6_header.src_addr;
+        if self.state.get() == MLEState::Disabled || ip6_header.get_hop_limit() != MLE_HOP_LIMIT
+            || !src.is_unicast_link_local()
+        {
+            return;
+        }
+        if payload.len() < 1 + AUX_HDR_LEN + 1 + MIC_LEN || payload[0] != SECURITY_SUITE_MLE
+            || payload[1] != SECURITY_CONTROL
+        {
+            return;
+        }
+        let aux = &payload[1..1 + AUX_HDR_LEN];
+        let frame_counter = (aux[1] as u32) | (aux[2] as u32) << 8 | (aux[3] as u32) << 16
+            | (aux[4] as u32) << 24;
+        let key_sequence =
+            (aux[5] as u32) << 24 | (aux[6] as u32) << 16 | (aux[7] as u32) << 8 | (aux[8] as u32);
+
+        let keys = if key_sequence == self.key_sequence.get() {
+            self.keys.get()
+        } else {
+            // Follow the network's key while attaching, once a message
+            // verifies under it
+            match self.state.get() {
+                MLEState::Detached | MLEState::ParentRequest { .. }
+                    if key_sequence > self.key_sequence.get() => {}
+                _ => return,
+            }
+            match self.candidate_keys.get() {
+                Some((candidate_sequence, keys)) if candidate_sequence == key_sequence => {
+                    Some(keys)
+                }
+                _ => {
+                    // The message is dropped, and verified when it is sent
+                    // again
+                    self.derive_keys(key_sequence);
+                    return;
+                }
+            }
+        };
+        let (_, mle_key) = match keys {
+            Some(keys) => keys,
+            None => return,
+        };
+        if !self.operation.get().is_idle() {
+            return;
+        }
+        let buf = match self.buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let len = payload.len() - 1 - AUX_HDR_LEN - MIC_LEN;
+        if CMD_OFF + len + MIC_LEN > buf.len() {
+            self.buf.replace(buf);
+            return;
+        }
+        buf[SRC_OFF..DST_OFF].copy_from_slice(&src.0);
+        buf[DST_OFF..AUX_OFF].copy_from_slice(&ip6_header.dst_addr.0);
+        buf[AUX_OFF..CMD_OFF + len + MIC_LEN].copy_from_slice(&payload[1..]);
+
+        let nonce = mle_nonce(&ext_addr_from_link_local(&src), frame_counter);
+        self.ccm.set_key(&mle_key);
+        self.ccm.set_nonce(&nonce);
+        match self.ccm.crypt(buf, SRC_OFF, CMD_OFF, len, MIC_LEN, true, false) {
+            (ReturnCode::SUCCESS, _) => {
+                self.operation.set(Operation::Verifying {
+                    src: src,
+                    len: len,
+                    frame_counter: frame_counter,
+                    key_sequence: key_sequence,
+                });
+            }
+            (_, buf) => {
+                buf.map(|buf| self.buf.replace(buf));
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> CCMClient for MLE<'a, A, D> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        match operation {
+            Operation::Securing { dst, len } => {
+                if res == ReturnCode::SUCCESS {
+                    buf[AUX_OFF - 1] = SECURITY_SUITE_MLE;
+                    let message = &buf[AUX_OFF - 1..CMD_OFF + len + MIC_LEN];
+                    self.udp_sender.send_to(dst, MLE_PORT, MLE_PORT, message);
+                }
+                self.buf.replace(buf);
+            }
+            Operation::Verifying {
+                src,
+                len,
+                frame_counter,
+                key_sequence,
+            } => {
+                let message = if res == ReturnCode::SUCCESS && tag_is_valid {
+                    Message::decode(&buf[CMD_OFF..CMD_OFF + len])
+                } else {
+                    None
+                };
+                self.buf.replace(buf);
+                if message.is_some() && key_sequence != self.key_sequence.get() {
+                    self.adopt_key_sequence(key_sequence);
+                }
+                message.map(|message| self.receive_message(src, frame_counter, message));
+            }
+            _ => {
+                self.buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> digest::Client for MLE<'a, A, D> {
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]) {
+        let data = if result == ReturnCode::SUCCESS {
+            match self.digest.run(data) {
+                (ReturnCode::SUCCESS, _) => return,
+                (_, data) => data,
+            }
+        } else {
+            Some(data)
+        };
+        data.map(|data| self.buf.replace(data));
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        self.derivation_failed(operation);
+    }
+
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        let key_sequence = match operation {
+            Operation::DerivingKeys { key_sequence } if result == ReturnCode::SUCCESS => {
+                key_sequence
+            }
+            _ => {
+                self.buf.replace(digest);
+                return self.derivation_failed(operation);
+            }
+        };
+        let mut mac_key = [0; 16];
+        let mut mle_key = [0; 16];
+        mac_key.copy_from_slice(&digest[..16]);
+        mle_key.copy_from_slice(&digest[16..SHA256_OUTPUT_SIZE]);
+        self.buf.replace(digest);
+        if key_sequence == self.key_sequence.get() {
+            self.keys.set(Some((mac_key, mle_key)));
+            self.keys_derived();
+        } else {
+            self.candidate_keys
+                .set(Some((key_sequence, (mac_key, mle_key))));
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> NonvolatileStorageClient
+    for MLE<'a, A, D>
+{
+    /// Resumes from the stored bound, and stores a new one before starting.
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let bound = (buffer[0] as u32) | (buffer[1] as u32) << 8 | (buffer[2] as u32) << 16
+            | (buffer[3] as u32) << 24;
+        self.frame_counter_buf.replace(buffer);
+        if length < 4 {
+            return;
+        }
+        if bound != u32::max_value() {
+            self.frame_counter
+                .set(cmp::max(self.frame_counter.get(), bound));
+        }
+        self.store_frame_counter();
+    }
+
+    /// Lets the frame counter go up to the stored bound, and starts
+    /// attaching once the first bound is stored.
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        let bound = (buffer[0] as u32) | (buffer[1] as u32) << 8 | (buffer[2] as u32) << 16
+            | (buffer[3] as u32) << 24;
+        self.frame_counter_buf.replace(buffer);
+        if length < 4 {
+            return;
+        }
+        self.frame_counter_limit.set(bound);
+        if self.state.get() == MLEState::Disabled {
+            self.attach();
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> rng::Client for MLE<'a, A, D> {
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        match (randomness.next(), randomness.next()) {
+            (Some(a), Some(b)) => {
+                let mut challenge = [0; 8];
+                for i in 0..4 {
+                    challenge[i] = (a >> (8 * i)) as u8;
+                    challenge[4 + i] = (b >> (8 * i)) as u8;
+                }
+                self.next_challenge.set(challenge);
+                rng::Continue::Done
+            }
+            _ => rng::Continue::More,
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> UDPSendClient for MLE<'a, A, D> {
+    /// Lost messages are sent again when their response times out. A sleepy
+    /// child polls for the response to its request, which the parent holds
+    /// for it.
+    fn send_done(&self, _result: ReturnCode) {
+        match self.state.get() {
+            MLEState::ChildIdRequest { .. } | MLEState::ChildUpdateRequest { .. } => {
+                if self.is_sleepy() {
+                    self.send_data_request();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> TxClient for MLE<'a, A, D> {
+    /// Missed polls are made up for by the next one.
+    fn send_done(&self, buf: &'static mut [u8], _acked: bool, _result: ReturnCode) {
+        self.poll_buf.replace(buf);
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> KeyProcedure for MLE<'a, A, D> {
+    fn lookup_key(&self, level: SecurityLevel, key_id: KeyId) -> Option<([u8; 16])> {
+        match (key_id, self.keys.get()) {
+            (KeyId::Index(index), Some((mac_key, _)))
+                if index == key_index(self.key_sequence.get()) =>
+            {
+                Some(mac_key)
+            }
+            _ => self.key_procedure
+                .get()
+                .and_then(|key_procedure| key_procedure.lookup_key(level, key_id)),
+        }
+    }
+}
+
+impl<'a, A: Alarm + 'a, D: Digest<'a> + HmacSha256 + 'a> DeviceProcedure for MLE<'a, A, D> {
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<([u8; 8])> {
+        let parent_addr = self.parent.get().and_then(|parent| match addr {
+            MacAddress::Short(short_addr) if short_addr == parent.rloc16 => Some(parent.ext_addr),
+            MacAddress::Long(long_addr) if long_addr == parent.ext_addr => Some(parent.ext_addr),
+            _ => None,
+        });
+        parent_addr.or_else(|| {
+            self.device_procedure
+                .get()
+                .and_then(|device_procedure| device_procedure.lookup_addr_long(addr))
+        })
+    }
+}
@@ -0,0 +1,382 @@
+//! UDP userspace interface for sending and receiving datagrams.
+//!
+//! Each app binds a single local port, which is reserved in the shared
+//! [UDPPortTable](../udp_port_table/struct.UDPPortTable.html), so no two apps
+//! or kernel capsules can receive on the same port. Datagrams are sent from
+//! the bound port, and datagrams received on it are copied into the app's
+//! receive buffer. Transmissions are queued, one per app, and sent in turn
+//! through a `UDPSender`. A port stays bound after its app exits or restarts
+//! until the next bind request, which releases it.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `allow` System Call
+//!
+//! * 0: The buffer that received payloads are copied into.
+//! * 1: The payload to send.
+//! * 2: The destination of the next transmission, 18 bytes: the IPv6
+//!   address followed by the port in network byte order.
+//! * 3: Where the source of each received datagram is written, in the same
+//!   format. Optional.
+//!
+//! ### `subscribe` System Call
+//!
+//! * 0: Receive callback, with the length of the received payload. The
+//!   payload is truncated if it does not fit in the receive buffer.
+//! * 1: Transmit callback, with the `ReturnCode` of the transmission.
+//!
+//! ### `command` System Call
+//!
+//! * 0: Driver check.
+//! * 1: Bind the local port `arg1`, replacing any previous binding of the
+//!   app. Port 0 releases the binding. Returns `EBUSY` if the port is bound
+//!   by someone else.
+//! * 2: Send the payload to the configured destination. The app must have
+//!   bound a port.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let udp_driver = static_init!(
+//!     capsules::net::udp::driver::UDPDriver<'static>,
+//!     capsules::net::udp::driver::UDPDriver::new(
+//!         udp_send_struct,
+//!         udp_port_table,
+//!         kernel::Grant::create()));
+//! udp_send_struct.set_client(udp_driver);
+//! udp_recv_mux.set_app_client(udp_driver);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use net::ipv6::ip_utils::IPAddr;
+use net::ipv6::ipv6::IP6Header;
+use net::stream::{decode_bytes, decode_u16, encode_bytes, encode_u16, SResult};
+use net::udp::udp::UDPHeader;
+use net::udp::udp_port_table::{PortOwner, UDPPortTable};
+use net::udp::udp_recv::UDPRecvClient;
+use net::udp::udp_send::{UDPSendClient, UDPSender};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x30002;
+
+/// Length of an IPv6 address and port, as exchanged with userland
+const ENDPOINT_LEN: usize = 18;
+
+/// Decodes an address and port in the format used by the userland driver.
+fn decode_endpoint(buf: &[u8]) -> SResult<(IPAddr, u16)> {
+    stream_len_cond!(buf, ENDPOINT_LEN);
+    let mut addr = IPAddr::new();
+    let off = dec_consume!(buf; decode_bytes, &mut addr.0);
+    let (off, port) = dec_try!(buf, off; decode_u16);
+    stream_done!(off, (addr, port));
+}
+
+/// Encodes an address and port in the format expected by the userland driver.
+fn encode_endpoint(buf: &mut [u8], addr: IPAddr, port: u16) -> SResult {
+    stream_len_cond!(buf, ENDPOINT_LEN);
+    let off = enc_consume!(buf; encode_bytes, &addr.0);
+    let off = enc_consume!(buf, off; encode_u16, port);
+    stream_done!(off);
+}
+
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<Shared, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    app_rx_cfg: Option<AppSlice<Shared, u8>>,
+    bound_port: Option<u16>,
+    pending_tx: Option<(IPAddr, u16)>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        App {
+            rx_callback: None,
+            tx_callback: None,
+            app_read: None,
+            app_write: None,
+            app_cfg: None,
+            app_rx_cfg: None,
+            bound_port: None,
+            pending_tx: None,
+        }
+    }
+}
+
+pub struct UDPDriver<'a> {
+    /// UDP sender, shared by all apps
+    sender: &'a UDPSender<'a>,
+
+    /// Table of the ports bound by apps and kernel capsules
+    port_table: &'a UDPPortTable,
+
+    /// Grant of apps that use this UDP driver.
+    apps: Grant<App>,
+    /// ID of app whose transmission request is being processed.
+    current_app: Cell<Option<AppId>>,
+}
+
+impl<'a> UDPDriver<'a> {
+    pub fn new(
+        sender: &'a UDPSender<'a>,
+        port_table: &'a UDPPortTable,
+        grant: Grant<App>,
+    ) -> UDPDriver<'a> {
+        UDPDriver {
+            sender: sender,
+            port_table: port_table,
+            apps: grant,
+            current_app: Cell::new(None),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Releases ports that are still bound to apps that have exited or
+    /// restarted since binding them. Their grant no longer records the port.
+    fn release_stale_ports(&self) {
+        self.port_table.retain(|port, owner| match owner {
+            PortOwner::Kernel => true,
+            PortOwner::App(appid) => self.apps
+                .enter(appid, |app, _| app.bound_port == Some(port))
+                .unwrap_or(false),
+        });
+    }
+
+    /// Binds `port` for the app, releasing the port it was bound to before.
+    fn bind(&self, appid: AppId, port: u16) -> ReturnCode {
+        self.release_stale_ports();
+        self.do_with_app(appid, |app| {
+            if port != 0 && app.bound_port != Some(port) {
+                let result = self.port_table.bind(port, PortOwner::App(appid));
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+            }
+            if app.bound_port != Some(port) {
+                app.bound_port
+                    .map(|old| self.port_table.unbind(old, PortOwner::App(appid)));
+            }
+            app.bound_port = if port == 0 { None } else { Some(port) };
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn get_next_tx_if_idle(&self) -> Option<AppId> {
+        if self.current_app.get().is_some() {
+            return None;
+        }
+        let mut pending_app = None;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.pending_tx.is_some() {
+                    pending_app = Some(app.appid());
+                }
+            });
+            if pending_app.is_some() {
+                break;
+            }
+        }
+        pending_app
+    }
+
+    #[inline]
+    fn perform_tx_async(&self, appid: AppId) {
+        let result = self.perform_tx_sync(appid);
+        if result != ReturnCode::SUCCESS {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback
+                    .map(|mut cb| cb.schedule(result.into(), 0, 0));
+            });
+        }
+    }
+
+    #[inline]
+    fn perform_tx_sync(&self, appid: AppId) -> ReturnCode {
+        self.do_with_app(appid, |app| {
+            let (dst_addr, dst_port) = match app.pending_tx.take() {
+                Some(pending_tx) => pending_tx,
+                None => {
+                    return ReturnCode::SUCCESS;
+                }
+            };
+            let src_port = match app.bound_port {
+                Some(port) => port,
+                None => {
+                    return ReturnCode::EINVAL;
+                }
+            };
+            // The sender may complete the transmission before returning
+            self.current_app.set(Some(appid));
+            let result = app.app_write
+                .as_ref()
+                .map(|payload| {
+                    self.sender
+                        .send_to(dst_addr, dst_port, src_port, payload.as_ref())
+                })
+                .unwrap_or(ReturnCode::EINVAL);
+            if result != ReturnCode::SUCCESS {
+                self.current_app.set(None);
+            }
+            result
+        })
+    }
+
+    #[inline]
+    fn do_next_tx_async(&self) {
+        self.get_next_tx_if_idle()
+            .map(|appid| self.perform_tx_async(appid));
+    }
+
+    #[inline]
+    fn do_next_tx_sync(&self, new_appid: AppId) -> ReturnCode {
+        self.get_next_tx_if_idle()
+            .map(|appid| {
+                if appid == new_appid {
+                    self.perform_tx_sync(appid)
+                } else {
+                    self.perform_tx_async(appid);
+                    ReturnCode::SUCCESS
+                }
+            })
+            .unwrap_or(ReturnCode::SUCCESS)
+    }
+}
+
+impl<'a> Driver for UDPDriver<'a> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice,
+                    2 => app.app_cfg = slice,
+                    3 => app.app_rx_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.rx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            1 => self.do_with_app(app_id, |app| {
+                app.tx_callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                if arg1 > (u16::max_value() as usize) {
+                    return ReturnCode::EINVAL;
+                }
+                self.bind(appid, arg1 as u16)
+            }
+            2 => {
+                self.do_with_app(appid, |app| {
+                    if app.pending_tx.is_some() {
+                        // Cannot support more than one pending tx per process.
+                        return ReturnCode::EBUSY;
+                    }
+                    if app.bound_port.is_none() {
+                        return ReturnCode::EINVAL;
+                    }
+                    let next_tx = app.app_cfg
+                        .as_ref()
+                        .and_then(|cfg| decode_endpoint(cfg.as_ref()).done())
+                        .map(|(_, endpoint)| endpoint);
+                    if next_tx.is_none() {
+                        return ReturnCode::EINVAL;
+                    }
+                    app.pending_tx = next_tx;
+
+                    self.do_next_tx_sync(appid)
+                })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> UDPSendClient for UDPDriver<'a> {
+    fn send_done(&self, result: ReturnCode) {
+        self.current_app.get().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback
+                    .map(|mut cb| cb.schedule(result.into(), 0, 0));
+            });
+        });
+        self.current_app.set(None);
+        self.do_next_tx_async();
+    }
+}
+
+impl<'a> UDPRecvClient for UDPDriver<'a> {
+    fn receive(&self, ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]) {
+        let dst_port = udp_header.get_dst_port();
+        let appid = match self.port_table.owner(dst_port) {
+            Some(PortOwner::App(appid)) => appid,
+            _ => return,
+        };
+        let _ = self.apps.enter(appid, |app, _| {
+            if app.bound_port != Some(dst_port) {
+                // The binding outlived the app that made it
+                return;
+            }
+            let read_present = app.app_read.as_mut().map_or(false, |rbuf| {
+                let rbuf = rbuf.as_mut();
+                let len = min(rbuf.len(), payload.len());
+                rbuf[..len].copy_from_slice(&payload[..len]);
+                true
+            });
+            if !read_present {
+                return;
+            }
+            app.app_rx_cfg.as_mut().map(|cfg| {
+                encode_endpoint(
+                    cfg.as_mut(),
+                    ip6_header.src_addr,
+                    udp_header.get_src_port(),
+                )
+            });
+            app.rx_callback
+                .map(|mut cb| cb.schedule(payload.len(), 0, 0));
+        });
+    }
+}
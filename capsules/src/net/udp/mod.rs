@@ -1,2 +1,5 @@
+pub mod driver;
 pub mod udp;
+pub mod udp_port_table;
+pub mod udp_recv;
 pub mod udp_send;
@@ -105,19 +105,18 @@ impl UDPHeader {
     /// # Return Value
     ///
     /// This function returns a `UDPHeader` struct wrapped in an SResult
-    // TODO: Decode has not been tested
     pub fn decode(buf: &[u8]) -> SResult<UDPHeader> {
         stream_len_cond!(buf, 8);
         let mut udp_header = Self::new();
         let off = 0;
         let (off, src_port) = dec_try!(buf, off; decode_u16);
-        udp_header.src_port = u16::from_be(src_port);
+        udp_header.src_port = src_port;
         let (off, dst_port) = dec_try!(buf, off; decode_u16);
-        udp_header.dst_port = u16::from_be(dst_port);
+        udp_header.dst_port = dst_port;
         let (off, len) = dec_try!(buf, off; decode_u16);
-        udp_header.len = u16::from_be(len);
+        udp_header.len = len;
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        udp_header.cksum = u16::from_be(cksum);
+        udp_header.cksum = cksum;
         stream_done!(off, udp_header);
     }
 }
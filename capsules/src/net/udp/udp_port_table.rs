@@ -0,0 +1,99 @@
+//! This file contains the table of bound UDP ports. Every user of the UDP
+//! layer, whether a kernel capsule or an app using the UDP syscall driver,
+//! binds the local port it receives on in the shared
+//! [UDPPortTable](struct.UDPPortTable.html), which ensures that each port is
+//! bound at most once. Received packets are dispatched to the owner of their
+//! destination port.
+
+use kernel::common::take_cell::MapCell;
+use kernel::{AppId, ReturnCode};
+
+/// The maximum number of ports that can be bound at the same time
+pub const MAX_BINDINGS: usize = 8;
+
+/// The user that a port is bound to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PortOwner {
+    Kernel,
+    App(AppId),
+}
+
+pub struct UDPPortTable {
+    bindings: MapCell<[Option<(u16, PortOwner)>; MAX_BINDINGS]>,
+}
+
+impl UDPPortTable {
+    pub fn new() -> UDPPortTable {
+        UDPPortTable {
+            bindings: MapCell::new([None; MAX_BINDINGS]),
+        }
+    }
+
+    /// Binds `port` to `owner`.
+    ///
+    /// # Return Value
+    /// `EINVAL` if `port` is 0, `EBUSY` if it is already bound, and `ENOMEM`
+    /// if the table is full.
+    pub fn bind(&self, port: u16, owner: PortOwner) -> ReturnCode {
+        if port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        self.bindings
+            .map_or(ReturnCode::ENOMEM, |bindings| {
+                if bindings
+                    .iter()
+                    .any(|binding| binding.map_or(false, |(bound, _)| bound == port))
+                {
+                    return ReturnCode::EBUSY;
+                }
+                match bindings.iter_mut().find(|binding| binding.is_none()) {
+                    Some(binding) => {
+                        *binding = Some((port, owner));
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::ENOMEM,
+                }
+            })
+    }
+
+    /// Releases `port`, if it is bound to `owner`.
+    pub fn unbind(&self, port: u16, owner: PortOwner) -> ReturnCode {
+        self.bindings.map_or(ReturnCode::FAIL, |bindings| {
+            match bindings
+                .iter_mut()
+                .find(|binding| **binding == Some((port, owner)))
+            {
+                Some(binding) => {
+                    *binding = None;
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Releases every binding for which `keep` returns false.
+    pub fn retain<F>(&self, keep: F)
+    where
+        F: Fn(u16, PortOwner) -> bool,
+    {
+        self.bindings.map(|bindings| {
+            for binding in bindings.iter_mut() {
+                if binding.map_or(false, |(port, owner)| !keep(port, owner)) {
+                    *binding = None;
+                }
+            }
+        });
+    }
+
+    /// Returns the owner of `port`, if it is bound.
+    pub fn owner(&self, port: u16) -> Option<PortOwner> {
+        self.bindings.and_then(|bindings| {
+            bindings
+                .iter()
+                .filter_map(|binding| *binding)
+                .find(|&(bound, _)| bound == port)
+                .map(|(_, owner)| owner)
+        })
+    }
+}
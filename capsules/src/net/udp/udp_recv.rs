@@ -0,0 +1,145 @@
+//! This file contains the definition of the interface through which UDP
+//! packets are received. The [UDPRecvClient](trait.UDPRecvClient.html) trait
+//! is implemented by upper layers, and registered with an
+//! [IP6Receiver](../../ipv6/ipv6_recv/trait.IP6Receiver.html), which passes
+//! on every UDP packet with a valid checksum.
+//!
+//! Since the `IP6Receiver` has a single UDP client, the
+//! [UDPRecvMux](struct.UDPRecvMux.html) is set as that client when several
+//! users share the UDP layer. It dispatches each packet according to the
+//! owner of its destination port in the
+//! [UDPPortTable](../udp_port_table/struct.UDPPortTable.html): packets for
+//! ports bound by the kernel go to the [UDPBinding](struct.UDPBinding.html)
+//! of that port, and packets for ports bound by apps go to the app client,
+//! usually the UDP syscall driver. Packets for unbound ports go to the
+//! unbound client, if there is one, which can answer them with an ICMPv6
+//! Destination Unreachable message.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let udp_port_table = static_init!(UDPPortTable, UDPPortTable::new());
+//! let udp_recv_mux = static_init!(
+//!     UDPRecvMux<'static>,
+//!     UDPRecvMux::new(udp_port_table));
+//! ip6_receiver.set_udp_client(udp_recv_mux);
+//! udp_recv_mux.set_app_client(udp_driver);
+//! udp_recv_mux.set_unbound_client(icmp_handler);
+//!
+//! let binding = static_init!(UDPBinding<'static>, UDPBinding::new(5683, kernel_client));
+//! udp_recv_mux.add_binding(binding);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::ReturnCode;
+use net::ipv6::ipv6::IP6Header;
+use net::udp::udp::UDPHeader;
+use net::udp::udp_port_table::{PortOwner, UDPPortTable};
+
+/// The `receive` function in this trait is invoked for each UDP packet that
+/// is received. Note that `IP6Receiver::set_udp_client` must be called to set
+/// the client.
+pub trait UDPRecvClient {
+    /// This function is called when a UDP packet has been received
+    ///
+    /// # Arguments
+    /// `ip6_header` - The IPv6 header of the packet. Its payload length and
+    /// next header describe the UDP packet, skipping any extension headers
+    /// `udp_header` - The decoded UDP header
+    /// `payload` - The UDP payload
+    fn receive(&self, ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]);
+}
+
+/// A local port bound by a kernel capsule, and the client that receives the
+/// packets sent to it.
+pub struct UDPBinding<'a> {
+    port: u16,
+    client: &'a UDPRecvClient,
+    next: ListLink<'a, UDPBinding<'a>>,
+}
+
+impl<'a> UDPBinding<'a> {
+    pub fn new(port: u16, client: &'a UDPRecvClient) -> UDPBinding<'a> {
+        UDPBinding {
+            port: port,
+            client: client,
+            next: ListLink::empty(),
+        }
+    }
+
+    pub fn get_port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl<'a> ListNode<'a, UDPBinding<'a>> for UDPBinding<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, UDPBinding<'a>> {
+        &self.next
+    }
+}
+
+pub struct UDPRecvMux<'a> {
+    port_table: &'a UDPPortTable,
+    bindings: List<'a, UDPBinding<'a>>,
+    app_client: Cell<Option<&'a UDPRecvClient>>,
+    unbound_client: Cell<Option<&'a UDPRecvClient>>,
+}
+
+impl<'a> UDPRecvMux<'a> {
+    pub fn new(port_table: &'a UDPPortTable) -> UDPRecvMux<'a> {
+        UDPRecvMux {
+            port_table: port_table,
+            bindings: List::new(),
+            app_client: Cell::new(None),
+            unbound_client: Cell::new(None),
+        }
+    }
+
+    /// Sets the client that receives the packets for ports bound by apps
+    pub fn set_app_client(&self, client: &'a UDPRecvClient) {
+        self.app_client.set(Some(client));
+    }
+
+    /// Sets the client that receives the packets for ports nobody has bound
+    pub fn set_unbound_client(&self, client: &'a UDPRecvClient) {
+        self.unbound_client.set(Some(client));
+    }
+
+    /// Binds the port of `binding` for the kernel, and starts passing the
+    /// packets sent to it to the client of `binding`.
+    ///
+    /// # Return Value
+    /// Fails as `UDPPortTable::bind` does if the port can't be bound.
+    pub fn add_binding(&self, binding: &'a UDPBinding<'a>) -> ReturnCode {
+        let result = self.port_table.bind(binding.port, PortOwner::Kernel);
+        if result == ReturnCode::SUCCESS {
+            self.bindings.push_head(binding);
+        }
+        result
+    }
+}
+
+impl<'a> UDPRecvClient for UDPRecvMux<'a> {
+    fn receive(&self, ip6_header: IP6Header, udp_header: UDPHeader, payload: &[u8]) {
+        match self.port_table.owner(udp_header.get_dst_port()) {
+            Some(PortOwner::Kernel) => {
+                self.bindings
+                    .iter()
+                    .find(|binding| binding.port == udp_header.get_dst_port())
+                    .map(|binding| binding.client.receive(ip6_header, udp_header, payload));
+            }
+            Some(PortOwner::App(_)) => {
+                self.app_client
+                    .get()
+                    .map(|client| client.receive(ip6_header, udp_header, payload));
+            }
+            None => {
+                self.unbound_client
+                    .get()
+                    .map(|client| client.receive(ip6_header, udp_header, payload));
+            }
+        }
+    }
+}
@@ -40,12 +40,12 @@ pub trait UDPSender<'a> {
     /// `dest` - IPv6 address to send the UDP packet to
     /// `dst_port` - Destination port to send the packet to
     /// `src_port` - Port to send the packet from
-    /// `buf` - UDP payload
+    /// `buf` - UDP payload, which is copied before this function returns
     ///
     /// # Return Value
     /// Any synchronous errors are returned via the returned `ReturnCode`
     /// value; asynchronous errors are delivered via the callback.
-    fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &'a [u8]) -> ReturnCode;
+    fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &[u8]) -> ReturnCode;
 
     /// This function constructs an IP packet from the completed `UDPHeader`
     /// and buffer, and sends it to the provided IP address
@@ -53,12 +53,13 @@ pub trait UDPSender<'a> {
     /// # Arguments
     /// `dest` - IP address to send the UDP packet to
     /// `udp_header` - Completed UDP header to be sent to the destination
-    /// `buf` - A byte array containing the UDP payload
+    /// `buf` - A byte array containing the UDP payload, which is copied
+    /// before this function returns
     ///
     /// # Return Value
     /// Returns any synchronous errors or success. Note that any asynchrounous
     /// errors are returned via the callback.
-    fn send(&self, dest: IPAddr, udp_header: UDPHeader, buf: &'a [u8]) -> ReturnCode;
+    fn send(&self, dest: IPAddr, udp_header: UDPHeader, buf: &[u8]) -> ReturnCode;
 }
 
 /// This is a specific instantiation of the `UDPSender` trait. Note
@@ -76,14 +77,14 @@ impl<'a, T: IP6Sender<'a>> UDPSender<'a> for UDPSendStruct<'a, T> {
         self.client.set(Some(client));
     }
 
-    fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &'a [u8]) -> ReturnCode {
+    fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &[u8]) -> ReturnCode {
         let mut udp_header = UDPHeader::new();
         udp_header.set_dst_port(dst_port);
         udp_header.set_src_port(src_port);
         self.send(dest, udp_header, buf)
     }
 
-    fn send(&self, dest: IPAddr, mut udp_header: UDPHeader, buf: &'a [u8]) -> ReturnCode {
+    fn send(&self, dest: IPAddr, mut udp_header: UDPHeader, buf: &[u8]) -> ReturnCode {
         let total_length = buf.len() + udp_header.get_hdr_size();
         udp_header.set_len(total_length as u16);
         let transport_header = TransportHeader::UDP(udp_header);
@@ -1,13 +1,23 @@
 //! This provides kernel and userspace access to nonvolatile memory.
 //!
-//! This is an initial implementation that does not provide safety for
-//! individual userland applications. Each application has full access to
-//! the entire memory space that has been provided to userland. Future revisions
-//! should update this to limit applications to only their allocated regions.
+//! The memory space provided to userland is divided into isolated partitions,
+//! one per application. The size of each app's partition is taken from the
+//! board's table of `AppStorageSize`s, matched by package name, or otherwise
+//! from the nonvolatile storage size the app requested in its TBF header
+//! (elf2tab's `--nonvolatile-storage` option). Apps that are not in the table
+//! and request no storage get the board's default partition size, so that
+//! apps built before partitions existed keep working. Partitions are laid out back to back in the order the apps are loaded. An
+//! app whose partition does not fit in the userspace region gets no storage.
+//! Addresses used by an app are relative to the start of its partition, and
+//! any access outside of the partition fails with `EINVAL`.
 //!
-//! However, the kernel accessible memory does not have to be the same range
-//! as the userspace accessible address space. The kernel memory can overlap
-//! if desired, or can be a completely separate range.
+//! Because partitions are assigned in load order, adding, removing or
+//! reordering apps moves the partitions of the apps after it. Boards that need
+//! stable locations should list every app in the table.
+//!
+//! The kernel accessible memory does not have to be the same range as the
+//! userspace accessible address space. The kernel memory can overlap if
+//! desired, or can be a completely separate range.
 //!
 //! Here is a diagram of the expected stack with this capsule:
 //! Boxes are components and between the boxes are the traits that are the
@@ -37,6 +47,13 @@
 //! Example instantiation:
 //!
 //! ```rust
+//! static APP_STORAGE_SIZES: [capsules::nonvolatile_storage_driver::AppStorageSize; 1] = [
+//!     capsules::nonvolatile_storage_driver::AppStorageSize {
+//!         package_name: "sensor_log",
+//!         length: 1024,
+//!     },
+//! ];
+//!
 //! let nonvolatile_storage = static_init!(
 //!     capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
 //!     capsules::nonvolatile_storage_driver::NonvolatileStorage::new(
@@ -48,6 +65,8 @@
 //!         0,                           // The byte start address of the region
 //!                                      // that is accessible by the kernel.
 //!         3000,                        // The length of the kernel region.
+//!         &APP_STORAGE_SIZES,          // Per-app partition sizes.
+//!         256,                         // Partition size for other apps.
 //!         &mut capsules::nonvolatile_storage_driver::BUFFER));
 //! hil::nonvolatile_storage::NonvolatileStorage::set_client(fm25cl, nonvolatile_storage);
 //! ```
@@ -71,6 +90,13 @@ pub enum NonvolatileCommand {
     KernelWrite,
 }
 
+/// Board configured size of the storage partition for the app with the given
+/// package name. This overrides any size requested in the app's TBF header.
+pub struct AppStorageSize {
+    pub package_name: &'static str,
+    pub length: usize,
+}
+
 #[derive(Clone, Copy)]
 pub enum NonvolatileUser {
     App { app_id: AppId },
@@ -122,6 +148,11 @@ pub struct NonvolatileStorage<'a> {
     kernel_start_address: usize,
     // How many bytes allocated to kernel.
     kernel_length: usize,
+    // Board configured partition sizes for apps.
+    app_storage_sizes: &'static [AppStorageSize],
+    // Partition size for apps that are not in the table and request no
+    // storage in their TBF header.
+    default_app_length: usize,
 
     // Optional client for the kernel. Only needed if the kernel intends to use
     // this nonvolatile storage.
@@ -146,6 +177,8 @@ impl<'a> NonvolatileStorage<'a> {
         userspace_length: usize,
         kernel_start_address: usize,
         kernel_length: usize,
+        app_storage_sizes: &'static [AppStorageSize],
+        default_app_length: usize,
         buffer: &'static mut [u8],
     ) -> NonvolatileStorage<'a> {
         NonvolatileStorage {
@@ -157,6 +190,8 @@ impl<'a> NonvolatileStorage<'a> {
             userspace_length: userspace_length,
             kernel_start_address: kernel_start_address,
             kernel_length: kernel_length,
+            app_storage_sizes: app_storage_sizes,
+            default_app_length: default_app_length,
             kernel_client: Cell::new(None),
             kernel_pending_command: Cell::new(false),
             kernel_command: Cell::new(NonvolatileCommand::KernelRead),
@@ -166,6 +201,39 @@ impl<'a> NonvolatileStorage<'a> {
         }
     }
 
+    // How many bytes of storage the app with the given index gets.
+    fn app_storage_size(&self, app_idx: usize) -> usize {
+        let appid = AppId::new(app_idx);
+        appid.get_package_name().map_or(0, |name| {
+            self.app_storage_sizes
+                .iter()
+                .find(|entry| entry.package_name == name)
+                .map_or_else(
+                    || match appid.get_nonvolatile_storage_size() {
+                        0 => self.default_app_length,
+                        requested => requested,
+                    },
+                    |entry| entry.length,
+                )
+        })
+    }
+
+    // Find the partition for this app. Returns the offset of the partition in
+    // the userspace region and its length.
+    fn app_partition(&self, appid: AppId) -> (usize, usize) {
+        let offset = (0..appid.idx()).fold(Some(0usize), |offset, idx| {
+            offset.and_then(|offset| offset.checked_add(self.app_storage_size(idx)))
+        });
+        let length = self.app_storage_size(appid.idx());
+
+        match offset.and_then(|offset| offset.checked_add(length).map(|end| (offset, end))) {
+            Some((offset, end)) if end <= self.userspace_length => (offset, length),
+            // This partition does not fit, or the sizes requested by the
+            // apps overflow.
+            _ => (0, 0),
+        }
+    }
+
     // Check so see if we are doing something. If not, go ahead and do this
     // command. If so, this is queued and will be run when the pending
     // command completes.
@@ -179,10 +247,11 @@ impl<'a> NonvolatileStorage<'a> {
         // Do bounds check.
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
-                // Userspace sees memory that starts at address 0 even if it
-                // is offset in the physical memory.
-                if offset >= self.userspace_length || length > self.userspace_length
-                    || offset + length > self.userspace_length
+                // Each app sees its partition starting at address 0, even
+                // though it is offset in the userspace region.
+                let partition_length = app_id.map_or(0, |appid| self.app_partition(appid).1);
+                if offset >= partition_length || length > partition_length
+                    || length > partition_length - offset
                 {
                     return ReturnCode::EINVAL;
                 }
@@ -205,6 +274,9 @@ impl<'a> NonvolatileStorage<'a> {
         match command {
             NonvolatileCommand::UserspaceRead | NonvolatileCommand::UserspaceWrite => {
                 app_id.map_or(ReturnCode::FAIL, |appid| {
+                    // Convert the offset in the partition to an offset in the
+                    // userspace region.
+                    let offset = self.app_partition(appid).0 + offset;
                     self.apps
                         .enter(appid, |app, _| {
                             // Get the length of the correct allowed buffer.
@@ -520,7 +592,7 @@ impl<'a> Driver for NonvolatileStorage<'a> {
     /// ### `command_num`
     ///
     /// - `0`: Return SUCCESS if this driver is included on the platform.
-    /// - `1`: Return the number of bytes available to userspace.
+    /// - `1`: Return the number of bytes available to this app.
     /// - `2`: Start a read from the nonvolatile storage.
     /// - `3`: Start a write to the nonvolatile_storage.
     fn command(&self, arg0: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
@@ -529,8 +601,8 @@ impl<'a> Driver for NonvolatileStorage<'a> {
         match command_num {
             0 => /* This driver exists. */ ReturnCode::SUCCESS,
 
-            // How many bytes are accessible from userspace.
-            1 => ReturnCode::SuccessWithValue { value: self.userspace_length },
+            // How many bytes are accessible to this app.
+            1 => ReturnCode::SuccessWithValue { value: self.app_partition(appid).1 },
 
             // Issue a read
             2 => {
@@ -7,6 +7,10 @@
 //! This module is designed to be used on top of any flash storage and below any
 //! user of `NonvolatileStorage`. This module handles different sized pages.
 //!
+//! This module also exposes a region of the flash as a
+//! `hil::block_storage::BlockStorage` device with one block per flash page. The
+//! region must be set with `set_block_region()` before it can be used.
+//!
 //! ```plain
 //! hil::nonvolatile_storage::NonvolatileStorage
 //!                ┌─────────────┐
@@ -29,6 +33,9 @@
 //!         &mut sam4l::flashcalw::FLASH_CONTROLLER,
 //!         &mut PAGEBUFFER));
 //! hil::flash::HasClient::set_client(&sam4l::flashcalw::FLASH_CONTROLLER, nv_to_page);
+//!
+//! // Optionally, use pages 0x100 through 0x17f as block storage.
+//! nv_to_page.set_block_region(0x100, 0x80);
 //! ```
 
 use core::cell::Cell;
@@ -43,6 +50,7 @@ enum State {
     Idle,
     Read,
     Write,
+    Erase,
 }
 
 pub struct NonvolatileToPages<'a, F: hil::flash::Flash + 'static> {
@@ -66,10 +74,21 @@ pub struct NonvolatileToPages<'a, F: hil::flash::Flash + 'static> {
     remaining_length: Cell<usize>,
     /// Where we are in the user buffer.
     buffer_index: Cell<usize>,
+    /// Size of a flash page in bytes, which is also the block size.
+    page_size: usize,
+    /// Callback to the user of the block storage interface.
+    block_client: Cell<Option<&'static hil::block_storage::Client>>,
+    /// Whether the current operation was requested through the block storage
+    /// interface.
+    block_request: Cell<bool>,
+    /// First flash page and number of pages exposed as block storage.
+    block_start: Cell<usize>,
+    block_count: Cell<usize>,
 }
 
 impl<'a, F: hil::flash::Flash + 'a> NonvolatileToPages<'a, F> {
     pub fn new(driver: &'a F, buffer: &'static mut F::Page) -> NonvolatileToPages<'a, F> {
+        let page_size = buffer.as_mut().len();
         NonvolatileToPages {
             driver: driver,
             client: Cell::new(None),
@@ -80,6 +99,59 @@ impl<'a, F: hil::flash::Flash + 'a> NonvolatileToPages<'a, F> {
             length: Cell::new(0),
             remaining_length: Cell::new(0),
             buffer_index: Cell::new(0),
+            page_size: page_size,
+            block_client: Cell::new(None),
+            block_request: Cell::new(false),
+            block_start: Cell::new(0),
+            block_count: Cell::new(0),
+        }
+    }
+
+    /// Expose `num_pages` flash pages starting at `start_page` through the
+    /// block storage interface. Block 0 maps to `start_page`.
+    pub fn set_block_region(&self, start_page: usize, num_pages: usize) {
+        self.block_start.set(start_page);
+        self.block_count.set(num_pages);
+    }
+
+    /// Check that a block storage request is within the configured region.
+    fn check_block_range(&self, block: usize, count: usize) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            ReturnCode::EBUSY
+        } else if count == 0
+            || block
+                .checked_add(count)
+                .map_or(true, |end| end > self.block_count.get())
+        {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.block_request.get() {
+            self.block_request.set(false);
+            self.block_client.get().map(move |client| {
+                client.read_done(buffer, length / self.page_size, ReturnCode::SUCCESS)
+            });
+        } else {
+            self.client
+                .get()
+                .map(move |client| client.read_done(buffer, length));
+        }
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        if self.block_request.get() {
+            self.block_request.set(false);
+            self.block_client.get().map(move |client| {
+                client.write_done(buffer, length / self.page_size, ReturnCode::SUCCESS)
+            });
+        } else {
+            self.client
+                .get()
+                .map(move |client| client.write_done(buffer, length));
         }
     }
 }
@@ -178,9 +250,7 @@ impl<'a, F: hil::flash::Flash + 'a> hil::flash::Client<F> for NonvolatileToPages
                         // Nothing more to do. Put things back and issue callback.
                         self.pagebuffer.replace(pagebuffer);
                         self.state.set(State::Idle);
-                        self.client
-                            .get()
-                            .map(move |client| client.read_done(buffer, self.length.get()));
+                        self.read_done(buffer, self.length.get());
                     } else {
                         // More to do!
                         self.buffer.replace(buffer);
@@ -234,9 +304,7 @@ impl<'a, F: hil::flash::Flash + 'a> hil::flash::Client<F> for NonvolatileToPages
                 // Done!
                 self.pagebuffer.replace(pagebuffer);
                 self.state.set(State::Idle);
-                self.client
-                    .get()
-                    .map(move |client| client.write_done(buffer, self.length.get()));
+                self.write_done(buffer, self.length.get());
             } else if self.remaining_length.get() >= page_size {
                 // Write an entire page!
                 let buffer_index = self.buffer_index.get();
@@ -262,5 +330,112 @@ impl<'a, F: hil::flash::Flash + 'a> hil::flash::Client<F> for NonvolatileToPages
         });
     }
 
-    fn erase_complete(&self, _error: hil::flash::Error) {}
+    fn erase_complete(&self, error: hil::flash::Error) {
+        if self.state.get() != State::Erase {
+            return;
+        }
+
+        // `address` is the page just erased and `remaining_length` is the
+        // number of pages left to erase.
+        let erased = self.length.get() - self.remaining_length.get() + 1;
+        if error != hil::flash::Error::CommandComplete {
+            self.state.set(State::Idle);
+            self.block_request.set(false);
+            self.block_client
+                .get()
+                .map(move |client| client.erase_done(erased - 1, ReturnCode::FAIL));
+        } else if self.remaining_length.get() <= 1 {
+            self.state.set(State::Idle);
+            self.block_request.set(false);
+            self.block_client
+                .get()
+                .map(move |client| client.erase_done(erased, ReturnCode::SUCCESS));
+        } else {
+            self.remaining_length.set(self.remaining_length.get() - 1);
+            self.address.set(self.address.get() + 1);
+            self.driver.erase_page(self.address.get());
+        }
+    }
+}
+
+impl<'a, F: hil::flash::Flash + 'a> hil::block_storage::BlockStorage
+    for NonvolatileToPages<'a, F>
+{
+    fn set_client(&self, client: &'static hil::block_storage::Client) {
+        self.block_client.set(Some(client));
+    }
+
+    fn block_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.block_count.get()
+    }
+
+    fn read_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        let rval = self.check_block_range(block, count);
+        if rval != ReturnCode::SUCCESS {
+            return rval;
+        }
+        if buffer.len() < count * self.page_size {
+            return ReturnCode::ESIZE;
+        }
+
+        let address = (self.block_start.get() + block) * self.page_size;
+        let rval = hil::nonvolatile_storage::NonvolatileStorage::read(
+            self,
+            buffer,
+            address,
+            count * self.page_size,
+        );
+        if rval == ReturnCode::SUCCESS {
+            self.block_request.set(true);
+        }
+        rval
+    }
+
+    fn write_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        let rval = self.check_block_range(block, count);
+        if rval != ReturnCode::SUCCESS {
+            return rval;
+        }
+        if buffer.len() < count * self.page_size {
+            return ReturnCode::ESIZE;
+        }
+
+        let address = (self.block_start.get() + block) * self.page_size;
+        let rval = hil::nonvolatile_storage::NonvolatileStorage::write(
+            self,
+            buffer,
+            address,
+            count * self.page_size,
+        );
+        if rval == ReturnCode::SUCCESS {
+            self.block_request.set(true);
+        }
+        rval
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        let rval = self.check_block_range(block, count);
+        if rval != ReturnCode::SUCCESS {
+            return rval;
+        }
+
+        // For erases `address` holds the current page number and the lengths
+        // count pages.
+        let page = self.block_start.get() + block;
+        self.state.set(State::Erase);
+        self.block_request.set(true);
+        self.address.set(page);
+        self.length.set(count);
+        self.remaining_length.set(count);
+        let rval = self.driver.erase_page(page);
+        if rval != ReturnCode::SUCCESS {
+            self.state.set(State::Idle);
+            self.block_request.set(false);
+        }
+        rval
+    }
 }
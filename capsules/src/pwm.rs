@@ -0,0 +1,216 @@
+//! Provides userspace applications with PWM outputs.
+//!
+//! This capsule takes an array of up to 32 PWM pins to expose to apps, e.g.
+//! to dim LEDs or drive servos; any further pins are not exposed. An app must
+//! reserve a pin before it can start a signal on it, and no other app can use
+//! the pin until it is released. A reservation ends when the app that holds it
+//! exits or is restarted, and any signal the app left running is stopped the
+//! next time a pin is reserved.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let pwm_pin0 = static_init!(
+//!     kernel::hil::pwm::PwmPinUser<'static, nrf52::pwm::Pwm>,
+//!     kernel::hil::pwm::PwmPinUser::new(&nrf52::pwm::PWM0, Pinmux::new(17)));
+//! let pwm_pin1 = static_init!(
+//!     kernel::hil::pwm::PwmPinUser<'static, nrf52::pwm::Pwm>,
+//!     kernel::hil::pwm::PwmPinUser::new(&nrf52::pwm::PWM0, Pinmux::new(18)));
+//! let pwm_pins = static_init!(
+//!     [&'static kernel::hil::pwm::PwmPin; 2],
+//!     [pwm_pin0, pwm_pin1]);
+//! let pwm = static_init!(
+//!     capsules::pwm::Pwm<'static>,
+//!     capsules::pwm::Pwm::new(pwm_pins, kernel::Grant::create()));
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Commands
+//!
+//! All PWM operations are synchronous. Duty cycles are given as a fraction of
+//! `0xffff`, which is 100%, whatever the precision of the hardware.
+//!
+//! - `0`: Return the number of PWM pins.
+//! - `1`: Reserve pin `arg1`.
+//! - `2`: Release pin `arg1`.
+//! - `3`: Start a signal on pin `arg1 & 0xffff` with duty cycle `arg1 >> 16`
+//!   and frequency `arg2` Hz.
+//! - `4`: Stop the signal on pin `arg1`.
+//! - `5`: Return the maximum frequency of pin `arg1`, in Hz.
+
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = 0x00000007;
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::hil::pwm::PwmPin;
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Duty cycle that apps use for 100%
+const APP_MAX_DUTY_CYCLE: usize = 0xffff;
+
+/// Most pins that can be exposed, one for each bit of `App::reserved`
+const MAX_PINS: usize = 32;
+
+#[derive(Default)]
+pub struct App {
+    /// Bit `n` is set if the app holds pin `n`
+    reserved: u32,
+}
+
+pub struct Pwm<'a> {
+    pins: &'a [&'a PwmPin],
+    apps: Grant<App>,
+    /// Bit `n` is set if pin `n` was started and not stopped since
+    running: Cell<u32>,
+}
+
+impl<'a> Pwm<'a> {
+    pub fn new(pins: &'a [&'a PwmPin], grant: Grant<App>) -> Pwm<'a> {
+        Pwm {
+            pins: &pins[..min(pins.len(), MAX_PINS)],
+            apps: grant,
+            running: Cell::new(0),
+        }
+    }
+
+    /// Stop the pins left running by apps that no longer hold them, because
+    /// they exited or were restarted
+    fn stop_abandoned_pins(&self) {
+        let held = self.apps
+            .iter()
+            .fold(0, |held, app| held | app.enter(|app, _| app.reserved));
+        let abandoned = self.running.get() & !held;
+        for (pin, pwm_pin) in self.pins.iter().enumerate() {
+            if abandoned & (1 << pin) != 0 {
+                pwm_pin.stop();
+            }
+        }
+        self.running.set(self.running.get() & held);
+    }
+
+    /// Whether any app other than `appid` holds the pin
+    fn reserved_by_other(&self, pin: usize, appid: AppId) -> bool {
+        self.apps.iter().any(|app| {
+            app.enter(|app, _| app.appid() != appid && app.reserved & (1 << pin) != 0)
+        })
+    }
+
+    /// Run `f` on the pin if the app holds it
+    fn with_reserved_pin<F>(&self, pin: usize, appid: AppId, f: F) -> ReturnCode
+    where
+        F: FnOnce(&PwmPin) -> ReturnCode,
+    {
+        if pin >= self.pins.len() {
+            return ReturnCode::EINVAL;
+        }
+        let reserved = self.apps
+            .enter(appid, |app, _| app.reserved & (1 << pin) != 0)
+            .unwrap_or(false);
+        if reserved {
+            f(self.pins[pin])
+        } else {
+            ReturnCode::ERESERVE
+        }
+    }
+}
+
+impl<'a> Driver for Pwm<'a> {
+    /// Control the PWM pins.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Number of pins.
+    /// - `1`: Reserve a pin.
+    /// - `2`: Release a pin.
+    /// - `3`: Start a signal.
+    /// - `4`: Stop a signal.
+    /// - `5`: Maximum frequency of a pin.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.pins.len(),
+            },
+
+            1 => {
+                let pin = arg1;
+                if pin >= self.pins.len() {
+                    return ReturnCode::EINVAL;
+                }
+                self.stop_abandoned_pins();
+                if self.reserved_by_other(pin, appid) {
+                    return ReturnCode::EBUSY;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.reserved |= 1 << pin;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            2 => {
+                let pin = arg1;
+                let result = self.with_reserved_pin(pin, appid, |pwm_pin| {
+                    // The pin may not be running, which is fine
+                    pwm_pin.stop();
+                    self.running.set(self.running.get() & !(1 << pin));
+                    ReturnCode::SUCCESS
+                });
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.reserved &= !(1 << pin);
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            3 => {
+                let pin = arg1 & 0xffff;
+                let duty_cycle = arg1 >> 16;
+                let frequency_hz = arg2;
+                self.with_reserved_pin(pin, appid, |pwm_pin| {
+                    if frequency_hz > pwm_pin.get_maximum_frequency_hz() {
+                        return ReturnCode::EINVAL;
+                    }
+                    let duty_cycle = (duty_cycle as u64
+                        * pwm_pin.get_maximum_duty_cycle() as u64
+                        / APP_MAX_DUTY_CYCLE as u64) as usize;
+                    let result = pwm_pin.start(frequency_hz, duty_cycle);
+                    if result == ReturnCode::SUCCESS {
+                        self.running.set(self.running.get() | 1 << pin);
+                    }
+                    result
+                })
+            }
+
+            4 => {
+                let pin = arg1;
+                self.with_reserved_pin(pin, appid, |pwm_pin| {
+                    let result = pwm_pin.stop();
+                    if result == ReturnCode::SUCCESS {
+                        self.running.set(self.running.get() & !(1 << pin));
+                    }
+                    result
+                })
+            }
+
+            5 => {
+                if arg1 >= self.pins.len() {
+                    return ReturnCode::EINVAL;
+                }
+                ReturnCode::SuccessWithValue {
+                    value: self.pins[arg1].get_maximum_frequency_hz(),
+                }
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
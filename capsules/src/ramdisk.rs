@@ -0,0 +1,203 @@
+//! Block storage backed by a RAM buffer.
+//!
+//! This is useful for testing users of `hil::block_storage::BlockStorage`
+//! without wearing out real storage, and for scratch filesystems whose contents
+//! do not need to survive a reset. The disk starts out erased, and erased
+//! blocks read back as `0xFF`, like flash.
+//!
+//! Operations complete immediately, but the callbacks are issued from an alarm
+//! so that clients are never called back from within their own request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub static mut RAMDISK_BUFFER: [u8; 8192] = [0; 8192];
+//! let ramdisk_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let ramdisk = static_init!(
+//!     capsules::ramdisk::RamDisk<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::ramdisk::RamDisk::new(ramdisk_alarm, &mut RAMDISK_BUFFER, 512));
+//! ramdisk_alarm.set_client(ramdisk);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::take_cell::TakeCell;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// The operation waiting for the alarm to complete it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    Idle,
+    Read { block: usize, count: usize },
+    Write { block: usize, count: usize },
+    Erase { block: usize, count: usize },
+}
+
+pub struct RamDisk<'a, A: hil::time::Alarm + 'a> {
+    alarm: &'a A,
+    /// Backing memory for the disk.
+    disk: TakeCell<'static, [u8]>,
+    block_size: usize,
+    block_count: usize,
+    client: Cell<Option<&'static hil::block_storage::Client>>,
+    operation: Cell<Operation>,
+    /// Buffer from the client for the current read or write.
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: hil::time::Alarm + 'a> RamDisk<'a, A> {
+    /// Create a RAM disk using `disk` as storage, which is erased. Any part of
+    /// `disk` that does not fill a whole block of `block_size` bytes is unused.
+    pub fn new(alarm: &'a A, disk: &'static mut [u8], block_size: usize) -> RamDisk<'a, A> {
+        let block_count = if block_size == 0 {
+            0
+        } else {
+            disk.len() / block_size
+        };
+        for byte in disk.iter_mut() {
+            *byte = 0xFF;
+        }
+        RamDisk {
+            alarm: alarm,
+            disk: TakeCell::new(disk),
+            block_size: block_size,
+            block_count: block_count,
+            client: Cell::new(None),
+            operation: Cell::new(Operation::Idle),
+            buffer: TakeCell::empty(),
+        }
+    }
+
+    /// Check that no operation is in progress and that the range is on the
+    /// disk.
+    fn check(&self, block: usize, count: usize) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            ReturnCode::EBUSY
+        } else if count == 0
+            || block
+                .checked_add(count)
+                .map_or(true, |end| end > self.block_count)
+        {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Check a read or write request, whose buffer must hold `count` blocks.
+    fn check_buffer(&self, buffer: &[u8], block: usize, count: usize) -> ReturnCode {
+        let rval = self.check(block, count);
+        if rval != ReturnCode::SUCCESS {
+            rval
+        } else if buffer.len() < count * self.block_size {
+            ReturnCode::ESIZE
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    /// Schedule the alarm that will complete a checked request.
+    fn start(&self, operation: Operation) {
+        self.operation.set(operation);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+    }
+}
+
+impl<'a, A: hil::time::Alarm + 'a> hil::block_storage::BlockStorage for RamDisk<'a, A> {
+    fn set_client(&self, client: &'static hil::block_storage::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    fn read_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        let rval = self.check_buffer(buffer, block, count);
+        if rval == ReturnCode::SUCCESS {
+            self.buffer.replace(buffer);
+            self.start(Operation::Read {
+                block: block,
+                count: count,
+            });
+        }
+        rval
+    }
+
+    fn write_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        let rval = self.check_buffer(buffer, block, count);
+        if rval == ReturnCode::SUCCESS {
+            self.buffer.replace(buffer);
+            self.start(Operation::Write {
+                block: block,
+                count: count,
+            });
+        }
+        rval
+    }
+
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode {
+        let rval = self.check(block, count);
+        if rval == ReturnCode::SUCCESS {
+            self.start(Operation::Erase {
+                block: block,
+                count: count,
+            });
+        }
+        rval
+    }
+}
+
+impl<'a, A: hil::time::Alarm + 'a> hil::time::Client for RamDisk<'a, A> {
+    fn fired(&self) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+
+        match operation {
+            Operation::Idle => {}
+            Operation::Read { block, count } => {
+                self.buffer.take().map(|buffer| {
+                    let start = block * self.block_size;
+                    let len = count * self.block_size;
+                    self.disk.map(|disk| {
+                        buffer[..len].copy_from_slice(&disk[start..start + len]);
+                    });
+                    self.client
+                        .get()
+                        .map(move |client| client.read_done(buffer, count, ReturnCode::SUCCESS));
+                });
+            }
+            Operation::Write { block, count } => {
+                self.buffer.take().map(|buffer| {
+                    let start = block * self.block_size;
+                    let len = count * self.block_size;
+                    self.disk.map(|disk| {
+                        disk[start..start + len].copy_from_slice(&buffer[..len]);
+                    });
+                    self.client
+                        .get()
+                        .map(move |client| client.write_done(buffer, count, ReturnCode::SUCCESS));
+                });
+            }
+            Operation::Erase { block, count } => {
+                let start = block * self.block_size;
+                let len = count * self.block_size;
+                self.disk.map(|disk| {
+                    for byte in disk[start..start + len].iter_mut() {
+                        *byte = 0xFF;
+                    }
+                });
+                self.client
+                    .get()
+                    .map(|client| client.erase_done(count, ReturnCode::SUCCESS));
+            }
+        }
+    }
+}
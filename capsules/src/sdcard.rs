@@ -1,6 +1,8 @@
 //! Provides driver for accessing an SD Card and a userspace Driver.
 //!
-//! This allows initialization and block reads or writes on top of SPI.
+//! This allows initialization and block reads or writes on top of SPI. The
+//! `SDCard` also implements `hil::block_storage::BlockStorage` so that other
+//! capsules can use it as a generic storage device.
 //!
 //! Usage
 //! -----
@@ -67,6 +69,7 @@ pub struct SDCard<'a, A: hil::time::Alarm + 'a> {
 
     is_initialized: Cell<bool>,
     card_type: Cell<SDCardType>,
+    total_size: Cell<u64>,
 
     detect_pin: Cell<Option<&'static hil::gpio::Pin>>,
 
@@ -76,6 +79,9 @@ pub struct SDCard<'a, A: hil::time::Alarm + 'a> {
     client: Cell<Option<&'static SDCardClient>>,
     client_buffer: TakeCell<'static, [u8]>,
     client_offset: Cell<usize>,
+
+    block_client: Cell<Option<&'static hil::block_storage::Client>>,
+    block_op: Cell<BlockOp>,
 }
 
 /// SD card command codes
@@ -164,6 +170,14 @@ enum ErrorCode {
     TimeoutFailure = -5,
 }
 
+/// Which `BlockStorage` operation, if any, the current transaction belongs to
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BlockOp {
+    None,
+    Read { count: usize },
+    Write { count: usize },
+}
+
 /// SD card types, determined during initialization
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum SDCardType {
@@ -175,6 +189,7 @@ enum SDCardType {
 }
 
 // Constants used in driver
+const BLOCK_SIZE: usize = 512;
 const SUCCESS_STATUS: u8 = 0x00;
 const INITIALIZING_STATUS: u8 = 0x01;
 const DATA_TOKEN: u8 = 0xFE;
@@ -231,12 +246,77 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
             alarm_count: Cell::new(0),
             is_initialized: Cell::new(false),
             card_type: Cell::new(SDCardType::Uninitialized),
+            total_size: Cell::new(0),
             detect_pin: Cell::new(pin),
             txbuffer: TakeCell::new(txbuffer),
             rxbuffer: TakeCell::new(rxbuffer),
             client: Cell::new(None),
             client_buffer: TakeCell::empty(),
             client_offset: Cell::new(0),
+            block_client: Cell::new(None),
+            block_op: Cell::new(BlockOp::None),
+        }
+    }
+
+    /// return a finished read to whichever client requested it
+    fn complete_read(&self, buffer: &'static mut [u8], len: usize) {
+        match self.block_op.get() {
+            BlockOp::Read { count } => {
+                self.block_op.set(BlockOp::None);
+                self.block_client.get().map(move |client| {
+                    client.read_done(buffer, count, ReturnCode::SUCCESS);
+                });
+            }
+            _ => {
+                self.client.get().map(move |client| {
+                    client.read_done(buffer, len);
+                });
+            }
+        }
+    }
+
+    /// return a finished write to whichever client requested it
+    fn complete_write(&self, buffer: &'static mut [u8]) {
+        match self.block_op.get() {
+            BlockOp::Write { count } => {
+                self.block_op.set(BlockOp::None);
+                self.block_client.get().map(move |client| {
+                    client.write_done(buffer, count, ReturnCode::SUCCESS);
+                });
+            }
+            _ => {
+                self.client.get().map(move |client| {
+                    client.write_done(buffer);
+                });
+            }
+        }
+    }
+
+    /// report a failed transaction. Block storage clients get their buffer
+    /// back, while SDCardClients are only notified of the error
+    fn report_error(&self, error: ErrorCode) {
+        let op = self.block_op.get();
+        self.block_op.set(BlockOp::None);
+        match op {
+            BlockOp::Read { .. } => {
+                self.client_buffer.take().map(move |buffer| {
+                    self.block_client.get().map(move |client| {
+                        client.read_done(buffer, 0, ReturnCode::FAIL);
+                    });
+                });
+            }
+            BlockOp::Write { .. } => {
+                self.client_buffer.take().map(move |buffer| {
+                    self.block_client.get().map(move |client| {
+                        client.write_done(buffer, 0, ReturnCode::FAIL);
+                    });
+                });
+            }
+            BlockOp::None => {
+                self.client.get().map(move |client| {
+                    client.error(error as u32);
+                });
+            }
         }
     }
 
@@ -444,9 +524,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -515,9 +593,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -544,9 +620,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -612,9 +686,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -651,9 +723,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -674,9 +744,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -724,6 +792,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     // initialization complete
                     self.state.set(SpiState::Idle);
                     self.is_initialized.set(true);
+                    self.total_size.set(total_size);
 
                     // perform callback
                     self.client.get().map(move |client| {
@@ -736,9 +805,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::InitializationFailure as u32);
-                    });
+                    self.report_error(ErrorCode::InitializationFailure);
                 }
             }
 
@@ -763,9 +830,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -794,9 +859,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -820,9 +883,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
 
                         // callback
                         let read_len = cmp::min(read_buffer.len(), cmp::min(buffer.len(), 512));
-                        self.client.get().map(move |client| {
-                            client.read_done(buffer, read_len);
-                        });
+                        self.complete_read(buffer, read_len);
                     });
                 });
             }
@@ -853,9 +914,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -904,9 +963,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
 
                     // read finished, perform callback
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.get().map(move |client| {
-                            client.read_done(buffer, self.client_offset.get());
-                        });
+                        self.complete_read(buffer, self.client_offset.get());
                     });
                 } else {
                     // error, send callback and quit
@@ -915,9 +972,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::ReadFailure as u32);
-                    });
+                    self.report_error(ErrorCode::ReadFailure);
                 }
             }
 
@@ -968,9 +1023,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                         self.state.set(SpiState::Idle);
                         self.alarm_state.set(AlarmState::Idle);
                         self.alarm_count.set(0);
-                        self.client.get().map(move |client| {
-                            client.error(ErrorCode::WriteFailure as u32);
-                        });
+                        self.report_error(ErrorCode::WriteFailure);
                     }
                 } else {
                     // error, send callback and quit
@@ -979,9 +1032,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.report_error(ErrorCode::WriteFailure);
                 }
             }
 
@@ -1003,9 +1054,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_state.set(AlarmState::Idle);
                     self.alarm_count.set(0);
-                    self.client.get().map(move |client| {
-                        client.error(ErrorCode::WriteFailure as u32);
-                    });
+                    self.report_error(ErrorCode::WriteFailure);
                 }
             }
 
@@ -1020,9 +1069,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
                     self.state.set(SpiState::Idle);
                     self.alarm_count.set(0);
                     self.client_buffer.take().map(move |buffer| {
-                        self.client.get().map(move |client| {
-                            client.write_done(buffer);
-                        });
+                        self.complete_write(buffer);
                     });
                 } else {
                     // replace buffers
@@ -1056,9 +1103,7 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
             self.alarm_count.set(0);
-            self.client.get().map(move |client| {
-                client.error(ErrorCode::TimeoutFailure as u32);
-            });
+            self.report_error(ErrorCode::TimeoutFailure);
         } else {
             self.alarm_count.set(repeats + 1);
         }
@@ -1328,6 +1373,86 @@ impl<'a, A: hil::time::Alarm + 'a> SDCard<'a, A> {
     }
 }
 
+/// Block storage interface to the SD card, so that other capsules can use it
+/// without depending on the SD card specifics. Multi-block writes are not
+/// supported by the card driver yet, so `write_blocks` only accepts a `count`
+/// of 1.
+impl<'a, A: hil::time::Alarm + 'a> hil::block_storage::BlockStorage for SDCard<'a, A> {
+    fn set_client(&self, client: &'static hil::block_storage::Client) {
+        self.block_client.set(Some(client));
+    }
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> usize {
+        if self.is_initialized() {
+            (self.total_size.get() / BLOCK_SIZE as u64) as usize
+        } else {
+            0
+        }
+    }
+
+    fn read_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        if self.block_op.get() != BlockOp::None || self.state.get() != SpiState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if count == 0
+            || block
+                .checked_add(count)
+                .map_or(true, |end| end > self.block_count())
+        {
+            return ReturnCode::EINVAL;
+        }
+        if count
+            .checked_mul(BLOCK_SIZE)
+            .map_or(true, |len| buffer.len() < len)
+        {
+            return ReturnCode::ESIZE;
+        }
+
+        let rval = SDCard::read_blocks(self, buffer, block as u32, count as u32);
+        if rval == ReturnCode::SUCCESS {
+            self.block_op.set(BlockOp::Read { count: count });
+        }
+        rval
+    }
+
+    fn write_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode {
+        if self.block_op.get() != BlockOp::None || self.state.get() != SpiState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if count == 0
+            || block
+                .checked_add(count)
+                .map_or(true, |end| end > self.block_count())
+        {
+            return ReturnCode::EINVAL;
+        }
+        if count > 1 {
+            return ReturnCode::ENOSUPPORT;
+        }
+        if count
+            .checked_mul(BLOCK_SIZE)
+            .map_or(true, |len| buffer.len() < len)
+        {
+            return ReturnCode::ESIZE;
+        }
+
+        let rval = SDCard::write_blocks(self, buffer, block as u32, count as u32);
+        if rval == ReturnCode::SUCCESS {
+            self.block_op.set(BlockOp::Write { count: count });
+        }
+        rval
+    }
+
+    fn erase_blocks(&self, _block: usize, _count: usize) -> ReturnCode {
+        // SD cards manage erasing internally
+        ReturnCode::ENOSUPPORT
+    }
+}
+
 /// Handle callbacks from the SPI peripheral
 impl<'a, A: hil::time::Alarm + 'a> hil::spi::SpiMasterClient for SDCard<'a, A> {
     fn read_write_done(
@@ -1359,9 +1484,7 @@ impl<'a, A: hil::time::Alarm + 'a> hil::gpio::Client for SDCard<'a, A> {
             //  send an error callback
             self.state.set(SpiState::Idle);
             self.alarm_state.set(AlarmState::Idle);
-            self.client.get().map(move |client| {
-                client.error(ErrorCode::CardStateChanged as u32);
-            });
+            self.report_error(ErrorCode::CardStateChanged);
         }
 
         // either the card is new or gone, in either case it isn't initialized
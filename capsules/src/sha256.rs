@@ -0,0 +1,544 @@
+//! Software SHA-256 and HMAC-SHA256.
+//!
+//! `Sha256Software` implements `hil::digest::Digest` in software, for chips
+//! without a hash accelerator. Data is hashed when the alarm fires rather than
+//! within `add_data()` and `run()`, so clients are never called back from
+//! within their own request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sha256_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let sha256 = static_init!(
+//!     capsules::sha256::Sha256Software<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::sha256::Sha256Software::new(sha256_alarm));
+//! sha256_alarm.set_client(sha256);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::take_cell::{MapCell, TakeCell};
+use kernel::hil;
+use kernel::hil::digest::{SHA256_BLOCK_SIZE, SHA256_OUTPUT_SIZE};
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+const HMAC_IPAD: u8 = 0x36;
+const HMAC_OPAD: u8 = 0x5c;
+
+/// The round constants (FIPS 180-4 section 4.2.2)
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The initial hash value (FIPS 180-4 section 5.3.3)
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The state of a SHA-256 computation.
+struct State {
+    h: [u32; 8],
+    /// Data not yet compressed, always less than a block
+    block: [u8; SHA256_BLOCK_SIZE],
+    block_len: usize,
+    /// Total message length in bytes
+    length: u64,
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            h: H0,
+            block: [0; SHA256_BLOCK_SIZE],
+            block_len: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let n = cmp::min(SHA256_BLOCK_SIZE - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == SHA256_BLOCK_SIZE {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// Pad the message and write the digest to `out`.
+    fn finish(&mut self, out: &mut [u8]) {
+        let bit_length = self.length.wrapping_mul(8);
+
+        self.block[self.block_len] = 0x80;
+        self.block_len += 1;
+        if self.block_len > SHA256_BLOCK_SIZE - 8 {
+            for b in self.block[self.block_len..].iter_mut() {
+                *b = 0;
+            }
+            self.compress();
+            self.block_len = 0;
+        }
+        for b in self.block[self.block_len..SHA256_BLOCK_SIZE - 8].iter_mut() {
+            *b = 0;
+        }
+        for i in 0..8 {
+            self.block[SHA256_BLOCK_SIZE - 8 + i] = (bit_length >> (56 - 8 * i)) as u8;
+        }
+        self.compress();
+
+        for (i, word) in self.h.iter().enumerate() {
+            for j in 0..4 {
+                out[4 * i + j] = (word >> (24 - 8 * j)) as u8;
+            }
+        }
+    }
+
+    /// Process the block (FIPS 180-4 section 6.2.2)
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (self.block[4 * i] as u32) << 24 | (self.block[4 * i + 1] as u32) << 16
+                | (self.block[4 * i + 2] as u32) << 8 | self.block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = self.h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v[7] = v[6];
+            v[6] = v[5];
+            v[5] = v[4];
+            v[4] = v[3].wrapping_add(t1);
+            v[3] = v[2];
+            v[2] = v[1];
+            v[1] = v[0];
+            v[0] = t1.wrapping_add(t2);
+        }
+
+        for i in 0..8 {
+            self.h[i] = self.h[i].wrapping_add(v[i]);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    None,
+    Sha256,
+    HmacSha256,
+}
+
+/// The operation waiting for the alarm to complete it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operation {
+    Idle,
+    AddData,
+    Run,
+}
+
+pub struct Sha256Software<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    client: Cell<Option<&'a hil::digest::Client>>,
+    mode: Cell<Mode>,
+    operation: Cell<Operation>,
+    state: MapCell<State>,
+    /// The HMAC key, padded to a block
+    hmac_key: MapCell<[u8; SHA256_BLOCK_SIZE]>,
+    data: TakeCell<'static, [u8]>,
+    data_len: Cell<usize>,
+    digest: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm + 'a> Sha256Software<'a, A> {
+    pub fn new(alarm: &'a A) -> Sha256Software<'a, A> {
+        Sha256Software {
+            alarm: alarm,
+            client: Cell::new(None),
+            mode: Cell::new(Mode::None),
+            operation: Cell::new(Operation::Idle),
+            state: MapCell::new(State::new()),
+            hmac_key: MapCell::new([0; SHA256_BLOCK_SIZE]),
+            data: TakeCell::empty(),
+            data_len: Cell::new(0),
+            digest: TakeCell::empty(),
+        }
+    }
+
+    /// Reset the state to start a message, which for HMAC begins with the
+    /// key padded with `pad`.
+    fn start_message(&self, pad: Option<u8>) {
+        self.state.map(|state| {
+            *state = State::new();
+            pad.map(|pad| {
+                self.hmac_key.map(|key| {
+                    let mut block = [0; SHA256_BLOCK_SIZE];
+                    for (b, k) in block.iter_mut().zip(key.iter()) {
+                        *b = k ^ pad;
+                    }
+                    state.update(&block);
+                });
+            });
+        });
+    }
+
+    /// Check that a new operation can start, and schedule the alarm that
+    /// will complete it.
+    fn start(&self, operation: Operation) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if self.mode.get() == Mode::None {
+            return ReturnCode::EOFF;
+        }
+        self.operation.set(operation);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        ReturnCode::SUCCESS
+    }
+
+    fn finish_digest(&self, digest: &mut [u8]) {
+        self.state.map(|state| state.finish(digest));
+        if self.mode.get() == Mode::HmacSha256 {
+            let mut inner = [0; SHA256_OUTPUT_SIZE];
+            inner.copy_from_slice(&digest[..SHA256_OUTPUT_SIZE]);
+            self.start_message(Some(HMAC_OPAD));
+            self.state.map(|state| {
+                state.update(&inner);
+                state.finish(digest);
+            });
+        }
+        // The message is finished, a new one must be started
+        self.mode.set(Mode::None);
+    }
+}
+
+impl<'a, A: Alarm + 'a> hil::digest::Digest<'a> for Sha256Software<'a, A> {
+    fn set_client(&'a self, client: &'a hil::digest::Client) {
+        self.client.set(Some(client));
+    }
+
+    fn add_data(
+        &self,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if len > data.len() {
+            return (ReturnCode::EINVAL, Some(data));
+        }
+        let result = self.start(Operation::AddData);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(data));
+        }
+        self.data.replace(data);
+        self.data_len.set(len);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn run(&self, digest: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if digest.len() < SHA256_OUTPUT_SIZE {
+            return (ReturnCode::EINVAL, Some(digest));
+        }
+        let result = self.start(Operation::Run);
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(digest));
+        }
+        self.digest.replace(digest);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn clear_data(&self) {
+        self.mode.set(Mode::None);
+        self.state.map(|state| *state = State::new());
+        self.hmac_key.map(|key| {
+            for b in key.iter_mut() {
+                *b = 0;
+            }
+        });
+    }
+}
+
+impl<'a, A: Alarm + 'a> hil::digest::Sha256 for Sha256Software<'a, A> {
+    fn set_mode_sha256(&self) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.mode.set(Mode::Sha256);
+        self.start_message(None);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm + 'a> hil::digest::HmacSha256 for Sha256Software<'a, A> {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> ReturnCode {
+        if self.operation.get() != Operation::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        // Keys longer than a block are replaced by their hash
+        self.hmac_key.map(|padded_key| {
+            for b in padded_key.iter_mut() {
+                *b = 0;
+            }
+            if key.len() > SHA256_BLOCK_SIZE {
+                let mut state = State::new();
+                state.update(key);
+                state.finish(&mut padded_key[..SHA256_OUTPUT_SIZE]);
+            } else {
+                padded_key[..key.len()].copy_from_slice(key);
+            }
+        });
+        self.mode.set(Mode::HmacSha256);
+        self.start_message(Some(HMAC_IPAD));
+        ReturnCode::SUCCESS
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for Sha256Software<'a, A> {
+    fn fired(&self) {
+        let operation = self.operation.get();
+        self.operation.set(Operation::Idle);
+        match operation {
+            Operation::Idle => {}
+            Operation::AddData => {
+                self.data.take().map(|data| {
+                    let len = self.data_len.get();
+                    self.state.map(|state| state.update(&data[..len]));
+                    self.client
+                        .get()
+                        .map(move |client| client.add_data_done(ReturnCode::SUCCESS, data));
+                });
+            }
+            Operation::Run => {
+                self.digest.take().map(|digest| {
+                    self.finish_digest(digest);
+                    self.client
+                        .get()
+                        .map(move |client| client.hash_done(ReturnCode::SUCCESS, digest));
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use self::std::boxed::Box;
+    use super::*;
+    use kernel::hil::digest::{Digest, HmacSha256, Sha256};
+    use kernel::hil::time::Client;
+
+    struct TestAlarm;
+
+    impl time::Time for TestAlarm {
+        type Frequency = time::Freq1KHz;
+
+        fn disable(&self) {}
+
+        fn is_armed(&self) -> bool {
+            false
+        }
+    }
+
+    impl Alarm for TestAlarm {
+        fn now(&self) -> u32 {
+            0
+        }
+
+        fn set_alarm(&self, _tics: u32) {}
+
+        fn get_alarm(&self) -> u32 {
+            0
+        }
+    }
+
+    struct TestClient {
+        data: TakeCell<'static, [u8]>,
+        digest: TakeCell<'static, [u8]>,
+    }
+
+    impl hil::digest::Client for TestClient {
+        fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]) {
+            assert_eq!(result, ReturnCode::SUCCESS);
+            self.data.replace(data);
+        }
+
+        fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]) {
+            assert_eq!(result, ReturnCode::SUCCESS);
+            self.digest.replace(digest);
+        }
+    }
+
+    /// Hash `message` repeated `repeat` times, adding it a block at a time
+    /// and firing the alarm by hand, with HMAC if there is a key.
+    fn hash(key: Option<&[u8]>, message: &[u8], repeat: usize) -> [u8; SHA256_OUTPUT_SIZE] {
+        let alarm = TestAlarm;
+        let client = TestClient {
+            data: TakeCell::new(Box::leak(Box::new([0; SHA256_BLOCK_SIZE]))),
+            digest: TakeCell::new(Box::leak(Box::new([0; SHA256_OUTPUT_SIZE]))),
+        };
+        let sha256 = Sha256Software::new(&alarm);
+        Digest::set_client(&sha256, &client);
+
+        let result = match key {
+            Some(key) => sha256.set_mode_hmacsha256(key),
+            None => sha256.set_mode_sha256(),
+        };
+        assert_eq!(result, ReturnCode::SUCCESS);
+        for _ in 0..repeat {
+            for chunk in message.chunks(SHA256_BLOCK_SIZE) {
+                let data = client.data.take().unwrap();
+                data[..chunk.len()].copy_from_slice(chunk);
+                assert_eq!(sha256.add_data(data, chunk.len()).0, ReturnCode::SUCCESS);
+                sha256.fired();
+            }
+        }
+        assert_eq!(sha256.run(client.digest.take().unwrap()).0, ReturnCode::SUCCESS);
+        sha256.fired();
+
+        let mut digest = [0; SHA256_OUTPUT_SIZE];
+        client.digest.map(|d| digest.copy_from_slice(d));
+        digest
+    }
+
+    fn from_hex(hex: &str) -> [u8; SHA256_OUTPUT_SIZE] {
+        let mut out = [0; SHA256_OUTPUT_SIZE];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // FIPS 180-4 examples (NIST CSRC "Examples with Intermediate Values")
+
+    #[test]
+    fn sha256_one_block() {
+        assert_eq!(
+            hash(None, b"abc", 1),
+            from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+    }
+
+    #[test]
+    fn sha256_two_blocks() {
+        assert_eq!(
+            hash(
+                None,
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                1
+            ),
+            from_hex("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1")
+        );
+    }
+
+    #[test]
+    fn sha256_empty() {
+        assert_eq!(
+            hash(None, b"", 1),
+            from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+    }
+
+    #[test]
+    fn sha256_million_a() {
+        assert_eq!(
+            hash(None, &[b'a'; 1000], 1000),
+            from_hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0")
+        );
+    }
+
+    // RFC 4231 test cases; case 5 tests truncation, which the HIL lacks
+
+    #[test]
+    fn hmac_case_1() {
+        assert_eq!(
+            hash(Some(&[0x0b; 20]), b"Hi There", 1),
+            from_hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+        );
+    }
+
+    #[test]
+    fn hmac_case_2() {
+        assert_eq!(
+            hash(Some(b"Jefe"), b"what do ya want for nothing?", 1),
+            from_hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+    }
+
+    #[test]
+    fn hmac_case_3() {
+        assert_eq!(
+            hash(Some(&[0xaa; 20]), &[0xdd; 50], 1),
+            from_hex("773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe")
+        );
+    }
+
+    #[test]
+    fn hmac_case_4() {
+        let key: [u8; 25] = [
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+            0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19,
+        ];
+        assert_eq!(
+            hash(Some(&key), &[0xcd; 50], 1),
+            from_hex("82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b")
+        );
+    }
+
+    #[test]
+    fn hmac_case_6() {
+        assert_eq!(
+            hash(
+                Some(&[0xaa; 131]),
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                1
+            ),
+            from_hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+    }
+
+    #[test]
+    fn hmac_case_7() {
+        assert_eq!(
+            hash(
+                Some(&[0xaa; 131]),
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                1
+            ),
+            from_hex("9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2")
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Software CRC computation.
+//!
+//! `SoftwareCrc` implements `hil::crc::CRC` with byte-wise lookup tables, for
+//! boards whose chip has no CRC unit. It gives the same results as the SAM4L
+//! CRCCU for every `CrcAlg`, including the `Sam4L*` variants without output
+//! post-processing, so `capsules::crc` behaves the same on all boards.
+//!
+//! Like the CRCCU, the computation starts from an all-ones register and
+//! consumes each input byte from LSB to MSB. The register is kept bit-reflected
+//! here, which is why the `Sam4L*` results are reversed at the end while the
+//! standard CRC-32 and CRC-32C results only need inverting.
+//!
+//! The CRC is computed within `compute()`, but the result is delivered from an
+//! alarm so that clients are never called back from within their own request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let crc_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let sw_crc = static_init!(
+//!     capsules::sw_crc::SoftwareCrc<'static, VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>,
+//!     capsules::sw_crc::SoftwareCrc::new(crc_alarm));
+//! crc_alarm.set_client(sw_crc);
+//! let crc = static_init!(
+//!     capsules::crc::Crc<'static, capsules::sw_crc::SoftwareCrc<'static,
+//!         VirtualMuxAlarm<'static, nrf5x::rtc::Rtc>>>,
+//!     capsules::crc::Crc::new(sw_crc, kernel::Grant::create()));
+//! sw_crc.set_client(crc);
+//! ```
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::crc::CrcAlg;
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+pub struct SoftwareCrc<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    client: Cell<Option<&'a hil::crc::Client>>,
+    /// The result waiting for the alarm to deliver it
+    result: Cell<Option<u32>>,
+}
+
+impl<'a, A: Alarm + 'a> SoftwareCrc<'a, A> {
+    pub fn new(alarm: &'a A) -> SoftwareCrc<'a, A> {
+        SoftwareCrc {
+            alarm: alarm,
+            client: Cell::new(None),
+            result: Cell::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a hil::crc::Client) {
+        self.client.set(Some(client));
+    }
+}
+
+/// Compute the CRC of `data` with `alg`, as the SAM4L CRCCU would.
+pub fn crc(data: &[u8], alg: CrcAlg) -> u32 {
+    match alg {
+        CrcAlg::Crc32 => !crc32_reflected(&CRC32_TABLE, data),
+        CrcAlg::Crc32C => !crc32_reflected(&CRC32C_TABLE, data),
+        CrcAlg::Sam4L16 => 0xffff0000 | reverse(crc16_reflected(&CRC16_TABLE, data) as u32, 16),
+        CrcAlg::Sam4L32 => reverse(crc32_reflected(&CRC32_TABLE, data), 32),
+        CrcAlg::Sam4L32C => reverse(crc32_reflected(&CRC32C_TABLE, data), 32),
+    }
+}
+
+/// Reverse the low `bits` bits of `n`.
+fn reverse(n: u32, bits: u32) -> u32 {
+    (0..bits).fold(0, |out, i| out | ((n >> i) & 1) << (bits - 1 - i))
+}
+
+fn crc32_reflected(table: &[u32; 256], data: &[u8]) -> u32 {
+    data.iter().fold(0xffffffff, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn crc16_reflected(table: &[u16; 256], data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, &byte| {
+        table[((crc ^ byte as u16) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+impl<'a, A: Alarm + 'a> hil::crc::CRC for SoftwareCrc<'a, A> {
+    fn compute(&self, data: &[u8], alg: CrcAlg) -> ReturnCode {
+        if self.result.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.result.set(Some(crc(data, alg)));
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(1));
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) {
+        // Nothing to power down
+    }
+}
+
+impl<'a, A: Alarm + 'a> time::Client for SoftwareCrc<'a, A> {
+    fn fired(&self) {
+        self.result.take().map(|result| {
+            self.client.get().map(|client| client.receive_result(result));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The standard check input, whose CRC is an algorithm's check value
+    const CHECK_INPUT: &'static [u8] = b"123456789";
+
+    /// Compute a CRC bit by bit from the polynomial, without the tables,
+    /// keeping the register unreflected as the CRCCU does.
+    fn crc_bitwise(poly: u32, width: u32, data: &[u8]) -> u32 {
+        let top = 1 << (width - 1);
+        let mask = if width == 32 { 0xffffffff } else { (1 << width) - 1 };
+        data.iter().fold(mask, |crc, &byte| {
+            (0..8).fold(crc, |crc, i| {
+                let bit = (byte as u32 >> i) & 1;
+                let crc = if (crc & top != 0) != (bit != 0) {
+                    (crc << 1) ^ poly
+                } else {
+                    crc << 1
+                };
+                crc & mask
+            })
+        })
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc(CHECK_INPUT, CrcAlg::Crc32), 0xcbf43926);
+        assert_eq!(crc(CHECK_INPUT, CrcAlg::Crc32C), 0xe3069283);
+        assert_eq!(crc(CHECK_INPUT, CrcAlg::Sam4L16), 0xffff89f6);
+        assert_eq!(crc(CHECK_INPUT, CrcAlg::Sam4L32), 0x9b63d02c);
+        assert_eq!(crc(CHECK_INPUT, CrcAlg::Sam4L32C), 0x3eb69f38);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(crc(&[], CrcAlg::Crc32), 0);
+        assert_eq!(crc(&[], CrcAlg::Crc32C), 0);
+        assert_eq!(crc(&[], CrcAlg::Sam4L16), 0xffffffff);
+        assert_eq!(crc(&[], CrcAlg::Sam4L32), 0xffffffff);
+        assert_eq!(crc(&[], CrcAlg::Sam4L32C), 0xffffffff);
+    }
+
+    #[test]
+    fn tables_match_polynomials() {
+        for i in 0..256 {
+            let byte = [i as u8];
+            assert_eq!(
+                reverse(crc32_reflected(&CRC32_TABLE, &byte), 32),
+                crc_bitwise(0x04c11db7, 32, &byte)
+            );
+            assert_eq!(
+                reverse(crc32_reflected(&CRC32C_TABLE, &byte), 32),
+                crc_bitwise(0x1edc6f41, 32, &byte)
+            );
+            assert_eq!(
+                reverse(crc16_reflected(&CRC16_TABLE, &byte) as u32, 16),
+                crc_bitwise(0x1021, 16, &byte)
+            );
+        }
+    }
+
+    #[test]
+    fn sam4l_results_match_polynomials() {
+        let data: &[u8] = b"The quick brown fox jumps over the lazy dog";
+        assert_eq!(
+            crc(data, CrcAlg::Sam4L16),
+            0xffff0000 | crc_bitwise(0x1021, 16, data)
+        );
+        assert_eq!(crc(data, CrcAlg::Sam4L32), crc_bitwise(0x04c11db7, 32, data));
+        assert_eq!(crc(data, CrcAlg::Sam4L32C), crc_bitwise(0x1edc6f41, 32, data));
+        assert_eq!(crc(data, CrcAlg::Crc32), 0x414fa339);
+    }
+}
+
+/// Byte table for polynomial `0x04C11DB7`, bit-reflected (`0xEDB88320`)
+const CRC32_TABLE: [u32; 256] = [
+    0x00000000, 0x77073096, 0xee0e612c, 0x990951ba, 0x076dc419, 0x706af48f,
+    0xe963a535, 0x9e6495a3, 0x0edb8832, 0x79dcb8a4, 0xe0d5e91e, 0x97d2d988,
+    0x09b64c2b, 0x7eb17cbd, 0xe7b82d07, 0x90bf1d91, 0x1db71064, 0x6ab020f2,
+    0xf3b97148, 0x84be41de, 0x1adad47d, 0x6ddde4eb, 0xf4d4b551, 0x83d385c7,
+    0x136c9856, 0x646ba8c0, 0xfd62f97a, 0x8a65c9ec, 0x14015c4f, 0x63066cd9,
+    0xfa0f3d63, 0x8d080df5, 0x3b6e20c8, 0x4c69105e, 0xd56041e4, 0xa2677172,
+    0x3c03e4d1, 0x4b04d447, 0xd20d85fd, 0xa50ab56b, 0x35b5a8fa, 0x42b2986c,
+    0xdbbbc9d6, 0xacbcf940, 0x32d86ce3, 0x45df5c75, 0xdcd60dcf, 0xabd13d59,
+    0x26d930ac, 0x51de003a, 0xc8d75180, 0xbfd06116, 0x21b4f4b5, 0x56b3c423,
+    0xcfba9599, 0xb8bda50f, 0x2802b89e, 0x5f058808, 0xc60cd9b2, 0xb10be924,
+    0x2f6f7c87, 0x58684c11, 0xc1611dab, 0xb6662d3d, 0x76dc4190, 0x01db7106,
+    0x98d220bc, 0xefd5102a, 0x71b18589, 0x06b6b51f, 0x9fbfe4a5, 0xe8b8d433,
+    0x7807c9a2, 0x0f00f934, 0x9609a88e, 0xe10e9818, 0x7f6a0dbb, 0x086d3d2d,
+    0x91646c97, 0xe6635c01, 0x6b6b51f4, 0x1c6c6162, 0x856530d8, 0xf262004e,
+    0x6c0695ed, 0x1b01a57b, 0x8208f4c1, 0xf50fc457, 0x65b0d9c6, 0x12b7e950,
+    0x8bbeb8ea, 0xfcb9887c, 0x62dd1ddf, 0x15da2d49, 0x8cd37cf3, 0xfbd44c65,
+    0x4db26158, 0x3ab551ce, 0xa3bc0074, 0xd4bb30e2, 0x4adfa541, 0x3dd895d7,
+    0xa4d1c46d, 0xd3d6f4fb, 0x4369e96a, 0x346ed9fc, 0xad678846, 0xda60b8d0,
+    0x44042d73, 0x33031de5, 0xaa0a4c5f, 0xdd0d7cc9, 0x5005713c, 0x270241aa,
+    0xbe0b1010, 0xc90c2086, 0x5768b525, 0x206f85b3, 0xb966d409, 0xce61e49f,
+    0x5edef90e, 0x29d9c998, 0xb0d09822, 0xc7d7a8b4, 0x59b33d17, 0x2eb40d81,
+    0xb7bd5c3b, 0xc0ba6cad, 0xedb88320, 0x9abfb3b6, 0x03b6e20c, 0x74b1d29a,
+    0xead54739, 0x9dd277af, 0x04db2615, 0x73dc1683, 0xe3630b12, 0x94643b84,
+    0x0d6d6a3e, 0x7a6a5aa8, 0xe40ecf0b, 0x9309ff9d, 0x0a00ae27, 0x7d079eb1,
+    0xf00f9344, 0x8708a3d2, 0x1e01f268, 0x6906c2fe, 0xf762575d, 0x806567cb,
+    0x196c3671, 0x6e6b06e7, 0xfed41b76, 0x89d32be0, 0x10da7a5a, 0x67dd4acc,
+    0xf9b9df6f, 0x8ebeeff9, 0x17b7be43, 0x60b08ed5, 0xd6d6a3e8, 0xa1d1937e,
+    0x38d8c2c4, 0x4fdff252, 0xd1bb67f1, 0xa6bc5767, 0x3fb506dd, 0x48b2364b,
+    0xd80d2bda, 0xaf0a1b4c, 0x36034af6, 0x41047a60, 0xdf60efc3, 0xa867df55,
+    0x316e8eef, 0x4669be79, 0xcb61b38c, 0xbc66831a, 0x256fd2a0, 0x5268e236,
+    0xcc0c7795, 0xbb0b4703, 0x220216b9, 0x5505262f, 0xc5ba3bbe, 0xb2bd0b28,
+    0x2bb45a92, 0x5cb36a04, 0xc2d7ffa7, 0xb5d0cf31, 0x2cd99e8b, 0x5bdeae1d,
+    0x9b64c2b0, 0xec63f226, 0x756aa39c, 0x026d930a, 0x9c0906a9, 0xeb0e363f,
+    0x72076785, 0x05005713, 0x95bf4a82, 0xe2b87a14, 0x7bb12bae, 0x0cb61b38,
+    0x92d28e9b, 0xe5d5be0d, 0x7cdcefb7, 0x0bdbdf21, 0x86d3d2d4, 0xf1d4e242,
+    0x68ddb3f8, 0x1fda836e, 0x81be16cd, 0xf6b9265b, 0x6fb077e1, 0x18b74777,
+    0x88085ae6, 0xff0f6a70, 0x66063bca, 0x11010b5c, 0x8f659eff, 0xf862ae69,
+    0x616bffd3, 0x166ccf45, 0xa00ae278, 0xd70dd2ee, 0x4e048354, 0x3903b3c2,
+    0xa7672661, 0xd06016f7, 0x4969474d, 0x3e6e77db, 0xaed16a4a, 0xd9d65adc,
+    0x40df0b66, 0x37d83bf0, 0xa9bcae53, 0xdebb9ec5, 0x47b2cf7f, 0x30b5ffe9,
+    0xbdbdf21c, 0xcabac28a, 0x53b39330, 0x24b4a3a6, 0xbad03605, 0xcdd70693,
+    0x54de5729, 0x23d967bf, 0xb3667a2e, 0xc4614ab8, 0x5d681b02, 0x2a6f2b94,
+    0xb40bbe37, 0xc30c8ea1, 0x5a05df1b, 0x2d02ef8d,
+];
+
+/// Byte table for polynomial `0x1EDC6F41`, bit-reflected (`0x82F63B78`)
+const CRC32C_TABLE: [u32; 256] = [
+    0x00000000, 0xf26b8303, 0xe13b70f7, 0x1350f3f4, 0xc79a971f, 0x35f1141c,
+    0x26a1e7e8, 0xd4ca64eb, 0x8ad958cf, 0x78b2dbcc, 0x6be22838, 0x9989ab3b,
+    0x4d43cfd0, 0xbf284cd3, 0xac78bf27, 0x5e133c24, 0x105ec76f, 0xe235446c,
+    0xf165b798, 0x030e349b, 0xd7c45070, 0x25afd373, 0x36ff2087, 0xc494a384,
+    0x9a879fa0, 0x68ec1ca3, 0x7bbcef57, 0x89d76c54, 0x5d1d08bf, 0xaf768bbc,
+    0xbc267848, 0x4e4dfb4b, 0x20bd8ede, 0xd2d60ddd, 0xc186fe29, 0x33ed7d2a,
+    0xe72719c1, 0x154c9ac2, 0x061c6936, 0xf477ea35, 0xaa64d611, 0x580f5512,
+    0x4b5fa6e6, 0xb93425e5, 0x6dfe410e, 0x9f95c20d, 0x8cc531f9, 0x7eaeb2fa,
+    0x30e349b1, 0xc288cab2, 0xd1d83946, 0x23b3ba45, 0xf779deae, 0x05125dad,
+    0x1642ae59, 0xe4292d5a, 0xba3a117e, 0x4851927d, 0x5b016189, 0xa96ae28a,
+    0x7da08661, 0x8fcb0562, 0x9c9bf696, 0x6ef07595, 0x417b1dbc, 0xb3109ebf,
+    0xa0406d4b, 0x522bee48, 0x86e18aa3, 0x748a09a0, 0x67dafa54, 0x95b17957,
+    0xcba24573, 0x39c9c670, 0x2a993584, 0xd8f2b687, 0x0c38d26c, 0xfe53516f,
+    0xed03a29b, 0x1f682198, 0x5125dad3, 0xa34e59d0, 0xb01eaa24, 0x42752927,
+    0x96bf4dcc, 0x64d4cecf, 0x77843d3b, 0x85efbe38, 0xdbfc821c, 0x2997011f,
+    0x3ac7f2eb, 0xc8ac71e8, 0x1c661503, 0xee0d9600, 0xfd5d65f4, 0x0f36e6f7,
+    0x61c69362, 0x93ad1061, 0x80fde395, 0x72966096, 0xa65c047d, 0x5437877e,
+    0x4767748a, 0xb50cf789, 0xeb1fcbad, 0x197448ae, 0x0a24bb5a, 0xf84f3859,
+    0x2c855cb2, 0xdeeedfb1, 0xcdbe2c45, 0x3fd5af46, 0x7198540d, 0x83f3d70e,
+    0x90a324fa, 0x62c8a7f9, 0xb602c312, 0x44694011, 0x5739b3e5, 0xa55230e6,
+    0xfb410cc2, 0x092a8fc1, 0x1a7a7c35, 0xe811ff36, 0x3cdb9bdd, 0xceb018de,
+    0xdde0eb2a, 0x2f8b6829, 0x82f63b78, 0x709db87b, 0x63cd4b8f, 0x91a6c88c,
+    0x456cac67, 0xb7072f64, 0xa457dc90, 0x563c5f93, 0x082f63b7, 0xfa44e0b4,
+    0xe9141340, 0x1b7f9043, 0xcfb5f4a8, 0x3dde77ab, 0x2e8e845f, 0xdce5075c,
+    0x92a8fc17, 0x60c37f14, 0x73938ce0, 0x81f80fe3, 0x55326b08, 0xa759e80b,
+    0xb4091bff, 0x466298fc, 0x1871a4d8, 0xea1a27db, 0xf94ad42f, 0x0b21572c,
+    0xdfeb33c7, 0x2d80b0c4, 0x3ed04330, 0xccbbc033, 0xa24bb5a6, 0x502036a5,
+    0x4370c551, 0xb11b4652, 0x65d122b9, 0x97baa1ba, 0x84ea524e, 0x7681d14d,
+    0x2892ed69, 0xdaf96e6a, 0xc9a99d9e, 0x3bc21e9d, 0xef087a76, 0x1d63f975,
+    0x0e330a81, 0xfc588982, 0xb21572c9, 0x407ef1ca, 0x532e023e, 0xa145813d,
+    0x758fe5d6, 0x87e466d5, 0x94b49521, 0x66df1622, 0x38cc2a06, 0xcaa7a905,
+    0xd9f75af1, 0x2b9cd9f2, 0xff56bd19, 0x0d3d3e1a, 0x1e6dcdee, 0xec064eed,
+    0xc38d26c4, 0x31e6a5c7, 0x22b65633, 0xd0ddd530, 0x0417b1db, 0xf67c32d8,
+    0xe52cc12c, 0x1747422f, 0x49547e0b, 0xbb3ffd08, 0xa86f0efc, 0x5a048dff,
+    0x8ecee914, 0x7ca56a17, 0x6ff599e3, 0x9d9e1ae0, 0xd3d3e1ab, 0x21b862a8,
+    0x32e8915c, 0xc083125f, 0x144976b4, 0xe622f5b7, 0xf5720643, 0x07198540,
+    0x590ab964, 0xab613a67, 0xb831c993, 0x4a5a4a90, 0x9e902e7b, 0x6cfbad78,
+    0x7fab5e8c, 0x8dc0dd8f, 0xe330a81a, 0x115b2b19, 0x020bd8ed, 0xf0605bee,
+    0x24aa3f05, 0xd6c1bc06, 0xc5914ff2, 0x37faccf1, 0x69e9f0d5, 0x9b8273d6,
+    0x88d28022, 0x7ab90321, 0xae7367ca, 0x5c18e4c9, 0x4f48173d, 0xbd23943e,
+    0xf36e6f75, 0x0105ec76, 0x12551f82, 0xe03e9c81, 0x34f4f86a, 0xc69f7b69,
+    0xd5cf889d, 0x27a40b9e, 0x79b737ba, 0x8bdcb4b9, 0x988c474d, 0x6ae7c44e,
+    0xbe2da0a5, 0x4c4623a6, 0x5f16d052, 0xad7d5351,
+];
+
+/// Byte table for polynomial `0x1021`, bit-reflected (`0x8408`)
+const CRC16_TABLE: [u16; 256] = [
+    0x0000, 0x1189, 0x2312, 0x329b, 0x4624, 0x57ad, 0x6536, 0x74bf,
+    0x8c48, 0x9dc1, 0xaf5a, 0xbed3, 0xca6c, 0xdbe5, 0xe97e, 0xf8f7,
+    0x1081, 0x0108, 0x3393, 0x221a, 0x56a5, 0x472c, 0x75b7, 0x643e,
+    0x9cc9, 0x8d40, 0xbfdb, 0xae52, 0xdaed, 0xcb64, 0xf9ff, 0xe876,
+    0x2102, 0x308b, 0x0210, 0x1399, 0x6726, 0x76af, 0x4434, 0x55bd,
+    0xad4a, 0xbcc3, 0x8e58, 0x9fd1, 0xeb6e, 0xfae7, 0xc87c, 0xd9f5,
+    0x3183, 0x200a, 0x1291, 0x0318, 0x77a7, 0x662e, 0x54b5, 0x453c,
+    0xbdcb, 0xac42, 0x9ed9, 0x8f50, 0xfbef, 0xea66, 0xd8fd, 0xc974,
+    0x4204, 0x538d, 0x6116, 0x709f, 0x0420, 0x15a9, 0x2732, 0x36bb,
+    0xce4c, 0xdfc5, 0xed5e, 0xfcd7, 0x8868, 0x99e1, 0xab7a, 0xbaf3,
+    0x5285, 0x430c, 0x7197, 0x601e, 0x14a1, 0x0528, 0x37b3, 0x263a,
+    0xdecd, 0xcf44, 0xfddf, 0xec56, 0x98e9, 0x8960, 0xbbfb, 0xaa72,
+    0x6306, 0x728f, 0x4014, 0x519d, 0x2522, 0x34ab, 0x0630, 0x17b9,
+    0xef4e, 0xfec7, 0xcc5c, 0xddd5, 0xa96a, 0xb8e3, 0x8a78, 0x9bf1,
+    0x7387, 0x620e, 0x5095, 0x411c, 0x35a3, 0x242a, 0x16b1, 0x0738,
+    0xffcf, 0xee46, 0xdcdd, 0xcd54, 0xb9eb, 0xa862, 0x9af9, 0x8b70,
+    0x8408, 0x9581, 0xa71a, 0xb693, 0xc22c, 0xd3a5, 0xe13e, 0xf0b7,
+    0x0840, 0x19c9, 0x2b52, 0x3adb, 0x4e64, 0x5fed, 0x6d76, 0x7cff,
+    0x9489, 0x8500, 0xb79b, 0xa612, 0xd2ad, 0xc324, 0xf1bf, 0xe036,
+    0x18c1, 0x0948, 0x3bd3, 0x2a5a, 0x5ee5, 0x4f6c, 0x7df7, 0x6c7e,
+    0xa50a, 0xb483, 0x8618, 0x9791, 0xe32e, 0xf2a7, 0xc03c, 0xd1b5,
+    0x2942, 0x38cb, 0x0a50, 0x1bd9, 0x6f66, 0x7eef, 0x4c74, 0x5dfd,
+    0xb58b, 0xa402, 0x9699, 0x8710, 0xf3af, 0xe226, 0xd0bd, 0xc134,
+    0x39c3, 0x284a, 0x1ad1, 0x0b58, 0x7fe7, 0x6e6e, 0x5cf5, 0x4d7c,
+    0xc60c, 0xd785, 0xe51e, 0xf497, 0x8028, 0x91a1, 0xa33a, 0xb2b3,
+    0x4a44, 0x5bcd, 0x6956, 0x78df, 0x0c60, 0x1de9, 0x2f72, 0x3efb,
+    0xd68d, 0xc704, 0xf59f, 0xe416, 0x90a9, 0x8120, 0xb3bb, 0xa232,
+    0x5ac5, 0x4b4c, 0x79d7, 0x685e, 0x1ce1, 0x0d68, 0x3ff3, 0x2e7a,
+    0xe70e, 0xf687, 0xc41c, 0xd595, 0xa12a, 0xb0a3, 0x8238, 0x93b1,
+    0x6b46, 0x7acf, 0x4854, 0x59dd, 0x2d62, 0x3ceb, 0x0e70, 0x1ff9,
+    0xf78f, 0xe606, 0xd49d, 0xc514, 0xb1ab, 0xa022, 0x92b9, 0x8330,
+    0x7bc7, 0x6a4e, 0x58d5, 0x495c, 0x3de3, 0x2c6a, 0x1ef1, 0x0f78,
+];
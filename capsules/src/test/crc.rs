@@ -0,0 +1,71 @@
+//! Test a CRC implementation against the check values of each algorithm.
+//!
+//! The check value of an algorithm is its CRC of the ASCII string
+//! `"123456789"`. For CRC-32 and CRC-32C these are the published values; the
+//! `Sam4L*` values are those computed by the SAM4L CRCCU. Running this test
+//! against both `sam4l::crccu::Crccu` and `capsules::sw_crc::SoftwareCrc`
+//! cross-checks the two implementations.
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::crc::{CrcAlg, CRC};
+use kernel::ReturnCode;
+
+static CHECK_INPUT: &'static [u8] = b"123456789";
+
+static CHECK_VALUES: [(CrcAlg, u32); 5] = [
+    (CrcAlg::Crc32, 0xcbf43926),
+    (CrcAlg::Crc32C, 0xe3069283),
+    (CrcAlg::Sam4L16, 0xffff89f6),
+    (CrcAlg::Sam4L32, 0x9b63d02c),
+    (CrcAlg::Sam4L32C, 0x3eb69f38),
+];
+
+pub struct TestCrc<'a, C: CRC + 'a> {
+    crc: &'a C,
+    current_test: Cell<usize>,
+}
+
+impl<'a, C: CRC + 'a> TestCrc<'a, C> {
+    pub fn new(crc: &'a C) -> TestCrc<'a, C> {
+        TestCrc {
+            crc: crc,
+            current_test: Cell::new(0),
+        }
+    }
+
+    pub fn run(&self) {
+        self.current_test.set(0);
+        self.run_current();
+    }
+
+    fn run_current(&self) {
+        let (alg, _) = CHECK_VALUES[self.current_test.get()];
+        let result = self.crc.compute(CHECK_INPUT, alg);
+        if result != ReturnCode::SUCCESS {
+            panic!("compute() failed: {:?}", result);
+        }
+    }
+}
+
+impl<'a, C: CRC + 'a> hil::crc::Client for TestCrc<'a, C> {
+    fn receive_result(&self, result: u32) {
+        let test = self.current_test.get();
+        let (_, expected) = CHECK_VALUES[test];
+        if result == expected {
+            debug!("CRC test {}: OK ({:#010x})", test, result);
+        } else {
+            debug!(
+                "CRC test {}: FAILED (expected {:#010x}, got {:#010x})",
+                test, expected, result
+            );
+        }
+
+        if test + 1 < CHECK_VALUES.len() {
+            self.current_test.set(test + 1);
+            self.run_current();
+        } else {
+            self.crc.disable();
+        }
+    }
+}
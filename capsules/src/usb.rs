@@ -1,9 +1,11 @@
 //! Platform-independent USB 2.0 protocol library
 
 use core::cell::Cell;
+use core::cmp::min;
 use core::convert::From;
 use core::fmt;
 use kernel::common::VolatileCell;
+pub use kernel::hil::usb::TransferType;
 
 /// The datastructure sent in a SETUP handshake
 #[derive(Debug, Copy, Clone)]
@@ -448,6 +450,36 @@ impl Descriptor for InterfaceDescriptor {
     }
 }
 
+/// Groups the interfaces of a function that uses more than one (USB ECN
+/// "Interface Association Descriptors"). Devices containing these should set
+/// their class to 0xEF, subclass 2, protocol 1.
+pub struct InterfaceAssociationDescriptor {
+    pub first_interface: u8,
+    pub interface_count: u8,
+    pub function_class: u8,
+    pub function_subclass: u8,
+    pub function_protocol: u8,
+    pub string_index: u8,
+}
+
+impl Descriptor for InterfaceAssociationDescriptor {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn write_to_unchecked(&self, buf: &[Cell<u8>]) -> usize {
+        buf[0].set(8); // Size of descriptor
+        buf[1].set(0x0b); // INTERFACE_ASSOCIATION
+        buf[2].set(self.first_interface);
+        buf[3].set(self.interface_count);
+        buf[4].set(self.function_class);
+        buf[5].set(self.function_subclass);
+        buf[6].set(self.function_protocol);
+        buf[7].set(self.string_index);
+        8
+    }
+}
+
 pub struct EndpointAddress(u8);
 
 impl EndpointAddress {
@@ -461,14 +493,6 @@ impl EndpointAddress {
     }
 }
 
-#[derive(Copy, Clone)]
-pub enum TransferType {
-    Control = 0,
-    Isochronous,
-    Bulk,
-    Interrupt,
-}
-
 pub struct EndpointDescriptor {
     pub endpoint_address: EndpointAddress,
     pub transfer_type: TransferType,
@@ -545,6 +569,117 @@ impl<'a> Descriptor for StringDescriptor<'a> {
     }
 }
 
+/// Assembles a configuration descriptor, followed by all of its interface,
+/// endpoint and class-specific descriptors, into a byte buffer.
+///
+/// Interfaces and endpoints are numbered in the order they are added, so that
+/// several class drivers can each contribute their descriptors at board
+/// initialization without knowing about one another.
+pub struct ConfigurationBuilder {
+    buf: &'static mut [u8],
+    len: usize,
+    num_interfaces: u8,
+    next_endpoint: usize,
+    max_endpoint: usize,
+    overflow: bool,
+}
+
+impl ConfigurationBuilder {
+    /// Start a configuration in `buf`. Endpoints are assigned numbers from 1
+    /// up to and including `max_endpoint`.
+    pub fn new(buf: &'static mut [u8], max_endpoint: usize) -> Self {
+        // Leave room for the configuration descriptor itself
+        let len = min(9, buf.len());
+        ConfigurationBuilder {
+            overflow: buf.len() < 9,
+            buf: buf,
+            len: len,
+            num_interfaces: 0,
+            next_endpoint: 1,
+            max_endpoint: max_endpoint,
+        }
+    }
+
+    /// The number the next interface added will get.
+    pub fn next_interface_number(&self) -> u8 {
+        self.num_interfaces
+    }
+
+    /// The number the next endpoint added will get.
+    pub fn next_endpoint_number(&self) -> usize {
+        self.next_endpoint
+    }
+
+    /// Append a descriptor.
+    pub fn add(&mut self, d: &Descriptor) {
+        // No descriptor that appears in a configuration is this long
+        let tmp: [Cell<u8>; 32] = Default::default();
+        let size = d.write_to(&tmp);
+        if size == 0 || self.len + size > self.buf.len() {
+            self.overflow = true;
+            return;
+        }
+        for (i, b) in tmp[..size].iter().enumerate() {
+            self.buf[self.len + i] = b.get();
+        }
+        self.len += size;
+    }
+
+    /// Append an interface descriptor, numbering it, and return its number.
+    pub fn add_interface(&mut self, mut d: InterfaceDescriptor) -> u8 {
+        let number = self.num_interfaces;
+        d.interface_number = number;
+        self.add(&d);
+        self.num_interfaces += 1;
+        number
+    }
+
+    /// Append an endpoint descriptor for the next free endpoint and return
+    /// its number, or `None` if the controller has no endpoints left.
+    pub fn add_endpoint(
+        &mut self,
+        direction: TransferDirection,
+        transfer_type: TransferType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> Option<usize> {
+        let endpoint = self.next_endpoint;
+        if endpoint > self.max_endpoint {
+            self.overflow = true;
+            return None;
+        }
+        self.add(&EndpointDescriptor {
+            endpoint_address: EndpointAddress::new(endpoint, direction),
+            transfer_type: transfer_type,
+            max_packet_size: max_packet_size,
+            interval: interval,
+        });
+        self.next_endpoint += 1;
+        Some(endpoint)
+    }
+
+    /// Write the configuration descriptor at the start of the buffer and
+    /// return the whole configuration, or `None` if the buffer or the
+    /// endpoints ran out.
+    pub fn finish(self, mut config: ConfigurationDescriptor) -> Option<&'static [u8]> {
+        if self.overflow {
+            return None;
+        }
+        config.num_interfaces = self.num_interfaces;
+        config.related_descriptor_length = self.len - 9;
+
+        let tmp: [Cell<u8>; 9] = Default::default();
+        config.write_to(&tmp);
+        for (i, b) in tmp.iter().enumerate() {
+            self.buf[i] = b.get();
+        }
+
+        let len = self.len;
+        let buf: &'static [u8] = self.buf;
+        Some(&buf[..len])
+    }
+}
+
 /// Parse a `u16` from two bytes as received on the bus
 fn get_u16(b0: u8, b1: u8) -> u16 {
     (b0 as u16) | ((b1 as u16) << 8)
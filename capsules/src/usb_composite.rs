@@ -0,0 +1,512 @@
+//! A USB device made of several independent functions.
+//!
+//! `CompositeDevice` is the client of the USB hardware interface. It answers
+//! the standard device requests on the default control endpoint and hands
+//! everything else to the class driver ("function") that owns the interface
+//! or endpoint concerned. Each function implements the `Function` trait.
+//!
+//! The configuration descriptor is assembled once at board initialization:
+//! `build()` asks each function in turn to add its interfaces and endpoints
+//! to a `usb::ConfigurationBuilder`, which numbers them. Functions remember
+//! the numbers they were given.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! // A serial port plus the vendor-specific bulk echo interface
+//! let cdc = static_init!(
+//!     capsules::cdc::CdcAcm<'static, sam4l::usbc::Usbc<'static>,
+//!                           VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::cdc::CdcAcm::new(&sam4l::usbc::USBC, cdc_alarm));
+//! let vendor = static_init!(
+//!     capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usbc_client::Client::new(&sam4l::usbc::USBC));
+//! let usb_functions = static_init!(
+//!     [&'static capsules::usb_composite::Function; 2],
+//!     [cdc, vendor]);
+//! let usb_device = static_init!(
+//!     capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_composite::CompositeDevice::new(
+//!         &sam4l::usbc::USBC,
+//!         capsules::usb::DeviceDescriptor {
+//!             class: 0xef, // Interface association
+//!             subclass: 2,
+//!             protocol: 1,
+//!             vendor_id: 0x6667,
+//!             product_id: 0xabd0,
+//!             manufacturer_string: 1,
+//!             product_string: 2,
+//!             ..Default::default()
+//!         },
+//!         &["XYZ Corp.", "Tock Composite Device"],
+//!         usb_functions));
+//! usb_device.build(&mut capsules::usb_composite::CONFIGURATION_BUF, sam4l::usbc::N_ENDPOINTS - 1);
+//! sam4l::usbc::USBC.set_client(usb_device);
+//! ```
+
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::VolatileCell;
+use kernel::hil;
+use kernel::hil::usb::*;
+use kernel::ReturnCode;
+use usb::*;
+
+pub static mut CONFIGURATION_BUF: [u8; 128] = [0; 128];
+
+static LANGUAGES: &'static [u16] = &[
+    0x0409, // English (United States)
+];
+
+/// Responses to control requests are composed in this much storage. Longer
+/// responses are sent from static data with `SetupResult::InStatic`, and
+/// string descriptors are encoded as they are sent.
+///
+/// 32 is the largest array size with a derived `Default` implementation.
+const RESPONSE_BUFLEN: usize = 32;
+
+/// The most interfaces and endpoints that can be routed to functions.
+const MAX_INTERFACES: usize = 16;
+const MAX_ENDPOINTS: usize = 16;
+
+/// The outcome of a function handling a control request.
+pub enum SetupResult {
+    /// The request is accepted. If the host is sending data, it will be
+    /// passed to `ctrl_out()`.
+    Ok,
+
+    /// The response to a device-to-host request was written to the response
+    /// buffer, and is this many bytes long.
+    In(usize),
+
+    /// The response to a device-to-host request is this static data, such
+    /// as a descriptor too long for the response buffer.
+    InStatic(&'static [u8]),
+
+    /// The request cannot be handled and is answered with STALL.
+    Error(CtrlSetupResult),
+}
+
+/// A class driver that is one part of a composite device.
+pub trait Function {
+    /// Add this function's interface, class-specific and endpoint
+    /// descriptors to the configuration, and remember the interface and
+    /// endpoint numbers assigned.
+    fn add_descriptors(&self, builder: &mut ConfigurationBuilder);
+
+    /// Give the controller buffers for, and enable, the endpoints this
+    /// function was assigned.
+    fn enable_endpoints(&self);
+
+    fn bus_reset(&self);
+
+    /// Handle a request addressed to one of this function's interfaces or
+    /// endpoints, or a non-standard request addressed to the device.
+    fn ctrl_setup(&self, setup_data: &SetupData, response: &[Cell<u8>]) -> SetupResult;
+
+    /// Consume a packet of the data stage of a host-to-device request that
+    /// this function accepted.
+    fn ctrl_out(&self, setup_data: &SetupData, packet: &[VolatileCell<u8>]) -> CtrlOutResult;
+
+    /// Handle an IN transaction on one of this function's non-control
+    /// endpoints.
+    fn bulk_in(&self, endpoint: usize) -> BulkInResult;
+
+    /// Handle an OUT transaction on one of this function's non-control
+    /// endpoints.
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> BulkOutResult;
+}
+
+#[derive(Copy, Clone)]
+enum State {
+    Init,
+
+    /// We are sending static data such as the configuration descriptor,
+    /// with the given extent remaining to send
+    StaticIn(&'static [u8], usize, usize),
+
+    /// We are sending the string descriptor for this string, encoding it as
+    /// we go, with the given extent remaining to send
+    StringIn(&'static str, usize, usize),
+
+    /// We are doing a Control In transfer of some data in
+    /// self.response_storage, with the given extent remaining to send
+    CtrlIn(usize, usize),
+
+    /// The data stage of a request goes to the function with this index
+    FunctionOut(usize, SetupData),
+
+    SetAddress,
+}
+
+pub struct CompositeDevice<'a, C: 'a> {
+    // The hardware controller
+    controller: &'a C,
+
+    device_descriptor: DeviceDescriptor,
+    strings: &'static [&'static str],
+    functions: &'a [&'a Function],
+
+    // The assembled configuration descriptor
+    configuration: Cell<Option<&'static [u8]>>,
+
+    // Index of the function that owns each interface and endpoint
+    interface_owner: [Cell<Option<usize>>; MAX_INTERFACES],
+    endpoint_owner: [Cell<Option<usize>>; MAX_ENDPOINTS],
+
+    state: Cell<State>,
+
+    // Whether the controller has been enabled and attached, so that a board
+    // and the userspace driver can both ask for it
+    enabled: Cell<bool>,
+    attached: Cell<bool>,
+
+    // Buffer for the default control endpoint
+    ctrl_buffer: [VolatileCell<u8>; 8],
+
+    // Storage for composing responses to control requests
+    response_storage: [Cell<u8>; RESPONSE_BUFLEN],
+}
+
+impl<'a, C: UsbController> CompositeDevice<'a, C> {
+    pub fn new(
+        controller: &'a C,
+        device_descriptor: DeviceDescriptor,
+        strings: &'static [&'static str],
+        functions: &'a [&'a Function],
+    ) -> Self {
+        CompositeDevice {
+            controller: controller,
+            device_descriptor: device_descriptor,
+            strings: strings,
+            functions: functions,
+            configuration: Cell::new(None),
+            interface_owner: Default::default(),
+            endpoint_owner: Default::default(),
+            state: Cell::new(State::Init),
+            enabled: Cell::new(false),
+            attached: Cell::new(false),
+            ctrl_buffer: Default::default(),
+            response_storage: Default::default(),
+        }
+    }
+
+    /// Assemble the configuration descriptor from all the functions into
+    /// `buffer`, assigning endpoints no higher than `max_endpoint`. This
+    /// must be called once before the device is enabled.
+    ///
+    /// Returns `ESIZE` if the descriptors do not fit in `buffer` or there
+    /// are not enough endpoints.
+    pub fn build(&self, buffer: &'static mut [u8], max_endpoint: usize) -> ReturnCode {
+        let mut builder =
+            ConfigurationBuilder::new(buffer, min(max_endpoint, MAX_ENDPOINTS - 1));
+
+        for (index, function) in self.functions.iter().enumerate() {
+            let first_interface = builder.next_interface_number() as usize;
+            let first_endpoint = builder.next_endpoint_number();
+            function.add_descriptors(&mut builder);
+
+            for i in first_interface..builder.next_interface_number() as usize {
+                if i < MAX_INTERFACES {
+                    self.interface_owner[i].set(Some(index));
+                }
+            }
+            for e in first_endpoint..builder.next_endpoint_number() {
+                self.endpoint_owner[e].set(Some(index));
+            }
+        }
+
+        match builder.finish(ConfigurationDescriptor {
+            configuration_value: 1,
+            ..Default::default()
+        }) {
+            Some(configuration) => {
+                self.configuration.set(Some(configuration));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ESIZE,
+        }
+    }
+
+    #[inline]
+    fn response_buf(&self) -> &[Cell<u8>] {
+        &self.response_storage
+    }
+
+    /// Pass a request that is not a standard device request to the function
+    /// it is addressed to.
+    fn function_request(&self, setup_data: SetupData) -> CtrlSetupResult {
+        let owner = match setup_data.request_type.recipient() {
+            Recipient::Interface => self.interface_owner
+                .get((setup_data.index & 0xff) as usize)
+                .and_then(|owner| owner.get()),
+            Recipient::Endpoint => self.endpoint_owner
+                .get((setup_data.index & 0x0f) as usize)
+                .and_then(|owner| owner.get()),
+            _ => None,
+        };
+
+        let response = self.response_buf();
+        let result = match owner {
+            Some(index) => Some((index, self.functions[index].ctrl_setup(&setup_data, response))),
+            None => {
+                // A request to the whole device: offer it to each function
+                // until one accepts it
+                self.functions.iter().enumerate().fold(None, |found, (index, function)| {
+                    found.or_else(|| match function.ctrl_setup(&setup_data, response) {
+                        SetupResult::Error(_) => None,
+                        result => Some((index, result)),
+                    })
+                })
+            }
+        };
+
+        match result {
+            Some((index, SetupResult::Ok)) => {
+                match setup_data.request_type.transfer_direction() {
+                    TransferDirection::HostToDevice if setup_data.length > 0 => {
+                        self.state.set(State::FunctionOut(index, setup_data));
+                    }
+                    _ => self.state.set(State::Init),
+                }
+                CtrlSetupResult::Ok
+            }
+            Some((_, SetupResult::In(len))) => {
+                let end = min(min(len, response.len()), setup_data.length as usize);
+                self.state.set(State::CtrlIn(0, end));
+                CtrlSetupResult::Ok
+            }
+            Some((_, SetupResult::InStatic(data))) => {
+                let end = min(data.len(), setup_data.length as usize);
+                self.state.set(State::StaticIn(data, 0, end));
+                CtrlSetupResult::Ok
+            }
+            Some((_, SetupResult::Error(err))) => err,
+            None => CtrlSetupResult::ErrUnrecognizedRequestType,
+        }
+    }
+}
+
+impl<'a, C: UsbController> hil::usb::Client for CompositeDevice<'a, C> {
+    fn enable(&self) {
+        if self.enabled.get() {
+            return;
+        }
+        self.enabled.set(true);
+
+        // Set up the default control endpoint
+        self.controller.endpoint_set_buffer(0, &self.ctrl_buffer);
+        self.controller.enable_as_device(DeviceSpeed::Full); // must be Full for Bulk transfers
+        self.controller.endpoint_ctrl_out_enable(0);
+
+        for function in self.functions.iter() {
+            function.enable_endpoints();
+        }
+    }
+
+    fn attach(&self) {
+        if self.attached.get() {
+            return;
+        }
+        self.attached.set(true);
+        self.controller.attach();
+    }
+
+    fn bus_reset(&self) {
+        self.state.set(State::Init);
+        for function in self.functions.iter() {
+            function.bus_reset();
+        }
+    }
+
+    /// Handle a Control Setup transaction
+    fn ctrl_setup(&self, endpoint: usize) -> CtrlSetupResult {
+        if endpoint != 0 {
+            // For now we only support the default Control endpoint
+            return CtrlSetupResult::ErrInvalidDeviceIndex;
+        }
+        SetupData::get(&self.ctrl_buffer).map_or(CtrlSetupResult::ErrNoParse, |setup_data| {
+            setup_data.get_standard_request().map_or_else(
+                || self.function_request(setup_data),
+                |request| match request {
+                    StandardDeviceRequest::GetDescriptor {
+                        descriptor_type,
+                        descriptor_index,
+                        lang_id,
+                        requested_length,
+                    } => match descriptor_type {
+                        DescriptorType::Device => match descriptor_index {
+                            0 => {
+                                let buf = self.response_buf();
+                                let len = self.device_descriptor.write_to(buf);
+                                let end = min(len, requested_length as usize);
+                                self.state.set(State::CtrlIn(0, end));
+                                CtrlSetupResult::Ok
+                            }
+                            _ => CtrlSetupResult::ErrInvalidDeviceIndex,
+                        },
+                        DescriptorType::Configuration => match descriptor_index {
+                            0 => self.configuration.get().map_or(
+                                CtrlSetupResult::ErrInvalidConfigurationIndex,
+                                |configuration| {
+                                    let end = min(configuration.len(), requested_length as usize);
+                                    self.state.set(State::StaticIn(configuration, 0, end));
+                                    CtrlSetupResult::Ok
+                                },
+                            ),
+                            _ => CtrlSetupResult::ErrInvalidConfigurationIndex,
+                        },
+                        DescriptorType::String => match descriptor_index {
+                            0 => {
+                                let buf = self.response_buf();
+                                let len = LanguagesDescriptor { langs: LANGUAGES }.write_to(buf);
+                                let end = min(len, requested_length as usize);
+                                self.state.set(State::CtrlIn(0, end));
+                                CtrlSetupResult::Ok
+                            }
+                            i if (i as usize) <= self.strings.len() && lang_id == LANGUAGES[0] => {
+                                let string = self.strings[i as usize - 1];
+                                let len = StringDescriptor { string: string }.size();
+                                let end = min(len, requested_length as usize);
+                                self.state.set(State::StringIn(string, 0, end));
+                                CtrlSetupResult::Ok
+                            }
+                            _ => CtrlSetupResult::ErrInvalidStringIndex,
+                        },
+                        DescriptorType::DeviceQualifier => {
+                            // We are full-speed only, so we must
+                            // respond with a request error
+                            CtrlSetupResult::ErrNoDeviceQualifier
+                        }
+                        _ => CtrlSetupResult::ErrUnrecognizedDescriptorType,
+                    },
+                    StandardDeviceRequest::SetAddress { device_address } => {
+                        // Load the address we've been assigned ...
+                        self.controller.set_address(device_address);
+
+                        // ... and when this request gets to the Status stage
+                        // we will actually enable the address.
+                        self.state.set(State::SetAddress);
+                        CtrlSetupResult::Ok
+                    }
+                    StandardDeviceRequest::SetConfiguration { .. } => CtrlSetupResult::Ok,
+                    StandardDeviceRequest::SetInterface => {
+                        // Functions with alternate settings handle this
+                        // themselves; every interface has a default setting
+                        match self.function_request(setup_data) {
+                            CtrlSetupResult::Ok => CtrlSetupResult::Ok,
+                            _ if setup_data.value == 0 => CtrlSetupResult::Ok,
+                            err => err,
+                        }
+                    }
+                    _ => CtrlSetupResult::ErrUnrecognizedRequestType,
+                },
+            )
+        })
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&self, endpoint: usize) -> CtrlInResult {
+        let buf = &self.ctrl_buffer;
+        match self.state.get() {
+            State::StaticIn(data, start, end) => {
+                let packet_bytes = min(8, end.saturating_sub(start));
+                for i in 0..packet_bytes {
+                    buf[i].set(data[start + i]);
+                }
+                self.state
+                    .set(State::StaticIn(data, start + packet_bytes, end));
+                CtrlInResult::Packet(packet_bytes, start + packet_bytes >= end)
+            }
+            State::StringIn(string, start, end) => {
+                let packet_bytes = min(8, end.saturating_sub(start));
+                for i in 0..packet_bytes {
+                    buf[i].set(string_descriptor_byte(string, start + i));
+                }
+                self.state
+                    .set(State::StringIn(string, start + packet_bytes, end));
+                CtrlInResult::Packet(packet_bytes, start + packet_bytes >= end)
+            }
+            State::CtrlIn(start, end) => {
+                let response = self.response_buf();
+                let packet_bytes = min(8, end.saturating_sub(start));
+                for i in 0..packet_bytes {
+                    buf[i].set(response[start + i].get());
+                }
+                self.state.set(State::CtrlIn(start + packet_bytes, end));
+                CtrlInResult::Packet(packet_bytes, start + packet_bytes >= end)
+            }
+            _ => {
+                debug!("Unexpected IN on control endpoint {}", endpoint);
+                CtrlInResult::Error
+            }
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&self, _endpoint: usize, packet_bytes: u32) -> CtrlOutResult {
+        match self.state.get() {
+            State::FunctionOut(index, setup_data) => {
+                let packet_bytes = min(packet_bytes as usize, self.ctrl_buffer.len());
+                self.functions[index].ctrl_out(&setup_data, &self.ctrl_buffer[..packet_bytes])
+            }
+            _ => {
+                // Bad state
+                CtrlOutResult::Halted
+            }
+        }
+    }
+
+    fn ctrl_status(&self, _endpoint: usize) {
+        // Entered Status stage
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&self, _endpoint: usize) {
+        match self.state.get() {
+            State::SetAddress => {
+                self.controller.enable_address();
+            }
+            _ => {}
+        };
+        self.state.set(State::Init);
+    }
+
+    fn bulk_in(&self, endpoint: usize) -> BulkInResult {
+        match self.endpoint_owner.get(endpoint).and_then(|owner| owner.get()) {
+            Some(index) => self.functions[index].bulk_in(endpoint),
+            None => BulkInResult::Error,
+        }
+    }
+
+    fn bulk_out(&self, endpoint: usize, packet_bytes: u32) -> BulkOutResult {
+        match self.endpoint_owner.get(endpoint).and_then(|owner| owner.get()) {
+            Some(index) => self.functions[index].bulk_out(endpoint, packet_bytes),
+            None => BulkOutResult::Error,
+        }
+    }
+}
+
+/// The byte at `index` of the string descriptor for `string`, which is the
+/// string encoded as UTF-16LE after a two-byte header.
+fn string_descriptor_byte(string: &str, index: usize) -> u8 {
+    match index {
+        0 => StringDescriptor { string: string }.size() as u8,
+        1 => DescriptorType::String as u8,
+        _ => {
+            // Find the UTF-16 code unit this byte is half of
+            let mut unit = (index - 2) / 2;
+            for ch in string.chars() {
+                let mut chbuf = [0; 2];
+                for w in ch.encode_utf16(&mut chbuf).iter() {
+                    if unit == 0 {
+                        return (*w >> (8 * (index % 2))) as u8;
+                    }
+                    unit -= 1;
+                }
+            }
+            0
+        }
+    }
+}
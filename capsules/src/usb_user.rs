@@ -10,16 +10,18 @@
 //! example:
 //!
 //! ```rust
-//! // Configure the USB controller
+//! // Configure the USB controller (see `capsules::usb_composite`)
 //! let usb_client = static_init!(
-//!     capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>,
-//!     capsules::usbc_client::Client::new(&sam4l::usbc::USBC));
+//!     capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>,
+//!     capsules::usb_composite::CompositeDevice::new(
+//!         &sam4l::usbc::USBC, device_descriptor, strings, usb_functions));
+//! usb_client.build(&mut capsules::usb_composite::CONFIGURATION_BUF, sam4l::usbc::N_ENDPOINTS - 1);
 //! sam4l::usbc::USBC.set_client(usb_client);
 //!
 //! // Configure the USB userspace driver
 //! let usb_driver = static_init!(
 //!     capsules::usb_user::UsbSyscallDriver<'static,
-//!         capsules::usbc_client::Client<'static, sam4l::usbc::Usbc<'static>>>,
+//!         capsules::usb_composite::CompositeDevice<'static, sam4l::usbc::Usbc<'static>>>,
 //!     capsules::usb_user::UsbSyscallDriver::new(
 //!         usb_client, kernel::Grant::create()));
 //! ```
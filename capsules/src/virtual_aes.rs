@@ -0,0 +1,360 @@
+//! Virtualize an AES-128 engine to enable multiple users of it.
+//!
+//! `MuxAES128` owns the hardware and `VirtualAES128` provides each user with
+//! its own `AES128` instance. Every virtual instance keeps its own key, IV,
+//! mode and message state, which the mux loads into the hardware before
+//! running one of its requests. Each user may have at most one outstanding
+//! request; requests from different users are run one at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mux_aes = static_init!(
+//!     capsules::virtual_aes::MuxAES128<'static, sam4l::aes::Aes<'static>>,
+//!     capsules::virtual_aes::MuxAES128::new(&sam4l::aes::AES));
+//! sam4l::aes::AES.set_client(mux_aes);
+//! sam4l::aes::AES.enable();
+//!
+//! let ccm_aes = static_init!(
+//!     capsules::virtual_aes::VirtualAES128<'static, sam4l::aes::Aes<'static>>,
+//!     capsules::virtual_aes::VirtualAES128::new(mux_aes));
+//! mux_aes.add_user(ccm_aes);
+//! let aes_ccm = static_init!(
+//!     capsules::aes_ccm::AES128CCM<'static, capsules::virtual_aes::VirtualAES128<'static,
+//!         sam4l::aes::Aes<'static>>>,
+//!     capsules::aes_ccm::AES128CCM::new(ccm_aes, &mut CRYPT_BUF));
+//! ccm_aes.set_client(aes_ccm);
+//! ```
+
+use core::cell::Cell;
+use kernel::ReturnCode;
+use kernel::common::take_cell::TakeCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::symmetric_encryption::{self, AES128, AES128CBC, AES128Ctr, AES128ECB,
+                                        AES128_BLOCK_SIZE, AES128_KEY_SIZE};
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Queued,
+    Running,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Ctr(bool),
+    Cbc(bool),
+    Ecb(bool),
+}
+
+/// The Mux struct manages multiple AES clients. Each client may have at most
+/// one outstanding request.
+pub struct MuxAES128<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> {
+    aes: &'a A,
+    users: List<'a, VirtualAES128<'a, A>>,
+    enabled: Cell<usize>,
+    inflight: Cell<Option<&'a VirtualAES128<'a, A>>>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> symmetric_encryption::Client<'a>
+    for MuxAES128<'a, A>
+{
+    fn crypt_done(&self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        self.inflight.get().map(move |user| {
+            self.inflight.set(None);
+            user.crypt_done(source, dest);
+        });
+        self.do_next_op();
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> MuxAES128<'a, A> {
+    pub const fn new(aes: &'a A) -> MuxAES128<'a, A> {
+        MuxAES128 {
+            aes: aes,
+            users: List::new(),
+            enabled: Cell::new(0),
+            inflight: Cell::new(None),
+        }
+    }
+
+    /// Add a user to the mux. This must be done once for each user before
+    /// it is used; adding a user again has no effect.
+    pub fn add_user(&self, user: &'a VirtualAES128<'a, A>) {
+        let added = self.users
+            .iter()
+            .any(|node| node as *const VirtualAES128<'a, A> == user as *const _);
+        if !added {
+            self.users.push_head(user);
+        }
+    }
+
+    fn enable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled + 1);
+        if enabled == 0 {
+            self.aes.enable();
+        }
+    }
+
+    fn disable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled - 1);
+        if enabled == 1 {
+            self.aes.disable();
+        }
+    }
+
+    fn do_next_op(&self) {
+        if self.inflight.get().is_none() {
+            let mnode = self.users
+                .iter()
+                .find(|node| node.state.get() == State::Queued);
+            mnode.map(|node| {
+                if let Some((_, source, dest)) = self.start(node) {
+                    // The request was checked when it was queued, so the
+                    // engine is in use behind the mux's back. Hand the
+                    // buffers back rather than holding on to them.
+                    node.client.get().map(move |client| {
+                        client.crypt_done(source, dest);
+                    });
+                    self.do_next_op();
+                }
+            });
+        }
+    }
+
+    /// Load the user's state into the engine and start its request. On
+    /// success the user becomes the one in flight.
+    fn start(
+        &self,
+        node: &'a VirtualAES128<'a, A>,
+    ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+        let dest = match node.dest.take() {
+            Some(dest) => dest,
+            None => {
+                node.state.set(State::Idle);
+                return None;
+            }
+        };
+        let source = node.source.take();
+        let (start, stop) = node.range.get();
+
+        // CBC decryption chains on the last ciphertext block, which is
+        // overwritten if the operation is in place
+        {
+            let input = source.as_ref().map_or(&dest[start..stop], |source| &source[..]);
+            if input.len() >= AES128_BLOCK_SIZE {
+                let mut last = [0; AES128_BLOCK_SIZE];
+                last.copy_from_slice(&input[input.len() - AES128_BLOCK_SIZE..]);
+                node.last_input.set(last);
+            }
+        }
+
+        self.aes.set_key(&node.key.get());
+        self.aes.set_iv(&node.chain.get().unwrap_or(node.iv.get()));
+        match node.mode.get() {
+            Mode::Ctr(encrypting) => self.aes.set_mode_aes128ctr(encrypting),
+            Mode::Cbc(encrypting) => self.aes.set_mode_aes128cbc(encrypting),
+            Mode::Ecb(encrypting) => self.aes.set_mode_aes128ecb(encrypting),
+        }
+        self.aes.start_message();
+
+        node.state.set(State::Running);
+        self.inflight.set(Some(node));
+        let result = self.aes.crypt(source, dest, start, stop);
+        if result.is_some() {
+            node.state.set(State::Idle);
+            self.inflight.set(None);
+        }
+        result
+    }
+}
+
+pub struct VirtualAES128<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB + 'a> {
+    mux: &'a MuxAES128<'a, A>,
+    enabled: Cell<bool>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    /// The IV a new message starts with
+    iv: Cell<[u8; AES128_BLOCK_SIZE]>,
+    /// The IV that continues the current message, if one was started
+    chain: Cell<Option<[u8; AES128_BLOCK_SIZE]>>,
+    /// The last input block of the request in flight
+    last_input: Cell<[u8; AES128_BLOCK_SIZE]>,
+    mode: Cell<Mode>,
+    source: TakeCell<'a, [u8]>,
+    dest: TakeCell<'a, [u8]>,
+    range: Cell<(usize, usize)>,
+    state: Cell<State>,
+    next: ListLink<'a, VirtualAES128<'a, A>>,
+    client: Cell<Option<&'a symmetric_encryption::Client<'a>>>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> VirtualAES128<'a, A> {
+    pub const fn new(mux: &'a MuxAES128<'a, A>) -> VirtualAES128<'a, A> {
+        VirtualAES128 {
+            mux: mux,
+            enabled: Cell::new(false),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            chain: Cell::new(None),
+            last_input: Cell::new([0; AES128_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ctr(true)),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            range: Cell::new((0, 0)),
+            state: Cell::new(State::Idle),
+            next: ListLink::empty(),
+            client: Cell::new(None),
+        }
+    }
+
+    /// Work out the IV that continues the message after the request that
+    /// just completed.
+    fn update_chain(&self, dest: &[u8]) {
+        let (start, stop) = self.range.get();
+        let mut chain = self.chain.get().unwrap_or(self.iv.get());
+        match self.mode.get() {
+            Mode::Ctr(_) => {
+                let mut carry = ((stop - start) / AES128_BLOCK_SIZE) as u64;
+                for byte in chain.iter_mut().rev() {
+                    if carry == 0 {
+                        break;
+                    }
+                    let sum = *byte as u64 + (carry & 0xff);
+                    *byte = sum as u8;
+                    carry = (carry >> 8) + (sum >> 8);
+                }
+            }
+            Mode::Cbc(true) => {
+                if stop - start >= AES128_BLOCK_SIZE {
+                    chain.copy_from_slice(&dest[stop - AES128_BLOCK_SIZE..stop]);
+                }
+            }
+            Mode::Cbc(false) => {
+                if stop - start >= AES128_BLOCK_SIZE {
+                    chain = self.last_input.get();
+                }
+            }
+            Mode::Ecb(_) => {}
+        }
+        self.chain.set(Some(chain));
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> symmetric_encryption::Client<'a>
+    for VirtualAES128<'a, A>
+{
+    fn crypt_done(&self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        self.state.set(State::Idle);
+        self.update_chain(dest);
+        self.client.get().map(move |client| {
+            client.crypt_done(source, dest);
+        });
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> ListNode<'a, VirtualAES128<'a, A>>
+    for VirtualAES128<'a, A>
+{
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAES128<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> AES128<'a> for VirtualAES128<'a, A> {
+    fn enable(&self) {
+        if !self.enabled.get() {
+            self.enabled.set(true);
+            self.mux.enable();
+        }
+    }
+
+    fn disable(&self) {
+        if self.enabled.get() {
+            self.enabled.set(false);
+            self.mux.disable();
+        }
+    }
+
+    fn set_client(&'a self, client: &'a symmetric_encryption::Client<'a>) {
+        self.client.set(Some(client));
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+        if iv.len() != AES128_BLOCK_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_iv = [0; AES128_BLOCK_SIZE];
+        new_iv.copy_from_slice(iv);
+        self.iv.set(new_iv);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_message(&self) {
+        if self.state.get() == State::Idle {
+            self.chain.set(None);
+        }
+    }
+
+    fn crypt(
+        &'a self,
+        source: Option<&'a mut [u8]>,
+        dest: &'a mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Some((ReturnCode::EBUSY, source, dest));
+        }
+        let len = stop_index.wrapping_sub(start_index);
+        if start_index > stop_index || stop_index > dest.len() || len % AES128_BLOCK_SIZE != 0
+            || source.as_ref().map_or(false, |source| source.len() != len)
+        {
+            return Some((ReturnCode::EINVAL, source, dest));
+        }
+
+        source.map(|source| self.source.replace(source));
+        self.dest.replace(dest);
+        self.range.set((start_index, stop_index));
+        self.state.set(State::Queued);
+
+        if self.mux.inflight.get().is_none() {
+            // Run straight away, so that any error goes back to the caller
+            let result = self.mux.start(self);
+            if result.is_some() {
+                return result;
+            }
+        }
+        None
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> AES128Ctr for VirtualAES128<'a, A> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) {
+        self.mode.set(Mode::Ctr(encrypting));
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> AES128CBC for VirtualAES128<'a, A> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) {
+        self.mode.set(Mode::Cbc(encrypting));
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> AES128ECB for VirtualAES128<'a, A> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.mode.set(Mode::Ecb(encrypting));
+    }
+}
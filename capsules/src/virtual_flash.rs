@@ -8,6 +8,9 @@
 //! must use a `FlashUser` instance to contain the per-user state for the
 //! virtualization.
 //!
+//! To track how worn the flash is and retire pages that fail, put a
+//! `capsules::flash_wear::FlashWear` between the flash controller and the mux.
+//!
 //! Usage
 //! -----
 //!
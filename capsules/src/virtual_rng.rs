@@ -0,0 +1,125 @@
+//! Virtualize a random number generator to enable multiple users of it.
+//!
+//! `MuxRng` owns the underlying `RNG` and `VirtualRng` provides each user
+//! with its own `RNG` instance. When randomness arrives, it is offered to
+//! every user that has asked for it, in turn, until it runs out. A user that
+//! returns `Continue::More` keeps its request pending and the mux asks the
+//! underlying generator for more.
+//!
+//! This is meant to sit on top of a `capsules::csprng::Csprng`, so that one
+//! seeded generator serves the userspace RNG driver as well as kernel users
+//! such as MAC backoffs and BLE address generation.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mux_rng = static_init!(
+//!     capsules::virtual_rng::MuxRng<'static>,
+//!     capsules::virtual_rng::MuxRng::new(csprng));
+//! csprng.set_client(mux_rng);
+//!
+//! let app_rng = static_init!(
+//!     capsules::virtual_rng::VirtualRng<'static>,
+//!     capsules::virtual_rng::VirtualRng::new(mux_rng));
+//! mux_rng.add_user(app_rng);
+//! let rng = static_init!(
+//!     capsules::rng::SimpleRng<'static, capsules::virtual_rng::VirtualRng<'static>>,
+//!     capsules::rng::SimpleRng::new(app_rng, kernel::Grant::create()));
+//! app_rng.set_client(rng);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::rng::{self, RNG};
+
+/// The Mux struct shares one random number generator between several users.
+pub struct MuxRng<'a> {
+    rng: &'a RNG,
+    users: List<'a, VirtualRng<'a>>,
+}
+
+impl<'a> MuxRng<'a> {
+    pub const fn new(rng: &'a RNG) -> MuxRng<'a> {
+        MuxRng {
+            rng: rng,
+            users: List::new(),
+        }
+    }
+
+    /// Add a user to the mux. This must be done once for each user before
+    /// it is used; adding a user again has no effect.
+    pub fn add_user(&self, user: &'a VirtualRng<'a>) {
+        let added = self.users
+            .iter()
+            .any(|node| node as *const VirtualRng<'a> == user as *const _);
+        if !added {
+            self.users.push_head(user);
+        }
+    }
+}
+
+impl<'a> rng::Client for MuxRng<'a> {
+    fn randomness_available(&self, randomness: &mut Iterator<Item = u32>) -> rng::Continue {
+        for user in self.users.iter() {
+            if !user.requested.get() {
+                continue;
+            }
+            // Clear the request first, so that a client may ask again from
+            // within its callback.
+            user.requested.set(false);
+            let result = user.client.get().map_or(rng::Continue::Done, |client| {
+                client.randomness_available(randomness)
+            });
+            if result == rng::Continue::More {
+                // This user used up the randomness we were given, the
+                // remaining users are served with the next batch.
+                user.requested.set(true);
+                break;
+            }
+        }
+
+        if self.users.iter().any(|user| user.requested.get()) {
+            rng::Continue::More
+        } else {
+            rng::Continue::Done
+        }
+    }
+}
+
+pub struct VirtualRng<'a> {
+    mux: &'a MuxRng<'a>,
+    client: Cell<Option<&'a rng::Client>>,
+    requested: Cell<bool>,
+    next: ListLink<'a, VirtualRng<'a>>,
+}
+
+impl<'a> ListNode<'a, VirtualRng<'a>> for VirtualRng<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualRng<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> VirtualRng<'a> {
+    pub const fn new(mux: &'a MuxRng<'a>) -> VirtualRng<'a> {
+        VirtualRng {
+            mux: mux,
+            client: Cell::new(None),
+            requested: Cell::new(false),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Set the client that receives this user's randomness. The user must
+    /// also be added to the mux with `MuxRng::add_user`.
+    pub fn set_client(&self, client: &'a rng::Client) {
+        self.client.set(Some(client));
+    }
+}
+
+impl<'a> RNG for VirtualRng<'a> {
+    fn get(&self) {
+        self.requested.set(true);
+        self.mux.rng.get();
+    }
+}
@@ -0,0 +1,209 @@
+//! Watchdog service with per-app heartbeats.
+//!
+//! `WatchdogService` wraps the hardware watchdog. The kernel main loop tickles
+//! it through `Platform::watchdog()`, so the chip resets if the kernel hangs.
+//! An alarm checks on the apps periodically, which also wakes the kernel often
+//! enough that the watchdog is tickled while every app is asleep.
+//!
+//! Critical apps can additionally register a heartbeat timeout. If such an app
+//! goes longer than its timeout without a heartbeat, the board's `Policy`
+//! decides what happens: either the app is restarted, or the service stops
+//! tickling the hardware watchdog and lets it reset the chip.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let watchdog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm));
+//! let watchdog = static_init!(
+//!     capsules::watchdog::WatchdogService<'static, sam4l::wdt::Wdt,
+//!                                         VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::watchdog::WatchdogService::new(
+//!         &sam4l::wdt::WDT,
+//!         watchdog_alarm,
+//!         capsules::watchdog::Policy::RestartApp,
+//!         kernel::Grant::create()));
+//! watchdog_alarm.set_client(watchdog);
+//! hil::watchdog::Watchdog::start(watchdog, 1000);
+//!
+//! // And in the board's `Platform` implementation:
+//! fn watchdog(&self) -> Option<&hil::watchdog::Watchdog> {
+//!     Some(self.watchdog)
+//! }
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! - `command` 0: check whether the driver exists.
+//! - `command` 1: register for heartbeats, with a timeout of `arg1`
+//!   milliseconds. This counts as a first heartbeat.
+//! - `command` 2: heartbeat.
+//! - `command` 3: unregister.
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x90000;
+
+/// Longest heartbeat timeout an app can ask for, in milliseconds
+const MAX_TIMEOUT_MS: u32 = 60000;
+
+/// What to do when an app misses its heartbeat.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Policy {
+    /// Restart the app that missed it
+    RestartApp,
+    /// Stop tickling the hardware watchdog, so it resets the chip
+    ResetChip,
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Heartbeat timeout in alarm ticks, if the app registered
+    timeout: Option<u32>,
+    last_heartbeat: u32,
+}
+
+pub struct WatchdogService<'a, W: hil::watchdog::Watchdog + 'a, A: Alarm + 'a> {
+    watchdog: &'a W,
+    alarm: &'a A,
+    policy: Policy,
+    /// How often to check on the apps, in alarm ticks
+    check_interval: Cell<u32>,
+    /// Set once an app missed its heartbeat under `Policy::ResetChip`
+    expired: Cell<bool>,
+    apps: Grant<App>,
+}
+
+impl<'a, W: hil::watchdog::Watchdog, A: Alarm> WatchdogService<'a, W, A> {
+    pub fn new(
+        watchdog: &'a W,
+        alarm: &'a A,
+        policy: Policy,
+        grant: Grant<App>,
+    ) -> WatchdogService<'a, W, A> {
+        WatchdogService {
+            watchdog: watchdog,
+            alarm: alarm,
+            policy: policy,
+            check_interval: Cell::new(0),
+            expired: Cell::new(false),
+            apps: grant,
+        }
+    }
+
+    fn ms_to_ticks(ms: u32) -> u32 {
+        let freq = <A::Frequency>::frequency();
+        (ms / 1000) * freq + (ms % 1000) * freq / 1000
+    }
+
+    fn check_apps(&self) {
+        let now = self.alarm.now();
+        for app in self.apps.iter() {
+            let missed = app.enter(|app, _| match app.timeout {
+                Some(timeout) if now.wrapping_sub(app.last_heartbeat) > timeout => {
+                    Some(app.appid())
+                }
+                _ => None,
+            });
+
+            missed.map(|appid| match self.policy {
+                Policy::RestartApp => {
+                    appid.restart();
+                }
+                Policy::ResetChip => {
+                    self.expired.set(true);
+                }
+            });
+        }
+    }
+}
+
+impl<'a, W: hil::watchdog::Watchdog, A: Alarm> hil::watchdog::Watchdog
+    for WatchdogService<'a, W, A>
+{
+    fn start(&self, period: usize) {
+        self.expired.set(false);
+        self.watchdog.start(period);
+
+        // Check twice per period, so the main loop runs often enough to
+        // tickle the watchdog in time
+        let interval = Self::ms_to_ticks(period as u32 / 2);
+        self.check_interval.set(interval);
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(interval));
+    }
+
+    fn stop(&self) {
+        self.alarm.disable();
+        self.watchdog.stop();
+    }
+
+    fn tickle(&self) {
+        if !self.expired.get() {
+            self.watchdog.tickle();
+        }
+    }
+}
+
+impl<'a, W: hil::watchdog::Watchdog, A: Alarm> time::Client for WatchdogService<'a, W, A> {
+    fn fired(&self) {
+        self.check_apps();
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(self.check_interval.get()));
+    }
+}
+
+impl<'a, W: hil::watchdog::Watchdog, A: Alarm> Driver for WatchdogService<'a, W, A> {
+    /// Register for and send heartbeats
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register with a heartbeat timeout of `arg1` milliseconds.
+    /// - `2`: Heartbeat.
+    /// - `3`: Unregister.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let now = self.alarm.now();
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => {
+                if arg1 == 0 || arg1 > MAX_TIMEOUT_MS as usize {
+                    return ReturnCode::EINVAL;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.timeout = Some(Self::ms_to_ticks(arg1 as u32));
+                        app.last_heartbeat = now;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            2 => self.apps
+                .enter(appid, |app, _| {
+                    if app.timeout.is_none() {
+                        return ReturnCode::ERESERVE;
+                    }
+                    app.last_heartbeat = now;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            3 => self.apps
+                .enter(appid, |app, _| {
+                    app.timeout = None;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
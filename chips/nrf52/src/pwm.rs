@@ -0,0 +1,216 @@
+//! Pulse width modulation, nRF52
+//!
+//! Chapter 45 of the nRF52832 Product Specification v1.4:
+//!
+//! Each of the three PWM instances has four output channels that share one
+//! counter, so all outputs of an instance run at the same frequency but each
+//! has its own duty cycle. The duty cycles are read by EasyDMA from a
+//! sequence in RAM; the instance plays a one-step sequence and then keeps
+//! generating the last value until it is stopped.
+//!
+//! Starting an output on a pin takes a free channel of the instance, and
+//! fails with `EBUSY` if other outputs of the instance already run at a
+//! different frequency.
+
+use core::cell::Cell;
+use kernel::common::regs::{ReadWrite, WriteOnly};
+use kernel::common::VolatileCell;
+use kernel::hil;
+use kernel::ReturnCode;
+use nrf5x;
+use nrf5x::pinmux::Pinmux;
+
+/// PWM counter clock before prescaling
+const PWM_CLOCK_HZ: usize = 16_000_000;
+
+/// Largest COUNTERTOP value
+const MAX_COUNTERTOP: usize = 32767;
+
+/// Duty cycles are given as a fraction of this
+const MAX_DUTY_CYCLE: usize = 0xffff;
+
+const NUM_CHANNELS: usize = 4;
+
+#[repr(C)]
+struct PwmRegisters {
+    _reserved0: u32,
+    /// Stops PWM pulse generation
+    /// Address: 0x004 - 0x008
+    tasks_stop: WriteOnly<u32>,
+    /// Loads the first PWM value from sequence 0 and starts playback
+    /// Address: 0x008 - 0x010
+    tasks_seqstart: [WriteOnly<u32>; 2],
+    _reserved1: [u32; 316],
+    /// Enables the PWM module
+    /// Address: 0x500 - 0x504
+    enable: ReadWrite<u32>,
+    /// Selects operating mode of the wave counter
+    /// Address: 0x504 - 0x508
+    mode: ReadWrite<u32>,
+    /// Value up to which the pulse generator counter counts
+    /// Address: 0x508 - 0x50C
+    countertop: ReadWrite<u32>,
+    /// Configuration for PWM_CLK
+    /// Address: 0x50C - 0x510
+    prescaler: ReadWrite<u32>,
+    /// Configuration of the decoder
+    /// Address: 0x510 - 0x514
+    decoder: ReadWrite<u32>,
+    /// Number of playbacks of a loop
+    /// Address: 0x514 - 0x518
+    loop_: ReadWrite<u32>,
+    _reserved2: [u32; 2],
+    /// Sequence 0: pointer, number of values, refresh count, end delay
+    /// Address: 0x520 - 0x530
+    seq0_ptr: ReadWrite<u32>,
+    seq0_cnt: ReadWrite<u32>,
+    seq0_refresh: ReadWrite<u32>,
+    seq0_enddelay: ReadWrite<u32>,
+    _reserved3: [u32; 12],
+    /// Output pin select for each channel
+    /// Address: 0x560 - 0x570
+    psel_out: [ReadWrite<u32>; NUM_CHANNELS],
+}
+
+/// Value of a `PSEL.OUT` register for an unused channel
+const PSEL_DISCONNECTED: u32 = 1 << 31;
+
+/// Bit of a sequence value that makes the output start the period high
+const POLARITY_FALLING_EDGE: u16 = 1 << 15;
+
+/// Decoder setting: one sequence value per channel, advance every period
+const DECODER_INDIVIDUAL: u32 = 2;
+
+pub struct Pwm {
+    registers: *const PwmRegisters,
+    /// The pin driven by each channel
+    pins: [Cell<Option<u32>>; NUM_CHANNELS],
+    /// The sequence EasyDMA reads: one compare value per channel
+    sequence: [VolatileCell<u16>; NUM_CHANNELS],
+    frequency_hz: Cell<usize>,
+}
+
+pub static mut PWM0: Pwm = Pwm::new(0x4001C000);
+pub static mut PWM1: Pwm = Pwm::new(0x40021000);
+pub static mut PWM2: Pwm = Pwm::new(0x40022000);
+
+impl Pwm {
+    const fn new(base_addr: usize) -> Pwm {
+        Pwm {
+            registers: base_addr as *const PwmRegisters,
+            pins: [
+                Cell::new(None),
+                Cell::new(None),
+                Cell::new(None),
+                Cell::new(None),
+            ],
+            sequence: [
+                VolatileCell::new(0),
+                VolatileCell::new(0),
+                VolatileCell::new(0),
+                VolatileCell::new(0),
+            ],
+            frequency_hz: Cell::new(0),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.pins.iter().any(|pin| pin.get().is_some())
+    }
+
+    /// The prescaler setting and counter top for a frequency
+    fn period(frequency_hz: usize) -> Option<(u32, usize)> {
+        if frequency_hz == 0 {
+            return None;
+        }
+        (0..8)
+            .map(|prescaler| (prescaler, (PWM_CLOCK_HZ >> prescaler) / frequency_hz))
+            .find(|&(_, top)| top <= MAX_COUNTERTOP)
+            .and_then(|(prescaler, top)| if top < 3 { None } else { Some((prescaler, top)) })
+    }
+}
+
+impl hil::pwm::Pwm for Pwm {
+    type Pin = Pinmux;
+
+    fn start(&self, pin: &Pinmux, frequency_hz: usize, duty_cycle: usize) -> ReturnCode {
+        let regs = unsafe { &*self.registers };
+        let pin: u32 = (*pin).into();
+
+        let (prescaler, top) = match Pwm::period(frequency_hz) {
+            Some(period) => period,
+            None => return ReturnCode::EINVAL,
+        };
+        if duty_cycle > MAX_DUTY_CYCLE {
+            return ReturnCode::EINVAL;
+        }
+
+        // Reuse the channel already driving the pin, or take a free one
+        let channel = match self.pins.iter().position(|p| p.get() == Some(pin)) {
+            Some(channel) => channel,
+            None => match self.pins.iter().position(|p| p.get().is_none()) {
+                Some(channel) => channel,
+                None => return ReturnCode::ENOMEM,
+            },
+        };
+        let others_running = self.pins
+            .iter()
+            .enumerate()
+            .any(|(i, p)| i != channel && p.get().is_some());
+        if others_running && frequency_hz != self.frequency_hz.get() {
+            return ReturnCode::EBUSY;
+        }
+
+        // The pin must be an output for the PWM to drive it
+        unsafe {
+            hil::gpio::Pin::make_output(&nrf5x::gpio::PORT[pin as usize]);
+        }
+
+        let compare = top * duty_cycle / MAX_DUTY_CYCLE;
+        self.sequence[channel].set(POLARITY_FALLING_EDGE | compare as u16);
+        self.pins[channel].set(Some(pin));
+        self.frequency_hz.set(frequency_hz);
+
+        regs.psel_out[channel].set(pin);
+        regs.enable.set(1);
+        regs.mode.set(0); // Count up
+        regs.prescaler.set(prescaler);
+        regs.countertop.set(top as u32);
+        regs.decoder.set(DECODER_INDIVIDUAL);
+        regs.loop_.set(0);
+        regs.seq0_ptr.set(self.sequence.as_ptr() as u32);
+        regs.seq0_cnt.set(NUM_CHANNELS as u32);
+        regs.seq0_refresh.set(0);
+        regs.seq0_enddelay.set(0);
+        regs.tasks_seqstart[0].set(1);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self, pin: &Pinmux) -> ReturnCode {
+        let regs = unsafe { &*self.registers };
+        let pin: u32 = (*pin).into();
+
+        let channel = match self.pins.iter().position(|p| p.get() == Some(pin)) {
+            Some(channel) => channel,
+            None => return ReturnCode::EINVAL,
+        };
+        self.pins[channel].set(None);
+        self.sequence[channel].set(POLARITY_FALLING_EDGE);
+        regs.psel_out[channel].set(PSEL_DISCONNECTED);
+
+        if !self.is_running() {
+            regs.tasks_stop.set(1);
+            regs.enable.set(0);
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        // The counter must count to at least 3
+        PWM_CLOCK_HZ / 3
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        MAX_DUTY_CYCLE
+    }
+}
@@ -1,28 +1,21 @@
 //! AES128 driver, nRF5X-family
 //!
-//! Provides a simple driverto encrypt and decrypt
-//! messages using aes128-ctr mode on top of aes128-ecb.
+//! The nRF5x ECB peripheral can only encrypt a single block. This driver
+//! builds the `AES128` modes on top of it, one block at a time:
 //!
-//! Roughly, the module three buffers with the following content:
+//! * CTR: the counter is encrypted and the result XOR:ed with the input. The
+//!   input need not be a whole number of blocks.
+//! * ECB: each input block is encrypted.
+//! * CBC: each input block is XOR:ed with the previous ciphertext block (or the
+//!   IV) and encrypted.
 //!
-//! * Key
-//! * Initial counter
-//! * Payload, to be encrypted or decrypted
-//!
-//! ### Key
-//! The key is used for getting a key and configure it in the AES chip
-//!
-//! ### Initial Counter
-//! Counter to be used for aes-ctr and it is entered into AES to generate the
-//! the keystream. After each encryption the initial counter is incremented
-//!
-//! ### Payload
-//! Data to be encrypted or decrypted it is XOR:ed with the generated keystream
+//! Since the hardware cannot decrypt, ECB and CBC decryption are not
+//! supported and `crypt()` returns `ENOSUPPORT` for them. CBC encryption is
+//! all that CBC-MAC, and thus AES-CCM, needs.
 //!
 //! ### Things to highlight that can be improved:
 //!
 //! * ECB_DATA must be a static mut [u8] and can't be located in the struct
-//! * PAYLOAD size is restricted to 128 bytes
 //!
 //! Authors
 //! --------
@@ -31,29 +24,23 @@
 //! * Date: April 21, 2017
 
 use core::cell::Cell;
+use core::cmp;
 use kernel;
 use kernel::common::regs::{ReadWrite, WriteOnly};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::AES128_BLOCK_SIZE;
 use kernel::ReturnCode;
 
 // DMA buffer that the aes chip will mutate during encryption
 // Byte 0-15   - Key
-// Byte 16-32  - Payload
-// Byte 33-47  - Ciphertext
+// Byte 16-31  - Plaintext
+// Byte 32-47  - Ciphertext
 static mut ECB_DATA: [u8; 48] = [0; 48];
 
-#[allow(dead_code)]
-const KEY_START: usize = 0;
-#[allow(dead_code)]
-const KEY_END: usize = 15;
 const PLAINTEXT_START: usize = 16;
-const PLAINTEXT_END: usize = 32;
-#[allow(dead_code)]
-const CIPHERTEXT_START: usize = 33;
-#[allow(dead_code)]
-const CIPHERTEXT_END: usize = 47;
-const MAX_LENGTH: usize = 128;
+const CIPHERTEXT_START: usize = 32;
+const CIPHERTEXT_END: usize = 48;
 const AESECB_BASE: usize = 0x4000E000;
 
 #[repr(C)]
@@ -116,14 +103,25 @@ register_bitfields! [u32,
     ]
 ];
 
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Ctr,
+    Ecb,
+    Cbc,
+}
+
 pub struct AesECB<'a> {
     regs: *const AesEcbRegisters,
     client: Cell<Option<&'a kernel::hil::symmetric_encryption::Client<'a>>>,
-    /// Input either plaintext or ciphertext to be encrypted or decrypted.
-    input: TakeCell<'a, [u8]>,
-    output: TakeCell<'a, [u8]>,
-    /// Keystream to be XOR'ed with the input.
-    keystream: Cell<[u8; MAX_LENGTH]>,
+    /// Input, if it is not in `dest`
+    source: TakeCell<'a, [u8]>,
+    /// Output, and input if there is no `source`
+    dest: TakeCell<'a, [u8]>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+    /// The counter in CTR mode, or the previous ciphertext block in CBC mode
+    iv: Cell<[u8; AES128_BLOCK_SIZE]>,
+    /// How much of the input has been processed
     current_idx: Cell<usize>,
     start_idx: Cell<usize>,
     end_idx: Cell<usize>,
@@ -136,9 +134,11 @@ impl<'a> AesECB<'a> {
         AesECB {
             regs: AESECB_BASE as *const AesEcbRegisters,
             client: Cell::new(None),
-            input: TakeCell::empty(),
-            output: TakeCell::empty(),
-            keystream: Cell::new([0; MAX_LENGTH]),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            mode: Cell::new(Mode::Ctr),
+            encrypting: Cell::new(true),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
             current_idx: Cell::new(0),
             start_idx: Cell::new(0),
             end_idx: Cell::new(0),
@@ -152,21 +152,47 @@ impl<'a> AesECB<'a> {
         }
     }
 
+    fn len(&self) -> usize {
+        self.end_idx.get() - self.start_idx.get()
+    }
+
+    /// Read byte `i` of the input
+    fn input(&self, i: usize) -> u8 {
+        self.source.map_or_else(
+            || self.dest.map_or(0, |dest| dest[self.start_idx.get() + i]),
+            |source| source[i],
+        )
+    }
+
     // FIXME: should this be performed in constant time i.e. skip the break part
     // and always loop 16 times?
     fn update_ctr(&self) {
-        for i in (PLAINTEXT_START..PLAINTEXT_END).rev() {
-            unsafe {
-                ECB_DATA[i] += 1;
-                if ECB_DATA[i] != 0 {
-                    break;
-                }
+        let mut ctr = self.iv.get();
+        for i in (0..AES128_BLOCK_SIZE).rev() {
+            ctr[i] = ctr[i].wrapping_add(1);
+            if ctr[i] != 0 {
+                break;
             }
         }
+        self.iv.set(ctr);
     }
 
-    fn crypt(&self) {
+    /// Load the next block to encrypt and start the hardware
+    fn crypt_block(&self) {
         let regs = unsafe { &*self.regs };
+        let current_idx = self.current_idx.get();
+        let iv = self.iv.get();
+
+        for i in 0..AES128_BLOCK_SIZE {
+            let block = match self.mode.get() {
+                Mode::Ctr => iv[i],
+                Mode::Ecb => self.input(current_idx + i),
+                Mode::Cbc => self.input(current_idx + i) ^ iv[i],
+            };
+            unsafe {
+                ECB_DATA[PLAINTEXT_START + i] = block;
+            }
+        }
 
         regs.event_endecb.write(Event::READY::CLEAR);
         regs.task_startecb.set(1);
@@ -183,57 +209,39 @@ impl<'a> AesECB<'a> {
 
         if regs.event_endecb.get() == 1 {
             let current_idx = self.current_idx.get();
-            let end_idx = self.end_idx.get();
+            let start_idx = self.start_idx.get();
 
-            // Get the number of bytes to be used in the keystream/block
-            let take = match end_idx.checked_sub(current_idx) {
-                Some(v) if v > symmetric_encryption::AES128_BLOCK_SIZE => {
-                    symmetric_encryption::AES128_BLOCK_SIZE
-                }
-                Some(v) => v,
-                None => 0,
-            };
+            // Get the number of bytes in this block
+            let take = cmp::min(AES128_BLOCK_SIZE, self.len() - current_idx);
 
-            let mut ks = self.keystream.get();
+            let mut output = [0; AES128_BLOCK_SIZE];
+            output.copy_from_slice(unsafe { &ECB_DATA[CIPHERTEXT_START..CIPHERTEXT_END] });
 
-            // Append keystream to the KEYSTREAM array
-            if take > 0 {
-                for i in current_idx..current_idx + take {
-                    ks[i] = unsafe { ECB_DATA[i - current_idx + PLAINTEXT_END] }
-                }
-                self.current_idx.set(current_idx + take);
-                self.update_ctr();
+            for i in 0..take {
+                let byte = match self.mode.get() {
+                    Mode::Ctr => self.input(current_idx + i) ^ output[i],
+                    Mode::Ecb | Mode::Cbc => output[i],
+                };
+                self.dest.map(|dest| dest[start_idx + current_idx + i] = byte);
             }
-
-            // More bytes to encrypt!!!
-            if self.current_idx.get() < self.end_idx.get() {
-                self.crypt();
+            match self.mode.get() {
+                Mode::Ctr => self.update_ctr(),
+                Mode::Ecb => {}
+                Mode::Cbc => self.iv.set(output),
             }
-            // Entire keystream generated we are done!
-            // XOR keystream the input
-            else if self.input.is_some() && self.output.is_some() {
-                self.input.take().map(|slice| {
-                    self.output.take().map(|buf| {
-                        let start = self.start_idx.get();
-                        let end = self.end_idx.get();
-                        let len = end - start;
-
-                        for ((i, out), inp) in buf.as_mut()[start..end]
-                            .iter_mut()
-                            .enumerate()
-                            .zip(slice.as_ref()[0..len].iter())
-                        {
-                            *out = ks[i] ^ *inp;
-                        }
-
-                        self.client
-                            .get()
-                            .map(move |client| client.crypt_done(Some(slice), buf));
-                    });
+            self.current_idx.set(current_idx + take);
+
+            if self.current_idx.get() < self.len() {
+                // More blocks to encrypt
+                self.crypt_block();
+            } else {
+                self.dest.take().map(|dest| {
+                    let source = self.source.take();
+                    self.client
+                        .get()
+                        .map(move |client| client.crypt_done(source, dest));
                 });
             }
-
-            self.keystream.set(ks);
         }
     }
 
@@ -279,14 +287,12 @@ impl<'a> kernel::hil::symmetric_encryption::AES128<'a> for AesECB<'a> {
     }
 
     fn set_iv(&self, iv: &[u8]) -> ReturnCode {
-        if iv.len() != symmetric_encryption::AES128_BLOCK_SIZE {
+        if iv.len() != AES128_BLOCK_SIZE {
             ReturnCode::EINVAL
         } else {
-            for (i, c) in iv.iter().enumerate() {
-                unsafe {
-                    ECB_DATA[i + PLAINTEXT_START] = *c;
-                }
-            }
+            let mut new_iv = [0; AES128_BLOCK_SIZE];
+            new_iv.copy_from_slice(iv);
+            self.iv.set(new_iv);
             ReturnCode::SUCCESS
         }
     }
@@ -296,8 +302,6 @@ impl<'a> kernel::hil::symmetric_encryption::AES128<'a> for AesECB<'a> {
         ()
     }
 
-    // start_index and stop_index not used!!!
-    // assuming that
     fn crypt(
         &'a self,
         source: Option<&'a mut [u8]>,
@@ -305,33 +309,55 @@ impl<'a> kernel::hil::symmetric_encryption::AES128<'a> for AesECB<'a> {
         start_index: usize,
         stop_index: usize,
     ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
-        match source {
-            None => Some((ReturnCode::EINVAL, source, dest)),
-            Some(src) => {
-                if stop_index - start_index <= MAX_LENGTH {
-                    // replace buffers
-                    self.input.replace(src);
-                    self.output.replace(dest);
-
-                    // configure buffer offsets
-                    self.current_idx.set(0);
-                    self.start_idx.set(start_index);
-                    self.end_idx.set(stop_index);
-
-                    // start crypt
-                    self.crypt();
-                    None
-                } else {
-                    Some((ReturnCode::ESIZE, Some(src), dest))
-                }
+        if self.dest.is_some() {
+            return Some((ReturnCode::EBUSY, source, dest));
+        }
+        let len = match stop_index.checked_sub(start_index) {
+            Some(len) if len > 0 && stop_index <= dest.len() => len,
+            _ => return Some((ReturnCode::EINVAL, source, dest)),
+        };
+        if source.as_ref().map_or(false, |source| source.len() != len) {
+            return Some((ReturnCode::EINVAL, source, dest));
+        }
+        if self.mode.get() != Mode::Ctr {
+            if !self.encrypting.get() {
+                // The hardware can only encrypt
+                return Some((ReturnCode::ENOSUPPORT, source, dest));
+            }
+            if len % AES128_BLOCK_SIZE != 0 {
+                return Some((ReturnCode::EINVAL, source, dest));
             }
         }
+
+        self.source.put(source);
+        self.dest.replace(dest);
+        self.current_idx.set(0);
+        self.start_idx.set(start_index);
+        self.end_idx.set(stop_index);
+
+        self.crypt_block();
+        None
     }
 }
 
 impl<'a> kernel::hil::symmetric_encryption::AES128Ctr for AesECB<'a> {
-    // not needed by NRF5x (the configuration is the same for encryption and decryption)
-    fn set_mode_aes128ctr(&self, _encrypting: bool) {
-        ()
+    // the configuration is the same for encryption and decryption
+    fn set_mode_aes128ctr(&self, encrypting: bool) {
+        self.mode.set(Mode::Ctr);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl<'a> kernel::hil::symmetric_encryption::AES128ECB for AesECB<'a> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.mode.set(Mode::Ecb);
+        self.encrypting.set(encrypting);
+    }
+}
+
+impl<'a> kernel::hil::symmetric_encryption::AES128CBC for AesECB<'a> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) {
+        self.mode.set(Mode::Cbc);
+        self.encrypting.set(encrypting);
     }
 }
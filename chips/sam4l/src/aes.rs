@@ -507,4 +507,10 @@ impl<'a> hil::symmetric_encryption::AES128CBC for Aes<'a> {
     }
 }
 
+impl<'a> hil::symmetric_encryption::AES128ECB for Aes<'a> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.set_mode(encrypting, ConfidentialityMode::ECB);
+    }
+}
+
 pub static mut AES: Aes<'static> = Aes::new();
@@ -11,6 +11,7 @@ use flashcalw;
 use gpio;
 use helpers::{DeferredCall, Task};
 use i2c;
+use kernel::hil::dma::Width;
 use kernel::support;
 use kernel::Chip;
 use pm;
@@ -27,36 +28,36 @@ pub struct Sam4l {
 impl Sam4l {
     pub unsafe fn new() -> Sam4l {
         usart::USART0.set_dma(&mut dma::DMA_CHANNELS[0], &mut dma::DMA_CHANNELS[1]);
-        dma::DMA_CHANNELS[0].initialize(&mut usart::USART0, dma::DMAWidth::Width8Bit);
-        dma::DMA_CHANNELS[1].initialize(&mut usart::USART0, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[0].initialize(&mut usart::USART0, Width::Width8Bit);
+        dma::DMA_CHANNELS[1].initialize(&mut usart::USART0, Width::Width8Bit);
 
         usart::USART1.set_dma(&mut dma::DMA_CHANNELS[2], &mut dma::DMA_CHANNELS[3]);
-        dma::DMA_CHANNELS[2].initialize(&mut usart::USART1, dma::DMAWidth::Width8Bit);
-        dma::DMA_CHANNELS[3].initialize(&mut usart::USART1, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[2].initialize(&mut usart::USART1, Width::Width8Bit);
+        dma::DMA_CHANNELS[3].initialize(&mut usart::USART1, Width::Width8Bit);
 
         usart::USART2.set_dma(&mut dma::DMA_CHANNELS[4], &mut dma::DMA_CHANNELS[5]);
-        dma::DMA_CHANNELS[4].initialize(&mut usart::USART2, dma::DMAWidth::Width8Bit);
-        dma::DMA_CHANNELS[5].initialize(&mut usart::USART2, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[4].initialize(&mut usart::USART2, Width::Width8Bit);
+        dma::DMA_CHANNELS[5].initialize(&mut usart::USART2, Width::Width8Bit);
 
         usart::USART3.set_dma(&mut dma::DMA_CHANNELS[6], &mut dma::DMA_CHANNELS[7]);
-        dma::DMA_CHANNELS[6].initialize(&mut usart::USART3, dma::DMAWidth::Width8Bit);
-        dma::DMA_CHANNELS[7].initialize(&mut usart::USART3, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[6].initialize(&mut usart::USART3, Width::Width8Bit);
+        dma::DMA_CHANNELS[7].initialize(&mut usart::USART3, Width::Width8Bit);
 
         spi::SPI.set_dma(&mut dma::DMA_CHANNELS[8], &mut dma::DMA_CHANNELS[9]);
-        dma::DMA_CHANNELS[8].initialize(&mut spi::SPI, dma::DMAWidth::Width8Bit);
-        dma::DMA_CHANNELS[9].initialize(&mut spi::SPI, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[8].initialize(&mut spi::SPI, Width::Width8Bit);
+        dma::DMA_CHANNELS[9].initialize(&mut spi::SPI, Width::Width8Bit);
 
         i2c::I2C0.set_dma(&dma::DMA_CHANNELS[10]);
-        dma::DMA_CHANNELS[10].initialize(&mut i2c::I2C0, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[10].initialize(&mut i2c::I2C0, Width::Width8Bit);
 
         i2c::I2C1.set_dma(&dma::DMA_CHANNELS[11]);
-        dma::DMA_CHANNELS[11].initialize(&mut i2c::I2C1, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[11].initialize(&mut i2c::I2C1, Width::Width8Bit);
 
         i2c::I2C2.set_dma(&dma::DMA_CHANNELS[12]);
-        dma::DMA_CHANNELS[12].initialize(&mut i2c::I2C2, dma::DMAWidth::Width8Bit);
+        dma::DMA_CHANNELS[12].initialize(&mut i2c::I2C2, Width::Width8Bit);
 
         adc::ADC0.set_dma(&dma::DMA_CHANNELS[13]);
-        dma::DMA_CHANNELS[13].initialize(&mut adc::ADC0, dma::DMAWidth::Width16Bit);
+        dma::DMA_CHANNELS[13].initialize(&mut adc::ADC0, Width::Width16Bit);
 
         Sam4l {
             mpu: cortexm4::mpu::MPU::new(),
@@ -314,10 +314,7 @@ impl<'a> Crccu<'a> {
             // A DMA transfer has completed
 
             if self.get_tcr().interrupt_enabled() {
-                if let Some(client) = self.get_client() {
-                    let result = post_process(regs.sr.read(Status::CRC), self.alg.get());
-                    client.receive_result(result);
-                }
+                let result = post_process(regs.sr.read(Status::CRC), self.alg.get());
 
                 // Disable the unit
                 regs.mr.write(Mode::ENABLE::Disabled);
@@ -330,6 +327,14 @@ impl<'a> Crccu<'a> {
 
                 // Disable DMA channel
                 regs.dmadis.write(DmaDisable::DMADIS::SET);
+
+                // Only now call the client. `capsules::crc` starts the next
+                // waiting app's computation from this callback, which the
+                // shutdown above would otherwise cancel; this also makes the
+                // CRCCU behave like `capsules::sw_crc::SoftwareCrc`
+                if let Some(client) = self.get_client() {
+                    client.receive_result(result);
+                }
             }
         }
     }
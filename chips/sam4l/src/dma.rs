@@ -1,10 +1,17 @@
 //! Implementation of the PDCA DMA peripheral.
+//!
+//! Channels 0-13 are assigned to the USARTs, SPI, I2C and ADC drivers in
+//! `chip.rs`. The rest can be taken through `hil::dma::DmaController` on
+//! `PDCA`.
 
 use core::cell::Cell;
 use core::{cmp, intrinsics};
 use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::take_cell::TakeCell;
 use kernel::common::VolatileCell;
+use kernel::hil;
+use kernel::hil::dma::{DmaChannel, Width};
+use kernel::ReturnCode;
 use pm;
 
 /// Memory registers for a DMA channel. Section 16.6.1 of the datasheet.
@@ -164,12 +171,11 @@ pub enum DMAPeripheral {
     LCDCA_ABMDR_TX = 38,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-#[repr(u8)]
-pub enum DMAWidth {
-    Width8Bit = 0,
-    Width16Bit = 1,
-    Width32Bit = 2,
+impl DMAPeripheral {
+    /// Whether the function moves data from the peripheral to memory.
+    pub fn is_receive(self) -> bool {
+        (self as u8) < (DMAPeripheral::USART0_TX as u8)
+    }
 }
 
 pub static mut DMA_CHANNELS: [DMAChannel; 16] = [
@@ -191,35 +197,112 @@ pub static mut DMA_CHANNELS: [DMAChannel; 16] = [
     DMAChannel::new(DMAChannelNum::DMAChannel15),
 ];
 
+/// The PDCA as a whole, which hands out the channels not assigned to a
+/// peripheral driver in `chip.rs`.
+pub struct Pdca(());
+
+pub static mut PDCA: Pdca = Pdca(());
+
+impl hil::dma::DmaController for Pdca {
+    type Channel = DMAChannel;
+
+    fn allocate_channel(&self) -> Option<&'static DMAChannel> {
+        unsafe { DMA_CHANNELS.iter() }
+            .find(|channel| !channel.allocated.get())
+            .map(|channel| {
+                channel.allocated.set(true);
+                channel
+            })
+    }
+
+    fn release_channel(&self, channel: &'static DMAChannel) {
+        channel.abort_transfer();
+        channel.disable();
+        channel.client.set(None);
+        channel.allocated.set(false);
+    }
+}
+
 pub struct DMAChannel {
     registers: *mut DMARegisters,
-    client: Cell<Option<&'static DMAClient>>,
-    width: Cell<DMAWidth>,
+    client: Cell<Option<&'static hil::dma::Client<DMAPeripheral>>>,
+    width: Cell<Width>,
     enabled: Cell<bool>,
+    allocated: Cell<bool>,
     buffer: TakeCell<'static, [u8]>,
 }
 
-pub trait DMAClient {
-    fn xfer_done(&self, pid: DMAPeripheral);
-}
-
 impl DMAChannel {
     const fn new(channel: DMAChannelNum) -> DMAChannel {
         DMAChannel {
             registers: (DMA_BASE_ADDR + (channel as usize) * DMA_CHANNEL_SIZE) as *mut DMARegisters,
             client: Cell::new(None),
-            width: Cell::new(DMAWidth::Width8Bit),
+            width: Cell::new(Width::Width8Bit),
             enabled: Cell::new(false),
+            allocated: Cell::new(false),
             buffer: TakeCell::empty(),
         }
     }
 
-    pub fn initialize(&self, client: &'static mut DMAClient, width: DMAWidth) {
+    /// Assign the channel to a peripheral driver, so that it is never handed
+    /// out by `PDCA.allocate_channel()`.
+    pub fn initialize(&self, client: &'static hil::dma::Client<DMAPeripheral>, width: Width) {
+        self.allocated.set(true);
+        self.client.set(Some(client));
+        self.width.set(width);
+    }
+
+    pub fn handle_interrupt(&mut self) {
+        let registers: &DMARegisters = unsafe { &*self.registers };
+        registers
+            .idr
+            .write(Interrupt::TERR::SET + Interrupt::TRC::SET + Interrupt::RCZ::SET);
+        let channel = registers.psr.get();
+
+        self.client.get().map(|client| {
+            client.transfer_done(channel);
+        });
+    }
+
+    fn prepare_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], mut len: usize) {
+        // TODO(alevy): take care of zero length case
+
+        let registers: &DMARegisters = unsafe { &*self.registers };
+
+        let maxlen = buf.len() / self.width.get().bytes();
+        len = cmp::min(len, maxlen);
+        registers.mr.write(match self.width.get() {
+            Width::Width8Bit => Mode::SIZE::Byte,
+            Width::Width16Bit => Mode::SIZE::Halfword,
+            Width::Width32Bit => Mode::SIZE::Word,
+        });
+
+        registers.psr.set(pid);
+        registers
+            .marr
+            .write(MemoryAddressReload::MARV.val(&buf[0] as *const u8 as u32));
+        registers.tcrr.write(TransferCounter::TCV.val(len as u32));
+
+        registers.ier.write(Interrupt::TRC::SET);
+
+        // Store the buffer reference in the TakeCell so it can be returned to
+        // the caller in `handle_interrupt`
+        self.buffer.replace(buf);
+    }
+}
+
+impl hil::dma::DmaChannel for DMAChannel {
+    type Peripheral = DMAPeripheral;
+
+    fn set_client(&self, client: &'static hil::dma::Client<DMAPeripheral>) {
         self.client.set(Some(client));
+    }
+
+    fn set_width(&self, width: Width) {
         self.width.set(width);
     }
 
-    pub fn enable(&self) {
+    fn enable(&self) {
         pm::enable_clock(pm::Clock::HSB(pm::HSBClock::PDCA));
         pm::enable_clock(pm::Clock::PBB(pm::PBBClock::PDCA));
 
@@ -241,7 +324,7 @@ impl DMAChannel {
         }
     }
 
-    pub fn disable(&self) {
+    fn disable(&self) {
         if self.enabled.get() {
             unsafe {
                 let num_enabled = intrinsics::atomic_xsub(&mut NUM_ENABLED, 1);
@@ -256,61 +339,46 @@ impl DMAChannel {
         }
     }
 
-    pub fn is_enabled(&self) -> bool {
+    fn is_enabled(&self) -> bool {
         self.enabled.get()
     }
 
-    pub fn handle_interrupt(&mut self) {
-        let registers: &DMARegisters = unsafe { &*self.registers };
-        registers
-            .idr
-            .write(Interrupt::TERR::SET + Interrupt::TRC::SET + Interrupt::RCZ::SET);
-        let channel = registers.psr.get();
-
-        self.client.get().as_mut().map(|client| {
-            client.xfer_done(channel);
-        });
+    fn prepare_transmit(
+        &self,
+        pid: DMAPeripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode {
+        if pid.is_receive() {
+            self.buffer.replace(buf);
+            return ReturnCode::EINVAL;
+        }
+        self.prepare_xfer(pid, buf, len);
+        ReturnCode::SUCCESS
     }
 
-    pub fn start_xfer(&self) {
-        let registers: &DMARegisters = unsafe { &*self.registers };
-        registers.cr.write(Control::TEN::SET);
+    fn prepare_receive(
+        &self,
+        pid: DMAPeripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode {
+        if !pid.is_receive() {
+            self.buffer.replace(buf);
+            return ReturnCode::EINVAL;
+        }
+        self.prepare_xfer(pid, buf, len);
+        ReturnCode::SUCCESS
     }
 
-    pub fn prepare_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], mut len: usize) {
-        // TODO(alevy): take care of zero length case
-
+    fn start_transfer(&self) {
         let registers: &DMARegisters = unsafe { &*self.registers };
-
-        let maxlen = buf.len() / match self.width.get() {
-                DMAWidth::Width8Bit /*  DMA is acting on bytes     */ => 1,
-                DMAWidth::Width16Bit /* DMA is acting on halfwords */ => 2,
-                DMAWidth::Width32Bit /* DMA is acting on words     */ => 4,
-            };
-        len = cmp::min(len, maxlen);
-        registers.mr.write(Mode::SIZE.val(self.width.get() as u32));
-
-        registers.psr.set(pid);
-        registers
-            .marr
-            .write(MemoryAddressReload::MARV.val(&buf[0] as *const u8 as u32));
-        registers.tcrr.write(TransferCounter::TCV.val(len as u32));
-
-        registers.ier.write(Interrupt::TRC::SET);
-
-        // Store the buffer reference in the TakeCell so it can be returned to
-        // the caller in `handle_interrupt`
-        self.buffer.replace(buf);
-    }
-
-    pub fn do_xfer(&self, pid: DMAPeripheral, buf: &'static mut [u8], len: usize) {
-        self.prepare_xfer(pid, buf, len);
-        self.start_xfer();
+        registers.cr.write(Control::TEN::SET);
     }
 
     /// Aborts any current transactions and returns the buffer used in the
     /// transaction.
-    pub fn abort_xfer(&self) -> Option<&'static mut [u8]> {
+    fn abort_transfer(&self) -> Option<&'static mut [u8]> {
         let registers: &DMARegisters = unsafe { &*self.registers };
         registers
             .idr
@@ -322,7 +390,7 @@ impl DMAChannel {
         self.buffer.take()
     }
 
-    pub fn transfer_counter(&self) -> usize {
+    fn transfer_counter(&self) -> usize {
         let registers: &DMARegisters = unsafe { &*self.registers };
         registers.tcr.read(TransferCounter::TCV) as usize
     }
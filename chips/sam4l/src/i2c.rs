@@ -10,11 +10,12 @@
 //! CHANGE THIS DRIVER, TEST RIGOROUSLY!!!
 
 use core::cell::Cell;
-use dma::{DMAChannel, DMAClient, DMAPeripheral};
+use dma::{DMAChannel, DMAPeripheral};
 use kernel::common::peripherals::{PeripheralManagement, PeripheralManager};
 use kernel::common::regs::{FieldValue, ReadOnly, ReadWrite, WriteOnly};
 use kernel::common::take_cell::TakeCell;
 use kernel::hil;
+use kernel::hil::dma::DmaChannel;
 use kernel::{ClockInterface, StaticRef};
 use pm;
 
@@ -802,7 +803,7 @@ impl I2CHw {
                     self.master_client.get().map(|client| {
                         let buf = match self.dma.get() {
                             Some(dma) => {
-                                let b = dma.abort_xfer();
+                                let b = dma.abort_transfer();
                                 self.dma.set(Some(dma));
                                 b
                             }
@@ -844,7 +845,7 @@ impl I2CHw {
                         self.master_client.get().map(|client| {
                             let buf = match self.dma.get() {
                                 Some(dma) => {
-                                    let b = dma.abort_xfer();
+                                    let b = dma.abort_transfer();
                                     self.dma.set(Some(dma));
                                     b
                                 }
@@ -867,9 +868,9 @@ impl I2CHw {
                         );
                     }
                     self.dma.get().map(|dma| {
-                        let buf = dma.abort_xfer().unwrap();
-                        dma.prepare_xfer(dma_periph, buf, len);
-                        dma.start_xfer();
+                        let buf = dma.abort_transfer().unwrap();
+                        dma.prepare_receive(dma_periph, buf, len);
+                        dma.start_transfer();
                     });
                 }
             }
@@ -936,10 +937,10 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.get().map(move |dma| {
             dma.enable();
-            dma.prepare_xfer(self.dma_pids.1, data, len as usize);
+            dma.prepare_transmit(self.dma_pids.1, data, len as usize);
             self.setup_xfer(twim, chip, flags, Command::READ::Transmit, len);
             self.master_enable(twim);
-            dma.start_xfer();
+            dma.start_transfer();
         });
     }
 
@@ -953,10 +954,10 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.get().map(move |dma| {
             dma.enable();
-            dma.prepare_xfer(self.dma_pids.0, data, len as usize);
+            dma.prepare_receive(self.dma_pids.0, data, len as usize);
             self.setup_xfer(twim, chip, flags, Command::READ::Receive, len);
             self.master_enable(twim);
-            dma.start_xfer();
+            dma.start_transfer();
         });
     }
 
@@ -964,7 +965,7 @@ impl I2CHw {
         let twim = &TWIMRegisterManager::new(&self);
         self.dma.get().map(move |dma| {
             dma.enable();
-            dma.prepare_xfer(self.dma_pids.1, data, split as usize);
+            dma.prepare_transmit(self.dma_pids.1, data, split as usize);
             self.setup_xfer(
                 twim,
                 chip,
@@ -980,7 +981,7 @@ impl I2CHw {
                 read_len,
             );
             self.on_deck.set(Some((self.dma_pids.0, read_len as usize)));
-            dma.start_xfer();
+            dma.start_transfer();
         });
     }
 
@@ -1296,8 +1297,8 @@ impl I2CHw {
     }
 }
 
-impl DMAClient for I2CHw {
-    fn xfer_done(&self, _pid: DMAPeripheral) {}
+impl hil::dma::Client<DMAPeripheral> for I2CHw {
+    fn transfer_done(&self, _pid: DMAPeripheral) {}
 }
 
 impl hil::i2c::I2CMaster for I2CHw {
@@ -10,6 +10,7 @@ use kernel::common::regs::{ReadOnly, ReadWrite, WriteOnly};
 use kernel::ReturnCode;
 // other modules
 use kernel::hil;
+use kernel::hil::dma::DmaChannel;
 // local modules
 use pm;
 
@@ -473,7 +474,7 @@ impl USART {
             let mut length = 0;
             let buffer = self.rx_dma.get().map_or(None, |rx_dma| {
                 length = self.rx_len.get() - rx_dma.transfer_counter();
-                let buf = rx_dma.abort_xfer();
+                let buf = rx_dma.abort_transfer();
                 rx_dma.disable();
                 buf
             });
@@ -501,7 +502,7 @@ impl USART {
             let mut length = 0;
             let buffer = self.tx_dma.get().map_or(None, |tx_dma| {
                 length = self.tx_len.get() - tx_dma.transfer_counter();
-                let buf = tx_dma.abort_xfer();
+                let buf = tx_dma.abort_transfer();
                 tx_dma.disable();
                 buf
             });
@@ -594,7 +595,7 @@ impl USART {
             // state machine, and clients cannot issue other USART calls from
             // the callback.
             let buffer = self.tx_dma.get().map_or(None, |tx_dma| {
-                let buf = tx_dma.abort_xfer();
+                let buf = tx_dma.abort_transfer();
                 tx_dma.disable();
                 buf
             });
@@ -684,8 +685,8 @@ impl USART {
     }
 }
 
-impl dma::DMAClient for USART {
-    fn xfer_done(&self, pid: dma::DMAPeripheral) {
+impl hil::dma::Client<dma::DMAPeripheral> for USART {
+    fn transfer_done(&self, pid: dma::DMAPeripheral) {
         let usart = &USARTRegManager::new(&self);
 
         match self.usart_mode.get() {
@@ -701,7 +702,7 @@ impl dma::DMAClient for USART {
 
                     // get buffer
                     let buffer = self.rx_dma.get().map_or(None, |rx_dma| {
-                        let buf = rx_dma.abort_xfer();
+                        let buf = rx_dma.abort_transfer();
                         rx_dma.disable();
                         buf
                     });
@@ -760,13 +761,13 @@ impl dma::DMAClient for USART {
 
                     // get buffer
                     let txbuf = self.tx_dma.get().map_or(None, |dma| {
-                        let buf = dma.abort_xfer();
+                        let buf = dma.abort_transfer();
                         dma.disable();
                         buf
                     });
 
                     let rxbuf = self.rx_dma.get().map_or(None, |dma| {
-                        let buf = dma.abort_xfer();
+                        let buf = dma.abort_transfer();
                         dma.disable();
                         buf
                     });
@@ -850,7 +851,7 @@ impl hil::uart::UART for USART {
         // set up dma transfer and start transmission
         self.tx_dma.get().map(move |dma| {
             dma.enable();
-            dma.do_xfer(self.tx_dma_peripheral, tx_data, tx_len);
+            dma.transmit(self.tx_dma_peripheral, tx_data, tx_len);
             self.tx_len.set(tx_len);
         });
     }
@@ -875,7 +876,7 @@ impl hil::uart::UART for USART {
         // set up dma transfer and start reception
         self.rx_dma.get().map(move |dma| {
             dma.enable();
-            dma.do_xfer(self.rx_dma_peripheral, rx_buffer, length);
+            dma.receive(self.rx_dma_peripheral, rx_buffer, length);
             self.rx_len.set(rx_len);
         });
     }
@@ -900,7 +901,7 @@ impl hil::uart::UARTAdvanced for USART {
         self.rx_dma.get().map(move |dma| {
             dma.enable();
             let length = rx_buffer.len();
-            dma.do_xfer(self.rx_dma_peripheral, rx_buffer, length);
+            dma.receive(self.rx_dma_peripheral, rx_buffer, length);
             self.rx_len.set(length);
         });
     }
@@ -923,7 +924,7 @@ impl hil::uart::UARTAdvanced for USART {
         self.rx_dma.get().map(move |dma| {
             dma.enable();
             let length = rx_buffer.len();
-            dma.do_xfer(self.rx_dma_peripheral, rx_buffer, length);
+            dma.receive(self.rx_dma_peripheral, rx_buffer, length);
             self.rx_len.set(length);
         });
     }
@@ -1004,12 +1005,12 @@ impl hil::spi::SpiMaster for USART {
                         self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
                         self.usart_rx_state.set(USARTStateRX::Idle);
                         dma.enable();
-                        dma.do_xfer(self.tx_dma_peripheral, write_buffer, count);
+                        dma.transmit(self.tx_dma_peripheral, write_buffer, count);
 
                         // Start the read transaction.
                         self.usart_rx_state.set(USARTStateRX::DMA_Receiving);
                         read.enable();
-                        read.do_xfer(self.rx_dma_peripheral, rbuf, count);
+                        read.receive(self.rx_dma_peripheral, rbuf, count);
                     });
                 });
             });
@@ -1019,7 +1020,7 @@ impl hil::spi::SpiMaster for USART {
                 self.usart_tx_state.set(USARTStateTX::DMA_Transmitting);
                 self.usart_rx_state.set(USARTStateRX::Idle);
                 dma.enable();
-                dma.do_xfer(self.tx_dma_peripheral, write_buffer, count);
+                dma.transmit(self.tx_dma_peripheral, write_buffer, count);
             });
         }
 
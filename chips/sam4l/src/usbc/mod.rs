@@ -675,14 +675,14 @@ impl<'a> Usbc<'a> {
         if config.matches_all(EndpointConfig::EPTYPE::Control) {
             endpoint_enable_interrupts(endpoint, EndpointControl::RXSTPE::SET);
             state.endpoint_states[endpoint] = EndpointState::Ctrl(CtrlState::Init);
-        } else if config.matches_all(EndpointConfig::EPTYPE::Bulk + EndpointConfig::EPDIR::In) {
+        } else if config.matches_all(EndpointConfig::EPDIR::In) {
+            // Bulk, interrupt and isochronous IN endpoints are all serviced
+            // the same way; they differ only in how the host schedules them
             endpoint_enable_interrupts(endpoint, EndpointControl::TXINE::SET);
             state.endpoint_states[endpoint] = EndpointState::BulkIn(BulkInState::Init);
-        } else if config.matches_all(EndpointConfig::EPTYPE::Bulk + EndpointConfig::EPDIR::Out) {
+        } else {
             endpoint_enable_interrupts(endpoint, EndpointControl::RXOUTE::SET);
             state.endpoint_states[endpoint] = EndpointState::BulkOut(BulkOutState::Init);
-        } else {
-            // Other endpoint types unimplemented
         }
 
         debug1!("Initialized endpoint {}", endpoint);
@@ -1405,9 +1405,36 @@ fn endpoint_enable_interrupts(endpoint: usize, mask: FieldValue<u32, EndpointCon
     usbc_regs().ueconset[endpoint].write(mask);
 }
 
+/// The EPTYPE field for an endpoint of the given transfer type
+fn endpoint_type(transfer_type: TransferType) -> FieldValue<u32, EndpointConfig::Register> {
+    match transfer_type {
+        TransferType::Control => EndpointConfig::EPTYPE::Control,
+        TransferType::Isochronous => EndpointConfig::EPTYPE::Isochronous,
+        TransferType::Bulk => EndpointConfig::EPTYPE::Bulk,
+        TransferType::Interrupt => EndpointConfig::EPTYPE::Interrupt,
+    }
+}
+
+/// The EPSIZE field for the smallest bank that holds a packet of
+/// `max_packet_size` bytes
+fn endpoint_size(max_packet_size: usize) -> FieldValue<u32, EndpointConfig::Register> {
+    if max_packet_size > 1024 {
+        client_err!("Bad endpoint packet size");
+    }
+
+    // Bank sizes go from 8 (EPSIZE = 0) to 1024 (EPSIZE = 7) bytes
+    let mut size = 0;
+    while size < 7 && (8 << size) < max_packet_size {
+        size += 1;
+    }
+    EndpointConfig::EPSIZE.val(size)
+}
+
 impl<'a> UsbController for Usbc<'a> {
     fn endpoint_set_buffer<'b>(&'b self, endpoint: usize, buf: &[VolatileCell<u8>]) {
-        if buf.len() != 8 {
+        // The buffer holds one bank, which is a power of two between 8 and
+        // 1024 bytes and must be at least the endpoint's max packet size
+        if buf.len() < 8 || buf.len() > 1024 || !buf.len().is_power_of_two() {
             client_err!("Bad endpoint buffer size");
         }
 
@@ -1490,6 +1517,34 @@ impl<'a> UsbController for Usbc<'a> {
         self._endpoint_enable(endpoint, endpoint_cfg)
     }
 
+    fn endpoint_in_enable(
+        &self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        max_packet_size: usize,
+    ) {
+        let endpoint_cfg = LocalRegisterCopy::new(From::from(
+            endpoint_type(transfer_type) + EndpointConfig::EPDIR::In
+                + endpoint_size(max_packet_size) + EndpointConfig::EPBK::Single,
+        ));
+
+        self._endpoint_enable(endpoint, endpoint_cfg)
+    }
+
+    fn endpoint_out_enable(
+        &self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        max_packet_size: usize,
+    ) {
+        let endpoint_cfg = LocalRegisterCopy::new(From::from(
+            endpoint_type(transfer_type) + EndpointConfig::EPDIR::Out
+                + endpoint_size(max_packet_size) + EndpointConfig::EPBK::Single,
+        ));
+
+        self._endpoint_enable(endpoint, endpoint_cfg)
+    }
+
     fn endpoint_bulk_resume(&self, endpoint: usize) {
         let mut requests = self.requests[endpoint].get();
         requests.resume = true;
@@ -1,6 +1,7 @@
 use core::cell::Cell;
 use kernel::common::VolatileCell;
 use kernel::hil;
+use kernel::ReturnCode;
 use sysctl;
 
 #[repr(C)]
@@ -130,3 +131,117 @@ impl hil::time::Alarm for AlarmTimer {
         regs.tamatchr.get()
     }
 }
+
+const TIMER1_BASE: usize = 0x40031000;
+const TIMER2_BASE: usize = 0x40032000;
+
+/// Timers 1 and 2 generate PWM signals on their CCP pins. The board must
+/// configure those pins for the timer's alternate function.
+pub static mut PWM_TIMER1: PwmTimer =
+    PwmTimer::new(TIMER1_BASE, sysctl::Clock::TIMER(sysctl::RCGCTIMER::TIMER1));
+pub static mut PWM_TIMER2: PwmTimer =
+    PwmTimer::new(TIMER2_BASE, sysctl::Clock::TIMER(sysctl::RCGCTIMER::TIMER2));
+
+/// PWM counter clock (PIOSC)
+const PWM_CLOCK_HZ: usize = 16_000_000;
+
+/// Largest period of a 16-bit timer extended by its 8-bit prescaler
+const MAX_PERIOD: usize = 0xffffff;
+
+/// Duty cycles are given as a fraction of this
+const MAX_DUTY_CYCLE: usize = 0xffff;
+
+/// The two outputs of a timer, one for each half
+#[derive(Copy, Clone, PartialEq)]
+pub enum PwmOutput {
+    /// Timer A, on the timer's CCP0 pin
+    A,
+    /// Timer B, on the timer's CCP1 pin
+    B,
+}
+
+/// A general purpose timer split into two 16-bit timers, each generating a
+/// PWM signal.
+pub struct PwmTimer {
+    registers: *mut Registers,
+    clock: sysctl::Clock,
+}
+
+impl PwmTimer {
+    const fn new(base_addr: usize, clock: sysctl::Clock) -> PwmTimer {
+        PwmTimer {
+            registers: base_addr as *mut Registers,
+            clock: clock,
+        }
+    }
+}
+
+impl hil::pwm::Pwm for PwmTimer {
+    type Pin = PwmOutput;
+
+    fn start(&self, output: &PwmOutput, frequency_hz: usize, duty_cycle: usize) -> ReturnCode {
+        if frequency_hz == 0 || duty_cycle > MAX_DUTY_CYCLE {
+            return ReturnCode::EINVAL;
+        }
+        let period = PWM_CLOCK_HZ / frequency_hz;
+        if period < 2 || period > MAX_PERIOD {
+            return ReturnCode::EINVAL;
+        }
+
+        // The output is high from the start of the period until the counter,
+        // counting down, reaches the match value
+        let high = (period as u64 * duty_cycle as u64 / MAX_DUTY_CYCLE as u64) as usize;
+        let match_value = (period - high) as u32;
+        let period = period as u32;
+
+        unsafe {
+            sysctl::enable_clock(self.clock);
+        }
+        let regs: &Registers = unsafe { &*self.registers };
+
+        // The halves share the configuration, which may only change while
+        // neither of them runs, so keep it if the other half is generating
+        if regs.ctl.get() & ((1 << 0) | (1 << 8)) == 0 {
+            regs.cfg.set((regs.cfg.get() & !0x7) | 0x4); // Split into two 16-bit timers
+            regs.cc.set(regs.cc.get() | 0x1); // Count PIOSC cycles
+        }
+        match *output {
+            PwmOutput::A => {
+                regs.ctl.set(regs.ctl.get() & !(1 << 0)); // TAEN
+                regs.tamr.set(0x0A); // Periodic, PWM mode
+                regs.tailr.set(period & 0xffff);
+                regs.tapr.set(period >> 16);
+                regs.tamatchr.set(match_value & 0xffff);
+                regs.tapmr.set(match_value >> 16);
+                regs.ctl.set(regs.ctl.get() | (1 << 0));
+            }
+            PwmOutput::B => {
+                regs.ctl.set(regs.ctl.get() & !(1 << 8)); // TBEN
+                regs.tbmr.set(0x0A); // Periodic, PWM mode
+                regs.tbilr.set(period & 0xffff);
+                regs.tbpr.set(period >> 16);
+                regs.tbmatchr.set(match_value & 0xffff);
+                regs.tbpmr.set(match_value >> 16);
+                regs.ctl.set(regs.ctl.get() | (1 << 8));
+            }
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self, output: &PwmOutput) -> ReturnCode {
+        let regs: &Registers = unsafe { &*self.registers };
+        match *output {
+            PwmOutput::A => regs.ctl.set(regs.ctl.get() & !(1 << 0)),
+            PwmOutput::B => regs.ctl.set(regs.ctl.get() & !(1 << 8)),
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn get_maximum_frequency_hz(&self) -> usize {
+        PWM_CLOCK_HZ / 2
+    }
+
+    fn get_maximum_duty_cycle(&self) -> usize {
+        MAX_DUTY_CYCLE
+    }
+}
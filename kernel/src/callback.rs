@@ -37,6 +37,18 @@ impl AppId {
     pub fn get_editable_flash_range(&self) -> (usize, usize) {
         process::get_editable_flash_range(self.idx)
     }
+
+    pub fn get_nonvolatile_storage_size(&self) -> usize {
+        process::get_nonvolatile_storage_size(self.idx)
+    }
+
+    pub fn get_package_name(&self) -> Option<&'static str> {
+        process::get_package_name(self.idx)
+    }
+
+    pub fn restart(&self) -> bool {
+        process::restart(self.idx)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -0,0 +1,93 @@
+//! Interface for block-addressed storage devices.
+//!
+//! Storage media such as SD cards, internal or external flash, and RAM disks
+//! all present themselves as an array of fixed-size blocks. This interface
+//! lets users such as filesystems and loggers be written once and run on top
+//! of any of them.
+//!
+//! All operations are asynchronous and operate on whole blocks. Buffers passed
+//! to `read_blocks` and `write_blocks` must be at least
+//! `count * block_size()` bytes long. Once an operation has started, that is
+//! the call returned `SUCCESS`, its completion is signaled to the
+//! [Client](trait.Client.html), which receives the buffer back even if the
+//! operation failed part way. If the call returns an error, the operation never
+//! started, no callback occurs and the buffer is dropped.
+//!
+//! Some media (e.g. SD cards) do not need blocks to be erased before they are
+//! written. Implementations for those media may return `ENOSUPPORT` from
+//! `erase_blocks`; writes on any medium must succeed without a prior erase.
+//!
+//! # Example
+//!
+//! ```rust
+//! struct Logger<'a> {
+//!     storage: &'a hil::block_storage::BlockStorage,
+//!     buffer: TakeCell<'static, [u8]>,
+//! }
+//!
+//! impl<'a> Logger<'a> {
+//!     fn append(&self, block: usize) -> ReturnCode {
+//!         self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+//!             self.storage.write_blocks(buffer, block, 1)
+//!         })
+//!     }
+//! }
+//!
+//! impl<'a> hil::block_storage::Client for Logger<'a> {
+//!     fn read_done(&self, buffer: &'static mut [u8], _count: usize, _error: ReturnCode) {
+//!         self.buffer.replace(buffer);
+//!     }
+//!     fn write_done(&self, buffer: &'static mut [u8], _count: usize, _error: ReturnCode) {
+//!         self.buffer.replace(buffer);
+//!     }
+//!     fn erase_done(&self, _count: usize, _error: ReturnCode) {}
+//! }
+//! ```
+
+use returncode::ReturnCode;
+
+/// A storage device organized as `block_count()` blocks of `block_size()`
+/// bytes each.
+pub trait BlockStorage {
+    /// Set the client to be called when operations complete.
+    fn set_client(&self, client: &'static Client);
+
+    /// Size of a single block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Number of blocks available on the device. This may be 0 if the device
+    /// is not present or has not been initialized yet.
+    fn block_count(&self) -> usize;
+
+    /// Read `count` blocks starting at block `block` into `buffer`.
+    ///
+    /// Returns `EINVAL` if `count` is 0 or the range is beyond the end of the
+    /// device, `ESIZE` if the buffer is too small, and `EBUSY` if another
+    /// operation is in progress. Callers should check the range before giving
+    /// up their buffer, as it is dropped on any of these errors.
+    fn read_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode;
+
+    /// Write `count` blocks from `buffer` starting at block `block`.
+    ///
+    /// Return values follow `read_blocks`.
+    fn write_blocks(&self, buffer: &'static mut [u8], block: usize, count: usize) -> ReturnCode;
+
+    /// Erase `count` blocks starting at block `block`.
+    ///
+    /// Returns `ENOSUPPORT` on media that do not need an explicit erase.
+    fn erase_blocks(&self, block: usize, count: usize) -> ReturnCode;
+}
+
+/// Implement `Client` to receive callbacks from `BlockStorage`.
+pub trait Client {
+    /// A read finished. `count` is the number of blocks that were read into
+    /// the buffer before the operation completed or failed.
+    fn read_done(&self, buffer: &'static mut [u8], count: usize, error: ReturnCode);
+
+    /// A write finished. `count` is the number of blocks that were written
+    /// before the operation completed or failed.
+    fn write_done(&self, buffer: &'static mut [u8], count: usize, error: ReturnCode);
+
+    /// An erase finished. `count` is the number of blocks that were erased.
+    fn erase_done(&self, count: usize, error: ReturnCode);
+}
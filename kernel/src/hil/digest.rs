@@ -0,0 +1,73 @@
+//! Interface for message digests (hashes) and message authentication codes.
+//!
+//! A digest engine absorbs data passed with `add_data()`, one buffer at a
+//! time, and writes the digest of everything added since the mode was last
+//! set into the buffer passed to `run()`. Setting the mode with
+//! `set_mode_sha256()` or `set_mode_hmacsha256()` starts a new message.
+//!
+//! Both operations complete asynchronously, and hand the buffer back to the
+//! client through `add_data_done()` or `hash_done()`.
+//!
+//! See the usage in `capsules::digest` for an example client, and
+//! `capsules::sha256` for a software engine.
+
+use returncode::ReturnCode;
+
+/// The number of bytes in a SHA-256 or HMAC-SHA256 digest.
+pub const SHA256_OUTPUT_SIZE: usize = 32;
+
+/// The number of bytes SHA-256 processes at a time. HMAC-SHA256 keys longer
+/// than this are hashed first.
+pub const SHA256_BLOCK_SIZE: usize = 64;
+
+/// Implement this trait and use `set_client()` in order to receive callbacks
+/// from a `Digest` instance.
+pub trait Client {
+    /// The data passed to `add_data()` has been absorbed, and `data` can be
+    /// reused.
+    fn add_data_done(&self, result: ReturnCode, data: &'static mut [u8]);
+
+    /// The digest has been written to the first `SHA256_OUTPUT_SIZE` bytes of
+    /// `digest`, if `result` is `SUCCESS`.
+    fn hash_done(&self, result: ReturnCode, digest: &'static mut [u8]);
+}
+
+pub trait Digest<'a> {
+    /// Set the client instance which will receive `add_data_done()` and
+    /// `hash_done()` callbacks
+    fn set_client(&'a self, client: &'a Client);
+
+    /// Add the first `len` bytes of `data` to the message.
+    ///
+    /// If `SUCCESS` is returned, `add_data_done()` will be called once the
+    /// data has been absorbed. Otherwise the buffer is returned along with
+    /// the error: `EINVAL` if `len` is longer than the buffer, `EBUSY` if an
+    /// operation is in progress, and `EOFF` if no mode has been set.
+    fn add_data(
+        &self,
+        data: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Finish the message and write its digest to `digest`, which must hold
+    /// at least `SHA256_OUTPUT_SIZE` bytes.
+    ///
+    /// If `SUCCESS` is returned, `hash_done()` will be called with the
+    /// digest. A new message must be started by setting the mode before more
+    /// data is added. Errors are as for `add_data()`.
+    fn run(&self, digest: &'static mut [u8]) -> (ReturnCode, Option<&'static mut [u8]>);
+
+    /// Discard the message in progress, including any HMAC key.
+    fn clear_data(&self);
+}
+
+pub trait Sha256 {
+    /// Start a new SHA-256 message
+    fn set_mode_sha256(&self) -> ReturnCode;
+}
+
+pub trait HmacSha256 {
+    /// Start a new HMAC-SHA256 message authenticated with `key`.
+    /// Returns `EBUSY` if an operation is in progress.
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> ReturnCode;
+}
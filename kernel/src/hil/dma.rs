@@ -0,0 +1,129 @@
+//! Interface for direct memory access (DMA) controllers.
+//!
+//! A DMA controller has a number of channels, each of which moves data
+//! between a buffer in memory and one peripheral at a time without involving
+//! the CPU. Peripheral drivers in a chip crate are usually given their
+//! channels at chip initialization; capsules and other drivers that want DMA
+//! (for instance to stream ADC samples, or to move SD card blocks) take a
+//! channel from the controller with `allocate_channel()`.
+//!
+//! A transfer is set up with `prepare_transmit()` (memory to peripheral) or
+//! `prepare_receive()` (peripheral to memory), started with
+//! `start_transfer()`, and reported to the channel's client with
+//! `transfer_done()`. The client then takes the buffer back with
+//! `abort_transfer()`, which also stops a transfer still in progress.
+//!
+//! Lengths are counted in elements of the channel's `Width`, not in bytes.
+
+use returncode::ReturnCode;
+
+/// The size of each element a channel moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Width {
+    Width8Bit,
+    Width16Bit,
+    Width32Bit,
+}
+
+impl Width {
+    /// The number of bytes in one element.
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::Width8Bit => 1,
+            Width::Width16Bit => 2,
+            Width::Width32Bit => 4,
+        }
+    }
+}
+
+/// Hands out the channels of a DMA controller.
+pub trait DmaController {
+    type Channel: DmaChannel + 'static;
+
+    /// Reserve a free channel, or return `None` if all channels are in use.
+    fn allocate_channel(&self) -> Option<&'static Self::Channel>;
+
+    /// Stop and free a channel obtained from `allocate_channel()`.
+    fn release_channel(&self, channel: &'static Self::Channel);
+}
+
+/// A single DMA channel.
+pub trait DmaChannel {
+    /// The chip-specific name of a peripheral function a channel can serve,
+    /// such as "SPI receive".
+    type Peripheral: Copy;
+
+    fn set_client(&self, client: &'static Client<Self::Peripheral>);
+
+    fn set_width(&self, width: Width);
+
+    /// Power the channel up. Must be called before a transfer.
+    fn enable(&self);
+
+    /// Stop the channel and power it down.
+    fn disable(&self);
+
+    fn is_enabled(&self) -> bool;
+
+    /// Set up a transfer of `len` elements from `buf` to the peripheral.
+    /// `len` is shortened to what fits in `buf`. On error, the buffer can be
+    /// recovered with `abort_transfer()`.
+    fn prepare_transmit(
+        &self,
+        peripheral: Self::Peripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode;
+
+    /// Set up a transfer of `len` elements from the peripheral into `buf`.
+    /// `len` is shortened to what fits in `buf`. On error, the buffer can be
+    /// recovered with `abort_transfer()`.
+    fn prepare_receive(
+        &self,
+        peripheral: Self::Peripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode;
+
+    /// Start the transfer that was prepared.
+    fn start_transfer(&self);
+
+    /// Prepare and start a transfer from memory to the peripheral.
+    fn transmit(
+        &self,
+        peripheral: Self::Peripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode {
+        let result = self.prepare_transmit(peripheral, buf, len);
+        if result == ReturnCode::SUCCESS {
+            self.start_transfer();
+        }
+        result
+    }
+
+    /// Prepare and start a transfer from the peripheral to memory.
+    fn receive(
+        &self,
+        peripheral: Self::Peripheral,
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> ReturnCode {
+        let result = self.prepare_receive(peripheral, buf, len);
+        if result == ReturnCode::SUCCESS {
+            self.start_transfer();
+        }
+        result
+    }
+
+    /// Stop any transfer in progress and return the buffer it used.
+    fn abort_transfer(&self) -> Option<&'static mut [u8]>;
+
+    /// The number of elements the current transfer has yet to move.
+    fn transfer_counter(&self) -> usize;
+}
+
+pub trait Client<P> {
+    /// Called when a transfer for `peripheral` has finished.
+    fn transfer_done(&self, peripheral: P);
+}
@@ -2,14 +2,18 @@
 
 pub mod adc;
 pub mod ble_advertising;
+pub mod block_storage;
 pub mod crc;
 pub mod dac;
+pub mod digest;
+pub mod dma;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
 pub mod i2c;
 pub mod led;
 pub mod nonvolatile_storage;
+pub mod pwm;
 pub mod radio;
 pub mod rng;
 pub mod sensors;
@@ -96,6 +96,11 @@ pub trait AES128CBC {
     fn set_mode_aes128cbc(&self, encrypting: bool);
 }
 
+pub trait AES128ECB {
+    /// Call before `AES128::crypt()` to perform AES128ECB
+    fn set_mode_aes128ecb(&self, encrypting: bool);
+}
+
 pub trait CCMClient {
     /// `res` is SUCCESS if the encryption/decryption process succeeded. This
     /// does not mean that the message has been verified in the case of
@@ -130,3 +135,44 @@ pub trait AES128CCM<'a> {
         encrypting: bool,
     ) -> (ReturnCode, Option<&'static mut [u8]>);
 }
+
+/// GCM nonces must have this length.
+pub const GCM_NONCE_LENGTH: usize = 12;
+
+/// The length of a full GCM authentication tag. Shorter tags are truncated.
+pub const GCM_TAG_LENGTH: usize = 16;
+
+pub trait GCMClient {
+    /// `res` is SUCCESS if the encryption/decryption process succeeded.
+    /// If we are encrypting: `tag_is_valid` is `true` iff `res` is SUCCESS.
+    /// If we are decrypting: `tag_is_valid` is `true` iff `res` is SUCCESS and the
+    /// message authentication tag is valid.
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool);
+}
+
+pub trait AES128GCM<'a> {
+    /// Set the client instance which will receive `crypt_done()` callbacks
+    fn set_client(&'a self, client: &'a GCMClient);
+
+    /// Set the key to be used for GCM encryption
+    fn set_key(&self, key: &[u8]) -> ReturnCode;
+
+    /// Set the nonce (length GCM_NONCE_LENGTH) to be used for GCM encryption
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode;
+
+    /// Try to begin the encryption/decryption process
+    ///
+    /// `buf[a_off..m_off]` is the additional authenticated data and
+    /// `buf[m_off..m_off + m_len]` the message, which is encrypted or
+    /// decrypted in place. The tag, of `tag_len` bytes, follows the message:
+    /// it is written there when encrypting and checked when decrypting.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        tag_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
@@ -24,6 +24,26 @@ pub trait UsbController {
 
     fn endpoint_bulk_out_enable(&self, endpoint: usize);
 
+    // Enable a non-control endpoint with the transfer type and maximum packet
+    // size from its endpoint descriptor. The endpoint's buffer must hold
+    // `max_packet_size` bytes. Interrupt and isochronous endpoints are
+    // serviced through the client's `bulk_in()` and `bulk_out()` callbacks,
+    // and resumed after a `Delay` with `endpoint_bulk_resume()`, just like
+    // bulk endpoints
+    fn endpoint_in_enable(
+        &self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        max_packet_size: usize,
+    );
+
+    fn endpoint_out_enable(
+        &self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        max_packet_size: usize,
+    );
+
     fn endpoint_bulk_resume(&self, endpoint: usize);
 }
 
@@ -32,6 +52,15 @@ pub enum DeviceSpeed {
     Low,
 }
 
+/// The transfer type of an endpoint, as encoded in its endpoint descriptor
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    Control = 0,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
 /// USB controller client interface
 pub trait Client {
     fn enable(&self);
@@ -62,6 +62,8 @@ pub fn main<P: Platform, C: Chip>(
     };
 
     loop {
+        platform.watchdog().map(|watchdog| watchdog.tickle());
+
         unsafe {
             chip.service_pending_interrupts();
 
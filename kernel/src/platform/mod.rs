@@ -1,4 +1,5 @@
 use driver::Driver;
+use hil;
 
 pub mod mpu;
 pub mod systick;
@@ -10,6 +11,12 @@ pub trait Platform {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
     where
         F: FnOnce(Option<&Driver>) -> R;
+
+    /// The watchdog timer the kernel main loop services, if the platform has
+    /// one running
+    fn watchdog(&self) -> Option<&hil::watchdog::Watchdog> {
+        None
+    }
 }
 
 /// Interface for individual MCUs.
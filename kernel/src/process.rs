@@ -132,6 +132,52 @@ pub fn get_editable_flash_range(app_idx: usize) -> (usize, usize) {
     }
 }
 
+/// Returns the number of bytes of nonvolatile storage the app requested in its
+/// TBF header, or 0 if there is no such app or it made no request.
+pub fn get_nonvolatile_storage_size(app_idx: usize) -> usize {
+    let procs = unsafe { &mut PROCS };
+    if app_idx >= procs.len() {
+        return 0;
+    }
+
+    match procs[app_idx] {
+        None => 0,
+        Some(ref p) => p.header.get_nonvolatile_storage_size() as usize,
+    }
+}
+
+/// Returns the package name of the app, or `None` if there is no such app.
+pub fn get_package_name(app_idx: usize) -> Option<&'static str> {
+    let procs = unsafe { &mut PROCS };
+    if app_idx >= procs.len() {
+        return None;
+    }
+
+    match procs[app_idx] {
+        None => None,
+        Some(ref p) => Some(p.package_name),
+    }
+}
+
+/// Restarts the app from its entry point, discarding its memory, grants and
+/// pending callbacks. Returns `false` if there is no such app.
+pub fn restart(app_idx: usize) -> bool {
+    let procs = unsafe { &mut PROCS };
+    if app_idx >= procs.len() {
+        return false;
+    }
+
+    match procs[app_idx] {
+        None => false,
+        Some(ref mut p) => {
+            unsafe {
+                p.restart();
+            }
+            true
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Error {
     NoSuchApp,
@@ -230,7 +276,10 @@ enum TbfHeaderTypes {
     TbfHeaderMain = 1,
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
-    Unused = 5,
+    // 4 is the PIC option (`PicOption1Fields`), which elf2tab already uses
+    // for apps that have the kernel do their PIC fixups.
+    TbfHeaderNonvolatileStorage = 5,
+    Unused = 6,
 }
 
 /// The TLV header (T and L).
@@ -264,6 +313,14 @@ struct TbfHeaderV2WriteableFlashRegion {
     writeable_flash_region_size: u32,
 }
 
+/// Amount of nonvolatile storage the app would like the kernel to reserve for
+/// it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TbfHeaderV2NonvolatileStorage {
+    storage_size: u32,
+}
+
 /// PIC fields for kernel provided PIC fixup.
 ///
 /// If an app wants the kernel to do the PIC fixup for it, it must pass this
@@ -290,6 +347,7 @@ struct TbfHeaderV2 {
     main: Option<&'static TbfHeaderV2Main>,
     package_name: Option<&'static str>,
     writeable_regions: Option<&'static [TbfHeaderV2WriteableFlashRegion]>,
+    nonvolatile_storage: Option<&'static TbfHeaderV2NonvolatileStorage>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -419,6 +477,15 @@ impl TbfHeader {
         }
     }
 
+    /// Get the number of bytes of nonvolatile storage the app requested.
+    fn get_nonvolatile_storage_size(&self) -> u32 {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) =>
+                hd.nonvolatile_storage.map_or(0, |ns| ns.storage_size),
+            _ => 0,
+        }
+    }
+
     /// Get the offset and size of a given flash region.
     fn get_writeable_flash_region(&self, index: usize) -> (u32, u32) {
         match *self {
@@ -522,6 +589,7 @@ unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfHeader>
                 // options.
                 let mut main_pointer: Option<&TbfHeaderV2Main> = None;
                 let mut wfr_pointer: Option<&'static [TbfHeaderV2WriteableFlashRegion]> = None;
+                let mut nvs_pointer: Option<&'static TbfHeaderV2NonvolatileStorage> = None;
                 let mut app_name_str = "";
 
                 // Loop through the header looking for known options.
@@ -559,6 +627,13 @@ unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfHeader>
                                     let _ = str::from_utf8(package_name_byte_array).map(|name_str| { app_name_str = name_str; });
                                 }
                             }
+                            TbfHeaderTypes::TbfHeaderNonvolatileStorage => /* Nonvolatile Storage */ {
+                                if remaining_length >= mem::size_of::<TbfHeaderV2NonvolatileStorage>() &&
+                                   tbf_tlv_header.length as usize == mem::size_of::<TbfHeaderV2NonvolatileStorage>() {
+                                    let tbf_nvs = &*(address.offset(offset) as *const TbfHeaderV2NonvolatileStorage);
+                                    nvs_pointer = Some(tbf_nvs);
+                                }
+                            }
                             TbfHeaderTypes::Unused => {}
                         }
                     }
@@ -574,6 +649,7 @@ unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfHeader>
                     main: main_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: wfr_pointer,
+                    nonvolatile_storage: nvs_pointer,
                 };
 
                 Some(TbfHeader::TbfHeaderV2(tbf_header))
@@ -770,18 +846,60 @@ impl<'a> Process<'a> {
                 panic!("Process {} had a fault", self.package_name);
             }
             FaultResponse::Restart => {
-                //XXX: unimplemented
-                panic!("Process {} had a fault and could not be restarted",
-                       self.package_name);
-                /*
-                // HAVE_WORK is really screwed up in this case
-                // the tasks ring buffer needs to be cleared
-                // need to re-load() the app
-                 */
+                // Start the process over from its entry point. It faulted
+                // while running, so `restart()` stops counting it as work.
+                self.restart();
             }
         }
     }
 
+    /// Put the process back in the state `create()` left it in, so that it
+    /// starts again from its entry point the next time it is scheduled.
+    pub unsafe fn restart(&mut self) {
+        // Drop pending callbacks, and stop counting this process as runnable
+        while self.dequeue_task().is_some() {}
+        if self.state != State::Yielded {
+            HAVE_WORK.set(HAVE_WORK.get() - 1);
+        }
+
+        // Forget all grants. The process struct sits right below the grant
+        // pointers and the callback buffer, where `create()` left the break.
+        let grant_ptrs_num = read_volatile(&grant::CONTAINER_COUNTER);
+        let memory_end = self.memory.as_ptr().offset(self.memory.len() as isize);
+        let grant_ptrs = memory_end.offset(-((grant_ptrs_num * mem::size_of::<*const usize>()) as isize));
+        let opts = slice::from_raw_parts_mut(grant_ptrs as *mut *const usize, grant_ptrs_num);
+        for opt in opts.iter_mut() {
+            *opt = ptr::null()
+        }
+        self.kernel_memory_break = self as *const Process as *const u8;
+
+        // Same initial layout as `load()`
+        let initial_pointer = self.memory.as_ptr().offset(128);
+        self.app_break = initial_pointer;
+        self.current_stack_pointer = initial_pointer;
+        self.debug.min_stack_pointer = initial_pointer;
+        for region in self.mpu_regions.iter() {
+            region.set((ptr::null(), math::PowerOfTwo::zero()));
+        }
+
+        let init_fn = self.text.as_ptr().offset(self.header.get_init_function_offset() as isize) as usize;
+        let flash_app_start = self.text.as_ptr() as usize + self.header.get_protected_size() as usize;
+        self.stored_regs = Default::default();
+        self.yield_pc = init_fn;
+        // Set the Thumb bit and clear everything else
+        self.psr = 0x01000000;
+        self.state = State::Yielded;
+
+        self.tasks.enqueue(Task::FunctionCall(FunctionCall {
+            pc: init_fn,
+            r0: flash_app_start,
+            r1: self.memory.as_ptr() as usize,
+            r2: self.memory.len() as usize,
+            r3: self.app_break as usize,
+        }));
+        HAVE_WORK.set(HAVE_WORK.get() + 1);
+    }
+
     pub fn dequeue_task(&mut self) -> Option<Task> {
         self.tasks.dequeue().map(|cb| {
             unsafe {
@@ -26,6 +26,10 @@ STACK_SIZE       ?= 2048
 APP_HEAP_SIZE    ?= 1024
 KERNEL_HEAP_SIZE ?= 1024
 
+# Bytes of nonvolatile storage the app asks the kernel for. With 0, the board
+# decides how much the app gets.
+NONVOLATILE_STORAGE_SIZE ?= 0
+
 # PACKAGE_NAME is used to identify the application for IPC and for error reporting
 PACKAGE_NAME ?= $(shell basename "$(shell pwd)")
 
@@ -36,6 +40,7 @@ TOCK_ARCHS ?= cortex-m0 cortex-m3 cortex-m4
 ELF2TAB ?= cargo run --manifest-path $(TOCK_USERLAND_BASE_DIR)/tools/elf2tab/Cargo.toml --
 ELF2TAB_ARGS += -n $(PACKAGE_NAME)
 ELF2TAB_ARGS += --stack $(STACK_SIZE) --app-heap $(APP_HEAP_SIZE) --kernel-heap $(KERNEL_HEAP_SIZE)
+ELF2TAB_ARGS += --nonvolatile-storage $(NONVOLATILE_STORAGE_SIZE)
 
 # Flags for building app Assembly, C, C++ files
 # n.b. make convention is that CPPFLAGS are shared for C and C++ sources
@@ -12,18 +12,17 @@ typedef struct {
 
 // Internal callback for creating synchronous functions
 //
-// callback_type - number indicating which type of callback occurred
-// currently 1(encryption) and 2(decryption)
+// ret - the result of the operation
 // callback_args - user data passed into the set_callback function
 //
-static void aes_cb(int callback_type,
+static void aes_cb(int ret,
                    __attribute__ ((unused)) int unused1,
                    __attribute__ ((unused)) int unused2,
                    void *callback_args) {
 
   aes_data_t *result = (aes_data_t*)callback_args;
   result->fired = true;
-  result->error = callback_type;
+  result->error = ret;
 }
 
 
@@ -44,16 +43,31 @@ int aes128_set_ctr(const unsigned char* ctr, unsigned char len) {
   return allow(AES_DRIVER, AES_CTR, (void*)ctr, len);
 }
 
+// Internal function to configure the IV, initial counter or nonce
+int aes128_set_iv(const unsigned char* iv, unsigned char len) {
+  return allow(AES_DRIVER, AES_IV, (void*)iv, len);
+}
+
+// Internal function to configure additional authenticated data
+int aes128_set_aad(const unsigned char* aad, unsigned char len) {
+  return allow(AES_DRIVER, AES_AAD, (void*)aad, len);
+}
+
+// Internal function to trigger an operation with the given mode
+int aes128_crypt_start(bool encrypt, int mode, int tag_len) {
+  return command(AES_DRIVER, encrypt ? AES_ENC : AES_DEC, mode, tag_len);
+}
+
 // Internal function to trigger encryption operation. Note that this doesn't
 // work by itself aes128_set_data() and aes128_set_ctr() must be called first
 int aes128_encrypt_start(void) {
-  return command(AES_DRIVER, AES_ENC, 0, 0);
+  return aes128_crypt_start(true, AES_MODE_CTR, 0);
 }
 
 // Internal function to trigger encryption operation. Note that this doesn't
 // work by itself aes128_set_data() and aes128_set_ctr() must be called first
 int aes128_decrypt_start(void) {
-  return command(AES_DRIVER, AES_DEC, 0, 0);
+  return aes128_crypt_start(false, AES_MODE_CTR, 0);
 }
 
 // Function to encrypt by aes128 counter-mode with a given payload and
@@ -107,7 +121,7 @@ int aes128_set_key_sync(const unsigned char* key, unsigned char len) {
   err = allow(AES_DRIVER, AES_KEY, (void*)key, len);
   if (err < TOCK_SUCCESS) return err;
 
-  return command(AES_DRIVER, AES_KEY, 0, 0);
+  return command(AES_DRIVER, AES_SET_KEY, 0, 0);
 }
 
 
@@ -161,3 +175,38 @@ int aes128_decrypt_ctr_sync(const unsigned char* buf, unsigned char buf_len,
 
   return result.error;
 }
+
+
+// Function to encrypt or decrypt in place with the given mode synchronously
+static int aes128_crypt_sync(bool encrypt, int mode, unsigned char* buf, unsigned char buf_len,
+                             const unsigned char* iv, unsigned char iv_len, int tag_len) {
+
+  int err;
+  aes_data_t result = { .fired = false, .error = TOCK_SUCCESS };
+
+  err = aes128_set_callback(aes_cb, &result);
+  if (err < TOCK_SUCCESS) return err;
+
+  err = aes128_set_data(buf, buf_len);
+  if (err < TOCK_SUCCESS) return err;
+
+  err = aes128_set_iv(iv, iv_len);
+  if (err < TOCK_SUCCESS) return err;
+
+  err = aes128_crypt_start(encrypt, mode, tag_len);
+  if (err < TOCK_SUCCESS) return err;
+
+  yield_for(&result.fired);
+
+  return result.error;
+}
+
+int aes128_encrypt_sync(int mode, unsigned char* buf, unsigned char buf_len,
+                        const unsigned char* iv, unsigned char iv_len, int tag_len) {
+  return aes128_crypt_sync(true, mode, buf, buf_len, iv, iv_len, tag_len);
+}
+
+int aes128_decrypt_sync(int mode, unsigned char* buf, unsigned char buf_len,
+                        const unsigned char* iv, unsigned char iv_len, int tag_len) {
+  return aes128_crypt_sync(false, mode, buf, buf_len, iv, iv_len, tag_len);
+}
@@ -7,15 +7,32 @@ extern "C" {
 #endif
 
 #define AES_DRIVER 0x40000
+
+// Allow numbers. The IV buffer holds the initial counter (CTR), the IV (CBC)
+// or the nonce (13 bytes for CCM, 12 for GCM).
 #define AES_KEY    0
 #define AES_DATA   1
-#define AES_ENC    2
-#define AES_DEC    3
-#define AES_CTR    4
+#define AES_IV     4
+#define AES_AAD    5
+#define AES_CTR    AES_IV
+
+// Command numbers
+#define AES_SET_KEY 0
+#define AES_CHECK   1
+#define AES_ENC     2
+#define AES_DEC     3
+
+// Modes, passed with AES_ENC and AES_DEC
+#define AES_MODE_CTR 0
+#define AES_MODE_ECB 1
+#define AES_MODE_CBC 2
+#define AES_MODE_CCM 3
+#define AES_MODE_GCM 4
 
 
 // function called by the encryption or decryption operation when they are 
-// finished
+// finished, with the result of the operation as its first argument. A CCM
+// or GCM decryption whose tag does not match fails with TOCK_FAIL.
 //
 // callback       - pointer to function to be called
 // callback_args  - pointer to data provided to the callback
@@ -37,12 +54,32 @@ int aes128_set_data(const unsigned char *data, unsigned char len);
 int aes128_set_ctr(const unsigned char *ctr, unsigned char len);
 
 
+// configures a buffer with the IV, initial counter or nonce for the mode
+//
+// iv             - buffer with the IV
+// len            - length of the IV buffer
+int aes128_set_iv(const unsigned char *iv, unsigned char len);
+
+
+// configures a buffer with additional authenticated data for CCM and GCM
+//
+// aad            - buffer with the additional data
+// len            - length of the additional data buffer
+int aes128_set_aad(const unsigned char *aad, unsigned char len);
+
+
 // Internal function to trigger encryption operation. 
 // Note that this has no effect if not aes128_set_data() and aes128_set_ctr()
 // have been invoked
 int aes128_encrypt_start(void);
 
 
+// Internal function to trigger an encryption or decryption with the given
+// mode. For CCM and GCM, the data buffer holds the message followed by room
+// for a tag of tag_len bytes, which is written or checked in place.
+int aes128_crypt_start(bool encrypt, int mode, int tag_len);
+
+
 // Internal function to trigger decryption operation. 
 // Note that this has no effect if not aes128_set_data() and aes128_set_ctr()
 // have been invoked
@@ -98,6 +135,25 @@ int aes128_encrypt_ctr_sync(const unsigned char* buf, unsigned char buf_len,
 int aes128_decrypt_ctr_sync(const unsigned char* buf, unsigned char buf_len, 
     const unsigned char* ctr, unsigned char ctr_len);
 
+// encrypts a payload in place with the given mode
+//
+// mode     - one of the AES_MODE_* values
+// buf      - buffer to encrypt (currently max 128 bytes are supported),
+//            followed by room for the tag for CCM and GCM
+// buf_len  - length of the buffer, including the tag
+// iv       - buffer with the IV, initial counter or nonce
+// iv_len   - length of the IV buffer
+// tag_len  - length of the CCM or GCM tag, ignored for other modes
+int aes128_encrypt_sync(int mode, unsigned char* buf, unsigned char buf_len,
+    const unsigned char* iv, unsigned char iv_len, int tag_len);
+
+
+// decrypts a payload in place with the given mode, with the same arguments
+// as aes128_encrypt_sync(). Fails with TOCK_FAIL if a CCM or GCM tag does
+// not match, leaving the buffer untouched.
+int aes128_decrypt_sync(int mode, unsigned char* buf, unsigned char buf_len,
+    const unsigned char* iv, unsigned char iv_len, int tag_len);
+
 #ifdef __cplusplus
 }
 #endif
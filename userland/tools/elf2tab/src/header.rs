@@ -16,6 +16,7 @@ enum TbfHeaderTypes {
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
     TbfHeaderPicOption1 = 4,
+    TbfHeaderNonvolatileStorage = 5,
 }
 
 #[repr(C)]
@@ -52,6 +53,13 @@ struct TbfHeaderWriteableFlashRegion {
     size: u32,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TbfHeaderNonvolatileStorage {
+    base: TbfHeaderTlv,
+    storage_size: u32,
+}
+
 impl fmt::Display for TbfHeaderBase {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -107,11 +115,24 @@ impl fmt::Display for TbfHeaderWriteableFlashRegion {
     }
 }
 
+impl fmt::Display for TbfHeaderNonvolatileStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "
+          storage_size: {:>8} {:>#10X}
+",
+            self.storage_size, self.storage_size,
+        )
+    }
+}
+
 pub struct TbfHeader {
     hdr_base: TbfHeaderBase,
     hdr_main: TbfHeaderMain,
     hdr_pkg_name_tlv: Option<TbfHeaderTlv>,
     hdr_wfr: Vec<TbfHeaderWriteableFlashRegion>,
+    hdr_nvs: Option<TbfHeaderNonvolatileStorage>,
     package_name: String,
     package_name_pad: usize,
 }
@@ -138,6 +159,7 @@ impl TbfHeader {
             },
             hdr_pkg_name_tlv: None,
             hdr_wfr: Vec::new(),
+            hdr_nvs: None,
             package_name: String::new(),
             package_name_pad: 0,
         }
@@ -155,6 +177,7 @@ impl TbfHeader {
         minimum_ram_size: u32,
         writeable_flash_regions: usize,
         package_name: String,
+        nonvolatile_storage_size: u32,
     ) -> usize {
         // Need to calculate lengths ahead of time.
         // Need the base and the main section.
@@ -176,6 +199,12 @@ impl TbfHeader {
         // Add room for the writeable flash regions header TLV.
         header_length += mem::size_of::<TbfHeaderWriteableFlashRegion>() * writeable_flash_regions;
 
+        // Add room for the nonvolatile storage header TLV, if the app wants
+        // storage.
+        if nonvolatile_storage_size > 0 {
+            header_length += mem::size_of::<TbfHeaderNonvolatileStorage>();
+        }
+
         // Flags default to app is enabled.
         let flags = 0x00000001;
 
@@ -205,6 +234,16 @@ impl TbfHeader {
             });
         }
 
+        if nonvolatile_storage_size > 0 {
+            self.hdr_nvs = Some(TbfHeaderNonvolatileStorage {
+                base: TbfHeaderTlv {
+                    tipe: TbfHeaderTypes::TbfHeaderNonvolatileStorage,
+                    length: 4,
+                },
+                storage_size: nonvolatile_storage_size,
+            });
+        }
+
         // Return the length by generating the header and seeing how long it is.
         self.generate().unwrap().get_ref().len()
     }
@@ -249,6 +288,10 @@ impl TbfHeader {
             header_buf.write_all(unsafe { util::as_byte_slice(wfr) })?;
         }
 
+        if let Some(ref nvs) = self.hdr_nvs {
+            header_buf.write_all(unsafe { util::as_byte_slice(nvs) })?;
+        }
+
         let current_length = header_buf.get_ref().len();
         util::do_pad(&mut header_buf, align4needed!(current_length))?;
 
@@ -301,6 +344,9 @@ impl fmt::Display for TbfHeader {
         for wfr in self.hdr_wfr.iter() {
             write!(f, "{}", wfr)?;
         }
+        if let Some(ref nvs) = self.hdr_nvs {
+            write!(f, "{}", nvs)?;
+        }
         Ok(())
     }
 }
@@ -35,6 +35,12 @@ fn main() {
         "set kernel heap size in bytes",
         "KERNEL_HEAP_SIZE",
     );
+    opts.optopt(
+        "",
+        "nonvolatile-storage",
+        "request a nonvolatile storage partition of this many bytes",
+        "STORAGE_SIZE",
+    );
     opts.optflag("", "crt0-header", "include crt0 header for PIC fixups");
     opts.optflag("v", "verbose", "be verbose");
 
@@ -63,6 +69,12 @@ fn main() {
         .unwrap()
         .parse::<u32>()
         .expect("Kernel heap size must be an integer.");
+    let nonvolatile_storage_len = matches
+        .opt_str("nonvolatile-storage")
+        .map_or(0, |size| {
+            size.parse::<u32>()
+                .expect("Nonvolatile storage size must be an integer.")
+        });
 
     // Check that we have at least one input file elf to process.
     if matches.free.is_empty() {
@@ -121,6 +133,7 @@ build-date = {}",
             stack_len,
             app_heap_len,
             kernel_heap_len,
+            nonvolatile_storage_len,
         ).unwrap();
 
         // Add the file to the TAB tar file.
@@ -159,6 +172,7 @@ fn elf_to_tbf(
     stack_len: u32,
     app_heap_len: u32,
     kernel_heap_len: u32,
+    nonvolatile_storage_len: u32,
 ) -> io::Result<()> {
     let package_name = package_name.unwrap_or(String::new());
 
@@ -233,6 +247,7 @@ fn elf_to_tbf(
         minimum_ram_size,
         writeable_flash_regions_count,
         package_name,
+        nonvolatile_storage_len,
     );
     let protected_region_size = header_length;
     binary_index += protected_region_size;